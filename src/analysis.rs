@@ -0,0 +1,387 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module computes features that summarize a single window of the
+// spectrum, for drawing on top of the spectrogram. The spectra are the squared
+// norms as returned by `dft::dft_fast`, so the values are powers.
+
+/// Return the spectral centroid in Hz, the power-weighted mean frequency.
+///
+/// The centroid is a measure of the brightness of a sound. For a silent window
+/// there is no meaningful centroid, we return 0.0 then.
+pub fn spectral_centroid(spectrum: &[f32], hz_per_bin: f32) -> f32 {
+    let mut weighted_sum = 0.0_f64;
+    let mut total = 0.0_f64;
+    for (j, &p) in spectrum.iter().enumerate() {
+        weighted_sum = (p as f64).mul_add(j as f64, weighted_sum);
+        total += p as f64;
+    }
+
+    if total > 0.0 {
+        (weighted_sum / total) as f32 * hz_per_bin
+    } else {
+        0.0
+    }
+}
+
+/// Return the frequency in Hz below which `fraction` of the power is contained.
+///
+/// A typical value for `fraction` is 0.85. For a silent window we return 0.0.
+pub fn spectral_rolloff(spectrum: &[f32], hz_per_bin: f32, fraction: f32) -> f32 {
+    let total: f64 = spectrum.iter().map(|&p| p as f64).sum();
+    let threshold = total * fraction as f64;
+
+    let mut cumulative = 0.0_f64;
+    for (j, &p) in spectrum.iter().enumerate() {
+        cumulative += p as f64;
+        if cumulative >= threshold && total > 0.0 {
+            return j as f32 * hz_per_bin;
+        }
+    }
+
+    0.0
+}
+
+/// Return the frequency in Hz above which the spectrum contains no significant power.
+///
+/// The spectrum should be averaged over many windows. Power more than 80 dB
+/// below the peak does not count as significant, which ignores the noise that
+/// dither and resampling leave behind. For silence we return 0.0.
+pub fn bandwidth(mean_spectrum: &[f32], hz_per_bin: f32) -> f32 {
+    let peak = mean_spectrum.iter().cloned().fold(0.0_f32, f32::max);
+    let threshold = peak * 1e-8;
+    match mean_spectrum.iter().rposition(|&p| p > threshold) {
+        Some(j) => j as f32 * hz_per_bin,
+        None => 0.0,
+    }
+}
+
+/// Return whether the spectrum ends in a steep edge at `bandwidth_hz`.
+///
+/// Natural content fades out gradually towards the bandwidth. The lowpass
+/// filter of a lossy encoder, or of a resampler, instead cuts it off abruptly,
+/// so in the 1 kHz below the edge there is still substantial power. We call
+/// the edge steep when the power there is within 50 dB of the peak.
+pub fn is_steep_cutoff(mean_spectrum: &[f32], hz_per_bin: f32, bandwidth_hz: f32) -> bool {
+    let peak = mean_spectrum.iter().cloned().fold(0.0_f32, f32::max);
+    let end = ((bandwidth_hz / hz_per_bin) as usize).min(mean_spectrum.len());
+    let begin = ((bandwidth_hz - 1000.0) / hz_per_bin).max(0.0) as usize;
+    if begin >= end {
+        return false;
+    }
+    let band = &mean_spectrum[begin..end];
+    let mean = band.iter().sum::<f32>() / band.len() as f32;
+    peak > 0.0 && mean > peak * 1e-5
+}
+
+/// Count the powers per level, for a histogram of levels between `min_db` and `max_db`.
+///
+/// The range is divided into `counts.len()` equal parts in dB, and we add one
+/// to the count of the part that every power falls in. Powers outside of the
+/// range count towards the first or last part.
+pub fn level_histogram<I: Iterator<Item = f32>>(
+    powers: I,
+    min_db: f32,
+    max_db: f32,
+    counts: &mut [u32],
+) {
+    let last = match counts.len() {
+        0 => return,
+        n => n - 1,
+    };
+    let scale = counts.len() as f32 / (max_db - min_db);
+    for p in powers {
+        let level_db = 10.0 * p.log10();
+        // For silence, the level is -inf, and the cast saturates to 0.
+        let i = ((level_db - min_db) * scale).max(0.0) as usize;
+        counts[i.min(last)] += 1;
+    }
+}
+
+/// Estimate the noise floor of a window, as the power that 10% of the bins are below.
+pub fn noise_floor(spectrum: &[f32]) -> f32 {
+    if spectrum.is_empty() {
+        return 0.0;
+    }
+    let mut powers = spectrum.to_vec();
+    let i = powers.len() / 10;
+    let (_, floor, _) = powers.select_nth_unstable_by(i, |a, b| a.total_cmp(b));
+    *floor
+}
+
+/// Smooth positive values with a moving geometric mean over `radius` values on either side.
+///
+/// Noise floors span orders of magnitude, so we average them in the log domain,
+/// where a single loud window does not raise the floor of its neighbors much.
+pub fn smooth_log(values: &[f32], radius: usize) -> Vec<f32> {
+    // Prefix sums make this linear in the number of values, regardless of the radius.
+    let mut prefix = Vec::with_capacity(values.len() + 1);
+    prefix.push(0.0_f64);
+    for &v in values {
+        let sum = prefix[prefix.len() - 1] + (v.max(f32::MIN_POSITIVE) as f64).ln();
+        prefix.push(sum);
+    }
+
+    (0..values.len())
+        .map(|i| {
+            let lo = i.saturating_sub(radius);
+            let hi = (i + radius + 1).min(values.len());
+            ((prefix[hi] - prefix[lo]) / (hi - lo) as f64).exp() as f32
+        })
+        .collect()
+}
+
+/// Return the spectral flux between two consecutive windows.
+///
+/// This is the sum of the increases in magnitude over all bins, normalized by
+/// the number of bins. Decreases do not count, so the flux peaks at onsets.
+pub fn spectral_flux(previous: &[f32], current: &[f32]) -> f32 {
+    let mut flux = 0.0_f64;
+    for (&p0, &p1) in previous.iter().zip(current) {
+        let increase = p1.sqrt() - p0.sqrt();
+        flux += increase.max(0.0) as f64;
+    }
+    (flux / current.len().max(1) as f64) as f32
+}
+
+/// Return the indices of windows where the spectral flux indicates an onset.
+///
+/// An onset is a window where the flux is the maximum of its neighborhood,
+/// and it exceeds the average flux in a wider neighborhood by a margin. The
+/// adaptive threshold makes detection work in both quiet and loud passages.
+pub fn detect_onsets(flux: &[f32]) -> Vec<usize> {
+    // Half-widths of the neighborhoods, in windows.
+    let max_radius = 2;
+    let mean_radius = 8;
+
+    let global_mean = flux.iter().sum::<f32>() / flux.len().max(1) as f32;
+    let mut onsets = Vec::new();
+
+    for (i, &f) in flux.iter().enumerate() {
+        let max_range = i.saturating_sub(max_radius)..(i + max_radius + 1).min(flux.len());
+        if flux[max_range].iter().any(|&g| g > f) {
+            continue;
+        }
+
+        let mean_range = i.saturating_sub(mean_radius)..(i + mean_radius + 1).min(flux.len());
+        let local_mean = flux[mean_range.clone()].iter().sum::<f32>() / mean_range.len() as f32;
+
+        // The global term prevents picking up tiny fluctuations in silence.
+        if f > 1.5 * local_mean + 0.1 * global_mean {
+            onsets.push(i);
+        }
+    }
+
+    onsets
+}
+
+/// A peak in the spectrum of a window.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Peak {
+    /// The interpolated frequency of the peak, in Hz.
+    pub hz: f32,
+
+    /// The interpolated power at the top of the peak.
+    pub power: f32,
+}
+
+/// Return the strongest `max_peaks` peaks in the spectrum, from low to high frequency.
+///
+/// A peak is a bin with more power than its neighbors. The true peak usually
+/// lies in between bins, so we fit a parabola through the log power of the
+/// peak and its neighbors, and take the top of that. For a Hann window this
+/// is accurate to a few percent of a bin. Peaks more than 60 dB below the
+/// strongest one are noise, we do not include those.
+pub fn find_peaks(spectrum: &[f32], hz_per_bin: f32, max_peaks: usize) -> Vec<Peak> {
+    let mut peaks = Vec::new();
+    for j in 1..spectrum.len().saturating_sub(1) {
+        let (a, b, c) = (spectrum[j - 1], spectrum[j], spectrum[j + 1]);
+        if !(b > a && b >= c) {
+            continue;
+        }
+
+        // Silent neighbors have a log power of -inf, then we take the bin.
+        let (la, lb, lc) = (a.ln(), b.ln(), c.ln());
+        let curvature = la - 2.0 * lb + lc;
+        let offset = 0.5 * (la - lc) / curvature;
+        let (offset, power) = if offset.is_finite() {
+            (offset, (lb - 0.25 * (la - lc) * offset).exp())
+        } else {
+            (0.0, b)
+        };
+        peaks.push(Peak {
+            hz: (j as f32 + offset) * hz_per_bin,
+            power,
+        });
+    }
+
+    peaks.sort_by(|p, q| q.power.total_cmp(&p.power));
+    let threshold = peaks.first().map_or(0.0, |p| p.power * 1e-6);
+    peaks.truncate(max_peaks);
+    peaks.retain(|p| p.power > threshold);
+    peaks.sort_by(|p, q| p.hz.total_cmp(&q.hz));
+    peaks
+}
+
+/// Link the peaks of consecutive windows into partials.
+///
+/// Returns a partial number for every peak, in the same layout as `frames`. A
+/// peak continues the partial of the nearest peak in the previous window, if
+/// that is within `max_jump_hz`, and if no stronger peak continued it already.
+/// Otherwise, the peak starts a new partial.
+pub fn track_partials(frames: &[Vec<Peak>], max_jump_hz: f32) -> Vec<Vec<usize>> {
+    let mut result: Vec<Vec<usize>> = Vec::with_capacity(frames.len());
+    let mut num_partials = 0;
+
+    for (i, peaks) in frames.iter().enumerate() {
+        let mut partials = vec![usize::MAX; peaks.len()];
+        let previous: &[Peak] = if i > 0 { &frames[i - 1] } else { &[] };
+        let mut is_continued = vec![false; previous.len()];
+
+        // Let the strongest peaks pick first.
+        let mut order: Vec<usize> = (0..peaks.len()).collect();
+        order.sort_by(|&k, &l| peaks[l].power.total_cmp(&peaks[k].power));
+
+        for k in order {
+            let nearest = (0..previous.len())
+                .filter(|&m| !is_continued[m])
+                .map(|m| (m, (previous[m].hz - peaks[k].hz).abs()))
+                .filter(|&(_, distance)| distance <= max_jump_hz)
+                .min_by(|x, y| x.1.total_cmp(&y.1));
+            partials[k] = match nearest {
+                Some((m, _)) => {
+                    is_continued[m] = true;
+                    result[i - 1][m]
+                }
+                None => {
+                    num_partials += 1;
+                    num_partials - 1
+                }
+            };
+        }
+
+        result.push(partials);
+    }
+
+    result
+}
+
+#[test]
+fn spectral_centroid_and_rolloff_are_correct() {
+    let mut spectrum = vec![0.0_f32; 16];
+    spectrum[2] = 1.0;
+    spectrum[6] = 3.0;
+
+    // The mean bin is (2 * 1 + 6 * 3) / 4 = 5, at 10 Hz per bin.
+    assert_eq!(spectral_centroid(&spectrum, 10.0), 50.0);
+    assert_eq!(spectral_rolloff(&spectrum, 10.0, 0.2), 20.0);
+    assert_eq!(spectral_rolloff(&spectrum, 10.0, 0.85), 60.0);
+
+    let silence = vec![0.0_f32; 16];
+    assert_eq!(spectral_centroid(&silence, 10.0), 0.0);
+    assert_eq!(spectral_rolloff(&silence, 10.0, 0.85), 0.0);
+}
+
+#[test]
+fn bandwidth_ignores_power_far_below_peak() {
+    let mut spectrum = vec![1e-12_f32; 16];
+    spectrum[1] = 1.0;
+    spectrum[9] = 1e-4;
+    assert_eq!(bandwidth(&spectrum, 10.0), 90.0);
+    assert_eq!(bandwidth(&[0.0; 16], 10.0), 0.0);
+}
+
+#[test]
+fn is_steep_cutoff_tells_a_lowpass_from_a_fade() {
+    // Flat up to 16 kHz, then nothing, like a lossy encoder would leave it.
+    let hz_per_bin = 100.0;
+    let lowpass: Vec<f32> = (0..220)
+        .map(|j| if j < 160 { 1.0 } else { 1e-12 })
+        .collect();
+    let cutoff = bandwidth(&lowpass, hz_per_bin);
+    assert_eq!(cutoff, 15_900.0);
+    assert!(is_steep_cutoff(&lowpass, hz_per_bin, cutoff));
+
+    // Falling by 8 dB per kHz, until it drops below the threshold.
+    let fade: Vec<f32> = (0..220).map(|j| 10.0_f32.powf(-0.08 * j as f32)).collect();
+    let cutoff = bandwidth(&fade, hz_per_bin);
+    assert!(!is_steep_cutoff(&fade, hz_per_bin, cutoff));
+
+    assert!(!is_steep_cutoff(&[0.0; 16], hz_per_bin, 0.0));
+}
+
+#[test]
+fn level_histogram_counts_per_level() {
+    let mut counts = [0; 4];
+    let powers = [1e-3_f32, 0.5, 2.0, 1e3, 0.0];
+    level_histogram(powers.iter().cloned(), -20.0, 20.0, &mut counts);
+    // Levels are -30, -3, 3, 30, and -inf dB.
+    assert_eq!(counts, [2, 1, 1, 1]);
+}
+
+#[test]
+fn noise_floor_is_low_percentile() {
+    let spectrum: Vec<f32> = (0..100).rev().map(|i| i as f32).collect();
+    assert_eq!(noise_floor(&spectrum), 10.0);
+    assert_eq!(noise_floor(&[]), 0.0);
+}
+
+#[test]
+fn smooth_log_takes_geometric_mean() {
+    let values = [1.0, 100.0, 1.0, 1.0];
+    let smoothed = smooth_log(&values, 1);
+    // Near the edges, the neighborhood is smaller.
+    assert!((smoothed[0] - 10.0).abs() < 1e-4);
+    assert!((smoothed[1] - 100.0_f32.powf(1.0 / 3.0)).abs() < 1e-4);
+    assert!((smoothed[3] - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn detect_onsets_finds_isolated_peaks() {
+    let mut flux = vec![0.1_f32; 40];
+    flux[10] = 2.0;
+    flux[11] = 1.0;
+    flux[30] = 3.0;
+    assert_eq!(detect_onsets(&flux), [10, 30]);
+
+    // A constant flux has no onsets, even though every window is a local maximum.
+    assert!(detect_onsets(&[0.5_f32; 40]).is_empty());
+}
+
+#[test]
+fn find_peaks_interpolates_between_bins() {
+    // A parabola in the log domain with its top at bin 10.25.
+    let spectrum: Vec<f32> = (0..32)
+        .map(|j| (-0.5 * (j as f32 - 10.25).powi(2)).exp())
+        .collect();
+    let peaks = find_peaks(&spectrum, 2.0, 4);
+    assert_eq!(peaks.len(), 1);
+    assert!((peaks[0].hz - 20.5).abs() < 1e-3, "{}", peaks[0].hz);
+    assert!((peaks[0].power - 1.0).abs() < 1e-3, "{}", peaks[0].power);
+
+    // Only the strongest peaks remain, sorted by frequency.
+    let mut spectrum = vec![0.0_f32; 32];
+    spectrum[5] = 3.0;
+    spectrum[10] = 1.0;
+    spectrum[20] = 2.0;
+    let peaks = find_peaks(&spectrum, 1.0, 2);
+    assert_eq!(peaks.iter().map(|p| p.hz).collect::<Vec<_>>(), [5.0, 20.0]);
+    assert!(find_peaks(&[0.0; 32], 1.0, 2).is_empty());
+}
+
+#[test]
+fn track_partials_links_nearby_peaks() {
+    let peak = |hz| Peak { hz, power: 1.0 };
+    let frames = vec![
+        vec![peak(100.0), peak(200.0)],
+        vec![peak(102.0), peak(300.0)],
+        vec![peak(104.0), peak(199.0)],
+    ];
+    let partials = track_partials(&frames, 5.0);
+    // The partial at 200 Hz pauses for a window, then it counts as a new one.
+    assert_eq!(partials, [vec![0, 1], vec![0, 2], vec![0, 3]]);
+}
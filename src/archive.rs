@@ -0,0 +1,63 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module reads audio files from zip archives. We read an entry into
+// memory entirely, the decoder then reads from there like it would from a file.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// File extensions of the audio files that we can decode.
+pub const AUDIO_EXTENSIONS: &[&str] = &["flac", "mp3", "wav"];
+
+/// Return whether the path refers to a zip archive, judging by its extension.
+pub fn is_archive(path: &Path) -> bool {
+    match path.extension().and_then(OsStr::to_str) {
+        Some(ext) => ext.eq_ignore_ascii_case("zip"),
+        None => false,
+    }
+}
+
+/// Return whether an archive entry is an audio file that we can decode.
+pub fn is_audio_entry(name: &str) -> bool {
+    match Path::new(name).extension().and_then(OsStr::to_str) {
+        Some(ext) => AUDIO_EXTENSIONS
+            .iter()
+            .any(|audio_ext| ext.eq_ignore_ascii_case(audio_ext)),
+        None => false,
+    }
+}
+
+/// List the names of the audio files in the archive, in archive order.
+pub fn list_audio_entries(path: &Path) -> zip::result::ZipResult<Vec<String>> {
+    let archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let entries = archive
+        .file_names()
+        .filter(|name| is_audio_entry(name))
+        .map(|name| name.to_string())
+        .collect();
+    Ok(entries)
+}
+
+/// Read an entry of the archive into memory.
+pub fn read_entry(path: &Path, name: &str) -> zip::result::ZipResult<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let mut entry = archive.by_name(name)?;
+    let mut bytes = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[test]
+fn is_audio_entry_matches_extension_case_insensitively() {
+    assert!(is_audio_entry("stems/Vocals.FLAC"));
+    assert!(is_audio_entry("drums.flac"));
+    assert!(!is_audio_entry("cover.jpg"));
+    assert!(!is_audio_entry("flac"));
+}
@@ -0,0 +1,312 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module parses the command-line flags that Audiograter handles itself.
+// Everything else, including the files to open, is passed on to GTK, which
+// takes care of its own flags, and of opening files through `connect_open`.
+
+use std::path::PathBuf;
+
+use audiograter::render::Colormap;
+
+use crate::config::Config;
+
+/// Which variant of the GTK theme to use.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Theme {
+    /// Respect the desktop setting.
+    System,
+    /// Prefer the light variant of the theme.
+    Light,
+    /// Prefer the dark variant of the theme.
+    Dark,
+}
+
+/// Which signal to analyze, of the channels in the file.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChannelMode {
+    /// A single channel, counting from 0.
+    Channel(usize),
+    /// The mean of all channels.
+    Mix,
+    /// The mean of the first two channels, what left and right have in common.
+    Mid,
+    /// Half the difference of the first two channels, what sets left and right apart.
+    Side,
+}
+
+pub struct Args {
+    /// The theme variant to use, if given, it takes precedence over the config file.
+    pub theme: Option<Theme>,
+
+    /// The signal to analyze, if given. By default we analyze the first channel.
+    pub channel_mode: Option<ChannelMode>,
+
+    /// Render the spectrogram to this png file, instead of showing it.
+    pub output: Option<PathBuf>,
+
+    /// The size of the rendered png files in pixels, if given.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+
+    /// Settings that take precedence over the config file, if given.
+    pub colormap: Option<Colormap>,
+    pub window_len: Option<usize>,
+    pub floor_db: Option<f32>,
+    pub ceiling_db: Option<f32>,
+
+    /// Render a png file per input file to this directory, instead of showing them.
+    pub output_dir: Option<PathBuf>,
+
+    /// Write the spectral peaks of the only input file to this csv file, instead of showing it.
+    pub peaks: Option<PathBuf>,
+
+    /// Print properties of the files, instead of showing them.
+    pub summary: bool,
+
+    /// Wall-clock time at the start of the files, in seconds since midnight.
+    pub start_time: Option<u32>,
+
+    /// Report how fast we decode and transform every file.
+    pub profile: bool,
+
+    /// Remaining arguments to pass on to GTK, including the program name.
+    pub gtk_args: Vec<String>,
+}
+
+impl Args {
+    /// Whether we should only compute, without initializing GTK.
+    pub fn is_headless(&self) -> bool {
+        self.output.is_some() || self.output_dir.is_some() || self.peaks.is_some() || self.summary
+    }
+
+    /// Replace the settings in the config by those given on the command line.
+    pub fn override_config(&self, config: &mut Config) {
+        config.colormap = self.colormap.or(config.colormap);
+        config.window_len = self.window_len.or(config.window_len);
+        config.floor_db = self.floor_db.or(config.floor_db);
+        config.ceiling_db = self.ceiling_db.or(config.ceiling_db);
+    }
+}
+
+/// Extract our own flags from the command-line arguments.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut result = Args {
+        theme: None,
+        channel_mode: None,
+        output: None,
+        width: None,
+        height: None,
+        colormap: None,
+        window_len: None,
+        floor_db: None,
+        ceiling_db: None,
+        output_dir: None,
+        peaks: None,
+        summary: false,
+        start_time: None,
+        profile: false,
+        gtk_args: Vec::new(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--system" => result.theme = Some(Theme::System),
+            "--light" => result.theme = Some(Theme::Light),
+            "--dark" => result.theme = Some(Theme::Dark),
+            "--summary" => result.summary = true,
+            "--profile" => result.profile = true,
+            "--mix" => result.channel_mode = Some(ChannelMode::Mix),
+            "--mid" => result.channel_mode = Some(ChannelMode::Mid),
+            "--side" => result.channel_mode = Some(ChannelMode::Side),
+            // On the command line, channels count from 1, like in audio editors.
+            "--channel" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => result.channel_mode = Some(ChannelMode::Channel(n - 1)),
+                _ => return Err("Expected a channel number from 1 after --channel.".into()),
+            },
+            "--output" => match args.next() {
+                Some(fname) => result.output = Some(fname.into()),
+                None => return Err("Expected a file name after --output.".into()),
+            },
+            // A shorthand for `--output`, with the files in the order of a copy.
+            "--render" => match (args.next(), args.next()) {
+                (Some(input), Some(output)) => {
+                    result.gtk_args.push(input);
+                    result.output = Some(output.into());
+                }
+                _ => return Err("Expected an input and an output file after --render.".into()),
+            },
+            "--width" => match args.next().and_then(|n| n.parse::<i32>().ok()) {
+                Some(n) if (16..=16_384).contains(&n) => result.width = Some(n),
+                _ => return Err("Expected a number from 16 to 16384 after --width.".into()),
+            },
+            "--height" => match args.next().and_then(|n| n.parse::<i32>().ok()) {
+                Some(n) if (16..=16_384).contains(&n) => result.height = Some(n),
+                _ => return Err("Expected a number from 16 to 16384 after --height.".into()),
+            },
+            "--colormap" => {
+                let name = args.next();
+                let colormaps = [Colormap::Magma, Colormap::Viridis, Colormap::Grayscale];
+                match colormaps.iter().find(|c| Some(c.name()) == name.as_deref()) {
+                    Some(&colormap) => result.colormap = Some(colormap),
+                    None => {
+                        let msg = "Expected 'magma', 'viridis', or 'grayscale' after --colormap.";
+                        return Err(msg.into());
+                    }
+                }
+            }
+            // The same ranges as in the config file.
+            "--window-len" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if (16..=65_536).contains(&n) => result.window_len = Some(n),
+                _ => return Err("Expected a number from 16 to 65536 after --window-len.".into()),
+            },
+            "--floor-db" => match args.next().and_then(|db| db.parse::<f32>().ok()) {
+                Some(db) if (-150.0..=-20.0).contains(&db) => result.floor_db = Some(db),
+                _ => return Err("Expected a number from -150 to -20 after --floor-db.".into()),
+            },
+            "--ceiling-db" => match args.next().and_then(|db| db.parse::<f32>().ok()) {
+                Some(db) if (-40.0..=20.0).contains(&db) => result.ceiling_db = Some(db),
+                _ => return Err("Expected a number from -40 to 20 after --ceiling-db.".into()),
+            },
+            "--peaks" => match args.next() {
+                Some(fname) => result.peaks = Some(fname.into()),
+                None => return Err("Expected a file name after --peaks.".into()),
+            },
+            "--output-dir" => match args.next() {
+                Some(dirname) => result.output_dir = Some(dirname.into()),
+                None => return Err("Expected a directory after --output-dir.".into()),
+            },
+            "--start-time" => match args.next().as_deref().and_then(parse_time_of_day) {
+                Some(t) => result.start_time = Some(t),
+                None => return Err("Expected a time as HH:MM:SS after --start-time.".into()),
+            },
+            _ => result.gtk_args.push(arg),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parse a time of day as hours, minutes, and seconds, into seconds since midnight.
+///
+/// The parts may be separated by any non-digit character, to also accept
+/// times as they are stored in the metadata of broadcast wave files.
+pub fn parse_time_of_day(time: &str) -> Option<u32> {
+    let parts: Vec<&str> = time.split(|c: char| !c.is_ascii_digit()).collect();
+    match &parts[..] {
+        [h, m, s] => {
+            let (h, m, s): (u32, u32, u32) = (h.parse().ok()?, m.parse().ok()?, s.parse().ok()?);
+            if h < 24 && m < 60 && s < 60 {
+                Some(h * 3600 + m * 60 + s)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn parse_extracts_theme_flags() {
+    let args = ["audiograter", "--dark", "a.flac", "--light"];
+    let result = parse(args.iter().map(|s| s.to_string())).unwrap();
+    assert_eq!(result.theme, Some(Theme::Light));
+    assert_eq!(result.gtk_args, ["audiograter", "a.flac"]);
+    assert!(!result.is_headless());
+    assert!(!result.profile);
+}
+
+#[test]
+fn parse_time_of_day_accepts_separators() {
+    assert_eq!(parse_time_of_day("00:00:00"), Some(0));
+    assert_eq!(parse_time_of_day("13:02:05"), Some(13 * 3600 + 2 * 60 + 5));
+    assert_eq!(parse_time_of_day("13-02-05"), Some(13 * 3600 + 2 * 60 + 5));
+    assert_eq!(parse_time_of_day("24:00:00"), None);
+    assert_eq!(parse_time_of_day("13:02"), None);
+    assert_eq!(parse_time_of_day("13::05"), None);
+}
+
+#[test]
+fn parse_extracts_output_file() {
+    let args = ["audiograter", "--output", "a.png", "a.flac"];
+    let result = parse(args.iter().map(|s| s.to_string())).unwrap();
+    assert_eq!(result.output, Some(PathBuf::from("a.png")));
+    assert_eq!(result.gtk_args, ["audiograter", "a.flac"]);
+    assert!(result.is_headless());
+
+    let args = ["audiograter", "a.flac", "--output"];
+    assert!(parse(args.iter().map(|s| s.to_string())).is_err());
+}
+
+#[test]
+fn parse_extracts_render_flags() {
+    let args = [
+        "audiograter",
+        "--render",
+        "in.flac",
+        "out.png",
+        "--width",
+        "1920",
+        "--height",
+        "1080",
+        "--colormap",
+        "viridis",
+        "--window-len",
+        "4096",
+        "--floor-db",
+        "-120",
+        "--ceiling-db",
+        "-6.5",
+    ];
+    let result = parse(args.iter().map(|s| s.to_string())).unwrap();
+    assert_eq!(result.output, Some(PathBuf::from("out.png")));
+    assert_eq!(result.gtk_args, ["audiograter", "in.flac"]);
+    assert_eq!((result.width, result.height), (Some(1920), Some(1080)));
+    assert!(result.is_headless());
+
+    let mut config = Config {
+        window_len: Some(1024),
+        window_off: Some(512),
+        ..Config::default()
+    };
+    result.override_config(&mut config);
+    assert_eq!(config.colormap, Some(Colormap::Viridis));
+    assert_eq!(config.window_len, Some(4096));
+    assert_eq!(config.window_off, Some(512));
+    assert_eq!(
+        (config.floor_db, config.ceiling_db),
+        (Some(-120.0), Some(-6.5))
+    );
+
+    for bad in [
+        &["--render", "in.flac"][..],
+        &["--width", "8"],
+        &["--colormap", "jet"],
+        &["--floor-db", "0"],
+    ] {
+        let args = std::iter::once("audiograter").chain(bad.iter().cloned());
+        assert!(parse(args.map(|s| s.to_string())).is_err());
+    }
+}
+
+#[test]
+fn parse_extracts_channel_mode() {
+    let args = ["audiograter", "--channel", "2", "a.flac"];
+    let result = parse(args.iter().map(|s| s.to_string())).unwrap();
+    assert_eq!(result.channel_mode, Some(ChannelMode::Channel(1)));
+    assert_eq!(result.gtk_args, ["audiograter", "a.flac"]);
+
+    let args = ["audiograter", "--channel", "1", "--side"];
+    let result = parse(args.iter().map(|s| s.to_string())).unwrap();
+    assert_eq!(result.channel_mode, Some(ChannelMode::Side));
+
+    for n in ["0", "left"] {
+        let args = ["audiograter", "--channel", n];
+        assert!(parse(args.iter().map(|s| s.to_string())).is_err());
+    }
+}
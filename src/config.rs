@@ -0,0 +1,393 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module reads the defaults for the adjustable settings from a config
+// file, `~/.config/audiograter/config.toml`. Every key is optional. Flags on
+// the command line take precedence over the config file, and adjustments in
+// the UI take precedence over both. Named presets of the same settings live
+// next to it, in `presets.toml`, which the application writes itself.
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use audiograter::dft::WindowFn;
+use audiograter::render::{Colormap, YScale};
+
+use crate::args::Theme;
+
+/// The settings from the config file. `None` means the key was absent.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub theme: Option<Theme>,
+    pub window_len: Option<usize>,
+    pub window_off: Option<usize>,
+    pub window_fn: Option<WindowFn>,
+    pub zero_pad_factor: Option<usize>,
+    pub row_step: Option<usize>,
+    pub oversample: Option<usize>,
+    pub colormap: Option<Colormap>,
+    pub invert_colormap: Option<bool>,
+    pub encode_srgb: Option<bool>,
+    pub adaptive_floor: Option<bool>,
+    pub group_delay: Option<bool>,
+    pub phase_coherence: Option<bool>,
+    pub constant_q: Option<bool>,
+    pub dc_strip: Option<bool>,
+    pub histogram: Option<bool>,
+    pub residual: Option<bool>,
+    pub auto_hop: Option<bool>,
+    pub equal_energy: Option<bool>,
+
+    /// Blend between a log (0.0) and linear (1.0) frequency axis.
+    pub frequency_axis: Option<f64>,
+    pub frequency_scale: Option<YScale>,
+    pub floor_db: Option<f32>,
+    pub ceiling_db: Option<f32>,
+}
+
+/// Return the directory of the config files, following the XDG base directory spec.
+fn config_dir() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("audiograter"))
+}
+
+/// Read the config file. When there is none, every setting has its default.
+pub fn load() -> Result<Config, String> {
+    let path = match config_dir() {
+        Some(dir) => dir.join("config.toml"),
+        None => return Ok(Config::default()),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => parse(&text).map_err(|msg| format!("In {}: {}", path.display(), msg)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(err) => Err(format!("Failed to read {}: {}", path.display(), err)),
+    }
+}
+
+/// Read the presets file. When there is none, there are no presets.
+pub fn load_presets() -> Result<Vec<(String, Config)>, String> {
+    let path = match config_dir() {
+        Some(dir) => dir.join("presets.toml"),
+        None => return Ok(Vec::new()),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => parse_presets(&text).map_err(|msg| format!("In {}: {}", path.display(), msg)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(format!("Failed to read {}: {}", path.display(), err)),
+    }
+}
+
+/// Replace the presets file with the given presets.
+pub fn save_presets(presets: &[(String, Config)]) -> Result<(), String> {
+    let dir = match config_dir() {
+        Some(dir) => dir,
+        None => return Err("Cannot locate the config directory, HOME is not set.".into()),
+    };
+    let path = dir.join("presets.toml");
+    fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(&path, format_presets(presets)))
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+/// Return the value as a bool, or an error that mentions the key.
+fn expect_bool(key: &str, value: &toml::Value) -> Result<Option<bool>, String> {
+    match value.as_bool() {
+        Some(b) => Ok(Some(b)),
+        None => Err(format!("Expected true or false for {}.", key)),
+    }
+}
+
+/// Parse the contents of a config file.
+///
+/// Unknown keys are an error rather than ignored, so a typo does not go
+/// unnoticed.
+pub fn parse(text: &str) -> Result<Config, String> {
+    let table: toml::Table = text.parse().map_err(|err| format!("{}", err))?;
+    parse_table(&table)
+}
+
+/// Parse the contents of a presets file, a table of settings per preset name.
+///
+/// The presets are ordered by name.
+pub fn parse_presets(text: &str) -> Result<Vec<(String, Config)>, String> {
+    let table: toml::Table = text.parse().map_err(|err| format!("{}", err))?;
+    let mut presets = Vec::with_capacity(table.len());
+    for (name, value) in &table {
+        let preset = match value.as_table() {
+            Some(t) => parse_table(t).map_err(|msg| format!("In preset '{}': {}", name, msg))?,
+            None => return Err(format!("Expected a table for preset '{}'.", name)),
+        };
+        presets.push((name.clone(), preset));
+    }
+    Ok(presets)
+}
+
+/// Parse the settings in one table, the top level of the config file, or a preset.
+fn parse_table(table: &toml::Table) -> Result<Config, String> {
+    let mut config = Config::default();
+
+    for (key, value) in table {
+        match &key[..] {
+            "theme" => match value.as_str() {
+                Some("system") => config.theme = Some(Theme::System),
+                Some("light") => config.theme = Some(Theme::Light),
+                Some("dark") => config.theme = Some(Theme::Dark),
+                _ => return Err("Expected 'system', 'light', or 'dark' for theme.".into()),
+            },
+            "window_len" => match value.as_integer() {
+                Some(n) if (16..=65_536).contains(&n) => config.window_len = Some(n as usize),
+                _ => return Err("Expected a number from 16 to 65536 for window_len.".into()),
+            },
+            "window_off" => match value.as_integer() {
+                Some(n) if (1..=1 << 20).contains(&n) => config.window_off = Some(n as usize),
+                _ => return Err("Expected a number from 1 to 1048576 for window_off.".into()),
+            },
+            "window_fn" => match value.as_str() {
+                Some("hann") => config.window_fn = Some(WindowFn::Hann),
+                Some("hamming") => config.window_fn = Some(WindowFn::Hamming),
+                Some("blackman") => config.window_fn = Some(WindowFn::Blackman),
+                _ => return Err("Expected 'hann', 'hamming', or 'blackman' for window_fn.".into()),
+            },
+            "zero_pad_factor" => match value.as_integer() {
+                Some(n @ (1 | 2 | 4)) => config.zero_pad_factor = Some(n as usize),
+                _ => return Err("Expected 1, 2, or 4 for zero_pad_factor.".into()),
+            },
+            "row_step" => match value.as_integer() {
+                Some(n @ (1 | 2 | 4 | 8)) => config.row_step = Some(n as usize),
+                _ => return Err("Expected 1, 2, 4, or 8 for row_step.".into()),
+            },
+            "oversample" => match value.as_integer() {
+                Some(n @ (1 | 2 | 4)) => config.oversample = Some(n as usize),
+                _ => return Err("Expected 1, 2, or 4 for oversample.".into()),
+            },
+            "frequency_axis" => match value.as_float().or(value.as_integer().map(|n| n as f64)) {
+                Some(blend) if (0.0..=1.0).contains(&blend) => config.frequency_axis = Some(blend),
+                _ => return Err("Expected a number from 0.0 to 1.0 for frequency_axis.".into()),
+            },
+            "frequency_scale" => match value.as_str() {
+                Some("blended") => config.frequency_scale = Some(YScale::Blended),
+                Some("linear") => config.frequency_scale = Some(YScale::Linear),
+                Some("log") => config.frequency_scale = Some(YScale::Log),
+                Some("mel") => config.frequency_scale = Some(YScale::Mel),
+                _ => {
+                    let msg = "Expected 'blended', 'linear', 'log', or 'mel' for frequency_scale.";
+                    return Err(msg.into());
+                }
+            },
+            "floor_db" => match value.as_float().or(value.as_integer().map(|n| n as f64)) {
+                Some(db) if (-150.0..=-20.0).contains(&db) => config.floor_db = Some(db as f32),
+                _ => return Err("Expected a number from -150 to -20 for floor_db.".into()),
+            },
+            "ceiling_db" => match value.as_float().or(value.as_integer().map(|n| n as f64)) {
+                Some(db) if (-40.0..=20.0).contains(&db) => config.ceiling_db = Some(db as f32),
+                _ => return Err("Expected a number from -40 to 20 for ceiling_db.".into()),
+            },
+            "colormap" => match value.as_str() {
+                Some("magma") => config.colormap = Some(Colormap::Magma),
+                Some("viridis") => config.colormap = Some(Colormap::Viridis),
+                Some("grayscale") => config.colormap = Some(Colormap::Grayscale),
+                _ => return Err("Expected 'magma', 'viridis', or 'grayscale' for colormap.".into()),
+            },
+            "invert_colormap" => config.invert_colormap = expect_bool(key, value)?,
+            "encode_srgb" => config.encode_srgb = expect_bool(key, value)?,
+            "adaptive_floor" => config.adaptive_floor = expect_bool(key, value)?,
+            "group_delay" => config.group_delay = expect_bool(key, value)?,
+            "phase_coherence" => config.phase_coherence = expect_bool(key, value)?,
+            "constant_q" => config.constant_q = expect_bool(key, value)?,
+            "dc_strip" => config.dc_strip = expect_bool(key, value)?,
+            "histogram" => config.histogram = expect_bool(key, value)?,
+            "residual" => config.residual = expect_bool(key, value)?,
+            "auto_hop" => config.auto_hop = expect_bool(key, value)?,
+            "equal_energy" => config.equal_energy = expect_bool(key, value)?,
+            _ => return Err(format!("Unknown key '{}'.", key)),
+        }
+    }
+
+    Ok(config)
+}
+
+/// Quote a string for use as a key, escaping what toml requires to be escaped.
+fn quote_key(key: &str) -> String {
+    let mut quoted = String::with_capacity(key.len() + 2);
+    quoted.push('"');
+    for ch in key.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            _ if ch.is_control() => write!(quoted, "\\u{:04X}", ch as u32).unwrap(),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Format the presets as a presets file, that `parse_presets` reads back.
+///
+/// The theme is not a setting of the analysis, presets do not include it.
+pub fn format_presets(presets: &[(String, Config)]) -> String {
+    let mut out = String::new();
+    for (name, preset) in presets {
+        writeln!(out, "[{}]", quote_key(name)).unwrap();
+        let numbers = [
+            ("window_len", preset.window_len),
+            ("window_off", preset.window_off),
+            ("zero_pad_factor", preset.zero_pad_factor),
+            ("row_step", preset.row_step),
+            ("oversample", preset.oversample),
+        ];
+        for (key, value) in numbers {
+            if let Some(n) = value {
+                writeln!(out, "{} = {}", key, n).unwrap();
+            }
+        }
+        if let Some(window_fn) = preset.window_fn {
+            writeln!(out, "window_fn = \"{}\"", window_fn.name()).unwrap();
+        }
+        if let Some(blend) = preset.frequency_axis {
+            writeln!(out, "frequency_axis = {:?}", blend).unwrap();
+        }
+        if let Some(y_scale) = preset.frequency_scale {
+            writeln!(out, "frequency_scale = \"{}\"", y_scale.name()).unwrap();
+        }
+        let levels = [
+            ("floor_db", preset.floor_db),
+            ("ceiling_db", preset.ceiling_db),
+        ];
+        for (key, value) in levels {
+            if let Some(db) = value {
+                writeln!(out, "{} = {:?}", key, db).unwrap();
+            }
+        }
+        if let Some(colormap) = preset.colormap {
+            writeln!(out, "colormap = \"{}\"", colormap.name()).unwrap();
+        }
+        let flags = [
+            ("invert_colormap", preset.invert_colormap),
+            ("encode_srgb", preset.encode_srgb),
+            ("adaptive_floor", preset.adaptive_floor),
+            ("group_delay", preset.group_delay),
+            ("phase_coherence", preset.phase_coherence),
+            ("constant_q", preset.constant_q),
+            ("dc_strip", preset.dc_strip),
+            ("histogram", preset.histogram),
+            ("residual", preset.residual),
+            ("auto_hop", preset.auto_hop),
+            ("equal_energy", preset.equal_energy),
+        ];
+        for (key, value) in flags {
+            if let Some(b) = value {
+                writeln!(out, "{} = {}", key, b).unwrap();
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn parse_reads_every_key() {
+    let text = r#"
+        theme = "dark"
+        window_len = 4096
+        window_off = 1024
+        window_fn = "blackman"
+        zero_pad_factor = 4
+        row_step = 2
+        oversample = 4
+        frequency_axis = 0.25
+        frequency_scale = "mel"
+        floor_db = -100
+        ceiling_db = -6.5
+        colormap = "viridis"
+        invert_colormap = true
+        encode_srgb = false
+        adaptive_floor = true
+        group_delay = false
+        phase_coherence = true
+        constant_q = true
+        dc_strip = true
+        histogram = false
+        residual = true
+        auto_hop = false
+        equal_energy = true
+    "#;
+    let config = parse(text).unwrap();
+    assert_eq!(config.theme, Some(Theme::Dark));
+    assert_eq!(config.window_len, Some(4096));
+    assert_eq!(config.window_off, Some(1024));
+    assert_eq!(config.window_fn, Some(WindowFn::Blackman));
+    assert_eq!(config.zero_pad_factor, Some(4));
+    assert_eq!(config.row_step, Some(2));
+    assert_eq!(config.oversample, Some(4));
+    assert_eq!(config.frequency_axis, Some(0.25));
+    assert_eq!(config.frequency_scale, Some(YScale::Mel));
+    assert_eq!(config.floor_db, Some(-100.0));
+    assert_eq!(config.ceiling_db, Some(-6.5));
+    assert_eq!(config.colormap, Some(Colormap::Viridis));
+    assert_eq!(config.invert_colormap, Some(true));
+    assert_eq!(config.encode_srgb, Some(false));
+    assert_eq!(config.adaptive_floor, Some(true));
+    assert_eq!(config.group_delay, Some(false));
+    assert_eq!(config.phase_coherence, Some(true));
+    assert_eq!(config.constant_q, Some(true));
+    assert_eq!(config.dc_strip, Some(true));
+    assert_eq!(config.histogram, Some(false));
+    assert_eq!(config.residual, Some(true));
+    assert_eq!(config.auto_hop, Some(false));
+    assert_eq!(config.equal_energy, Some(true));
+
+    assert_eq!(parse("").unwrap(), Config::default());
+}
+
+#[test]
+fn parse_rejects_unknown_keys_and_invalid_values() {
+    assert!(parse("zero_pad = 2").is_err());
+    assert!(parse("zero_pad_factor = 3").is_err());
+    assert!(parse("window_len = 8").is_err());
+    assert!(parse("window_off = 0").is_err());
+    assert!(parse("window_fn = \"kaiser\"").is_err());
+    assert!(parse("frequency_axis = 1.5").is_err());
+    assert!(parse("floor_db = 0").is_err());
+    assert!(parse("frequency_scale = \"bark\"").is_err());
+    assert!(parse("invert_colormap = \"yes\"").is_err());
+    assert!(parse("theme = ").is_err());
+    assert!(parse("colormap = \"jet\"").is_err());
+}
+
+#[test]
+fn parse_presets_reads_back_format_presets() {
+    let speech = Config {
+        zero_pad_factor: Some(2),
+        window_len: Some(2048),
+        window_fn: Some(WindowFn::Hamming),
+        frequency_axis: Some(1.0),
+        floor_db: Some(-72.5),
+        adaptive_floor: Some(true),
+        ..Config::default()
+    };
+    let odd_name = Config {
+        row_step: Some(8),
+        colormap: Some(Colormap::Viridis),
+        frequency_scale: Some(YScale::Linear),
+        dc_strip: Some(false),
+        ..Config::default()
+    };
+    let presets = vec![
+        ("Quote \" and \\ and\ttab".to_string(), odd_name),
+        ("Speech".to_string(), speech),
+    ];
+    assert_eq!(parse_presets(&format_presets(&presets)).unwrap(), presets);
+
+    assert!(parse_presets("speech = 2").is_err());
+    assert!(parse_presets("[speech]\nzero_pad = 2").is_err());
+}
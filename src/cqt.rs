@@ -0,0 +1,226 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module implements a constant-Q transform. The bins of a DFT are all
+// equally wide in Hz, so an octave at the low end spans a handful of bins, and
+// an octave at the top spans thousands. The bins of a constant-Q transform are
+// equally wide in pitch instead: every bin correlates the signal with a tone
+// over a fixed number of its periods, so low bins use long kernels, and high
+// bins use short ones. We evaluate the kernels directly, rather than through
+// an FFT. For the bins we have that is about as fast, and a lot simpler.
+
+use std::f64::consts::PI;
+use std::thread;
+
+/// The kernel of one bin, a windowed complex tone.
+struct Kernel {
+    /// The index in the window of the first sample that the kernel covers.
+    offset: usize,
+
+    /// The real and imaginary parts of the kernel, including its gain.
+    re: Box<[f32]>,
+    im: Box<[f32]>,
+}
+
+/// The kernels of a constant-Q transform, for windows of a given length.
+pub struct ConstantQ {
+    kernels: Vec<Kernel>,
+    window_len: usize,
+}
+
+/// Return the fractional constant-Q bin of the frequency `hz`, see `bin_hz`.
+pub fn bin_at_hz(hz: f64, min_hz: f64, bins_per_octave: usize) -> f64 {
+    bins_per_octave as f64 * (hz / min_hz).log2()
+}
+
+/// Return the frequency in Hz of constant-Q bin `k`, the inverse of `bin_at_hz`.
+pub fn bin_hz(k: f64, min_hz: f64, bins_per_octave: usize) -> f64 {
+    min_hz * 2.0_f64.powf(k / bins_per_octave as f64)
+}
+
+impl ConstantQ {
+    /// Make the kernels for bins from `min_hz` up to the Nyquist frequency.
+    ///
+    /// No kernel is longer than the window. The bins at the low end whose
+    /// kernel would be longer get the full window instead, so their bandwidth
+    /// is that of a DFT of the window, and no longer proportional to their
+    /// frequency. Every kernel is centered in the window, and it is scaled
+    /// such that a tone at its frequency has the same power as it has in the
+    /// DFT of the full window.
+    pub fn new(
+        sample_rate: u32,
+        window_len: usize,
+        window_fn: impl Fn(usize, usize) -> f32,
+        min_hz: f64,
+        bins_per_octave: usize,
+    ) -> ConstantQ {
+        let q = 1.0 / (2.0_f64.powf(1.0 / bins_per_octave as f64) - 1.0);
+        let nyquist_hz = 0.5 * sample_rate as f64;
+        let full_gain: f64 = (0..window_len)
+            .map(|i| window_fn(window_len, i) as f64)
+            .sum();
+
+        let mut kernels = Vec::new();
+        for k in 0.. {
+            let hz = bin_hz(k as f64, min_hz, bins_per_octave);
+            if hz >= nyquist_hz {
+                break;
+            }
+            let len = ((q * sample_rate as f64 / hz).ceil() as usize).clamp(1, window_len);
+            let window: Vec<f64> = (0..len).map(|i| window_fn(len, i) as f64).collect();
+            let gain = full_gain / window.iter().sum::<f64>().max(1e-30);
+            let omega = 2.0 * PI * hz / sample_rate as f64;
+            kernels.push(Kernel {
+                offset: (window_len - len) / 2,
+                re: (0..len)
+                    .map(|i| (gain * window[i] * (omega * i as f64).cos()) as f32)
+                    .collect(),
+                im: (0..len)
+                    .map(|i| (-gain * window[i] * (omega * i as f64).sin()) as f32)
+                    .collect(),
+            });
+        }
+
+        ConstantQ {
+            kernels,
+            window_len,
+        }
+    }
+
+    /// Return the number of bins.
+    pub fn len(&self) -> usize {
+        self.kernels.len()
+    }
+
+    /// Return whether there are no bins, which happens when `min_hz` is above the Nyquist frequency.
+    pub fn is_empty(&self) -> bool {
+        self.kernels.is_empty()
+    }
+
+    /// Return the squared norm of every bin, for one window of samples.
+    pub fn transform(&self, window: &[f32]) -> Box<[f32]> {
+        assert_eq!(window.len(), self.window_len);
+        self.kernels
+            .iter()
+            .map(|kernel| {
+                let xs = &window[kernel.offset..kernel.offset + kernel.re.len()];
+                let mut re = 0.0_f32;
+                let mut im = 0.0_f32;
+                for ((&x, &kr), &ki) in xs.iter().zip(kernel.re.iter()).zip(kernel.im.iter()) {
+                    re = x.mul_add(kr, re);
+                    im = x.mul_add(ki, im);
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    /// Transform windows `begin..end` of the signal, where window `i` starts at sample `i * hop`.
+    ///
+    /// Like `Stft::collect_parallel`, every thread takes a contiguous range of
+    /// the windows. The result is in order.
+    pub fn transform_parallel(
+        &self,
+        samples: &[f32],
+        hop: usize,
+        begin: usize,
+        end: usize,
+    ) -> Vec<Box<[f32]>> {
+        let window = |i: usize| &samples[i * hop..i * hop + self.window_len];
+        let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let num_threads = num_threads.min(end.saturating_sub(begin));
+        if num_threads <= 1 {
+            return (begin..end).map(|i| self.transform(window(i))).collect();
+        }
+
+        let chunk_len = (end - begin + num_threads - 1) / num_threads;
+        thread::scope(|scope| {
+            let chunks: Vec<_> = (begin..end)
+                .step_by(chunk_len)
+                .map(|chunk_begin| {
+                    let chunk_end = (chunk_begin + chunk_len).min(end);
+                    scope.spawn(move || {
+                        (chunk_begin..chunk_end)
+                            .map(|i| self.transform(window(i)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            chunks
+                .into_iter()
+                .flat_map(|chunk| chunk.join().unwrap())
+                .collect()
+        })
+    }
+}
+
+#[test]
+fn bin_at_hz_inverts_bin_hz() {
+    assert_eq!(bin_hz(0.0, 55.0, 12), 55.0);
+    assert_eq!(bin_hz(24.0, 55.0, 12), 220.0);
+    assert!((bin_at_hz(440.0, 55.0, 24) - 72.0).abs() < 1e-9);
+    assert!((bin_at_hz(bin_hz(7.3, 30.0, 36), 30.0, 36) - 7.3).abs() < 1e-9);
+}
+
+#[test]
+fn constant_q_bins_are_spaced_by_pitch() {
+    let cq = ConstantQ::new(8_000, 1024, crate::dft::hann, 100.0, 12);
+    // From 100 Hz up to 4 kHz is a little over 5 octaves.
+    assert_eq!(cq.len(), 64);
+    assert_eq!(cq.kernels[0].re.len(), 1024);
+    assert!(cq.kernels[63].re.len() < 40);
+
+    // Centered in the window.
+    let last = &cq.kernels[63];
+    let center = last.offset as f64 + 0.5 * last.re.len() as f64;
+    assert!((center - 512.0).abs() <= 0.5);
+
+    assert!(ConstantQ::new(8_000, 1024, crate::dft::hann, 5_000.0, 12).is_empty());
+}
+
+#[test]
+fn constant_q_peaks_at_the_bin_of_a_tone() {
+    let sample_rate = 8_000;
+    let window_len = 2048;
+    let cq = ConstantQ::new(sample_rate, window_len, crate::dft::hann, 100.0, 12);
+
+    // A tone at bin 30 of 12 per octave above 100 Hz, 566 Hz.
+    let hz = bin_hz(30.0, 100.0, 12);
+    let omega = 2.0 * PI * hz / sample_rate as f64;
+    let window: Vec<f32> = (0..window_len)
+        .map(|i| (omega * i as f64).sin() as f32)
+        .collect();
+    let power = cq.transform(&window);
+
+    let k_max = (0..power.len())
+        .max_by(|&a, &b| power[a].total_cmp(&power[b]))
+        .unwrap();
+    assert_eq!(k_max, 30);
+    assert!(power[27] < 0.01 * power[30]);
+    assert!(power[33] < 0.01 * power[30]);
+
+    // The power of the peak matches that of the DFT of the full window.
+    let dft = crate::dft::dft_fast(&window, crate::dft::hann, 1);
+    let dft_max = dft.iter().cloned().fold(0.0_f32, f32::max);
+    assert!(
+        (power[30] / dft_max - 1.0).abs() < 0.2,
+        "{} vs {}",
+        power[30],
+        dft_max
+    );
+
+    // Windows in parallel are the same as one at a time.
+    let samples: Vec<f32> = (0..10_000)
+        .map(|i| (omega * i as f64).sin() as f32)
+        .collect();
+    let parallel = cq.transform_parallel(&samples, 1_000, 2, 7);
+    assert_eq!(parallel.len(), 5);
+    assert_eq!(
+        parallel[1],
+        cq.transform(&samples[3_000..3_000 + window_len])
+    );
+}
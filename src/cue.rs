@@ -0,0 +1,152 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module reads the track boundaries from a cue sheet, a text file that
+// describes how one big audio file splits into tracks. Rips of a full album
+// to a single file often come with one, next to the audio file.
+
+use std::fs;
+use std::path::Path;
+
+/// The number of frames per second in cue sheet timestamps, as on a CD.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// A track in a cue sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+
+    /// The start of the track, as the number of frames since the start of the file.
+    pub start_frames: u64,
+}
+
+impl CueTrack {
+    /// Return the start of the track as a sample index.
+    pub fn start_sample(&self, sample_rate: u32) -> u64 {
+        self.start_frames * sample_rate as u64 / FRAMES_PER_SECOND
+    }
+
+    /// Return the label to show at the marker, the number and the title if there is one.
+    pub fn label(&self) -> String {
+        match &self.title {
+            Some(title) => format!("{:02} {}", self.number, title),
+            None => format!("{:02}", self.number),
+        }
+    }
+}
+
+/// Parse a timestamp in `mm:ss:ff` format into a number of frames.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let mut parts = timestamp.split(':');
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = parts.next()?.parse().ok()?;
+    let frames: u64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || seconds >= 60 || frames >= FRAMES_PER_SECOND {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+/// Return the value of a `TITLE` command, without the quotes.
+fn parse_title(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(unquoted) => unquoted.to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Parse the tracks from the contents of a cue sheet.
+///
+/// The start of a track is its `INDEX 01`, the pregap in `INDEX 00` belongs to
+/// the previous track. Lines that we do not understand, we skip, so a damaged
+/// cue sheet still yields the tracks that it does describe. Cue sheets that
+/// reference multiple files are not supported; their timestamps are relative
+/// to the start of every file, we ignore the tracks past the first file.
+pub fn parse(text: &str) -> Vec<CueTrack> {
+    let mut tracks = Vec::new();
+    let mut current: Option<(u32, Option<String>)> = None;
+    let mut num_files = 0;
+
+    // Many cue sheets start with a byte order mark.
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "FILE" => num_files += 1,
+            _ if num_files > 1 => break,
+            "TRACK" => {
+                let number = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+                current = number.map(|n| (n, None));
+            }
+            "TITLE" => {
+                if let Some((_, title)) = current.as_mut() {
+                    *title = Some(parse_title(rest));
+                }
+            }
+            "INDEX" => {
+                let mut args = rest.split_whitespace();
+                let is_start = args.next().and_then(|i| i.parse::<u32>().ok()) == Some(1);
+                let start_frames = args.next().and_then(parse_timestamp);
+                if let (true, Some(start_frames)) = (is_start, start_frames) {
+                    if let Some((number, title)) = current.take() {
+                        tracks.push(CueTrack {
+                            number,
+                            title,
+                            start_frames,
+                        });
+                    }
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    tracks
+}
+
+/// Read the tracks from the cue sheet next to the audio file, if there is one.
+///
+/// The cue sheet has the same name as the audio file, with a `.cue` extension.
+/// Many cue sheets are not UTF-8 but Latin-1; we lose the accents in titles
+/// then, but the timestamps are ASCII, so the boundaries are still correct.
+pub fn read_beside(audio_path: &Path) -> Vec<CueTrack> {
+    match fs::read(audio_path.with_extension("cue")) {
+        Ok(bytes) => parse(&String::from_utf8_lossy(&bytes)),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn parse_reads_track_starts_and_titles() {
+    let text = "\u{feff}REM GENRE Jazz\r\n\
+        TITLE \"Album\"\r\n\
+        FILE \"album.flac\" WAVE\r\n\
+        \x20 TRACK 01 AUDIO\r\n\
+        \x20   TITLE \"First\"\r\n\
+        \x20   INDEX 01 00:00:00\r\n\
+        \x20 TRACK 02 AUDIO\r\n\
+        \x20   INDEX 00 03:59:70\r\n\
+        \x20   INDEX 01 04:01:15\r\n";
+    let tracks = parse(text);
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].label(), "01 First");
+    assert_eq!(tracks[0].start_frames, 0);
+    assert_eq!(tracks[1].label(), "02");
+    assert_eq!(tracks[1].start_frames, (4 * 60 + 1) * 75 + 15);
+    assert_eq!(tracks[1].start_sample(44_100), 241 * 44_100 + 8_820);
+}
+
+#[test]
+fn parse_timestamp_rejects_invalid_timestamps() {
+    assert_eq!(parse_timestamp("75:00:00"), Some(75 * 60 * 75));
+    assert_eq!(parse_timestamp("00:60:00"), None);
+    assert_eq!(parse_timestamp("00:00:75"), None);
+    assert_eq!(parse_timestamp("00:00"), None);
+    assert_eq!(parse_timestamp("00:00:00:00"), None);
+}
@@ -14,11 +14,10 @@
 /// Apply a disrete Fourier Transform.
 ///
 /// Returns the squared norms of the results. Only resturns the first half
-/// of the coefficients, as they are symmetric.
-#[cfg(test)]
+/// of the coefficients, as they are symmetric. This works for any length, but
+/// it takes quadratic time, we use it to test the fast transforms against.
 pub fn dft_naive(xs: &[f32]) -> Box<[f32]> {
     let half_len = xs.len() / 2;
-    assert_eq!(half_len * 2, xs.len(), "Length must be multiple of 2.");
 
     let mut result = Vec::with_capacity(half_len + 1);
     let inv_len = (xs.len() as f64).recip();
@@ -37,10 +36,11 @@ pub fn dft_naive(xs: &[f32]) -> Box<[f32]> {
     result.into_boxed_slice()
 }
 
-#[derive(Copy, Clone)]
-struct Complex {
-    real: f32,
-    imag: f32,
+/// A complex number, the input and output of `FftPlan::transform`.
+#[derive(Copy, Clone, Debug)]
+pub struct Complex {
+    pub real: f32,
+    pub imag: f32,
 }
 
 impl Complex {
@@ -76,6 +76,16 @@ impl std::ops::Sub for Complex {
     }
 }
 
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            real: self.real * other.real - self.imag * other.imag,
+            imag: self.real * other.imag + self.imag * other.real,
+        }
+    }
+}
+
 impl std::ops::Neg for Complex {
     type Output = Complex;
     fn neg(self) -> Complex {
@@ -86,6 +96,279 @@ impl std::ops::Neg for Complex {
     }
 }
 
+/// Return the factors e^(-2πik / len) for k in 0..len / 2, that `fft` needs.
+fn twiddle_factors(len: usize) -> Vec<Complex> {
+    let two_pi_over_len = std::f64::consts::PI * 2.0 / len as f64;
+    (0..len / 2)
+        .map(|k| {
+            let arg = k as f64 * two_pi_over_len;
+            Complex {
+                real: arg.cos() as f32,
+                imag: -arg.sin() as f32,
+            }
+        })
+        .collect()
+}
+
+/// Apply a discrete Fourier transform in place, with the iterative radix-2 Cooley-Tukey algorithm.
+///
+/// The length must be a power of two, and `twiddles` must be the
+/// `twiddle_factors` for that length, or for a multiple of it; then we use
+/// every n-th factor. We first put the input in bit-reversed order, so the
+/// butterflies of every stage can combine the transforms of the previous
+/// stage in place, instead of recursing and copying.
+fn fft(xs: &mut [Complex], twiddles: &[Complex]) {
+    let len = xs.len();
+    assert!(len.is_power_of_two(), "Length must be a power of two.");
+    if len < 2 {
+        return;
+    }
+    assert_eq!(twiddles.len() % (len / 2), 0);
+    let twiddle_step = twiddles.len() / (len / 2);
+
+    let shift = usize::BITS - len.trailing_zeros();
+    for i in 0..len {
+        let j = i.reverse_bits() >> shift;
+        if i < j {
+            xs.swap(i, j);
+        }
+    }
+
+    // At every stage, we combine pairs of transforms of `half_len` into one
+    // of twice that length. Its twiddle factors are every `stride`-th one.
+    let mut half_len = 1;
+    while half_len < len {
+        let stride = len / (2 * half_len) * twiddle_step;
+        for chunk in xs.chunks_exact_mut(2 * half_len) {
+            let (evens, odds) = chunk.split_at_mut(half_len);
+            for (i, (even, odd)) in evens.iter_mut().zip(odds.iter_mut()).enumerate() {
+                let cexp = twiddles[i * stride];
+                let (e, o) = (*even, *odd);
+                *even = cexp.mul_add(o, e);
+                *odd = cexp.mul_add(-o, e);
+            }
+        }
+        half_len *= 2;
+    }
+}
+
+/// The precomputed chirp for Bluestein's algorithm, for lengths that are not a power of two.
+#[derive(Clone)]
+struct Chirp {
+    /// The factors e^(-πin² / len) for n in 0..len.
+    factors: Vec<Complex>,
+
+    /// The transform of the conjugate chirp, wrapped around a power of two of at least 2 len - 1.
+    spectrum: Vec<Complex>,
+
+    /// Scratch space for the convolution, as long as `spectrum`.
+    work: Vec<Complex>,
+}
+
+impl Chirp {
+    /// Return the chirp for `len`, and the twiddle factors for the length of its convolution.
+    fn new(len: usize) -> (Chirp, Vec<Complex>) {
+        // Reduce n² modulo 2 len before we multiply by π, to keep precision for large n.
+        let pi_over_len = std::f64::consts::PI / len as f64;
+        let factors: Vec<Complex> = (0..len)
+            .map(|n| {
+                let arg = ((n * n) % (2 * len)) as f64 * pi_over_len;
+                Complex {
+                    real: arg.cos() as f32,
+                    imag: -arg.sin() as f32,
+                }
+            })
+            .collect();
+
+        // The convolution is circular, so the conjugate chirp for negative n
+        // wraps around to the end.
+        let conv_len = (2 * len - 1).next_power_of_two();
+        let twiddles = twiddle_factors(conv_len);
+        let zero = Complex {
+            real: 0.0,
+            imag: 0.0,
+        };
+        let mut spectrum = vec![zero; conv_len];
+        for (n, w) in factors.iter().enumerate() {
+            let conj = Complex {
+                real: w.real,
+                imag: -w.imag,
+            };
+            spectrum[n] = conj;
+            spectrum[(conv_len - n) % conv_len] = conj;
+        }
+        fft(&mut spectrum, &twiddles);
+
+        let chirp = Chirp {
+            factors,
+            spectrum,
+            work: Vec::with_capacity(conv_len),
+        };
+        (chirp, twiddles)
+    }
+
+    /// Apply a discrete Fourier transform in place, with Bluestein's algorithm.
+    ///
+    /// Bluestein writes the transform as a convolution with a chirp, which we
+    /// can compute with power-of-two transforms of `twiddles`, after
+    /// zero-padding. The transform of the chirp is the same for every input.
+    fn transform(&mut self, xs: &mut [Complex], twiddles: &[Complex]) {
+        assert_eq!(xs.len(), self.factors.len(), "Chirp is for another length.");
+        let zero = Complex {
+            real: 0.0,
+            imag: 0.0,
+        };
+        self.work.clear();
+        self.work
+            .extend(xs.iter().zip(&self.factors).map(|(&x, &w)| x * w));
+        self.work.resize(self.spectrum.len(), zero);
+        fft(&mut self.work, twiddles);
+
+        // We take the inverse transform by conjugating before and after a
+        // forward transform, and dividing by the length.
+        for (y, &s) in self.work.iter_mut().zip(&self.spectrum) {
+            let p = *y * s;
+            *y = Complex {
+                real: p.real,
+                imag: -p.imag,
+            };
+        }
+        fft(&mut self.work, twiddles);
+
+        let inv_len = (self.work.len() as f32).recip();
+        for ((x, &y), &w) in xs.iter_mut().zip(&self.work).zip(&self.factors) {
+            let y = Complex {
+                real: y.real * inv_len,
+                imag: -y.imag * inv_len,
+            };
+            *x = y * w;
+        }
+    }
+}
+
+/// Twiddle factors and scratch space, to transform many windows of the same length.
+///
+/// Computing the twiddle factors takes a sine and cosine each, so for a
+/// spectrogram, where we transform thousands of windows of the same length,
+/// we compute them once. To transform on multiple threads, clone the plan.
+#[derive(Clone)]
+pub struct FftPlan {
+    /// The length of the (zero-padded) input that the plan is for.
+    len: usize,
+
+    /// The `twiddle_factors` for `len`, or for the convolution of `chirp`.
+    twiddles: Vec<Complex>,
+
+    /// The chirp to transform with, if `len` is not a power of two.
+    chirp: Option<Chirp>,
+
+    /// The input of `dft_windowed`, packed into half as many complex numbers
+    /// for a power of two, or converted to complex otherwise.
+    scratch: Vec<Complex>,
+}
+
+impl FftPlan {
+    pub fn new(len: usize) -> FftPlan {
+        let (chirp, twiddles) = if len.is_power_of_two() || len == 0 {
+            (None, twiddle_factors(len))
+        } else {
+            let (chirp, twiddles) = Chirp::new(len);
+            (Some(chirp), twiddles)
+        };
+        FftPlan {
+            len,
+            twiddles,
+            chirp,
+            scratch: Vec::with_capacity(len),
+        }
+    }
+
+    /// Return the length of the input that the plan is for.
+    pub fn input_len(&self) -> usize {
+        self.len
+    }
+
+    /// Apply a discrete Fourier transform in place.
+    ///
+    /// If `input_len()` is a power of two, the length of `xs` must be a power
+    /// of two that is at most that. Otherwise it must be `input_len()`.
+    pub fn transform(&mut self, xs: &mut [Complex]) {
+        match &mut self.chirp {
+            Some(chirp) => chirp.transform(xs, &self.twiddles),
+            None => {
+                assert!(xs.len() <= self.len, "Plan is for shorter inputs.");
+                fft(xs, &self.twiddles);
+            }
+        }
+    }
+
+    /// Apply a windowed discrete Fourier transform, after zero-padding the input to `input_len()`.
+    ///
+    /// Returns the squared norms of the first half of the coefficients, see
+    /// `dft_fast`. We pack the even samples into the real part and the odd
+    /// samples into the imaginary part of a signal of half the length,
+    /// transform that, and then separate the transforms of the even and odd
+    /// samples again using the symmetry of the transform of a real signal.
+    /// If `input_len()` is not a power of two, we transform the input as a
+    /// complex signal with Bluestein's algorithm instead.
+    pub fn dft_windowed(&mut self, xs: &[f32], window: impl Fn(usize, usize) -> f32) -> Box<[f32]> {
+        assert!(xs.len() <= self.len, "Plan is for shorter inputs.");
+        let windowed = |i: usize| match xs.get(i) {
+            Some(x) => x * window(xs.len(), i),
+            None => 0.0,
+        };
+
+        let half_len = self.len / 2;
+        if self.len < 2 {
+            return Vec::new().into_boxed_slice();
+        }
+        if let Some(chirp) = &mut self.chirp {
+            self.scratch.clear();
+            self.scratch.extend((0..self.len).map(|n| Complex {
+                real: windowed(n),
+                imag: 0.0,
+            }));
+            chirp.transform(&mut self.scratch, &self.twiddles);
+            let result: Vec<f32> = self.scratch[..half_len]
+                .iter()
+                .map(|z| z.real * z.real + z.imag * z.imag)
+                .collect();
+            return result.into_boxed_slice();
+        }
+
+        self.scratch.clear();
+        self.scratch.extend((0..half_len).map(|n| Complex {
+            real: windowed(2 * n),
+            imag: windowed(2 * n + 1),
+        }));
+        let zs = &mut self.scratch[..];
+        fft(zs, &self.twiddles);
+
+        let result: Vec<f32> = (0..half_len)
+            .map(|k| {
+                let zk = zs[k];
+                let zc = zs[(half_len - k) % half_len];
+                // The transform of the even samples is (zk + conj(zc)) / 2, the
+                // transform of the odd samples is (zk - conj(zc)) / 2i.
+                let even = Complex {
+                    real: 0.5 * (zk.real + zc.real),
+                    imag: 0.5 * (zk.imag - zc.imag),
+                };
+                let odd = Complex {
+                    real: 0.5 * (zk.imag + zc.imag),
+                    imag: -0.5 * (zk.real - zc.real),
+                };
+                let x = self.twiddles[k].mul_add(odd, even);
+                x.real * x.real + x.imag * x.imag
+            })
+            .collect();
+
+        result.into_boxed_slice()
+    }
+}
+
+/// The recursive form of `fft`, that we test it against.
+#[cfg(test)]
 fn cooley_tukey(xs: &mut [Complex], tmp: &mut [Complex]) {
     if xs.len() < 2 {
         return;
@@ -123,6 +406,9 @@ fn cooley_tukey(xs: &mut [Complex], tmp: &mut [Complex]) {
     }
 }
 
+/// Return coefficient `i` of a Hann window of `len` samples.
+///
+/// This has the signature that `dft_fast` expects of a window function.
 pub fn hann(len: usize, i: usize) -> f32 {
     // Factor used for the Hann window, normalized (the factor 2.0) to ensure
     // that the integral of hann(i) is 1.0.
@@ -131,29 +417,136 @@ pub fn hann(len: usize, i: usize) -> f32 {
     2.0 * (factor_sqrt * factor_sqrt)
 }
 
+/// Return coefficient `i` of a window of `len` samples that is a sum of cosines.
+///
+/// The window is `a0 - a1 cos(x) + a2 cos(2x)` for x from 0 to 2π, divided by
+/// `a0`, so its integral is 1.0, like that of `hann`.
+fn cosine_sum(len: usize, i: usize, [a0, a1, a2]: [f32; 3]) -> f32 {
+    let x = i as f32 * 2.0 * std::f32::consts::PI / (len - 1) as f32;
+    (a0 - a1 * x.cos() + a2 * (2.0 * x).cos()) / a0
+}
+
+/// Return coefficient `i` of a Hamming window of `len` samples.
+///
+/// The Hamming window does not go to zero at the edges. This cancels the
+/// first sidelobe, but the further sidelobes fall off slower than for Hann.
+pub fn hamming(len: usize, i: usize) -> f32 {
+    cosine_sum(len, i, WindowFn::Hamming.coefficients())
+}
+
+/// Return coefficient `i` of a Blackman window of `len` samples.
+///
+/// The Blackman window has lower sidelobes than the Hann window, so a faint
+/// tone next to a loud one stands out better, at the cost of a wider peak.
+pub fn blackman(len: usize, i: usize) -> f32 {
+    cosine_sum(len, i, WindowFn::Blackman.coefficients())
+}
+
+/// The window functions that the spectrogram can use.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WindowFn {
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowFn {
+    /// Return the coefficients `[a0, a1, a2]` of the window as a sum of cosines.
+    fn coefficients(self) -> [f32; 3] {
+        match self {
+            WindowFn::Hann => [0.5, 0.5, 0.0],
+            WindowFn::Hamming => [0.54, 0.46, 0.0],
+            WindowFn::Blackman => [0.42, 0.5, 0.08],
+        }
+    }
+
+    /// Return coefficient `i` of the window of `len` samples.
+    pub fn coefficient(self, len: usize, i: usize) -> f32 {
+        match self {
+            WindowFn::Hann => hann(len, i),
+            WindowFn::Hamming => hamming(len, i),
+            WindowFn::Blackman => blackman(len, i),
+        }
+    }
+
+    /// Return the mean of the squared coefficients, for long windows.
+    ///
+    /// By Parseval, this relates the power in the spectrum to the power of
+    /// the signal.
+    pub fn mean_square(self) -> f32 {
+        let [a0, a1, a2] = self.coefficients();
+        (a0 * a0 + 0.5 * (a1 * a1 + a2 * a2)) / (a0 * a0)
+    }
+
+    /// Return the name of the window, as in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowFn::Hann => "hann",
+            WindowFn::Hamming => "hamming",
+            WindowFn::Blackman => "blackman",
+        }
+    }
+}
+
 /// Integrate the Hann window from `t0` to `t1`.
 ///
-/// This assumes that the domain of the Hann window is [0, 1],
-/// such that `hann_int(0.0, 1.0)` would return 1.0 if the result was not
-/// scaled. However, we scale the result by some constant as we normalize
-/// later anyway.
+/// This assumes that the domain of the Hann window is [0, 1]. The window is
+/// normalized like `hann`, so `hann_int(0.0, 1.0)` returns 1.0.
 pub fn hann_int(t0: f32, t1: f32) -> f32 {
-    let t0_2pi = (2.0 * std::f32::consts::PI) * t0;
-    let t1_2pi = (2.0 * std::f32::consts::PI) * t1;
-    t1_2pi - t0_2pi + t0_2pi.sin() - t1_2pi.sin()
+    let two_pi = 2.0 * std::f32::consts::PI;
+    let t0_2pi = two_pi * t0;
+    let t1_2pi = two_pi * t1;
+    t1 - t0 + (t0_2pi.sin() - t1_2pi.sin()) / two_pi
 }
 
-pub fn dft_fast(xs: &[f32], window: impl Fn(usize, usize) -> f32) -> Box<[f32]> {
-    let half_len = xs.len() / 2;
-    assert_eq!(half_len * 2, xs.len(), "Length must be even.");
+/// Apply a windowed discrete Fourier transform, after zero-padding the input.
+///
+/// The window is applied to `xs`, which is then extended with zeros to
+/// `zero_pad_factor` times its length. The result has `zero_pad_factor` times
+/// as many bins, spaced more closely. This interpolates the spectrum, but the
+/// frequency resolution is still determined by the length of `xs`.
+///
+/// Returns the squared norms of the first half of the coefficients.
+///
+/// The window is a function of the length of `xs` and the sample index, such as
+/// `hann`, that returns the coefficient to multiply sample `i` by.
+///
+/// The fast path halves the length until it is 1, so it needs a padded length
+/// that is a power of two. Other lengths take a few times longer.
+///
+/// To transform many windows of the same length, use an `FftPlan` instead.
+pub fn dft_fast(
+    xs: &[f32],
+    window: impl Fn(usize, usize) -> f32,
+    zero_pad_factor: usize,
+) -> Box<[f32]> {
+    FftPlan::new(xs.len() * zero_pad_factor).dft_windowed(xs, window)
+}
+
+/// Apply a discrete Fourier transform to real input, at half the cost of a complex one.
+///
+/// Returns the squared norms of the first half of the coefficients, like
+/// `dft_naive`, see `FftPlan::dft_windowed` for how. This is fastest when the
+/// length is a power of two, but it works for any length.
+pub fn dft_real(xs: &[f32]) -> Box<[f32]> {
+    FftPlan::new(xs.len()).dft_windowed(xs, |_, _| 1.0)
+}
+
+/// Window and zero-pad `xs` like `dft_fast` does, and return all coefficients.
+///
+/// Like `dft_fast`, this works for any length, not only for even ones.
+fn transform_padded(
+    xs: &[f32],
+    window: impl Fn(usize, usize) -> f32,
+    zero_pad_factor: usize,
+) -> Vec<Complex> {
+    let len = xs.len();
+    let padded_len = len * zero_pad_factor;
 
     let z = Complex {
         real: 0.0,
         imag: 0.0,
     };
-    let mut tmp: Vec<_> = std::iter::repeat(z).take(half_len).collect();
-
-    let len = xs.len();
     let mut xs_complex: Vec<_> = xs
         .iter()
         .enumerate()
@@ -162,13 +555,100 @@ pub fn dft_fast(xs: &[f32], window: impl Fn(usize, usize) -> f32) -> Box<[f32]>
             imag: 0.0,
         })
         .collect();
+    xs_complex.resize(padded_len, z);
 
-    cooley_tukey(&mut xs_complex[..], &mut tmp[..]);
+    FftPlan::new(padded_len).transform(&mut xs_complex[..]);
+
+    xs_complex
+}
+
+/// Apply a windowed discrete Fourier transform, and return the first half of the coefficients.
+///
+/// This is like `dft_fast` without zero-padding, but it keeps the phase. The
+/// squared norm of coefficient `k` is the power that `dft_fast` returns for
+/// bin `k`. For the spectrogram we only need the power, `dft_fast` is faster.
+pub fn dft_complex(xs: &[f32], window: impl Fn(usize, usize) -> f32) -> Box<[Complex]> {
+    let mut xs_complex = transform_padded(xs, window, 1);
+    xs_complex.truncate(xs.len() / 2);
+    xs_complex.into_boxed_slice()
+}
+
+/// Return the group delay of the first half of the bins, in samples.
+///
+/// The group delay is the negative derivative of the phase with respect to
+/// frequency. We take the phase difference between a bin and the next one,
+/// which is in (-pi, pi] by construction, so the phase needs no unwrapping.
+/// The delay is relative to the center of `xs`. For an impulse, every bin has
+/// the delay of the impulse, which is why it highlights transients. Where
+/// there is little energy, the phase is noise, and so is the delay.
+pub fn group_delay(
+    xs: &[f32],
+    window: impl Fn(usize, usize) -> f32,
+    zero_pad_factor: usize,
+) -> Box<[f32]> {
+    let xs_complex = transform_padded(xs, window, zero_pad_factor);
+    let half_len = xs_complex.len() / 2;
+    let samples_per_radian = xs_complex.len() as f32 / (2.0 * std::f32::consts::PI);
+    let center = (xs.len() / 2) as f32;
 
     let result: Vec<f32> = xs_complex
-        .iter()
+        .windows(2)
         .take(half_len)
-        .map(|&z| z.real * z.real + z.imag * z.imag)
+        .map(|pair| {
+            // The phase of z1 * conj(z0) is the phase difference.
+            let (z0, z1) = (pair[0], pair[1]);
+            let real = z1.real * z0.real + z1.imag * z0.imag;
+            let imag = z1.imag * z0.real - z1.real * z0.imag;
+            -imag.atan2(real) * samples_per_radian - center
+        })
+        .collect();
+
+    result.into_boxed_slice()
+}
+
+/// Return the phase coherence between two signals for the first half of the bins, in [-1, 1].
+///
+/// This is the real part of the cross-spectrum, normalized by the magnitudes.
+/// It is 1.0 where the signals are in phase, -1.0 where they are in
+/// antiphase, and around 0.0 where they are unrelated. The value of a single
+/// bin is the cosine of the phase difference, which is noisy, so we sum the
+/// cross-spectrum and powers over `radius` bins on either side before we
+/// normalize. Where both signals are silent, the coherence is 0.0.
+pub fn phase_coherence(
+    xs: &[f32],
+    ys: &[f32],
+    window: impl Fn(usize, usize) -> f32,
+    zero_pad_factor: usize,
+    radius: usize,
+) -> Box<[f32]> {
+    assert_eq!(xs.len(), ys.len(), "Signals must have the same length.");
+    let xs_complex = transform_padded(xs, &window, zero_pad_factor);
+    let ys_complex = transform_padded(ys, &window, zero_pad_factor);
+    let half_len = xs_complex.len() / 2;
+
+    // The cross power, and the product of the powers, per bin.
+    let mut cross = Vec::with_capacity(half_len);
+    let mut power_x = Vec::with_capacity(half_len);
+    let mut power_y = Vec::with_capacity(half_len);
+    for (x, y) in xs_complex.iter().zip(&ys_complex).take(half_len) {
+        cross.push(x.real * y.real + x.imag * y.imag);
+        power_x.push(x.real * x.real + x.imag * x.imag);
+        power_y.push(y.real * y.real + y.imag * y.imag);
+    }
+
+    let result: Vec<f32> = (0..half_len)
+        .map(|k| {
+            let range = k.saturating_sub(radius)..(k + radius + 1).min(half_len);
+            let c: f32 = cross[range.clone()].iter().sum();
+            let px: f32 = power_x[range.clone()].iter().sum();
+            let py: f32 = power_y[range].iter().sum();
+            let norm = (px * py).sqrt();
+            if norm > 0.0 {
+                (c / norm).max(-1.0).min(1.0)
+            } else {
+                0.0
+            }
+        })
         .collect();
 
     result.into_boxed_slice()
@@ -223,12 +703,94 @@ fn dft_naive_finds_peaks() {
     }
 }
 
+#[test]
+fn hann_is_normalized() {
+    let len = 4096;
+    let sum: f32 = (0..len).map(|i| hann(len, i)).sum();
+    assert!((sum / len as f32 - 1.0).abs() < 1e-3, "Sum is {}.", sum);
+    assert_eq!(hann(len, 0), 0.0);
+    assert!((hann(len, len / 2) - 2.0).abs() < 1e-3);
+}
+
+#[test]
+fn windows_are_normalized() {
+    let len = 4096;
+    for window_fn in [WindowFn::Hann, WindowFn::Hamming, WindowFn::Blackman] {
+        let coefficients: Vec<f32> = (0..len).map(|i| window_fn.coefficient(len, i)).collect();
+        let mean = coefficients.iter().sum::<f32>() / len as f32;
+        let mean_square = coefficients.iter().map(|w| w * w).sum::<f32>() / len as f32;
+        assert!(
+            (mean - 1.0).abs() < 1e-3,
+            "{:?} has mean {}.",
+            window_fn,
+            mean
+        );
+        assert!(
+            (mean_square - window_fn.mean_square()).abs() < 1e-2,
+            "{:?} has mean square {}.",
+            window_fn,
+            mean_square
+        );
+    }
+    assert_eq!(WindowFn::Hann.mean_square(), 1.5);
+}
+
+#[test]
+fn hann_int_integrates_hann() {
+    assert!((hann_int(0.0, 1.0) - 1.0).abs() < 1e-6);
+    assert_eq!(hann_int(0.3, 0.3), 0.0);
+
+    // Compare against a Riemann sum of `hann` over every interval of a grid.
+    let len = 1001;
+    let inv_len = ((len - 1) as f32).recip();
+    for (i0, i1) in [(0, 1000), (0, 500), (250, 750), (100, 101), (900, 1000)] {
+        let sum: f32 = (i0..i1).map(|i| hann(len, i) + hann(len, i + 1)).sum();
+        let numerical = 0.5 * sum * inv_len;
+        let exact = hann_int(i0 as f32 * inv_len, i1 as f32 * inv_len);
+        assert!(
+            (numerical - exact).abs() < 1e-4,
+            "Integral over [{}, {}] is {}, expected {}.",
+            i0,
+            i1,
+            exact,
+            numerical
+        );
+    }
+}
+
+#[test]
+fn fft_equals_cooley_tukey() {
+    let buffer = generate_test_signal();
+    for len in [1, 2, 8, 4096] {
+        let mut xs: Vec<Complex> = buffer[..len]
+            .iter()
+            .map(|&x| Complex { real: x, imag: 0.0 })
+            .collect();
+        let mut ys = xs.clone();
+        let mut tmp = xs.clone();
+        cooley_tukey(&mut xs, &mut tmp);
+        fft(&mut ys, &twiddle_factors(len));
+
+        for (i, (x, y)) in xs.iter().zip(&ys).enumerate() {
+            let diff = (x.real - y.real).abs() + (x.imag - y.imag).abs();
+            assert!(
+                diff / (len as f32) < 1e-5,
+                "Difference at index {} of {}: {} vs {}.",
+                i,
+                len,
+                x.real,
+                y.real
+            );
+        }
+    }
+}
+
 #[test]
 fn dft_fast_equals_dft_naive() {
     let rect_window = |_, _| 1.0;
     let buffer = generate_test_signal();
     let result_naive = dft_naive(&buffer[..]);
-    let result_fast = dft_fast(&buffer[..], rect_window);
+    let result_fast = dft_fast(&buffer[..], rect_window, 1);
 
     for (i, (&naive, &fast)) in result_naive.iter().zip(result_fast.iter()).enumerate() {
         let diff = (naive.sqrt() - fast.sqrt()).abs() / (buffer.len() as f32);
@@ -241,3 +803,183 @@ fn dft_fast_equals_dft_naive() {
         );
     }
 }
+
+#[test]
+fn dft_real_equals_dft_naive() {
+    let buffer = generate_test_signal();
+    for len in [2, 4, 64, 4096] {
+        let result_naive = dft_naive(&buffer[..len]);
+        let result_real = dft_real(&buffer[..len]);
+        assert_eq!(result_real.len(), len / 2);
+
+        for (i, (&naive, &real)) in result_naive.iter().zip(result_real.iter()).enumerate() {
+            let diff = (naive.sqrt() - real.sqrt()).abs() / (len as f32);
+            assert!(
+                diff < 2e-4,
+                "Difference at index {} of {}: {} vs {}.",
+                i,
+                len,
+                naive,
+                real
+            );
+        }
+    }
+}
+
+#[test]
+fn dft_real_equals_dft_naive_for_other_lengths() {
+    let buffer = generate_test_signal();
+    for len in [3, 7, 100, 1021, 3000] {
+        let result_naive = dft_naive(&buffer[..len]);
+        let result_real = dft_real(&buffer[..len]);
+        assert_eq!(result_real.len(), len / 2);
+
+        for (i, (&naive, &real)) in result_naive.iter().zip(result_real.iter()).enumerate() {
+            let diff = (naive.sqrt() - real.sqrt()).abs() / (len as f32);
+            assert!(
+                diff < 2e-4,
+                "Difference at index {} of {}: {} vs {}.",
+                i,
+                len,
+                naive,
+                real
+            );
+        }
+    }
+}
+
+#[test]
+fn dft_complex_keeps_the_phase() {
+    let rect_window = |_, _| 1.0;
+    let two_pi = std::f32::consts::PI * 2.0;
+    let len = 256;
+    let buffer: Vec<f32> = (0..len)
+        .map(|i| {
+            let t = i as f32 / len as f32;
+            (t * 10.0 * two_pi).cos() + (t * 23.0 * two_pi).sin()
+        })
+        .collect();
+
+    let result = dft_complex(&buffer, rect_window);
+    let powers = dft_fast(&buffer, rect_window, 1);
+    assert_eq!(result.len(), len / 2);
+    for (z, &power) in result.iter().zip(powers.iter()) {
+        let norm_sqr = z.real * z.real + z.imag * z.imag;
+        assert!((norm_sqr - power).abs() <= 1e-3 * (1.0 + power));
+    }
+
+    // A cosine has phase 0, a sine has phase -π/2.
+    let phase = |z: Complex| z.imag.atan2(z.real);
+    assert!(phase(result[10]).abs() < 1e-4);
+    assert!((phase(result[23]) + 0.5 * std::f32::consts::PI).abs() < 1e-4);
+}
+
+#[test]
+fn dft_fast_zero_padded_preserves_peaks() {
+    let rect_window = |_, _| 1.0;
+    let buffer = generate_test_signal();
+    let result = dft_fast(&buffer[..], rect_window, 1);
+    let result_padded = dft_fast(&buffer[..], rect_window, 4);
+    assert_eq!(result_padded.len(), result.len() * 4);
+
+    for &k in &[5, 31, 53, 541] {
+        // Every fourth bin of the padded transform is a bin of the unpadded
+        // transform, so the peaks should be at the same frequencies, with the
+        // same magnitude, and be local maxima.
+        let a = result[k].sqrt();
+        let a_padded = result_padded[4 * k].sqrt();
+        assert!(
+            (a - a_padded).abs() / (buffer.len() as f32) < 2e-4,
+            "Peak at {} differs: {} vs {}.",
+            k,
+            a,
+            a_padded
+        );
+        assert!(result_padded[4 * k] > result_padded[4 * k - 1]);
+        assert!(result_padded[4 * k] > result_padded[4 * k + 1]);
+    }
+}
+
+#[test]
+fn dft_fast_falls_back_for_other_lengths() {
+    let rect_window = |_, _| 1.0;
+    let two_pi = std::f32::consts::PI * 2.0;
+    let buffer: Vec<f32> = (0..100)
+        .map(|i| {
+            let t = i as f32 / 100.0;
+            3.0 * (t * 10.0 * two_pi).sin() + 1.0 * (t * 23.0 * two_pi).cos()
+        })
+        .collect();
+
+    for zero_pad_factor in [1, 3] {
+        let result = dft_fast(&buffer[..], rect_window, zero_pad_factor);
+        assert_eq!(result.len(), 50 * zero_pad_factor);
+        for (i, &power) in result.iter().enumerate() {
+            if i % zero_pad_factor != 0 {
+                continue;
+            }
+            let a = 2.0 * power.sqrt() / buffer.len() as f32;
+            match i / zero_pad_factor {
+                10 => assert!((a - 3.0).abs() < 1e-4, "{} at {}", a, i),
+                23 => assert!((a - 1.0).abs() < 1e-4, "{} at {}", a, i),
+                _ => assert!(a < 1e-4, "Unexpected peak of {} at {}", a, i),
+            }
+        }
+    }
+
+    // Odd lengths work too, without panicking.
+    assert_eq!(dft_fast(&buffer[..99], rect_window, 1).len(), 49);
+}
+
+#[test]
+fn group_delay_finds_impulse() {
+    let mut buffer = vec![0.0_f32; 512];
+    buffer[100] = 1.0;
+    for zero_pad_factor in [1, 2] {
+        let delays = group_delay(&buffer[..], |_, _| 1.0, zero_pad_factor);
+        assert_eq!(delays.len(), 256 * zero_pad_factor);
+        for (k, &delay) in delays.iter().enumerate() {
+            // The impulse is 156 samples before the center of the window.
+            assert!((delay + 156.0).abs() < 1e-2, "Delay {} at {}.", delay, k);
+        }
+    }
+}
+
+#[test]
+fn phase_coherence_tells_mono_from_antiphase() {
+    let buffer = generate_test_signal();
+    let inverted: Vec<f32> = buffer.iter().map(|x| -x).collect();
+    let silence = vec![0.0_f32; buffer.len()];
+
+    let mono = phase_coherence(&buffer, &buffer, hann, 1, 2);
+    let antiphase = phase_coherence(&buffer, &inverted, hann, 1, 2);
+    let one_sided = phase_coherence(&buffer, &silence, hann, 1, 2);
+    assert_eq!(mono.len(), 2048);
+    for &k in &[5, 31, 53, 541] {
+        assert!((mono[k] - 1.0).abs() < 1e-4, "{} at {}", mono[k], k);
+        assert!(
+            (antiphase[k] + 1.0).abs() < 1e-4,
+            "{} at {}",
+            antiphase[k],
+            k
+        );
+        assert_eq!(one_sided[k], 0.0);
+    }
+}
+
+#[test]
+fn phase_transforms_accept_odd_lengths() {
+    let buffer = generate_test_signal();
+    let odd = &buffer[..1001];
+    assert_eq!(dft_complex(odd, hann).len(), 500);
+    assert_eq!(phase_coherence(odd, odd, hann, 1, 2).len(), 500);
+
+    let mut impulse = vec![0.0_f32; 1001];
+    impulse[100] = 1.0;
+    let delays = group_delay(&impulse, |_, _| 1.0, 1);
+    assert_eq!(delays.len(), 500);
+    for (k, &delay) in delays.iter().enumerate() {
+        // The impulse is 400 samples before the center of the window.
+        assert!((delay + 400.0).abs() < 1e-2, "Delay {} at {}.", delay, k);
+    }
+}
@@ -0,0 +1,122 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module decodes formats that we have no decoder for, by running the
+// command-line decoder of the format. The decoders write a wav stream to
+// stdout, which we read through a pipe, so we never need a temporary file.
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+/// A command-line program that decodes a format to wav.
+pub struct Tool {
+    /// The file extension of the format, in lowercase.
+    pub extension: &'static str,
+
+    /// The program to run.
+    pub program: &'static str,
+
+    /// The project that provides the program, to point users to.
+    pub package: &'static str,
+
+    /// Arguments to pass before the input file name.
+    args_before: &'static [&'static str],
+
+    /// Arguments to pass after the input file name.
+    args_after: &'static [&'static str],
+}
+
+pub const TOOLS: &[Tool] = &[
+    Tool {
+        extension: "wv",
+        program: "wvunpack",
+        package: "WavPack",
+        args_before: &["-q", "-y"],
+        args_after: &["-"],
+    },
+    Tool {
+        extension: "ape",
+        program: "mac",
+        package: "Monkey's Audio",
+        args_before: &[],
+        args_after: &["-", "-d"],
+    },
+];
+
+/// Return the tool that decodes the file, if it needs one, based on the extension.
+pub fn tool_for(path: &Path) -> Option<&'static Tool> {
+    let extension = path.extension().and_then(OsStr::to_str)?.to_lowercase();
+    TOOLS.iter().find(|tool| tool.extension == extension)
+}
+
+/// The stdout of a running decoder.
+///
+/// When this is dropped before the end of the stream, we stop the decoder,
+/// and in any case we wait for it, so it does not linger as a zombie.
+pub struct DecoderOutput {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl io::Read for DecoderOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for DecoderOutput {
+    fn drop(&mut self) {
+        // If the process exited already, killing it fails, that is fine.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Start decoding the file with the tool.
+///
+/// If the tool is not installed, the error message says where to get it.
+pub fn spawn(tool: &Tool, path: &Path) -> Result<DecoderOutput, String> {
+    let result = Command::new(tool.program)
+        .args(tool.args_before)
+        .arg(path)
+        .args(tool.args_after)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+
+    let mut child = match result {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(format!(
+                "Opening .{} files requires '{}', which is part of {}. Please install it.",
+                tool.extension, tool.program, tool.package,
+            ));
+        }
+        Err(err) => return Err(format!("Failed to run '{}': {}", tool.program, err)),
+    };
+
+    let stdout = child
+        .stdout
+        .take()
+        .expect("We asked for a pipe for stdout.");
+    Ok(DecoderOutput { child, stdout })
+}
+
+#[test]
+fn tool_for_matches_extension_case_insensitively() {
+    assert_eq!(
+        tool_for(Path::new("a/b.WV")).map(|t| t.program),
+        Some("wvunpack")
+    );
+    assert_eq!(tool_for(Path::new("b.ape")).map(|t| t.program), Some("mac"));
+    assert!(tool_for(Path::new("b.flac")).is_none());
+    assert!(tool_for(Path::new("c.mp3")).is_none());
+    assert!(tool_for(Path::new("wv")).is_none());
+}
@@ -0,0 +1,391 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module runs the model without a user interface, for when we only want
+// to compute something and not show it. It never initializes GTK, so it works
+// without a display too, for example over ssh. The model still reports to a
+// view through a glib channel, but we handle those events on this thread.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::args::{Args, ChannelMode};
+use crate::config::Config;
+use crate::{archive, external};
+use crate::{format_hz, format_time, Bitmap, Model, ModelEvent, Profile, ViewEvent};
+
+/// The size of the spectrogram that `--output` renders, in pixels, unless
+/// `--width` or `--height` say otherwise.
+const OUTPUT_SIZE: (i32, i32) = (1280, 720);
+
+/// The size that we pretend axis labels to be, for the tick computations.
+const LABEL_SIZE: (i32, i32) = (60, 15);
+
+/// A model that we drive on the current thread, without a view.
+struct Headless {
+    model: Model,
+    events: mpsc::Receiver<ModelEvent>,
+    context: glib::MainContext,
+    bitmap: Rc<RefCell<Option<Bitmap>>>,
+}
+
+impl Headless {
+    fn new(config: &Config) -> Headless {
+        let (send_model, recv_model) = mpsc::sync_channel(10);
+        let (send_view, recv_view) =
+            glib::MainContext::sync_channel(glib::source::Priority::DEFAULT, 100);
+
+        // Of the view events we only need the most recent spectrogram, the
+        // rest would be drawn over it in the user interface, and the errors.
+        let context = glib::MainContext::new();
+        let bitmap = Rc::new(RefCell::new(None));
+        let bitmap_clone = bitmap.clone();
+        recv_view.attach(Some(&context), move |event| {
+            match event {
+                ViewEvent::SetView(b) => *bitmap_clone.borrow_mut() = Some(b),
+                ViewEvent::ShowError(message) => eprintln!("{}", message),
+                _ => {}
+            }
+            glib::ControlFlow::Continue
+        });
+
+        let mut headless = Headless {
+            model: Model::new(send_view, send_model),
+            events: recv_model,
+            context,
+            bitmap,
+        };
+        headless.model.pending_config = Some(*config);
+        headless
+    }
+
+    /// Handle the event, and everything that the model posts in response.
+    fn handle(&mut self, event: ModelEvent) {
+        self.model.handle_event(event);
+        self.dispatch_view_events();
+        while let Ok(next_event) = self.events.try_recv() {
+            self.model.handle_event(next_event);
+            self.dispatch_view_events();
+        }
+    }
+
+    /// Handle the pending view events, so the model never blocks on a full channel.
+    fn dispatch_view_events(&self) {
+        let may_block = false;
+        while self.context.iteration(may_block) {}
+    }
+
+    /// Open the file and decode it completely. Returns false if that failed.
+    fn open(&mut self, fname: &Path, args: &Args) -> bool {
+        let width = args.width.unwrap_or(OUTPUT_SIZE.0);
+        let height = args.height.unwrap_or(OUTPUT_SIZE.1);
+        let (label_width, label_height) = LABEL_SIZE;
+        self.handle(ModelEvent::SetStartTime(args.start_time));
+        if let Some(mode) = args.channel_mode {
+            self.handle(ModelEvent::SetChannelMode(mode));
+        }
+        if args.profile {
+            self.model.profile = Some(Profile::default());
+        }
+        self.handle(ModelEvent::Resize(width, height, label_width, label_height));
+        self.handle(ModelEvent::OpenFile(fname.to_path_buf()));
+        if self.model.duration.is_none() {
+            return false;
+        }
+
+        // Rather than render silence for a channel that is not there, fail.
+        let num_channels = self.model.decoded_channels.len();
+        match args.channel_mode {
+            Some(ChannelMode::Channel(ch)) if ch >= num_channels => {
+                eprintln!(
+                    "Cannot analyze channel {} of {}, it has {} channels.",
+                    ch + 1,
+                    fname.display(),
+                    num_channels,
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Print the properties of the file, and of its content, to stdout.
+fn print_summary(fname: &Path, model: &Model) {
+    let duration = model.duration.unwrap_or(0) as f64 / model.sample_rate as f64;
+    let bandwidth = model.bandwidth.unwrap_or(0.0) as f64;
+    println!("{}", fname.display());
+    println!("  duration:    {}", format_time(duration));
+    println!("  sample rate: {} Hz", model.sample_rate);
+    println!("  channels:    {}", model.decoded_channels.len());
+    println!("  bandwidth:   {}", format_hz(bandwidth));
+}
+
+/// Write the rendered spectrogram to a png file.
+fn save_png(bitmap: Bitmap, out_fname: &Path) -> bool {
+    match bitmap.into_pixbuf().savev(out_fname, "png", &[]) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("Failed to write {}: {}", out_fname.display(), err);
+            false
+        }
+    }
+}
+
+/// Return whether we can open the file, judging by its extension.
+fn is_supported(fname: &Path) -> bool {
+    let is_audio = fname.to_str().map_or(false, archive::is_audio_entry);
+    is_audio || external::tool_for(fname).is_some()
+}
+
+/// List the files to render, where we replace directories by the audio files in them.
+fn collect_inputs(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut fnames = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            fnames.push(path.clone());
+            continue;
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            if entry_path.is_file() && is_supported(&entry_path) {
+                entries.push(entry_path);
+            }
+        }
+        entries.sort();
+        fnames.extend(entries);
+    }
+    Ok(fnames)
+}
+
+/// Return the names of the png files in `out_dir`, one for every input file.
+///
+/// The name is that of the input file, with the extension replaced. When
+/// inputs would get the same name that way, like `a.flac` and `a.wav`, they
+/// keep their extension, and if that is not enough either, because they are
+/// in different directories, we number them.
+fn output_fnames(out_dir: &Path, fnames: &[PathBuf]) -> Vec<PathBuf> {
+    let stem = |fname: &Path| {
+        fname
+            .file_stem()
+            .unwrap_or(fname.as_os_str())
+            .to_os_string()
+    };
+    let mut stem_counts = HashMap::new();
+    for fname in fnames {
+        *stem_counts.entry(stem(fname)).or_insert(0) += 1;
+    }
+
+    let mut used = HashSet::new();
+    fnames
+        .iter()
+        .map(|fname| {
+            let base = match stem_counts[&stem(fname)] {
+                1 => stem(fname),
+                _ => fname
+                    .file_name()
+                    .unwrap_or(fname.as_os_str())
+                    .to_os_string(),
+            };
+            let mut name = base.clone();
+            let mut n = 1;
+            while !used.insert(name.clone()) {
+                n += 1;
+                name = base.clone();
+                name.push(format!("-{}", n));
+            }
+            name.push(".png");
+            out_dir.join(name)
+        })
+        .collect()
+}
+
+/// Render every input file to a png file in `out_dir`. Returns the exit code.
+///
+/// Files are independent, so we render them on as many threads as there are
+/// cores. When a file fails, we report it and continue with the others.
+fn run_batch(args: &Args, config: &Config, fnames: &[PathBuf], out_dir: &Path) -> i32 {
+    let fnames = match collect_inputs(fnames) {
+        Ok(fnames) => fnames,
+        Err(err) => {
+            eprintln!("Failed to list input files: {}", err);
+            return 1;
+        }
+    };
+    if let Err(err) = fs::create_dir_all(out_dir) {
+        eprintln!("Failed to create {}: {}", out_dir.display(), err);
+        return 1;
+    }
+
+    // Say so when we could not name the output after the input alone, so
+    // the user can tell which image belongs to which file.
+    let out_fnames = output_fnames(out_dir, &fnames);
+    let inputs: Vec<(&PathBuf, &PathBuf)> = fnames.iter().zip(&out_fnames).collect();
+    for &(fname, out_fname) in &inputs {
+        if out_fname.file_stem() != fname.file_stem() {
+            eprintln!(
+                "Rendering {} to {}, another input has the same name.",
+                fname.display(),
+                out_fname.display(),
+            );
+        }
+    }
+
+    let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let next_index = AtomicUsize::new(0);
+    let num_failed = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads.min(fnames.len()) {
+            scope.spawn(|| {
+                while let Some(&(fname, out_fname)) =
+                    inputs.get(next_index.fetch_add(1, Ordering::Relaxed))
+                {
+                    let mut headless = Headless::new(config);
+                    let bitmap = if headless.open(fname, args) {
+                        headless.bitmap.borrow_mut().take()
+                    } else {
+                        None
+                    };
+                    let is_ok = match bitmap {
+                        Some(bitmap) => save_png(bitmap, out_fname),
+                        None => false,
+                    };
+                    if !is_ok {
+                        eprintln!("Failed to render {}.", fname.display());
+                        num_failed.fetch_add(1, Ordering::Relaxed);
+                    } else if args.summary {
+                        // Hold the lock, so summaries of different files do not interleave.
+                        let _stdout = io::stdout().lock();
+                        print_summary(fname, &headless.model);
+                    }
+                }
+            });
+        }
+    });
+
+    let num_failed = num_failed.into_inner();
+    println!(
+        "Rendered {} of {} files to {}.",
+        fnames.len() - num_failed,
+        fnames.len(),
+        out_dir.display(),
+    );
+
+    if num_failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Handle the `--output`, `--output-dir`, `--peaks`, and `--summary` flags. Returns the exit code.
+pub fn run(args: &Args, config: &Config) -> i32 {
+    // The first argument is the program name, the others are files to open.
+    let fnames: Vec<PathBuf> = args.gtk_args.iter().skip(1).map(PathBuf::from).collect();
+    if fnames.is_empty() {
+        eprintln!("No input file given.");
+        return 1;
+    }
+
+    // The peaks go to a single csv file, so they can only be of a single file.
+    if args.peaks.is_some() && (fnames.len() > 1 || args.output_dir.is_some()) {
+        eprintln!("--peaks writes the peaks of one file, it takes a single input file.");
+        return 1;
+    }
+
+    if let Some(out_dir) = &args.output_dir {
+        return run_batch(args, config, &fnames, out_dir);
+    }
+
+    let mut exit_code = 0;
+
+    for (i, fname) in fnames.iter().enumerate() {
+        let mut headless = Headless::new(config);
+        if !headless.open(fname, args) {
+            exit_code = 1;
+            continue;
+        }
+
+        if args.summary {
+            print_summary(fname, &headless.model);
+        }
+
+        // There is only one output file, it shows the first input file.
+        let bitmap = headless.bitmap.borrow_mut().take();
+        if let (0, Some(out_fname), Some(bitmap)) = (i, &args.output, bitmap) {
+            if !save_png(bitmap, out_fname) {
+                exit_code = 1;
+            }
+        }
+
+        if let Some(peaks_fname) = &args.peaks {
+            if let Err(err) = headless.model.save_peaks(peaks_fname) {
+                eprintln!("Failed to write {}: {}", peaks_fname.display(), err);
+                exit_code = 1;
+            }
+        }
+    }
+
+    exit_code
+}
+
+#[test]
+fn run_rejects_peaks_of_multiple_files() {
+    let peaks_fname = std::env::temp_dir().join("audiograter-multiple-peaks.csv");
+    for extra_args in [
+        &["a.flac", "b.flac"][..],
+        &["--output-dir", "thumbs", "a.flac"],
+    ] {
+        let mut argv = vec!["audiograter", "--peaks", peaks_fname.to_str().unwrap()];
+        argv.extend_from_slice(extra_args);
+        let args = crate::args::parse(argv.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(run(&args, &Config::default()), 1);
+        assert!(!peaks_fname.exists());
+        assert!(!Path::new("thumbs").exists());
+    }
+}
+
+#[test]
+fn output_fnames_replace_extension() {
+    let out_dir = Path::new("thumbs");
+    let fnames = [PathBuf::from("music/a.flac"), PathBuf::from("b.1.wv")];
+    assert_eq!(
+        output_fnames(out_dir, &fnames),
+        [Path::new("thumbs/a.png"), Path::new("thumbs/b.1.png")]
+    );
+}
+
+#[test]
+fn output_fnames_do_not_collide() {
+    let out_dir = Path::new("thumbs");
+    let fnames = [
+        PathBuf::from("a.flac"),
+        PathBuf::from("a.wav"),
+        PathBuf::from("live/a.flac"),
+        PathBuf::from("a.flac-2.flac"),
+        PathBuf::from("b.flac"),
+    ];
+    assert_eq!(
+        output_fnames(out_dir, &fnames),
+        [
+            Path::new("thumbs/a.flac.png"),
+            Path::new("thumbs/a.wav.png"),
+            Path::new("thumbs/a.flac-2.png"),
+            Path::new("thumbs/a.flac-2-2.png"),
+            Path::new("thumbs/b.png"),
+        ]
+    );
+}
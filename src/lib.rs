@@ -0,0 +1,15 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// The library contains the parts of Audiograter that do not depend on GTK,
+// so they can be used and tested outside of the application. The exception is
+// the `gui` feature, which adds conversion of rendered bitmaps to pixbufs.
+
+pub mod cqt;
+pub mod dft;
+pub mod render;
+pub mod stft;
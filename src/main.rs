@@ -5,29 +5,61 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
-mod dft;
-
-use std::cell::RefCell;
+mod analysis;
+mod archive;
+mod args;
+mod config;
+mod cue;
+mod external;
+mod headless;
+mod source;
+mod wav;
+
+#[cfg(test)]
+mod test_flac;
+
+use std::cell::{Ref, RefCell};
 use std::ffi::OsStr;
 use std::fs;
 use std::i32;
-use std::path::PathBuf;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::slice;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use gio::prelude::*;
 use gtk::prelude::*;
 
-/// The number of samples in a single DFT window.
+use audiograter::cqt;
+use audiograter::cqt::ConstantQ;
+use audiograter::dft;
+use audiograter::dft::{FftPlan, WindowFn};
+use audiograter::render;
+use audiograter::render::{Bitmap, ColorScheme, Colormap, DEFAULT_Y_BLEND, UNDECODED};
+use audiograter::render::{YScale, DEFAULT_CEILING_DB, DEFAULT_FLOOR_DB};
+use audiograter::stft::Stft;
+
+use crate::args::ChannelMode;
+use crate::config::Config;
+use crate::source::{FlacSource, Mp3Source, SampleSource};
+
+/// The default number of samples in a single DFT window, see `Model::window_len`.
 const WINDOW_LEN: usize = 8192;
-const SPECTRUM_LEN: usize = WINDOW_LEN / 2;
 
-/// The number of samples between two DFT windows.
+/// The default number of samples between two DFT windows.
 ///
-/// This is smaller than `WINDOW_LEN`, which means that windows overlap.
+/// This is smaller than `WINDOW_LEN`, which means that windows overlap. For
+/// long files, the model can pick a larger hop, see `Model::window_off`.
 const WINDOW_OFF: usize = 4096;
 
+/// The window lengths that `l` cycles through.
+const WINDOW_LENS: [usize; 5] = [1024, 2048, 4096, 8192, 16384];
+
 /// The length of a tick on the axis, in display pixels.
 const TICK_SIZE: f64 = 5.0;
 
@@ -37,104 +69,241 @@ const TICK_PADDING: f64 = 5.0;
 /// The width of the border around the graph.
 const BORDER_WIDTH: f64 = 1.0;
 
-/// Given t in [0, 1], return an RGB value in [0, 1]^3.
-pub fn colormap_magma(t: f32) -> (f32, f32, f32) {
-    // Based on https://www.shadertoy.com/view/WlfXRN (licensed CC0), which in
-    // turn is a fit of https://github.com/BIDS/colormap/blob/master/colormaps.py,
-    // which is also licensed CC0.
-
-    let c: [[f32; 3]; 7] = [
-        [18.65570506591883, -11.48977351997711, -5.601961508734096],
-        [-50.76852536473588, 29.04658282127291, 4.23415299384598],
-        [52.17613981234068, -27.94360607168351, 12.94416944238394],
-        [-27.66873308576866, 14.26473078096533, -13.64921318813922],
-        [8.353717279216625, -3.577719514958484, 0.3144679030132573],
-        [0.2516605407371642, 0.6775232436837668, 2.494026599312351],
-        [
-            -0.002136485053939582,
-            -0.000749655052795221,
-            -0.005386127855323933,
-        ],
-    ];
+/// The frequency of A4 in Hz, that the note grid is tuned to.
+const A4_HZ: f64 = 440.0;
 
-    let mut result = c[0];
+/// The minimum distance between lines of the note grid, in display pixels.
+///
+/// Notes that are closer together than this are not distinguishable, we skip those.
+const MIN_NOTE_SPACING: f64 = 4.0;
 
-    // LLVM, if you can hear this comment, please unroll and vectorize.
-    for j in 1..7 {
-        for i in 0..3 {
-            result[i] = result[i].mul_add(t, c[j][i]);
-        }
-    }
+/// A typical axis label, we reserve at least enough space to fit it.
+const REFERENCE_LABEL: &str = "00.0 kHz";
 
-    (result[0], result[1], result[2])
-}
+/// The power level at the left edge of the histogram view, in dB.
+const HISTOGRAM_MIN_DB: f64 = -60.0;
 
-/// Map the unit interval to the range `(min_y, max_y)`.
-///
-/// The scale is logarithmic near `min_y`, and linear near `max_y`. This way, we
-/// can meaningfully distinguish tones in the low frequencies, and it makes
-/// sense for music, where a doubling of the frequency corresponds to an one
-/// octrave increase in pitch.
+/// The power level at the right edge of the histogram view, in dB.
+const HISTOGRAM_MAX_DB: f64 = 40.0;
+
+/// The width of the colorbar to the right of the graph, in display pixels.
+const COLORBAR_WIDTH: f64 = 12.0;
+
+/// The space between the graph and the colorbar, in display pixels.
+const COLORBAR_PADDING: f64 = 12.0;
+
+/// How far the mouse must move while pressed before it counts as a drag, in display pixels.
+const DRAG_THRESHOLD: f64 = 3.0;
+
+/// How much one step of the scroll wheel zooms in or out on the time axis.
+const SCROLL_ZOOM_FACTOR: f64 = 1.25;
+
+/// How long a status message stays in the header bar.
+const STATUS_DURATION: Duration = Duration::from_secs(2);
+
+/// Steps of a slider closer together than this belong to the same drag, for undo.
+const SLIDER_DRAG_GAP: Duration = Duration::from_secs(1);
+
+/// The horizontal scale in fixed-scale mode, in device pixels per second.
+const FIXED_SCALE_PIXELS_PER_SECOND: f64 = 100.0;
+
+/// How many windows on either side we average the noise floor over, for the adaptive floor.
+const FLOOR_RADIUS: usize = 16;
+
+/// The fraction of the power below the spectral rolloff frequency.
+const ROLLOFF_FRACTION: f32 = 0.85;
+
+/// How many bins on either side we sum the cross-spectrum over, for the phase coherence.
+const COHERENCE_RADIUS: usize = 4;
+
+/// The frequency of the lowest bin of the constant-Q transform, C1, in Hz.
+const CONSTANT_Q_MIN_HZ: f64 = 32.703;
+
+/// The number of bins per octave of the constant-Q transform, two per semitone.
+const CONSTANT_Q_BINS_PER_OCTAVE: usize = 24;
+
+/// The number of strongest peaks per window that we export, see `Model::write_peaks`.
+const PEAKS_PER_WINDOW: usize = 16;
+
+/// The fraction of the graph height for the strip with DC and sub-audio content, when shown.
+const DC_STRIP_HEIGHT: f64 = 0.15;
+
+/// The frequency at the top of the strip with DC and sub-audio content.
+const DC_STRIP_HZ: f64 = 20.0;
+
+/// Replace a non-finite sample with silence.
 ///
-/// Yet, at the high end of the spectrum, I want to be able to see if anything
-/// was cut off around 18 kHz to see if lossy compression was involved, and in
-/// general, to look at patterns in the frequency spectrum that would be
-/// squashed severely on a log scale.
+/// A single NaN would spread through the DFT to every bin of every window
+/// that contains it, so we stop it at the door.
 #[inline]
-pub fn map_y_axis(y: f64, min_y: f64, max_y: f64) -> f64 {
-    let log_min_y = min_y.log2();
-    let log_max_y = max_y.log2();
-    let y_log = (log_min_y + y * (log_max_y - log_min_y)).exp2();
-    let y_lin = min_y + y * (max_y - min_y);
-    y_lin * y + y_log * (1.0 - y)
+fn sanitize_sample(x: f32) -> f32 {
+    if x.is_finite() {
+        x
+    } else {
+        0.0
+    }
 }
 
-/// Thread-safe bitmap that we can fill on one thread and display on another.
-struct Bitmap {
-    data: Vec<u8>,
-    width: i32,
-    height: i32,
+/// Return the samples to analyze for the channel mode, if the file has them.
+///
+/// For a single channel these are the decoded samples of that channel, for
+/// the other modes they are `mixed`, which the model derives as it decodes.
+fn select_samples<'a>(
+    channels: &'a [Vec<f32>],
+    mixed: &'a [f32],
+    mode: ChannelMode,
+) -> Option<&'a [f32]> {
+    match mode {
+        ChannelMode::Channel(ch) => channels.get(ch).map(|samples| &samples[..]),
+        _ if channels.is_empty() => None,
+        _ => Some(mixed),
+    }
 }
 
-impl Bitmap {
-    pub fn generate<F: Fn(i32, i32) -> f32>(width: i32, height: i32, f: F) -> Bitmap {
-        let len = width * height * 3;
-        let mut data = Vec::with_capacity(len as usize);
+/// Format a time in seconds for display, as minutes and seconds.
+fn format_time(t_sec: f64) -> String {
+    // Round before we split, so 59.96 s does not round up to 0:60.0.
+    let tenths = (t_sec * 10.0).round();
+    let min = (tenths / 600.0).floor();
+    let sec = (tenths - min * 600.0) / 10.0;
+    format!("{:.0}:{:04.1}", min, sec)
+}
 
-        for y in 0..height {
-            for x in 0..width {
-                let t = f(x, y);
-                let (r, g, b) = colormap_magma(t);
-                data.push((r.min(1.0).max(0.0) * 255.0) as u8);
-                data.push((g.min(1.0).max(0.0) * 255.0) as u8);
-                data.push((b.min(1.0).max(0.0) * 255.0) as u8);
-            }
-        }
-
-        Bitmap {
-            data,
-            width,
-            height,
-        }
-    }
-
-    pub fn into_pixbuf(self) -> gdk_pixbuf::Pixbuf {
-        let has_alpha = false;
-        let bits_per_sample = 8;
-        let row_stride = 3 * self.width;
-        gdk_pixbuf::Pixbuf::from_mut_slice(
-            self.data,
-            gdk_pixbuf::Colorspace::Rgb,
-            has_alpha,
-            bits_per_sample,
-            self.width,
-            self.height,
-            row_stride,
-        )
+/// Format a time in whole seconds as m:ss, the way the time axis labels it.
+fn format_duration(t_sec: u64) -> String {
+    format!("{}:{:02}", t_sec / 60, t_sec % 60)
+}
+
+/// Describe the format of a file for the header bar, for example "44100 Hz, 16-bit, stereo, 3:25".
+fn format_metadata(
+    sample_rate: u32,
+    bits_per_sample: u32,
+    channels: u32,
+    duration_seconds: Option<f64>,
+) -> String {
+    let channels = match channels {
+        1 => "mono".to_string(),
+        2 => "stereo".to_string(),
+        n => format!("{} channels", n),
+    };
+    let mut result = format!("{} Hz, {}-bit, {}", sample_rate, bits_per_sample, channels);
+    if let Some(t_sec) = duration_seconds {
+        result.push_str(", ");
+        result.push_str(&format_duration(t_sec.round() as u64));
+    }
+    result
+}
+
+/// Round the spacing of time ticks in whole seconds up to a duration that formats as a round mm:ss.
+fn quantize_tick_seconds(seconds: u64) -> u64 {
+    match seconds {
+        0 => 1,
+        1..=5 => 5,
+        6..=10 => 10,
+        11..=15 => 15,
+        16..=30 => 30,
+        31..=60 => 60,
+        61..=90 => 90,
+        91..=120 => 120,
+        121..=300 => 300,
+        301..=600 => 600,
+        601..=900 => 900,
+        _ => 1200,
+    }
+}
+
+/// Format a time in seconds since midnight as a wall-clock time.
+fn format_time_of_day(t_sec: u64) -> String {
+    let t_sec = t_sec % (24 * 3600);
+    format!(
+        "{:02}:{:02}:{:02}",
+        t_sec / 3600,
+        (t_sec / 60) % 60,
+        t_sec % 60
+    )
+}
+
+/// Format the number of things per second for display, with a k or M suffix for large rates.
+fn format_rate(count: u64, elapsed: Duration) -> String {
+    let rate = count as f64 / elapsed.as_secs_f64().max(1e-9);
+    match () {
+        () if rate >= 1e6 => format!("{:.1}M", rate * 1e-6),
+        () if rate >= 1e3 => format!("{:.1}k", rate * 1e-3),
+        () => format!("{:.0}", rate),
+    }
+}
+
+/// Format a frequency for display, in Hz or kHz depending on the magnitude.
+fn format_hz(hz: f64) -> String {
+    match () {
+        () if hz > 1000.0 => format!("{:.2} kHz", hz / 1000.0),
+        _ => format!("{:.0} Hz", hz),
+    }
+}
+
+/// Return the frequency of the note with the MIDI note number, in equal temperament.
+fn note_hz(note: i32) -> f64 {
+    A4_HZ * 2.0_f64.powf((note - 69) as f64 / 12.0)
+}
+
+/// Return the name of the note with the MIDI note number, for example "C4" for 60.
+fn note_name(note: i32) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C♯", "D", "D♯", "E", "F", "F♯", "G", "G♯", "A", "A♯", "B",
+    ];
+    let octave = note.div_euclid(12) - 1;
+    format!("{}{}", NAMES[note.rem_euclid(12) as usize], octave)
+}
+
+/// Parse a frequency as the user types it, in Hz, or in kHz with a `k` or `kHz` suffix.
+fn parse_hz(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let text = text
+        .strip_suffix("Hz")
+        .or(text.strip_suffix("hz"))
+        .unwrap_or(text)
+        .trim_end();
+    let (number, scale) = match text.strip_suffix('k') {
+        Some(number) => (number, 1000.0),
+        None => (text, 1.0),
+    };
+    match number.trim_end().parse::<f64>() {
+        Ok(hz) if hz.is_finite() => Some(hz * scale),
+        _ => None,
+    }
+}
+
+/// Mark the entry as invalid, with the message as tooltip, or clear the mark.
+fn set_entry_error(entry: &gtk::Entry, error: Option<&str>) {
+    let style = entry.style_context();
+    match error {
+        Some(_) => style.add_class("error"),
+        None => style.remove_class("error"),
+    }
+    entry.set_tooltip_text(error);
+}
+
+/// Return the name of the signal, for the channel picker.
+fn channel_mode_label(mode: ChannelMode) -> String {
+    match mode {
+        ChannelMode::Channel(ch) => format!("Channel {}", ch + 1),
+        ChannelMode::Mix => "Mix of all".to_string(),
+        ChannelMode::Mid => "Mid".to_string(),
+        ChannelMode::Side => "Side".to_string(),
+    }
+}
+
+/// Return the name of the window function, for the legend.
+fn window_fn_label(window_fn: WindowFn) -> &'static str {
+    match window_fn {
+        WindowFn::Hann => "Hann",
+        WindowFn::Hamming => "Hamming",
+        WindowFn::Blackman => "Blackman",
     }
 }
 
 /// An axis tick label on the spectrogram.
+#[derive(Debug, PartialEq)]
 struct Tick {
     /// Tick position, where 0.0 is bottom/left and 1.0 is top/right.
     position: f64,
@@ -143,6 +312,153 @@ struct Tick {
     label: String,
 }
 
+/// Return the tick position nearest to `x`, or the edge of the graph if that is nearer.
+fn snap_to_ticks(x: f64, ticks: &[Tick]) -> f64 {
+    ticks
+        .iter()
+        .map(|tick| tick.position)
+        .chain([0.0, 1.0])
+        .min_by(|a, b| (a - x).abs().total_cmp(&(b - x).abs()))
+        .unwrap()
+}
+
+/// Accumulated statistics of the samples that fall in one pixel column.
+#[derive(Copy, Clone, Default)]
+struct EnvelopeColumn {
+    /// Largest absolute value of the samples.
+    peak: f32,
+
+    /// Sum of the squares of the samples.
+    sum_squares: f32,
+
+    /// Number of samples.
+    count: u32,
+}
+
+/// The frequency bins that one row of the spectrogram covers.
+struct RowBins {
+    /// The fractional bin at the center of the row.
+    center: f64,
+
+    /// The first whole bin in the row.
+    lo: usize,
+
+    /// The last whole bin in the row, less than `lo` if there is none.
+    hi: usize,
+
+    /// The bandwidth that the row represents, relative to the average row.
+    ///
+    /// On a linear frequency axis this is 1.0 for every row. On the warped
+    /// axis, rows near the bottom cover a fraction of a bin, and rows near
+    /// the top cover many bins.
+    weight: f32,
+}
+
+/// The windows that intersect one column of the spectrogram.
+struct ColumnWindows {
+    /// The index of the first window that intersects the column.
+    begin: usize,
+
+    /// The weight of every window from `begin` on, they sum to 1.
+    ///
+    /// Empty when no window intersects the column.
+    weights: Vec<f32>,
+}
+
+/// The windows of every column, for one horizontal layout.
+///
+/// The windows that a column covers do not depend on the rows, so we keep
+/// them when only the height or the frequency axis changes.
+#[derive(Default)]
+struct ColumnPlan {
+    /// The number of columns, the time view, the window length, the hop, and the number of windows.
+    key: (i32, (f64, f64), usize, usize, usize),
+
+    columns: Vec<ColumnWindows>,
+}
+
+/// The brightness of every pixel that we last painted, before the colormap.
+struct PaintedValues {
+    size: (i32, i32),
+    row_step: usize,
+    values: Vec<f32>,
+}
+
+/// A point on the spectrogram that the user locked in place for measuring.
+struct Probe {
+    /// Tells the model which probe we mean, zero for the crosshair that follows the pointer.
+    id: u32,
+
+    /// Horizontal position, where 0.0 is the left and 1.0 the right of the graph.
+    x: f64,
+
+    /// Vertical position, where 0.0 is the bottom and 1.0 the top of the graph.
+    y: f64,
+
+    /// Time, frequency, and amplitude at the probe, as computed by the model.
+    label: String,
+}
+
+/// Move every probe to the position that the model computed, and give it its label.
+///
+/// The probes may have changed since the model computed the positions. Probes
+/// that it did not know about yet stay where they are until the next update.
+fn place_probes(probes: &mut [Probe], placed: Vec<(u32, (f64, f64), String)>) {
+    for (id, (x, y), label) in placed {
+        if let Some(probe) = probes.iter_mut().find(|p| p.id == id) {
+            probe.x = x;
+            probe.y = y;
+            probe.label = label;
+        }
+    }
+}
+
+/// A rectangle on the spectrogram that the user placed to measure the energy in it.
+struct Region {
+    /// Left, right, bottom, and top edge, in graph coordinates.
+    bounds: (f64, f64, f64, f64),
+
+    /// Name that the user can change, to tell regions apart in the list.
+    name: String,
+
+    /// Time range, frequency range, and energy, as computed by the model.
+    label: String,
+}
+
+/// The parameters of the analysis and the view that the user can adjust.
+///
+/// The model keeps a history of these, to undo and redo adjustments.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Settings {
+    /// The time range that the x-axis spans, in samples.
+    time_view: (f64, f64),
+    /// The frequency range that the y-axis spans at most, in Hz.
+    freq_view: (f64, f64),
+    window_len: usize,
+    window_off: usize,
+    window_fn: WindowFn,
+    zero_pad_factor: usize,
+    show_histogram: bool,
+    show_group_delay: bool,
+    show_coherence: bool,
+    show_constant_q: bool,
+    show_dc_strip: bool,
+    y_scale: YScale,
+    y_blend: f64,
+    /// The levels at the bottom and top of the colormap, in dB.
+    floor_db: f32,
+    ceiling_db: f32,
+    colormap: Colormap,
+    invert_colormap: bool,
+    encode_srgb: bool,
+    adaptive_floor: bool,
+    show_residual: bool,
+    auto_hop: bool,
+    equal_energy: bool,
+    row_step: usize,
+    oversample: usize,
+}
+
 /// Container for the application widgets.
 ///
 /// Although GTK widgets are already refcounted, the view itself is also kept in
@@ -158,21 +474,229 @@ struct View {
     /// Drawing area widget that draws the spectrogram and axes.
     image: gtk::DrawingArea,
 
+    /// Scrollbar below the spectrogram, visible when the view does not span the full file.
+    scrollbar: gtk::Scrollbar,
+
+    /// Progress bar in the header bar, visible while the file is being decoded.
+    progress_bar: gtk::ProgressBar,
+
+    /// The format of the loaded file, shown below the title when there is no status message.
+    metadata: Option<String>,
+
+    /// Slider for the blend between a log and linear frequency axis.
+    blend_scale: gtk::Scale,
+
+    /// Handler that tells the model when the user moves `blend_scale`.
+    blend_handler: glib::SignalHandlerId,
+
+    /// Sliders for the levels at the bottom and top of the colormap, in dB.
+    floor_scale: gtk::Scale,
+    ceiling_scale: gtk::Scale,
+
+    /// Handlers that tell the model when the user moves `floor_scale` or `ceiling_scale`.
+    level_handlers: Vec<glib::SignalHandlerId>,
+
+    /// Picker in the header bar that applies a saved preset.
+    preset_combo: gtk::ComboBoxText,
+
+    /// Picker in the header bar for the signal to analyze.
+    channel_combo: gtk::ComboBoxText,
+
+    /// The signal that every entry of `channel_combo` selects, in order.
+    channel_modes: Vec<ChannelMode>,
+
     /// Tick positions and labels for the x-axis.
     x_ticks: Vec<Tick>,
 
     /// Tick positions and labels for the y-axis.
     y_ticks: Vec<Tick>,
 
+    /// Laid out labels of `x_ticks`, in the same order.
+    x_layouts: Vec<pango::Layout>,
+
+    /// Laid out labels of `y_ticks`, in the same order.
+    y_layouts: Vec<pango::Layout>,
+
+    /// The colormap from bottom to top, as it maps the values of the spectrogram.
+    colorbar: Option<gdk_pixbuf::Pixbuf>,
+
+    /// Tick positions and labels for the colorbar, where 0.0 is the bottom.
+    colorbar_ticks: Vec<Tick>,
+
+    /// Laid out labels of `colorbar_ticks`, in the same order.
+    colorbar_layouts: Vec<pango::Layout>,
+
     /// Maximum width of y-tick labels in display pixels.
     label_width: i32,
 
-    /// Maximum height of x-tick labels in display pixels.
+    /// Maximum height of tick labels in display pixels.
     label_height: i32,
 
+    /// Maximum width of x-tick labels in display pixels.
+    x_label_width: i32,
+
+    /// Distance from the top of a label to the middle of its lowercase letters.
+    label_center: f64,
+
     /// The pixbuf with the rendered specrogram.
     pixbuf: Option<gdk_pixbuf::Pixbuf>,
 
+    /// Crosshairs placed by clicking on the graph.
+    probes: Vec<Probe>,
+
+    /// The id to give to the next probe that we place.
+    next_probe_id: u32,
+
+    /// The crosshair that follows the pointer while it hovers over the graph.
+    cursor: Option<Probe>,
+
+    /// The position on the colorbar under the pointer, where 0.0 is the bottom.
+    colorbar_hover: Option<f64>,
+
+    /// Where the left mouse button was pressed, in widget and graph coordinates.
+    press_position: Option<((f64, f64), (f64, f64))>,
+
+    /// The time range that is being selected by dragging, in graph coordinates.
+    selection: Option<(f64, f64)>,
+
+    /// Rectangles placed by dragging with Ctrl held, to measure the energy in.
+    regions: Vec<Region>,
+
+    /// The corners of the region that is being placed by dragging, in graph coordinates.
+    region_selection: Option<(f64, f64, f64, f64)>,
+
+    /// Side panel that lists the regions, only visible when there are any.
+    region_panel: gtk::Box,
+
+    /// The list of regions in the side panel, one row per region.
+    region_list: gtk::ListBox,
+
+    /// Entry to rename the region that is selected in the list.
+    region_name: gtk::Entry,
+
+    /// Spectral centroid over time, as a polyline in graph coordinates.
+    centroid_line: Vec<(f64, f64)>,
+
+    /// Spectral rolloff over time, as a polyline in graph coordinates.
+    rolloff_line: Vec<(f64, f64)>,
+
+    /// Whether to draw the spectral centroid line, toggled with `b`.
+    show_centroid: bool,
+
+    /// Whether to draw the spectral rolloff line, toggled with `r`.
+    show_rolloff: bool,
+
+    /// Spectral flux over time, in graph coordinates, normalized to [0, 1].
+    flux_line: Vec<(f64, f64)>,
+
+    /// Horizontal positions of detected onsets, in graph coordinates.
+    onsets: Vec<f64>,
+
+    /// Whether to draw the spectral flux strip and onsets, toggled with `f`.
+    show_flux: bool,
+
+    /// Spectral peaks of the previously loaded file, as position in graph coordinates and opacity.
+    compare_peaks: Vec<(f64, f64, f32)>,
+
+    /// Whether we asked the model for the peaks of the previous file, toggled with `k`.
+    show_compare_peaks: bool,
+
+    /// The Nyquist frequency and the apparent bandwidth, in graph coordinates.
+    nyquist_lines: Vec<Tick>,
+
+    /// Whether to draw the Nyquist and bandwidth lines, toggled with `q`.
+    show_nyquist: bool,
+
+    /// The notes in view from low to high, in graph coordinates. Only the Cs have a label.
+    note_lines: Vec<Tick>,
+
+    /// Whether to draw the note grid, toggled with `n`.
+    show_notes: bool,
+
+    /// Starts of the tracks in the cue sheet of the file, in graph coordinates.
+    track_markers: Vec<Tick>,
+
+    /// The frequency that the user typed in the header bar, in graph coordinates.
+    frequency_marker: Option<Tick>,
+
+    /// Entry in the header bar to type a frequency to mark.
+    frequency_entry: gtk::Entry,
+
+    /// A description of the analysis parameters, as reported by the model.
+    legend: String,
+
+    /// Whether to draw the legend in the corner of the graph, toggled with `i`.
+    show_legend: bool,
+
+    /// Whether to hide the axes and all overlays at once, toggled with `h`.
+    clean_view: bool,
+
+    /// Whether we asked the model for the level histogram, toggled with `d`.
+    ///
+    /// The histogram has no time axis, so we do not draw anything that is
+    /// positioned in time over it, and clicks do not place probes.
+    show_histogram: bool,
+
+    /// The window length that we asked the model to use, cycled with `l`.
+    window_len: usize,
+
+    /// The hop between windows that we asked the model to use, cycled with `L`.
+    window_off: usize,
+
+    /// The window function that we asked the model to use, cycled with `W`.
+    window_fn: WindowFn,
+
+    /// The zero-padding factor that we asked the model to use, cycled with `z`.
+    zero_pad_factor: usize,
+
+    /// Whether we asked the model to paint the group delay, toggled with `g`.
+    show_group_delay: bool,
+
+    /// Whether we asked the model to paint the phase coherence, toggled with `p`.
+    show_coherence: bool,
+
+    /// Whether we asked the model to paint the constant-Q transform, toggled with `Q`.
+    show_constant_q: bool,
+
+    /// Whether we asked the model for a linear strip from DC to 20 Hz, toggled with `D`.
+    show_dc_strip: bool,
+
+    /// The frequency scale that we asked the model for, cycled with `y`.
+    y_scale: YScale,
+
+    /// The number of pixel rows per row that the model samples, cycled with `v`.
+    row_step: usize,
+
+    /// The number of columns that the model samples per pixel column, cycled with `O`.
+    oversample: usize,
+
+    /// The colormap that we asked the model to paint with, cycled with `c`.
+    colormap: Colormap,
+
+    /// Whether we asked the model to invert the colormap, toggled with `C`.
+    invert_colormap: bool,
+
+    /// Whether we asked the model to sRGB-encode the colormap, toggled with `S`.
+    encode_srgb: bool,
+
+    /// Whether we asked the model for an adaptive floor, toggled with `a`.
+    adaptive_floor: bool,
+
+    /// Whether we asked the model for the residual over the average spectrum, toggled with `e`.
+    show_residual: bool,
+
+    /// Whether we asked the model to pick the hop for the file length, toggled with `o`.
+    auto_hop: bool,
+
+    /// Whether we asked the model to weigh rows by their bandwidth, toggled with `u`.
+    equal_energy: bool,
+
+    /// Peak and RMS amplitude of the waveform per pixel column.
+    envelope: Vec<(f32, f32)>,
+
+    /// Whether to draw the waveform envelope over the spectrogram, toggled with `w`.
+    show_envelope: bool,
+
     /// Sender to send events to the model.
     sender: mpsc::SyncSender<ModelEvent>,
 }
@@ -180,12 +704,184 @@ struct View {
 enum ViewEvent {
     SetTitle(String),
     SetView(Bitmap),
+    /// The colorbar, a bitmap one pixel wide, and its ticks.
+    SetColorbar(Bitmap, Vec<Tick>),
     SetTicks(Vec<Tick>, Vec<Tick>),
+    /// Id, position in graph coordinates, and readout of every probe.
+    SetProbes(Vec<(u32, (f64, f64), String)>),
+    /// Readout for the crosshair that follows the pointer.
+    SetCursorLabel(String),
+    /// Put the frequency in Hz on the clipboard.
+    CopyFrequency(f64),
+    /// Tell the user that something failed, such as opening or decoding a file.
+    ShowError(String),
+    /// The fraction of the file that we transformed, 1.0 once decoding is complete.
+    ///
+    /// NaN when we are decoding a file of which we do not know the length.
+    SetProgress(f64),
+    /// Forget the spectrogram and everything drawn over it, there is no file loaded.
+    ClearView,
+    /// Bounds in graph coordinates and readouts of the regions, in the order that they were placed.
+    SetRegions(Vec<((f64, f64, f64, f64), String)>),
+    /// Positions and labels of the Nyquist frequency and the bandwidth.
+    SetNyquistLines(Vec<Tick>),
+    /// Positions of the notes in view, and names of the Cs.
+    SetNoteLines(Vec<Tick>),
+    /// Positions and labels of the track starts in view, from the cue sheet.
+    SetTrackMarkers(Vec<Tick>),
+    /// Position and label of the frequency marker, or why the frequency is not on the axis.
+    SetFrequencyMarker(Option<Result<Tick, String>>),
+    /// A description of the analysis parameters, one per line.
+    SetLegend(String),
+    /// Spectral centroid and rolloff polylines, in graph coordinates.
+    SetFeatureLines(Vec<(f64, f64)>, Vec<(f64, f64)>),
+    /// Peak and RMS amplitude of the waveform per pixel column.
+    SetEnvelope(Vec<(f32, f32)>),
+    /// Spectral flux polyline in graph coordinates, and positions of onsets.
+    SetFlux(Vec<(f64, f64)>, Vec<f64>),
+    /// Peaks of the previous file in graph coordinates, with their opacity.
+    SetComparePeaks(Vec<(f64, f64, f32)>),
+    /// Ask the user which of the audio files in the archive to open.
+    ChooseArchiveEntry(PathBuf, Vec<String>),
+    /// The settings that the model restored on undo or redo.
+    SetSettings(Settings),
+    /// Begin and end of the time range in view, as fractions of the duration.
+    SetScrollPosition(f64, f64),
+    /// The names of the saved presets, and the one to select in the picker, if any.
+    SetPresetNames(Vec<String>, Option<String>),
+    /// The blend of the frequency axis that a preset applied.
+    SetYBlend(f64),
+    /// The levels at the bottom and top of the colormap that a preset applied, in dB.
+    SetLevelRange(f32, f32),
+    /// The signal that we analyze, to select in the channel picker.
+    SetChannelMode(ChannelMode),
+    /// The number of channels in the loaded file, to list in the channel picker.
+    SetNumChannels(usize),
+    /// The format of the loaded file, and its duration if we know it already.
+    SetMetadata {
+        sample_rate: u32,
+        bits_per_sample: u32,
+        channels: u32,
+        duration_seconds: Option<f64>,
+    },
+}
+
+/// The number of samples per channel to decode per batch.
+///
+/// This is about as many samples as 100 typical FLAC blocks have.
+const DECODE_BATCH_LEN: usize = 100 * 4096;
+
+/// The state of a loaded file that is not the one in view.
+///
+/// We keep it, so that swapping back to it does not need to decode and
+/// transform it again. See the fields of `Model` for what these mean.
+struct LoadedFile {
+    title: String,
+    path: Option<PathBuf>,
+    tracks: Vec<cue::CueTrack>,
+    reader: Option<Box<dyn SampleSource>>,
+    duration: Option<u64>,
+    time_view: (f64, f64),
+    start_time: Option<u32>,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    num_channels: u32,
+    decoded_channels: Vec<Vec<f32>>,
+    spectrum: Vec<Box<[f32]>>,
+    /// The zero-padding factor that `spectrum` was computed with.
+    zero_pad_factor: usize,
+    /// The window length that `spectrum` was computed with.
+    window_len: usize,
+    /// The window function that `spectrum` was computed with.
+    window_fn: WindowFn,
+    /// The channel mode that `spectrum` was computed with.
+    channel_mode: ChannelMode,
+    window_off: usize,
+    group_delays: Vec<Box<[f32]>>,
+    coherences: Vec<Box<[f32]>>,
+    constant_q: Vec<Box<[f32]>>,
+    centroids: Vec<f32>,
+    rolloffs: Vec<f32>,
+    fluxes: Vec<f32>,
+    floors: Vec<f32>,
+    mean_spectrum: Vec<f32>,
+    bandwidth: Option<f32>,
+    undo_stack: Vec<Settings>,
+    redo_stack: Vec<Settings>,
+}
+
+/// Time spent on the loaded file, accumulated over the batches, for `--profile`.
+#[derive(Default)]
+struct Profile {
+    decode_time: Duration,
+    /// The number of samples per channel that we decoded.
+    decoded_samples: u64,
+    transform_time: Duration,
+    /// The number of windows that we transformed, including transforms after changing settings.
+    transformed_windows: u64,
+}
+
+impl Profile {
+    /// Describe the decode throughput, for a file with the given sample rate.
+    fn decode_report(&self, sample_rate: u32) -> String {
+        let audio_seconds = self.decoded_samples as f64 / sample_rate.max(1) as f64;
+        let decode_seconds = self.decode_time.as_secs_f64().max(1e-9);
+        format!(
+            "{} samples/s, {:.0}× real time",
+            format_rate(self.decoded_samples, self.decode_time),
+            audio_seconds / decode_seconds,
+        )
+    }
+
+    /// Describe the transform throughput.
+    fn transform_report(&self) -> String {
+        format!(
+            "{} windows/s",
+            format_rate(self.transformed_windows, self.transform_time)
+        )
+    }
 }
 
 struct Model {
-    /// The currently loaded file.
-    flac_reader: Option<claxon::FlacReader<fs::File>>,
+    /// The decoder of the loaded file, until we have decoded all of it.
+    reader: Option<Box<dyn SampleSource>>,
+
+    /// Incremented whenever `reader` is replaced.
+    ///
+    /// A decode event is queued for the reader that was current when it was
+    /// posted. When the user opens a new file in the meantime, that event is
+    /// stale, and handling it would start a second chain of decode events.
+    decode_generation: u64,
+
+    /// The title of the loaded file, to show in the UI.
+    title: String,
+
+    /// The path that the loaded file was read from, if it is a file on disk.
+    ///
+    /// We keep it to reload the file when it changed on disk. Files from an
+    /// archive or from memory have no path, they cannot change.
+    path: Option<PathBuf>,
+
+    /// The file that was loaded before the current one, if any.
+    previous_file: Option<Box<LoadedFile>>,
+
+    /// Whether to send the spectral peaks of the previous file, to overlay on this one.
+    compare_peaks: bool,
+
+    /// Throughput of decoding and transforming the loaded file, if we measure it.
+    profile: Option<Profile>,
+
+    /// The config file, to apply once the first resize tells us the size of the graph.
+    pending_config: Option<Config>,
+
+    /// The tracks from the cue sheet next to the loaded file, if there is one.
+    tracks: Vec<cue::CueTrack>,
+
+    /// The frequency in Hz that the user asked to mark, if any.
+    ///
+    /// We keep it when opening another file, even if it is then past the
+    /// Nyquist frequency, so it comes back for the next file that has it.
+    frequency_marker: Option<f64>,
 
     /// The target size of the spectogram bitmap, in device pixels.
     target_size: (i32, i32),
@@ -196,64 +892,574 @@ struct Model {
     /// The duration of the loaded file, in samples.
     duration: Option<u64>,
 
+    /// The time range that the x-axis spans, in samples.
+    time_view: (f64, f64),
+
+    /// The frequency range that the y-axis spans at most, in Hz.
+    ///
+    /// This only narrows the range that the file has, see `hz_range`. When
+    /// the user did not zoom in, it is unbounded, so it spans every file.
+    freq_view: (f64, f64),
+
+    /// Whether the x-axis has a fixed number of pixels per second.
+    ///
+    /// When it does, a long file does not fit in view, and the user scrolls
+    /// through it. Otherwise the time range is the full file, or what the
+    /// user zoomed into.
+    fixed_scale: bool,
+
+    /// Wall-clock time at the start of the loaded file, in seconds since midnight.
+    ///
+    /// When this is known, the x-axis shows wall-clock time, rather than
+    /// the time since the start of the file.
+    start_time: Option<u32>,
+
+    /// Wall-clock time at the start of every file, if the user specified it.
+    ///
+    /// This takes precedence over the time in the metadata of the file.
+    start_time_override: Option<u32>,
+
     /// The sample rate of the loaded file, in Hz.
-    /// The value is only meaningful when `flac_reader` is not `None`.
+    /// The value is only meaningful when a file is loaded.
     sample_rate: u32,
 
-    /// Decoded samples that we still need to take the DFT of.
-    samples: Vec<f32>,
+    /// The bit depth and number of channels of the loaded file, according to its header.
+    bits_per_sample: u32,
+    num_channels: u32,
+
+    /// Decoded samples of the loaded file, one buffer per channel.
+    ///
+    /// We keep the full decoded signal, rather than only the samples that we
+    /// still need to take the DFT of, so that changing analysis parameters
+    /// such as the channel, the window length, or the hop does not require
+    /// decoding the file again. This costs 4 bytes per sample per channel,
+    /// for an hour of 48 kHz stereo that is 1.4 GB. We favor a correct
+    /// recomputation over the memory.
+    decoded_channels: Vec<Vec<f32>>,
+
+    /// The channel, or combination of channels, that we compute the spectrum of.
+    channel_mode: ChannelMode,
+
+    /// The combination of the channels for the channel mode, unless it is a single channel.
+    ///
+    /// We extend this as we decode, it has the same length as the channels.
+    /// Like those, we keep all of it, which costs another 4 bytes per sample.
+    mixed_samples: Vec<f32>,
+
+    /// Statistics of the analyzed samples per pixel column.
+    envelope: Vec<EnvelopeColumn>,
+
+    /// The number of analyzed samples that `envelope` includes.
+    envelope_len: usize,
 
     /// DFTs of windows of the decoded samples.
     spectrum: Vec<Box<[f32]>>,
 
-    /// A sender to send messages to the UI.
-    sender: glib::SyncSender<ViewEvent>,
+    /// The blend between a log and linear frequency axis, see `map_y_axis`.
+    y_blend: f64,
 
-    /// A sender to send messages to this model.
-    self_sender: mpsc::SyncSender<ModelEvent>,
-}
+    /// The level at the bottom of the colormap, in dB relative to full scale, see `level_db`.
+    floor_db: f32,
 
-enum ModelEvent {
-    OpenFile(PathBuf),
-    /// Width, height, tick label width, tick label height (in device pixels).
-    Resize(i32, i32, i32, i32),
-    Decode,
-}
+    /// The level at the top of the colormap, in dB relative to full scale.
+    ceiling_db: f32,
 
-impl View {
-    fn new(
-        application: &gtk::Application,
-        sender: mpsc::SyncSender<ModelEvent>,
-    ) -> Rc<RefCell<View>> {
-        let window = gtk::ApplicationWindow::new(application);
+    /// Whether to show DC and sub-audio content in a linear strip at the bottom.
+    ///
+    /// Without it, the axis starts at the lowest bin above DC. With it, the
+    /// bottom `DC_STRIP_HEIGHT` of the graph spans 0 to `DC_STRIP_HZ`
+    /// linearly, including bin 0, and the usual axis starts above the strip.
+    show_dc_strip: bool,
 
-        window.set_title("Audiograter");
-        window.set_border_width(10);
-        window.set_position(gtk::WindowPosition::Center);
-        window.set_default_size(640, 480);
+    /// How to space frequencies on the axis. Only `Blended` uses `y_blend`.
+    y_scale: YScale,
 
-        let header_bar = gtk::HeaderBar::new();
-        header_bar.set_show_close_button(true);
-        header_bar.set_title(Some("Audiograter"));
-        window.set_titlebar(Some(&header_bar));
+    /// The number of samples in a single DFT window.
+    ///
+    /// A longer window resolves frequencies more finely, at the cost of
+    /// smearing events in time.
+    window_len: usize,
 
-        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
-        window.add(&vbox);
+    /// The number of samples between two windows, as the user chose it.
+    base_window_off: usize,
 
-        let image = gtk::DrawingArea::new();
+    /// The window function to multiply every window with before the DFT.
+    window_fn: WindowFn,
 
-        let expand = true;
-        let fill = true;
-        let padding = 0;
-        vbox.pack_start(&image, expand, fill, padding);
+    /// The number of samples between two windows in `spectrum`.
+    ///
+    /// This is `base_window_off`, unless `auto_hop` is set. Then for a long
+    /// file, we skip windows that would fall in the same pixel column anyway.
+    window_off: usize,
 
-        // Accept single strings for dropping. We could accept "text/uri-list" too,
-        // but the application cannot handle more than one file at a time anyway.
-        const DRAG_EVENT_INFO: u32 = 0;
-        let drag_targets = [
-            gtk::TargetEntry::new("text/plain", gtk::TargetFlags::OTHER_APP, DRAG_EVENT_INFO),
-            gtk::TargetEntry::new(
-                "text/uri-list",
+    /// Whether to pick `window_off` based on the length of the file, see `choose_window_off`.
+    auto_hop: bool,
+
+    /// How many times `window_len` we zero-pad every window to before the DFT.
+    ///
+    /// This interpolates the spectrum, so it looks smoother, but it does not
+    /// improve the frequency resolution.
+    zero_pad_factor: usize,
+
+    /// The plan for transforms of `window_len * zero_pad_factor` samples.
+    ///
+    /// We keep it across batches, so we compute its twiddle factors only when
+    /// the window length or zero-padding changes.
+    fft_plan: FftPlan,
+
+    /// Id, time in samples, and frequency in Hz of the probes to report the amplitude at.
+    probes: Vec<(u32, f64, f64)>,
+
+    /// Position of the pointer over the graph, in graph coordinates.
+    cursor: Option<(f64, f64)>,
+
+    /// Begin and end in samples, and lowest and highest frequency in Hz, of
+    /// the regions to report the energy in.
+    regions: Vec<(f64, f64, f64, f64)>,
+
+    /// Spectral centroid in Hz of every window in `spectrum`.
+    centroids: Vec<f32>,
+
+    /// Spectral rolloff in Hz of every window in `spectrum`.
+    rolloffs: Vec<f32>,
+
+    /// Spectral flux from the previous window, for every window in `spectrum`.
+    fluxes: Vec<f32>,
+
+    /// Estimated noise floor of every window in `spectrum`, as a power.
+    floors: Vec<f32>,
+
+    /// Whether to paint the power relative to a slowly varying floor, rather than absolute.
+    ///
+    /// This reveals detail in quiet sections and in sections with a high noise
+    /// floor alike, but the colors no longer correspond to a level.
+    adaptive_floor: bool,
+
+    /// The noise floor of every window, smoothed over neighboring windows.
+    ///
+    /// We only compute this while `adaptive_floor` is set.
+    smoothed_floors: Vec<f32>,
+
+    /// The power of every bin, averaged over all windows of the file.
+    ///
+    /// We compute this when decoding is complete, until then it is empty.
+    mean_spectrum: Vec<f32>,
+
+    /// Whether to paint the power in excess of `mean_spectrum`, rather than the power.
+    ///
+    /// In a steady recording, this makes transients and anomalies stand out
+    /// against a dark background.
+    show_residual: bool,
+
+    /// Whether to weigh the power in every row by the bandwidth that the row represents.
+    ///
+    /// Bins are spaced equally in Hz, but the frequency axis is warped, so
+    /// a row near the top covers more of the spectrum than one near the
+    /// bottom. With the weighting, the brightness of a row reflects the energy
+    /// in it, rather than the power per bin.
+    equal_energy: bool,
+
+    /// Whether to paint the distribution of power levels per frequency, instead of the spectrogram.
+    show_histogram: bool,
+
+    /// Whether to paint the group delay, instead of the power.
+    show_group_delay: bool,
+
+    /// The group delay of every bin, for every window in `spectrum`.
+    ///
+    /// We only compute these while `show_group_delay` is set, they are
+    /// as large as the spectrum itself.
+    group_delays: Vec<Box<[f32]>>,
+
+    /// Whether to paint the phase coherence between the first two channels, instead of the power.
+    ///
+    /// This shows per frequency whether the content is mono, wide, or out of
+    /// phase. Files with a single channel have no coherence to show.
+    show_coherence: bool,
+
+    /// The phase coherence of every bin, for every window in `spectrum`.
+    ///
+    /// Like `group_delays`, we only compute these while `show_coherence` is set.
+    coherences: Vec<Box<[f32]>>,
+
+    /// Whether to paint the constant-Q transform, instead of the DFT.
+    ///
+    /// The bins of the constant-Q transform are equally wide in pitch rather
+    /// than in Hz, which suits music. The analyses, such as the centroid and
+    /// the probes, remain based on the DFT, which has the same windows.
+    show_constant_q: bool,
+
+    /// The power per constant-Q bin, for every window in `spectrum`.
+    ///
+    /// Like `group_delays`, we only compute these while `show_constant_q` is set.
+    constant_q: Vec<Box<[f32]>>,
+
+    /// The kernels of the constant-Q transform, for the sample rate, window
+    /// length, and window function that we made them for.
+    ///
+    /// Like `fft_plan`, we keep them across batches, they are costly to make.
+    constant_q_plan: Option<((u32, usize, WindowFn), ConstantQ)>,
+
+    /// The colormap to paint the spectrogram with.
+    colormap: Colormap,
+
+    /// Whether to traverse the colormap from bright to dark, instead of dark to bright.
+    invert_colormap: bool,
+
+    /// Whether to sRGB-encode the output of the colormap, see `ColorScheme`.
+    encode_srgb: bool,
+
+    /// The number of pixel rows of the spectrogram per row that we sample.
+    ///
+    /// Sampling fewer rows makes painting faster, at the cost of vertical
+    /// resolution. We take the maximum over the bins in a row, so peaks do
+    /// not disappear when we sample less.
+    row_step: usize,
+
+    /// The number of columns that we sample per pixel column of the spectrogram.
+    ///
+    /// When many windows map to one pixel, averaging them smears a transient
+    /// over the pixel. Instead we can sample narrower columns, that average
+    /// fewer windows each, and average their brightness, as if we painted a
+    /// wider bitmap and scaled it down.
+    oversample: usize,
+
+    /// The bins that every sampled row of the spectrogram covers, from the top down.
+    ///
+    /// This depends only on the geometry, so we compute it once when the
+    /// height, the row step, or the zero-padding changes, rather than on
+    /// every repaint.
+    row_plan: Vec<RowBins>,
+
+    /// The windows that every column of the spectrogram covers.
+    ///
+    /// Like `row_plan` this depends only on the geometry, we recompute it
+    /// when the width, the time view, or the windows change. It is in a cell
+    /// because we update it while painting.
+    column_plan: RefCell<ColumnPlan>,
+
+    /// The values of the last repaint, so we can change only the colors.
+    painted: RefCell<Option<PaintedValues>>,
+
+    /// Settings before every adjustment, the most recent one last.
+    undo_stack: Vec<Settings>,
+
+    /// Settings before every undo, the most recent one last.
+    ///
+    /// Any new adjustment clears this, because it branches off the history.
+    redo_stack: Vec<Settings>,
+
+    /// The slider that the last adjustment came from, and when.
+    ///
+    /// A slider sends an event for every step of a drag, but we undo the
+    /// drag as a whole, so steps shortly after one another are one adjustment.
+    last_slider_step: Option<(mem::Discriminant<ModelEvent>, Instant)>,
+
+    /// Named sets of settings from the presets file, ordered by name.
+    presets: Vec<(String, Config)>,
+
+    /// Frequency in Hz above which the file has no significant content.
+    ///
+    /// This is only known when the file is fully decoded. When it is well
+    /// below the Nyquist frequency, the file was likely upsampled.
+    bandwidth: Option<f32>,
+
+    /// A sender to send messages to the UI.
+    sender: glib::SyncSender<ViewEvent>,
+
+    /// A sender to send messages to this model.
+    self_sender: mpsc::SyncSender<ModelEvent>,
+}
+
+enum ModelEvent {
+    OpenFile(PathBuf),
+    /// Read the loaded file from disk again, keeping the zoom and undo history.
+    Reload,
+    /// Write the spectral peaks of every window to a csv file.
+    ExportPeaks(PathBuf),
+    /// Open the audio file with the given name from a zip archive.
+    OpenArchiveEntry(PathBuf, String),
+    /// Open a file that is already in memory, with a name to display.
+    OpenBytes(Vec<u8>, String),
+    /// Width, height, tick label width, tick label height (in device pixels).
+    Resize(i32, i32, i32, i32),
+    /// Decode the next batch of the reader, if the generation is still the current one.
+    Decode(u64),
+    /// Lock a probe with the given id in place, at a position in graph coordinates.
+    AddProbe(u32, f64, f64),
+    /// Remove the probe with the given id.
+    RemoveProbe(u32),
+    /// Position of the pointer, in graph coordinates, or none when it left the graph.
+    SetCursor(Option<(f64, f64)>),
+    /// Copy the frequency at height `y` in graph coordinates to the clipboard.
+    CopyFrequencyAt(f64),
+    /// Add a region with the given left, right, bottom, and top edge, in graph coordinates.
+    AddRegion(f64, f64, f64, f64),
+    /// Remove the region at the given index, in the order that they were placed.
+    RemoveRegion(usize),
+    /// Wall-clock time at the start of every file, in seconds since midnight.
+    SetStartTime(Option<u32>),
+    /// Analyze a different channel, or combination of channels.
+    SetChannelMode(ChannelMode),
+    SetZeroPadFactor(usize),
+    /// Use windows of a different length, and a different hop between them, both in samples.
+    SetWindowParams(usize, usize),
+    /// Multiply every window with a different window function, and transform again.
+    SetWindowFn(WindowFn),
+    /// Zoom into the range between two positions on the x-axis, in graph coordinates.
+    SetTimeRange(f64, f64),
+    /// Scale the length of the time range by a factor, keeping the time at `x` in place.
+    ZoomAt(f64, f64),
+    /// Scale the frequency range by a factor, keeping the frequency at `y` in view.
+    ZoomFrequencyAt(f64, f64),
+    /// Show the full file and the full frequency range again.
+    ResetZoom,
+    /// Paint the distribution of power levels per frequency, instead of the spectrogram.
+    SetHistogram(bool),
+    /// Sample one row of the spectrogram for this many pixel rows.
+    SetRowStep(usize),
+    /// Sample this many columns per pixel column, and average them.
+    SetOversample(usize),
+    /// Paint the group delay, instead of the power.
+    SetGroupDelay(bool),
+    /// Paint the phase coherence between the first two channels, instead of the power.
+    SetCoherence(bool),
+    /// Paint the constant-Q transform, instead of the DFT.
+    SetConstantQ(bool),
+    /// Show DC and sub-audio content in a linear strip at the bottom of the frequency axis.
+    SetDcStrip(bool),
+    /// Space frequencies on a different scale.
+    SetYScale(YScale),
+    /// Paint the spectrogram with a different colormap.
+    SetColormap(Colormap),
+    /// Map the levels from a floor to a ceiling in dB onto the colormap.
+    SetLevelRange(f32, f32),
+    /// Traverse the colormap from bright to dark.
+    SetInvertColormap(bool),
+    /// Apply sRGB encoding to the output of the colormap.
+    SetEncodeSrgb(bool),
+    /// Paint the power relative to a slowly varying noise floor.
+    SetAdaptiveFloor(bool),
+    /// Paint only how much the power exceeds the average spectrum of the file.
+    SetResidual(bool),
+    /// Pick the hop between windows for the length of the file, rather than `WINDOW_OFF`.
+    SetAutoHop(bool),
+    /// Weigh every row by the bandwidth it represents on the frequency axis.
+    SetEqualEnergy(bool),
+    /// Revert the most recent adjustment of the settings.
+    Undo,
+    /// Reapply the most recently undone adjustment of the settings.
+    Redo,
+    /// Make the previously loaded file the current one, and the reverse.
+    SwapFiles,
+    /// Change the blend between a log and linear frequency axis.
+    SetYBlend(f64),
+    /// Switch between fitting the file in view, and a fixed number of pixels per second.
+    ToggleFixedScale,
+    /// Scroll the time range in view to begin at a fraction of the duration.
+    ScrollTo(f64),
+    /// Mark the frequency in Hz with a horizontal line, or remove the marker.
+    SetFrequencyMarker(Option<f64>),
+    /// Overlay the spectral peaks of the previous file on the spectrogram.
+    SetComparePeaks(bool),
+    /// Read the presets file, and list the presets in the view.
+    LoadPresets,
+    /// Save the current settings as a preset with this name, replacing any preset by that name.
+    SavePreset(String),
+    /// Apply the settings of the preset with this name.
+    ApplyPreset(String),
+    /// Remove the preset with this name from the presets file.
+    DeletePreset(String),
+}
+
+impl View {
+    fn new(
+        application: &gtk::Application,
+        sender: mpsc::SyncSender<ModelEvent>,
+        config: &Config,
+    ) -> Rc<RefCell<View>> {
+        let window = gtk::ApplicationWindow::new(application);
+
+        window.set_title("Audiograter");
+        window.set_border_width(10);
+        window.set_position(gtk::WindowPosition::Center);
+        window.set_default_size(640, 480);
+
+        let header_bar = gtk::HeaderBar::new();
+        header_bar.set_show_close_button(true);
+        header_bar.set_title(Some("Audiograter"));
+        window.set_titlebar(Some(&header_bar));
+
+        let reload_button =
+            gtk::Button::from_icon_name(Some("view-refresh-symbolic"), gtk::IconSize::Button);
+        reload_button.set_tooltip_text(Some("Reload the file from disk (Ctrl+R)"));
+        let reload_sender = sender.clone();
+        reload_button.connect_clicked(move |_| {
+            reload_sender.send(ModelEvent::Reload).unwrap();
+        });
+        header_bar.pack_end(&reload_button);
+
+        let open_button =
+            gtk::Button::from_icon_name(Some("document-open-symbolic"), gtk::IconSize::Button);
+        open_button.set_tooltip_text(Some("Open a file (Ctrl+O)"));
+        header_bar.pack_start(&open_button);
+
+        // The exports are in a menu, the actions are on the window, below.
+        let menu = gio::Menu::new();
+        menu.append(Some("Export as PNG…"), Some("win.export-png"));
+        menu.append(Some("Export as SVG…"), Some("win.export-svg"));
+        menu.append(Some("Export peaks as CSV…"), Some("win.export-peaks"));
+        menu.append(Some("Save preset…"), Some("win.save-preset"));
+        menu.append(Some("Delete preset"), Some("win.delete-preset"));
+        let menu_button = gtk::MenuButton::new();
+        menu_button.set_image(Some(&gtk::Image::from_icon_name(
+            Some("open-menu-symbolic"),
+            gtk::IconSize::Button,
+        )));
+        menu_button.set_menu_model(Some(&menu));
+        header_bar.pack_end(&menu_button);
+
+        // The model shows the progress bar while it decodes a file.
+        let progress_bar = gtk::ProgressBar::new();
+        progress_bar.set_valign(gtk::Align::Center);
+        progress_bar.set_no_show_all(true);
+        header_bar.pack_end(&progress_bar);
+
+        // The model fills the picker once it has read the presets file.
+        let preset_combo = gtk::ComboBoxText::new();
+        preset_combo.set_tooltip_text(Some("Apply a saved preset"));
+        header_bar.pack_start(&preset_combo);
+
+        let channel_modes = vec![
+            ChannelMode::Mix,
+            ChannelMode::Channel(0),
+            ChannelMode::Channel(1),
+        ];
+        let channel_combo = gtk::ComboBoxText::new();
+        channel_combo.set_tooltip_text(Some("The channel to analyze"));
+        for &mode in &channel_modes {
+            channel_combo.append_text(&channel_mode_label(mode));
+        }
+        channel_combo.set_active(Some(1));
+        header_bar.pack_start(&channel_combo);
+
+        // An entry to mark a frequency, for when pointing at it is not precise enough.
+        let frequency_entry = gtk::Entry::new();
+        frequency_entry.set_placeholder_text(Some("Frequency"));
+        frequency_entry.set_width_chars(10);
+        let marker_sender = sender.clone();
+        frequency_entry.connect_activate(move |entry| {
+            let text = entry.text();
+            let hz = match parse_hz(&text) {
+                _ if text.trim().is_empty() => None,
+                Some(hz) => Some(hz),
+                None => {
+                    set_entry_error(entry, Some("Enter a frequency, like 440 or 15 kHz."));
+                    return;
+                }
+            };
+            marker_sender
+                .send(ModelEvent::SetFrequencyMarker(hz))
+                .unwrap();
+        });
+        header_bar.pack_start(&frequency_entry);
+
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+        window.add(&vbox);
+
+        let image = gtk::DrawingArea::new();
+
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let expand = true;
+        let fill = true;
+        let padding = 0;
+        hbox.pack_start(&image, expand, fill, padding);
+        vbox.pack_start(&hbox, expand, fill, padding);
+
+        // The side panel with the regions, and controls to rename and delete them.
+        let region_panel = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        let region_list = gtk::ListBox::new();
+        region_list.set_selection_mode(gtk::SelectionMode::Single);
+        let region_scrolled =
+            gtk::ScrolledWindow::new(gtk::Adjustment::NONE, gtk::Adjustment::NONE);
+        region_scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        region_scrolled.set_size_request(220, -1);
+        region_scrolled.add(&region_list);
+        let region_name = gtk::Entry::new();
+        region_name.set_placeholder_text(Some("Name"));
+        let delete_button = gtk::Button::with_label("Delete");
+        region_panel.pack_start(&gtk::Label::new(Some("Regions")), false, false, padding);
+        region_panel.pack_start(&region_scrolled, expand, fill, padding);
+        region_panel.pack_start(&region_name, false, false, padding);
+        region_panel.pack_start(&delete_button, false, false, padding);
+        hbox.pack_start(&region_panel, false, false, padding);
+
+        // The scrollbar value and page size are fractions of the file duration.
+        let adjustment = gtk::Adjustment::new(0.0, 0.0, 1.0, 0.1, 0.9, 1.0);
+        let scrollbar = gtk::Scrollbar::new(gtk::Orientation::Horizontal, Some(&adjustment));
+        scrollbar.set_no_show_all(true);
+        let scroll_sender = sender.clone();
+        adjustment.connect_value_changed(move |adjustment| {
+            scroll_sender
+                .send(ModelEvent::ScrollTo(adjustment.value()))
+                .unwrap();
+        });
+        vbox.pack_start(&scrollbar, false, false, padding);
+
+        // A slider to bias the frequency axis towards log or linear.
+        let blend_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let blend_scale = gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, 1.0, 0.05);
+        blend_scale.set_value(config.frequency_axis.unwrap_or(DEFAULT_Y_BLEND));
+        blend_scale.set_draw_value(false);
+        blend_scale.set_size_request(200, -1);
+        let blend_sender = sender.clone();
+        let blend_handler = blend_scale.connect_value_changed(move |scale| {
+            blend_sender
+                .send(ModelEvent::SetYBlend(scale.value()))
+                .unwrap();
+        });
+        let expand = false;
+        let fill = false;
+        blend_box.pack_end(&gtk::Label::new(Some("Linear")), expand, fill, padding);
+        blend_box.pack_end(&blend_scale, expand, fill, padding);
+        blend_box.pack_end(&gtk::Label::new(Some("Log")), expand, fill, padding);
+
+        // Sliders for the range of levels that the colormap spans, to adjust the contrast.
+        let floor_scale = gtk::Scale::with_range(gtk::Orientation::Horizontal, -150.0, -20.0, 1.0);
+        let ceiling_scale = gtk::Scale::with_range(gtk::Orientation::Horizontal, -40.0, 20.0, 1.0);
+        floor_scale.set_value(config.floor_db.unwrap_or(DEFAULT_FLOOR_DB) as f64);
+        ceiling_scale.set_value(config.ceiling_db.unwrap_or(DEFAULT_CEILING_DB) as f64);
+        floor_scale.set_tooltip_text(Some("The level at the bottom of the colormap, in dB"));
+        ceiling_scale.set_tooltip_text(Some("The level at the top of the colormap, in dB"));
+        let mut level_handlers = Vec::new();
+        for scale in [&floor_scale, &ceiling_scale] {
+            scale.set_value_pos(gtk::PositionType::Right);
+            scale.set_size_request(150, -1);
+            let level_sender = sender.clone();
+            let floor_scale = floor_scale.clone();
+            let ceiling_scale = ceiling_scale.clone();
+            let handler = scale.connect_value_changed(move |_| {
+                let event = ModelEvent::SetLevelRange(
+                    floor_scale.value() as f32,
+                    ceiling_scale.value() as f32,
+                );
+                level_sender.send(event).unwrap();
+            });
+            level_handlers.push(handler);
+        }
+        blend_box.pack_start(&gtk::Label::new(Some("Floor")), expand, fill, padding);
+        blend_box.pack_start(&floor_scale, expand, fill, padding);
+        blend_box.pack_start(&gtk::Label::new(Some("Ceiling")), expand, fill, padding);
+        blend_box.pack_start(&ceiling_scale, expand, fill, padding);
+        vbox.pack_start(&blend_box, expand, fill, padding);
+
+        // Accept single strings for dropping. We could accept "text/uri-list" too,
+        // but the application cannot handle more than one file at a time anyway.
+        const DRAG_EVENT_INFO: u32 = 0;
+        let drag_targets = [
+            gtk::TargetEntry::new("text/plain", gtk::TargetFlags::OTHER_APP, DRAG_EVENT_INFO),
+            gtk::TargetEntry::new(
+                "text/uri-list",
                 gtk::TargetFlags::OTHER_APP,
                 DRAG_EVENT_INFO,
             ),
@@ -265,21 +1471,84 @@ impl View {
             gdk::DragAction::COPY,
         );
 
-        // Create a Pango layout for an axis label, with the right UI font. We
-        // use this layout to measure the size of a label, to reserve the right
-        // amount of space for labels.
-        let label_layout = window.create_pango_layout(Some("00.0 kHz"));
-        let (label_width, label_height) = label_layout.pixel_size();
-
         let view_cell = Rc::new(RefCell::new(View {
             window: window.clone(),
             header_bar: header_bar.clone(),
+            scrollbar: scrollbar.clone(),
+            progress_bar: progress_bar.clone(),
+            metadata: None,
+            blend_scale: blend_scale.clone(),
+            blend_handler,
+            floor_scale: floor_scale.clone(),
+            ceiling_scale: ceiling_scale.clone(),
+            level_handlers,
+            preset_combo: preset_combo.clone(),
+            channel_combo: channel_combo.clone(),
+            channel_modes,
             image: image.clone(),
             x_ticks: Vec::new(),
             y_ticks: Vec::new(),
-            label_width: label_width,
-            label_height: label_height,
+            x_layouts: Vec::new(),
+            colorbar: None,
+            colorbar_ticks: Vec::new(),
+            colorbar_layouts: Vec::new(),
+            y_layouts: Vec::new(),
+            label_width: 0,
+            label_height: 0,
+            x_label_width: 0,
+            label_center: 0.0,
             pixbuf: None,
+            probes: Vec::new(),
+            next_probe_id: 1,
+            cursor: None,
+            colorbar_hover: None,
+            press_position: None,
+            selection: None,
+            regions: Vec::new(),
+            region_selection: None,
+            region_panel: region_panel.clone(),
+            region_list: region_list.clone(),
+            region_name: region_name.clone(),
+            centroid_line: Vec::new(),
+            rolloff_line: Vec::new(),
+            show_centroid: false,
+            show_rolloff: false,
+            flux_line: Vec::new(),
+            onsets: Vec::new(),
+            show_flux: false,
+            compare_peaks: Vec::new(),
+            show_compare_peaks: false,
+            nyquist_lines: Vec::new(),
+            track_markers: Vec::new(),
+            frequency_marker: None,
+            frequency_entry: frequency_entry.clone(),
+            show_nyquist: false,
+            note_lines: Vec::new(),
+            show_notes: false,
+            legend: String::new(),
+            show_legend: false,
+            clean_view: false,
+            show_histogram: false,
+            window_len: WINDOW_LEN,
+            window_off: WINDOW_OFF,
+            window_fn: WindowFn::Hann,
+            zero_pad_factor: 1,
+            show_group_delay: false,
+            show_coherence: false,
+            show_constant_q: false,
+            show_dc_strip: false,
+            y_scale: YScale::Blended,
+            row_step: 1,
+            oversample: 1,
+            colormap: Colormap::Magma,
+            invert_colormap: false,
+            encode_srgb: false,
+            adaptive_floor: false,
+            show_residual: false,
+            auto_hop: false,
+            equal_energy: false,
+            envelope: Vec::new(),
+            show_envelope: false,
             sender: sender,
         }));
 
@@ -297,24 +1566,238 @@ impl View {
             glib::signal::Propagation::Stop
         });
 
+        // There is nothing to export until a file is loaded, see `set_exports_enabled`.
+        let export_png = gio::SimpleAction::new("export-png", None);
+        export_png.set_enabled(false);
+        let view_cell_clone = view_cell.clone();
+        export_png.connect_activate(move |_, _| View::choose_png_file(&view_cell_clone));
+        window.add_action(&export_png);
+
+        let export_svg = gio::SimpleAction::new("export-svg", None);
+        export_svg.set_enabled(false);
+        let view_cell_clone = view_cell.clone();
+        export_svg.connect_activate(move |_, _| View::choose_svg_file(&view_cell_clone));
+        window.add_action(&export_svg);
+
+        let export_peaks = gio::SimpleAction::new("export-peaks", None);
+        export_peaks.set_enabled(false);
+        let view_cell_clone = view_cell.clone();
+        export_peaks.connect_activate(move |_, _| view_cell_clone.borrow().choose_peaks_file());
+        window.add_action(&export_peaks);
+
+        let save_preset = gio::SimpleAction::new("save-preset", None);
+        let view_cell_clone = view_cell.clone();
+        save_preset.connect_activate(move |_, _| view_cell_clone.borrow().choose_preset_name());
+        window.add_action(&save_preset);
+
+        let delete_preset = gio::SimpleAction::new("delete-preset", None);
+        let view_cell_clone = view_cell.clone();
+        delete_preset.connect_activate(move |_, _| view_cell_clone.borrow().delete_preset());
+        window.add_action(&delete_preset);
+
+        let view_cell_clone = view_cell.clone();
+        open_button.connect_clicked(move |_| view_cell_clone.borrow().choose_file_to_open());
+
+        let view_cell_clone = view_cell.clone();
+        window.connect_key_press_event(move |_self, event| {
+            // Exporting draws the view once the user has chosen a file, so
+            // the dialog needs the cell, not just the view.
+            let ctrl = event.state().contains(gdk::ModifierType::CONTROL_MASK);
+            if ctrl && event.keyval() == gdk::keys::constants::S {
+                View::choose_svg_file(&view_cell_clone);
+                return glib::signal::Propagation::Stop;
+            }
+            if ctrl && event.keyval() == gdk::keys::constants::s {
+                View::choose_png_file(&view_cell_clone);
+                return glib::signal::Propagation::Stop;
+            }
+            view_cell_clone.borrow_mut().on_key_press(event)
+        });
+
+        image.add_events(
+            gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON_RELEASE_MASK
+                | gdk::EventMask::POINTER_MOTION_MASK
+                | gdk::EventMask::LEAVE_NOTIFY_MASK
+                | gdk::EventMask::SCROLL_MASK,
+        );
+        let view_cell_clone = view_cell.clone();
+        image.connect_button_press_event(move |_self, event| {
+            view_cell_clone.borrow_mut().on_button_press(event);
+            glib::signal::Propagation::Stop
+        });
+
+        let view_cell_clone = view_cell.clone();
+        image.connect_motion_notify_event(move |_self, event| {
+            view_cell_clone.borrow_mut().on_motion(event);
+            glib::signal::Propagation::Stop
+        });
+
+        let view_cell_clone = view_cell.clone();
+        image.connect_leave_notify_event(move |_self, _event| {
+            let mut view = view_cell_clone.borrow_mut();
+            view.set_colorbar_hover(None);
+            view.set_cursor(None);
+            glib::signal::Propagation::Proceed
+        });
+
+        let view_cell_clone = view_cell.clone();
+        image.connect_button_release_event(move |_self, event| {
+            view_cell_clone.borrow_mut().on_button_release(event);
+            glib::signal::Propagation::Stop
+        });
+
+        let view_cell_clone = view_cell.clone();
+        image.connect_scroll_event(move |_self, event| {
+            view_cell_clone.borrow_mut().on_scroll(event);
+            glib::signal::Propagation::Stop
+        });
+
         let view_cell_clone = view_cell.clone();
         image.connect_size_allocate(move |_self, rect| {
             view_cell_clone.borrow_mut().on_size_allocate(rect);
         });
 
+        // The size of the labels depends on the font and the DPI, so lay them
+        // out again when the style changes. When the scale factor changes, the
+        // size in display pixels stays the same, but the device pixels change.
+        let view_cell_clone = view_cell.clone();
+        image.connect_style_updated(move |_self| {
+            view_cell_clone.borrow_mut().layout_labels();
+        });
+
+        let view_cell_clone = view_cell.clone();
+        image.connect_scale_factor_notify(move |image| {
+            view_cell_clone
+                .borrow()
+                .on_size_allocate(&image.allocation());
+        });
+
+        // When we update the list ourselves, the view is borrowed already, and
+        // the entry is up to date, so then there is nothing to do here.
+        let view_cell_clone = view_cell.clone();
+        region_list.connect_row_selected(move |_self, row| {
+            if let Ok(view) = view_cell_clone.try_borrow() {
+                view.on_region_selected(row);
+            }
+        });
+
+        // When the model tells us the channel mode, the view is borrowed
+        // already, and then there is no need to send it back.
+        let view_cell_clone = view_cell.clone();
+        channel_combo.connect_changed(move |combo| {
+            if let (Ok(view), Some(i)) = (view_cell_clone.try_borrow(), combo.active()) {
+                view.sender
+                    .send(ModelEvent::SetChannelMode(view.channel_modes[i as usize]))
+                    .unwrap();
+            }
+        });
+
+        // Like for the region list, when we fill the picker ourselves, the
+        // view is borrowed already, and we should not apply the preset then.
+        let view_cell_clone = view_cell.clone();
+        preset_combo.connect_changed(move |combo| {
+            if let (Ok(view), Some(name)) = (view_cell_clone.try_borrow(), combo.active_id()) {
+                view.sender
+                    .send(ModelEvent::ApplyPreset(name.to_string()))
+                    .unwrap();
+            }
+        });
+
+        let view_cell_clone = view_cell.clone();
+        region_name.connect_activate(move |entry| {
+            let name = entry.text().to_string();
+            view_cell_clone.borrow_mut().rename_selected_region(name);
+        });
+
+        let view_cell_clone = view_cell.clone();
+        delete_button.connect_clicked(move |_self| {
+            view_cell_clone.borrow_mut().delete_selected_region();
+        });
+
+        view_cell.borrow_mut().layout_labels();
         window.show_all();
+        region_panel.hide();
 
         view_cell
     }
 
+    /// Lay out the tick labels, and measure how much space they need.
+    ///
+    /// We reserve space for the widest label, but at least for `REFERENCE_LABEL`,
+    /// so the graph does not change size for small differences in labels.
+    fn layout_labels(&mut self) {
+        let reference = self.window.create_pango_layout(Some(REFERENCE_LABEL));
+        let (mut label_width, mut label_height) = reference.pixel_size();
+        let mut x_label_width = label_width;
+
+        self.y_layouts = self
+            .y_ticks
+            .iter()
+            .map(|tick| self.window.create_pango_layout(Some(&tick.label[..])))
+            .collect();
+        self.x_layouts = self
+            .x_ticks
+            .iter()
+            .map(|tick| self.window.create_pango_layout(Some(&tick.label[..])))
+            .collect();
+        self.colorbar_layouts = self
+            .colorbar_ticks
+            .iter()
+            .map(|tick| self.window.create_pango_layout(Some(&tick.label[..])))
+            .collect();
+
+        // The colorbar labels are on the right, we reserve the same width for them.
+        for layout in self.y_layouts.iter().chain(&self.colorbar_layouts) {
+            let (width, height) = layout.pixel_size();
+            label_width = label_width.max(width);
+            label_height = label_height.max(height);
+        }
+        for layout in &self.x_layouts {
+            let (width, height) = layout.pixel_size();
+            x_label_width = x_label_width.max(width);
+            label_height = label_height.max(height);
+        }
+
+        // Measure the vertical center of the lowercase letters, to align the
+        // y-tick labels with their ticks. This is the same for every label.
+        // Based on http://gtk.10911.n7.nabble.com/Pango-Accessing-x-height-mean-line-in-Pango-layout-td79374.html.
+        let pango_context = reference.context();
+        let font = reference.font_description();
+        let language = None;
+        let metrics = pango_context.metrics(font.as_ref(), language);
+        let baseline = reference.baseline();
+        let strike_pos = metrics.strikethrough_position();
+        let strike_thick = metrics.strikethrough_thickness();
+        let x_center_font_units = baseline - strike_pos - strike_thick / 2;
+        // Convert font units to view pixels, see also
+        // https://developer.gnome.org/pango/stable/pango-Glyph-Storage.html#PANGO-PIXELS:CAPS
+        self.label_center = ((x_center_font_units + 512) >> 10) as f64;
+
+        let old_size = (self.label_width, self.label_height, self.x_label_width);
+        self.label_width = label_width;
+        self.label_height = label_height;
+        self.x_label_width = x_label_width;
+
+        // The size of the graph depends on the size of the labels, so if that
+        // changed, the model needs to know.
+        if old_size != (label_width, label_height, x_label_width) {
+            self.on_size_allocate(&self.image.allocation());
+        }
+    }
+
     /// Given the size of the widget, compute the size of the spectrogram graph.
     ///
     /// This excludes the space for labels and a border. Units are display pixels.
     fn get_graph_size(&self, width: i32, height: i32) -> (i32, i32) {
-        // Subtract space for the label, ticks, and a 1px border.
+        // Subtract space for the label, ticks, and a 1px border. On the right
+        // we also subtract space for the colorbar, and its labels.
+        let colorbar_width = COLORBAR_PADDING + COLORBAR_WIDTH + TICK_SIZE + TICK_PADDING;
         (
             1.max(
-                width - self.label_width - (2.0 * BORDER_WIDTH + TICK_SIZE + TICK_PADDING) as i32,
+                width
+                    - 2 * self.label_width
+                    - (2.0 * BORDER_WIDTH + TICK_SIZE + TICK_PADDING + colorbar_width) as i32,
             ),
             1.max(
                 height - self.label_height - (2.0 * BORDER_WIDTH + TICK_SIZE + TICK_PADDING) as i32,
@@ -322,82 +1805,758 @@ impl View {
         )
     }
 
-    fn on_drag_data_received(&self, data: &gtk::SelectionData) {
-        if let Some(uri) = data.text() {
-            // When dropped, the uri is terminated by a newline. Strip it.
-            let uri_stripped = uri.as_str().trim_end();
-            if let Ok((fname, _)) = glib::filename_from_uri(uri_stripped) {
-                self.sender.send(ModelEvent::OpenFile(fname)).unwrap();
-            }
-
-            // Nautilus used to send uris as text, but now it sends uris handled
-            // by the loop below. Nemo used to send urls, but now it sends just
-            // the file path as text. So if it's not a filename uri, then try
-            // and see if the path exists.
-            if let Ok(_meta) = std::fs::metadata(&uri_stripped) {
-                self.sender.send(ModelEvent::OpenFile(uri_stripped.into())).unwrap();
-            }
-
-            return;
+    /// Convert widget coordinates into graph coordinates.
+    ///
+    /// Graph coordinates are in [0, 1]^2 with the origin at the bottom left,
+    /// the same as tick positions. Returns `None` for points outside the graph.
+    fn get_graph_position(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (gx, gy) = self.get_graph_coordinates(x, y);
+        if (0.0..=1.0).contains(&gx) && (0.0..=1.0).contains(&gy) {
+            Some((gx, gy))
+        } else {
+            None
         }
+    }
 
-        for uri in data.uris() {
-            if let Ok((fname, _)) = glib::filename_from_uri(&uri) {
-                self.sender.send(ModelEvent::OpenFile(fname)).unwrap();
-            }
-            return;
+    /// Return the position on the colorbar at widget coordinates, if it shows levels.
+    ///
+    /// The position is 0.0 at the bottom and 1.0 at the top, like the
+    /// brightness that the colorbar shows there. The tick marks to the right
+    /// of the colorbar count as part of it, so it is not too narrow to hit.
+    fn get_colorbar_position(&self, x: f64, y: f64) -> Option<f64> {
+        if self.colorbar.is_none() || !self.colorbar_shows_levels() {
+            return None;
+        }
+        let actual_size = self.image.allocation();
+        let (graph_width, graph_height) =
+            self.get_graph_size(actual_size.width(), actual_size.height());
+        let left = self.label_width as f64
+            + TICK_SIZE
+            + TICK_PADDING
+            + graph_width as f64
+            + 2.0 * BORDER_WIDTH
+            + COLORBAR_PADDING;
+        let position = 1.0 - (y - BORDER_WIDTH) / graph_height as f64;
+        if (left..=left + COLORBAR_WIDTH + TICK_SIZE).contains(&x)
+            && (0.0..=1.0).contains(&position)
+        {
+            Some(position)
+        } else {
+            None
         }
     }
 
-    fn on_size_allocate(&self, rect: &gtk::Rectangle) {
-        let (width, height) = self.get_graph_size(rect.width(), rect.height());
-        let f = self.image.scale_factor();
-        let event = ModelEvent::Resize(
-            width * f,
-            height * f,
-            self.label_width * f,
-            self.label_height * f,
-        );
-        self.sender.send(event).unwrap();
+    /// Return whether the colorbar maps levels in dB, rather than group delay or coherence.
+    ///
+    /// Relative to the adaptive floor, a color does not mean a level either.
+    fn colorbar_shows_levels(&self) -> bool {
+        !self.show_histogram
+            && !self.show_group_delay
+            && !self.show_coherence
+            && !self.adaptive_floor
     }
 
-    fn on_draw(&self, ctx: &cairo::Context) {
+    /// Return the level in dB at a position on the colorbar, for the levels of the sliders.
+    fn level_at_colorbar(&self, position: f64) -> f32 {
+        let floor_db = self.floor_scale.value() as f32;
+        let ceiling_db = self.ceiling_scale.value() as f32;
+        render::level_at_brightness(position as f32, floor_db, ceiling_db)
+    }
+
+    /// Convert a position in the image into graph coordinates, which may lie outside the graph.
+    fn get_graph_coordinates(&self, x: f64, y: f64) -> (f64, f64) {
         let actual_size = self.image.allocation();
-        let transform = ctx.matrix();
         let (graph_width, graph_height) =
             self.get_graph_size(actual_size.width(), actual_size.height());
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let top = BORDER_WIDTH;
+        let gx = (x - left) / graph_width as f64;
+        let gy = 1.0 - (y - top) / graph_height as f64;
+        (gx, gy)
+    }
 
-        if let Some(pixbuf) = self.pixbuf.as_ref() {
-            // Stretch the bitmap to fill the entire widget. This has two
-            // purposes. First, we sized the bitmap to take the DPI scaling
-            // factor into account, so we may need to scale it down, because the
-            // Cairo context by default measures display pixels, not device
-            // pixels. Second, if you are resizing and the new pixel-perfect
-            // bitmap is still being rendered, we can stretch the old one to
-            // hide the fact that the new one is not ready, instead of having a
-            // gap, or having the image be truncated.
-            let scale_x = graph_width as f64 / pixbuf.width() as f64;
-            let scale_y = graph_height as f64 / pixbuf.height() as f64;
-            ctx.scale(scale_x, scale_y);
-            ctx.set_source_pixbuf(
-                pixbuf,
-                // To the left we have the label and ticks, and a 1px border. At
-                // the top we only have the 1px border.
-                (self.label_width as f64 + TICK_SIZE + TICK_PADDING + 1.0) / scale_x,
-                1.0 / scale_y,
-            );
-            ctx.paint().unwrap();
+    fn on_key_press(&mut self, event: &gdk::EventKey) -> glib::signal::Propagation {
+        use gdk::keys::constants as key;
 
-            // Undo the scale, so we can draw in display pixels again later.
-            ctx.set_matrix(transform);
+        // Keys typed into an entry, like a region name or a frequency, are text.
+        if self
+            .window
+            .focused_widget()
+            .map_or(false, |w| w.is::<gtk::Entry>())
+        {
+            return glib::signal::Propagation::Proceed;
         }
 
-        // Draw a frame around the spectrum view.
-        ctx.rectangle(
-            self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH * 0.5,
-            BORDER_WIDTH * 0.5,
-            graph_width as f64 + BORDER_WIDTH,
-            graph_height as f64 + BORDER_WIDTH,
+        let ctrl = event.state().contains(gdk::ModifierType::CONTROL_MASK);
+
+        match event.keyval() {
+            key::z if ctrl => self.sender.send(ModelEvent::Undo).unwrap(),
+            key::y | key::Z if ctrl => self.sender.send(ModelEvent::Redo).unwrap(),
+            key::r if ctrl => self.sender.send(ModelEvent::Reload).unwrap(),
+            key::o if ctrl => self.choose_file_to_open(),
+            key::b => self.show_centroid = !self.show_centroid,
+            key::r => self.show_rolloff = !self.show_rolloff,
+            key::w => self.show_envelope = !self.show_envelope,
+            key::f => self.show_flux = !self.show_flux,
+            key::k => {
+                self.show_compare_peaks = !self.show_compare_peaks;
+                let event = ModelEvent::SetComparePeaks(self.show_compare_peaks);
+                self.sender.send(event).unwrap();
+            }
+            key::q => self.show_nyquist = !self.show_nyquist,
+            key::n => self.show_notes = !self.show_notes,
+            key::i => self.show_legend = !self.show_legend,
+            key::h => self.clean_view = !self.clean_view,
+            key::t => self.sender.send(ModelEvent::ToggleFixedScale).unwrap(),
+            key::d => {
+                self.show_histogram = !self.show_histogram;
+                let event = ModelEvent::SetHistogram(self.show_histogram);
+                self.sender.send(event).unwrap();
+            }
+            key::D => {
+                self.show_dc_strip = !self.show_dc_strip;
+                let event = ModelEvent::SetDcStrip(self.show_dc_strip);
+                self.sender.send(event).unwrap();
+            }
+            key::y => {
+                self.y_scale = match self.y_scale {
+                    YScale::Blended => YScale::Linear,
+                    YScale::Linear => YScale::Log,
+                    YScale::Log => YScale::Mel,
+                    YScale::Mel => YScale::Blended,
+                };
+                let event = ModelEvent::SetYScale(self.y_scale);
+                self.sender.send(event).unwrap();
+            }
+            key::l => {
+                // Keep the overlap the same, relative to the window length.
+                let i = WINDOW_LENS.iter().position(|&n| n == self.window_len);
+                let window_len = WINDOW_LENS[i.map_or(0, |i| (i + 1) % WINDOW_LENS.len())];
+                self.window_off = (self.window_off * window_len / self.window_len).max(1);
+                self.window_len = window_len;
+                let event = ModelEvent::SetWindowParams(self.window_len, self.window_off);
+                self.sender.send(event).unwrap();
+            }
+            key::L => {
+                // Cycle the overlap between 50%, 75%, and 87.5%.
+                self.window_off = match self.window_len / self.window_off.max(1) {
+                    2 => self.window_len / 4,
+                    4 => self.window_len / 8,
+                    _ => self.window_len / 2,
+                };
+                let event = ModelEvent::SetWindowParams(self.window_len, self.window_off);
+                self.sender.send(event).unwrap();
+            }
+            key::W => {
+                self.window_fn = match self.window_fn {
+                    WindowFn::Hann => WindowFn::Hamming,
+                    WindowFn::Hamming => WindowFn::Blackman,
+                    WindowFn::Blackman => WindowFn::Hann,
+                };
+                let event = ModelEvent::SetWindowFn(self.window_fn);
+                self.sender.send(event).unwrap();
+            }
+            key::z => {
+                self.zero_pad_factor = match self.zero_pad_factor {
+                    1 => 2,
+                    2 => 4,
+                    _ => 1,
+                };
+                let event = ModelEvent::SetZeroPadFactor(self.zero_pad_factor);
+                self.sender.send(event).unwrap();
+            }
+            key::g => {
+                self.show_group_delay = !self.show_group_delay;
+                let event = ModelEvent::SetGroupDelay(self.show_group_delay);
+                self.sender.send(event).unwrap();
+            }
+            key::p => {
+                self.show_coherence = !self.show_coherence;
+                let event = ModelEvent::SetCoherence(self.show_coherence);
+                self.sender.send(event).unwrap();
+            }
+            key::Q => {
+                self.show_constant_q = !self.show_constant_q;
+                let event = ModelEvent::SetConstantQ(self.show_constant_q);
+                self.sender.send(event).unwrap();
+            }
+            key::v => {
+                self.row_step = match self.row_step {
+                    1 => 2,
+                    2 => 4,
+                    4 => 8,
+                    _ => 1,
+                };
+                let event = ModelEvent::SetRowStep(self.row_step);
+                self.sender.send(event).unwrap();
+            }
+            key::O => {
+                self.oversample = match self.oversample {
+                    1 => 2,
+                    2 => 4,
+                    _ => 1,
+                };
+                let event = ModelEvent::SetOversample(self.oversample);
+                self.sender.send(event).unwrap();
+            }
+            key::c => {
+                self.colormap = match self.colormap {
+                    Colormap::Magma => Colormap::Viridis,
+                    Colormap::Viridis => Colormap::Grayscale,
+                    Colormap::Grayscale => Colormap::Magma,
+                };
+                let event = ModelEvent::SetColormap(self.colormap);
+                self.sender.send(event).unwrap();
+            }
+            key::C => {
+                self.invert_colormap = !self.invert_colormap;
+                let event = ModelEvent::SetInvertColormap(self.invert_colormap);
+                self.sender.send(event).unwrap();
+            }
+            key::S => {
+                self.encode_srgb = !self.encode_srgb;
+                let event = ModelEvent::SetEncodeSrgb(self.encode_srgb);
+                self.sender.send(event).unwrap();
+            }
+            key::a => {
+                self.adaptive_floor = !self.adaptive_floor;
+                let event = ModelEvent::SetAdaptiveFloor(self.adaptive_floor);
+                self.sender.send(event).unwrap();
+            }
+            key::e => {
+                self.show_residual = !self.show_residual;
+                let event = ModelEvent::SetResidual(self.show_residual);
+                self.sender.send(event).unwrap();
+            }
+            key::o => {
+                self.auto_hop = !self.auto_hop;
+                let event = ModelEvent::SetAutoHop(self.auto_hop);
+                self.sender.send(event).unwrap();
+            }
+            key::u => {
+                self.equal_energy = !self.equal_energy;
+                let event = ModelEvent::SetEqualEnergy(self.equal_energy);
+                self.sender.send(event).unwrap();
+            }
+            // Not Tab, GTK needs that to move the focus between widgets.
+            key::x => self.sender.send(ModelEvent::SwapFiles).unwrap(),
+            key::Escape => self.sender.send(ModelEvent::ResetZoom).unwrap(),
+            _ => return glib::signal::Propagation::Proceed,
+        }
+
+        self.image.queue_draw();
+        glib::signal::Propagation::Stop
+    }
+
+    /// Start a click or drag on left press, remove the nearest probe on right click.
+    ///
+    /// On the colorbar, a left click sets the floor to the level under the
+    /// pointer, and a right click sets the ceiling.
+    fn on_button_press(&mut self, event: &gdk::EventButton) {
+        if self.show_histogram {
+            return;
+        }
+        let (x, y) = event.position();
+        if let Some(position) = self.get_colorbar_position(x, y) {
+            // The sliders tell the model about the new range when they change.
+            let level_db = self.level_at_colorbar(position).round() as f64;
+            match event.button() {
+                1 => self.floor_scale.set_value(level_db),
+                3 => self.ceiling_scale.set_value(level_db),
+                _ => {}
+            }
+            return;
+        }
+        let (gx, gy) = match self.get_graph_position(x, y) {
+            Some(p) => p,
+            None => return,
+        };
+
+        match event.button() {
+            // Whether this places a probe or selects a time range, we only
+            // know when the button is released. With Ctrl, it places a region.
+            1 => {
+                self.press_position = Some(((x, y), (gx, gy)));
+                if event.state().contains(gdk::ModifierType::CONTROL_MASK) {
+                    self.region_selection = Some((gx, gy, gx, gy));
+                }
+            }
+            3 => {
+                let dist = |p: &Probe| (p.x - gx).powi(2) + (p.y - gy).powi(2);
+                let nearest = self
+                    .probes
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, p), (_, q)| dist(p).total_cmp(&dist(q)))
+                    .map(|(i, _)| i);
+                if let Some(i) = nearest {
+                    let probe = self.probes.remove(i);
+                    self.sender.send(ModelEvent::RemoveProbe(probe.id)).unwrap();
+                    self.image.queue_draw();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Update the time range or region selection while dragging with the left button.
+    fn on_motion(&mut self, event: &gdk::EventMotion) {
+        let (x, y) = event.position();
+        let ((x0, _y0), (gx0, gy0)) = match self.press_position {
+            Some(p) => p,
+            None => {
+                self.set_colorbar_hover(self.get_colorbar_position(x, y));
+                return self.set_cursor(self.get_graph_position(x, y));
+            }
+        };
+
+        if self.region_selection.is_some() {
+            // Clamp to the graph, so dragging past the edge selects up to the edge.
+            let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+            let (graph_width, graph_height) =
+                self.get_graph_size(self.image.allocated_width(), self.image.allocated_height());
+            let gx = ((x - left) / graph_width as f64).max(0.0).min(1.0);
+            let gy = (1.0 - (y - BORDER_WIDTH) / graph_height as f64)
+                .max(0.0)
+                .min(1.0);
+            self.region_selection = Some((gx0, gy0, gx, gy));
+            return self.image.queue_draw();
+        }
+
+        if self.selection.is_none() && (x - x0).abs() < DRAG_THRESHOLD {
+            return;
+        }
+
+        // Clamp to the graph, so dragging past the edge selects up to the edge.
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let (graph_width, _) =
+            self.get_graph_size(self.image.allocated_width(), self.image.allocated_height());
+        let gx = ((x - left) / graph_width as f64).max(0.0).min(1.0);
+
+        self.selection = Some((gx0, gx));
+        self.image.queue_draw();
+    }
+
+    /// Move the crosshair that follows the pointer, or hide it, and ask the model for its readout.
+    fn set_cursor(&mut self, position: Option<(f64, f64)>) {
+        if position.is_none() && self.cursor.is_none() {
+            return;
+        }
+        // Keep the previous readout until the new one arrives, to avoid flicker.
+        self.cursor = position.map(|(x, y)| Probe {
+            id: 0,
+            x,
+            y,
+            label: self.cursor.take().map_or(String::new(), |c| c.label),
+        });
+        self.sender.send(ModelEvent::SetCursor(position)).unwrap();
+        self.image.queue_draw();
+    }
+
+    /// Show the level under the pointer next to the colorbar, or hide it.
+    fn set_colorbar_hover(&mut self, position: Option<f64>) {
+        if position != self.colorbar_hover {
+            self.colorbar_hover = position;
+            self.image.queue_draw();
+        }
+    }
+
+    /// Place a probe after a left click, or zoom into the range after a drag.
+    fn on_button_release(&mut self, event: &gdk::EventButton) {
+        if event.button() != 1 {
+            return;
+        }
+        let (_, (gx, gy)) = match self.press_position.take() {
+            Some(p) => p,
+            None => return,
+        };
+
+        if let Some((x0, y0, x1, y1)) = self.region_selection.take() {
+            if x0 != x1 && y0 != y1 {
+                let bounds = (x0.min(x1), x0.max(x1), y0.min(y1), y0.max(y1));
+                self.regions.push(Region {
+                    bounds,
+                    name: format!("Region {}", self.regions.len() + 1),
+                    label: String::new(),
+                });
+                let (x0, x1, y0, y1) = bounds;
+                let event = ModelEvent::AddRegion(x0, x1, y0, y1);
+                self.sender.send(event).unwrap();
+                self.update_region_list();
+            }
+            return self.image.queue_draw();
+        }
+
+        let (a, b) = match self.selection.take() {
+            Some(selection) => selection,
+            None => {
+                let id = self.next_probe_id;
+                self.next_probe_id += 1;
+                self.probes.push(Probe {
+                    id,
+                    x: gx,
+                    y: gy,
+                    label: String::new(),
+                });
+                self.sender.send(ModelEvent::AddProbe(id, gx, gy)).unwrap();
+                self.sender.send(ModelEvent::CopyFrequencyAt(gy)).unwrap();
+                return self.image.queue_draw();
+            }
+        };
+
+        let mut x0 = a.min(b);
+        let mut x1 = a.max(b);
+
+        // Snap to the ticks so repeated zooms land on round times, unless
+        // shift is held. When both edges snap to the same tick, the selection
+        // is too narrow to snap.
+        if !event.state().contains(gdk::ModifierType::SHIFT_MASK) {
+            let s0 = snap_to_ticks(x0, &self.x_ticks);
+            let s1 = snap_to_ticks(x1, &self.x_ticks);
+            if s1 > s0 {
+                x0 = s0;
+                x1 = s1;
+            }
+        }
+
+        if x1 > x0 {
+            self.sender.send(ModelEvent::SetTimeRange(x0, x1)).unwrap();
+        }
+        self.image.queue_draw();
+    }
+
+    /// Zoom in or out around the pointer, with the scroll wheel.
+    ///
+    /// Over the y-axis labels, or with Ctrl held, this zooms the frequency
+    /// axis, otherwise the time axis.
+    fn on_scroll(&mut self, event: &gdk::EventScroll) {
+        if self.show_histogram {
+            return;
+        }
+        let factor = match event.direction() {
+            gdk::ScrollDirection::Up => SCROLL_ZOOM_FACTOR.recip(),
+            gdk::ScrollDirection::Down => SCROLL_ZOOM_FACTOR,
+            _ => return,
+        };
+        let (x, y) = event.position();
+        let (gx, gy) = self.get_graph_coordinates(x, y);
+        if !(0.0..=1.0).contains(&gy) || gx > 1.0 {
+            return;
+        }
+        let ctrl = event.state().contains(gdk::ModifierType::CONTROL_MASK);
+        let event = if ctrl || gx < 0.0 {
+            ModelEvent::ZoomFrequencyAt(gy, factor)
+        } else {
+            ModelEvent::ZoomAt(gx, factor)
+        };
+        self.sender.send(event).unwrap();
+    }
+
+    /// Make the rows of the region list match the regions, and show the list if there are any.
+    fn update_region_list(&self) {
+        let rows = self.region_list.children();
+
+        // Update the rows in place if we can, rebuilding them would lose the selection.
+        if rows.len() == self.regions.len() {
+            for (row, region) in rows.iter().zip(&self.regions) {
+                let row = row.downcast_ref::<gtk::ListBoxRow>().unwrap();
+                let label = row.child().unwrap().downcast::<gtk::Label>().unwrap();
+                label.set_text(&format!("{}\n{}", region.name, region.label));
+            }
+        } else {
+            for row in &rows {
+                self.region_list.remove(row);
+            }
+            for region in &self.regions {
+                let label = gtk::Label::new(Some(&format!("{}\n{}", region.name, region.label)));
+                label.set_xalign(0.0);
+                self.region_list.add(&label);
+            }
+            self.region_list.show_all();
+            self.region_name.set_text("");
+        }
+
+        self.region_panel.set_visible(!self.regions.is_empty());
+    }
+
+    /// Show the name of the selected region in the entry, so it can be changed.
+    fn on_region_selected(&self, row: Option<&gtk::ListBoxRow>) {
+        let region = row.and_then(|row| self.regions.get(row.index() as usize));
+        self.region_name
+            .set_text(region.map_or("", |region| &region.name[..]));
+    }
+
+    /// Return the index of the region that is selected in the list, if any.
+    fn selected_region(&self) -> Option<usize> {
+        let row = self.region_list.selected_row()?;
+        Some(row.index() as usize).filter(|&i| i < self.regions.len())
+    }
+
+    fn rename_selected_region(&mut self, name: String) {
+        if let Some(i) = self.selected_region() {
+            self.regions[i].name = name;
+            self.update_region_list();
+            self.image.queue_draw();
+        }
+    }
+
+    fn delete_selected_region(&mut self) {
+        if let Some(i) = self.selected_region() {
+            self.regions.remove(i);
+            self.sender.send(ModelEvent::RemoveRegion(i)).unwrap();
+            self.update_region_list();
+            self.image.queue_draw();
+        }
+    }
+
+    fn on_drag_data_received(&self, data: &gtk::SelectionData) {
+        if let Some(uri) = data.text() {
+            // When dropped, the uri is terminated by a newline. Strip it.
+            let uri_stripped = uri.as_str().trim_end();
+            if let Ok((fname, _)) = glib::filename_from_uri(uri_stripped) {
+                self.sender.send(ModelEvent::OpenFile(fname)).unwrap();
+            }
+
+            // Nautilus used to send uris as text, but now it sends uris handled
+            // by the loop below. Nemo used to send urls, but now it sends just
+            // the file path as text. So if it's not a filename uri, then try
+            // and see if the path exists.
+            if let Ok(_meta) = std::fs::metadata(&uri_stripped) {
+                self.sender.send(ModelEvent::OpenFile(uri_stripped.into())).unwrap();
+            }
+
+            return;
+        }
+
+        for uri in data.uris() {
+            if let Ok((fname, _)) = glib::filename_from_uri(&uri) {
+                self.sender.send(ModelEvent::OpenFile(fname)).unwrap();
+            }
+            return;
+        }
+    }
+
+    fn on_size_allocate(&self, rect: &gtk::Rectangle) {
+        let (width, height) = self.get_graph_size(rect.width(), rect.height());
+        let f = self.image.scale_factor();
+        let event = ModelEvent::Resize(
+            width * f,
+            height * f,
+            self.x_label_width * f,
+            self.label_height * f,
+        );
+        self.sender.send(event).unwrap();
+    }
+
+    fn on_draw(&self, ctx: &cairo::Context) {
+        let actual_size = self.image.allocation();
+
+        // Draw the frame, ticks, and labels in the foreground color of the
+        // theme, so they are visible on both light and dark themes.
+        let fg = self.image.style_context().color(gtk::StateFlags::NORMAL);
+
+        if self.encode_srgb {
+            self.draw_linear(ctx, actual_size.width(), actual_size.height(), &fg);
+        } else {
+            self.draw(ctx, actual_size.width(), actual_size.height(), &fg);
+        }
+
+        // The crosshair that follows the pointer is not part of the view, so
+        // we draw it here, and not in `draw`, which also exports to svg.
+        if let (Some(cursor), false, false, None) = (
+            &self.cursor,
+            self.clean_view,
+            self.show_histogram,
+            self.press_position,
+        ) {
+            let (graph_width, graph_height) =
+                self.get_graph_size(actual_size.width(), actual_size.height());
+            self.draw_probes(ctx, slice::from_ref(cursor), graph_width, graph_height);
+        }
+    }
+
+    /// Draw the spectrogram with its axes and overlays, at the given size in display pixels.
+    fn draw(&self, ctx: &cairo::Context, width: i32, height: i32, fg: &gdk::RGBA) {
+        self.draw_spectrogram(ctx, width, height);
+        self.draw_overlays(ctx, width, height, fg);
+    }
+
+    /// Like `draw`, but blend the overlays onto the spectrogram in linear light.
+    ///
+    /// Cairo blends the sRGB-encoded values as they are, which makes
+    /// translucent overlays too dark. So we draw the spectrogram and the
+    /// overlays onto separate surfaces, and blend those ourselves. An svg has
+    /// no pixels to blend, so the svg export always uses `draw`.
+    fn draw_linear(&self, ctx: &cairo::Context, width: i32, height: i32, fg: &gdk::RGBA) {
+        let scale = self.image.scale_factor();
+        let new_layer = || {
+            let format = cairo::Format::ARgb32;
+            let layer = cairo::ImageSurface::create(format, width * scale, height * scale).unwrap();
+            layer.set_device_scale(scale as f64, scale as f64);
+            layer
+        };
+        let mut base = new_layer();
+        let mut overlays = new_layer();
+        self.draw_spectrogram(&cairo::Context::new(&base).unwrap(), width, height);
+        self.draw_overlays(&cairo::Context::new(&overlays).unwrap(), width, height, fg);
+
+        {
+            let overlays_data = overlays.data().unwrap();
+            let mut base_data = base.data().unwrap();
+            render::blend_over_linear(&mut base_data, &overlays_data);
+        }
+
+        ctx.set_source_surface(&base, 0.0, 0.0).unwrap();
+        ctx.paint().unwrap();
+    }
+
+    /// Draw the bitmap of the spectrogram into the graph area.
+    fn draw_spectrogram(&self, ctx: &cairo::Context, width: i32, height: i32) {
+        let transform = ctx.matrix();
+        let (graph_width, graph_height) = self.get_graph_size(width, height);
+
+        if let Some(pixbuf) = self.pixbuf.as_ref() {
+            // Stretch the bitmap to fill the entire widget. This has two
+            // purposes. First, we sized the bitmap to take the DPI scaling
+            // factor into account, so we may need to scale it down, because the
+            // Cairo context by default measures display pixels, not device
+            // pixels. Second, if you are resizing and the new pixel-perfect
+            // bitmap is still being rendered, we can stretch the old one to
+            // hide the fact that the new one is not ready, instead of having a
+            // gap, or having the image be truncated.
+            let scale_x = graph_width as f64 / pixbuf.width() as f64;
+            let scale_y = graph_height as f64 / pixbuf.height() as f64;
+            ctx.scale(scale_x, scale_y);
+            ctx.set_source_pixbuf(
+                pixbuf,
+                // To the left we have the label and ticks, and a 1px border. At
+                // the top we only have the 1px border.
+                (self.label_width as f64 + TICK_SIZE + TICK_PADDING + 1.0) / scale_x,
+                1.0 / scale_y,
+            );
+            ctx.paint().unwrap();
+
+            // Undo the scale, so we can draw in display pixels again later.
+            ctx.set_matrix(transform);
+        }
+    }
+
+    /// Draw the axes, the colorbar, and everything else that goes over the spectrogram.
+    fn draw_overlays(&self, ctx: &cairo::Context, width: i32, height: i32, fg: &gdk::RGBA) {
+        let (graph_width, graph_height) = self.get_graph_size(width, height);
+
+        // In the clean view, we draw nothing over the spectrogram but the
+        // selection, which is feedback for dragging. The flags of the
+        // individual overlays remain, so they come back when we leave it.
+        let overlays = !self.clean_view;
+
+        // Everything that is positioned in time, only makes sense on the spectrogram.
+        let over_time = overlays && !self.show_histogram;
+
+        if over_time && self.show_envelope && !self.envelope.is_empty() {
+            self.draw_envelope(ctx, graph_width, graph_height);
+        }
+
+        if overlays {
+            self.draw_axes(ctx, graph_width, graph_height, fg);
+            self.draw_colorbar(ctx, graph_width, graph_height, fg);
+        }
+
+        // Separate the DC strip, so its linear axis is not mistaken for the rest.
+        if overlays && self.show_dc_strip {
+            let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+            let y = BORDER_WIDTH + graph_height as f64 * (1.0 - DC_STRIP_HEIGHT);
+            ctx.move_to(left, y.round() + 0.5);
+            ctx.line_to(left + graph_width as f64, y.round() + 0.5);
+            ctx.set_line_width(1.0);
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.6);
+            ctx.stroke().unwrap();
+        }
+
+        if over_time && self.show_centroid {
+            ctx.set_source_rgba(1.0, 0.6, 0.2, 0.9);
+            self.draw_line(ctx, &self.centroid_line, graph_width, graph_height);
+        }
+        if over_time && self.show_rolloff {
+            ctx.set_source_rgba(0.3, 0.8, 1.0, 0.9);
+            self.draw_line(ctx, &self.rolloff_line, graph_width, graph_height);
+        }
+
+        if over_time && self.show_flux {
+            self.draw_flux(ctx, graph_width, graph_height);
+        }
+
+        if over_time && !self.compare_peaks.is_empty() {
+            self.draw_compare_peaks(ctx, graph_width, graph_height);
+        }
+
+        if overlays && self.show_notes {
+            self.draw_note_lines(ctx, graph_width, graph_height);
+        }
+
+        if overlays && self.show_nyquist {
+            self.draw_nyquist_lines(ctx, graph_width, graph_height);
+        }
+
+        if over_time && !self.track_markers.is_empty() {
+            self.draw_track_markers(ctx, graph_width, graph_height);
+        }
+
+        if let (true, Some(marker)) = (overlays, &self.frequency_marker) {
+            self.draw_frequency_marker(ctx, marker, graph_width, graph_height);
+        }
+
+        if overlays && self.show_legend && !self.legend.is_empty() {
+            self.draw_legend(ctx);
+        }
+
+        if let Some((a, b)) = self.selection {
+            let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+            let x0 = left + graph_width as f64 * a.min(b);
+            let x1 = left + graph_width as f64 * a.max(b);
+            ctx.rectangle(x0, BORDER_WIDTH, x1 - x0, graph_height as f64);
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.2);
+            ctx.fill_preserve().unwrap();
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.6);
+            ctx.set_line_width(1.0);
+            ctx.stroke().unwrap();
+        }
+
+        if let Some((x0, y0, x1, y1)) = self.region_selection {
+            let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+            ctx.rectangle(
+                left + graph_width as f64 * x0.min(x1),
+                BORDER_WIDTH + graph_height as f64 * (1.0 - y0.max(y1)),
+                graph_width as f64 * (x1 - x0).abs(),
+                graph_height as f64 * (y1 - y0).abs(),
+            );
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.2);
+            ctx.fill_preserve().unwrap();
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.6);
+            ctx.set_line_width(1.0);
+            ctx.stroke().unwrap();
+        }
+
+        if over_time {
+            // Regions and probes that are out of view lie outside of the graph.
+            let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+            ctx.save().unwrap();
+            ctx.rectangle(left, BORDER_WIDTH, graph_width as f64, graph_height as f64);
+            ctx.clip();
+            self.draw_regions(ctx, graph_width, graph_height);
+            self.draw_probes(ctx, &self.probes, graph_width, graph_height);
+            ctx.restore().unwrap();
+        }
+    }
+
+    /// Draw the frame around the graph, and the ticks and their labels, in color `fg`.
+    fn draw_axes(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32, fg: &gdk::RGBA) {
+        // Draw a frame around the spectrum view.
+        ctx.rectangle(
+            self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH * 0.5,
+            BORDER_WIDTH * 0.5,
+            graph_width as f64 + BORDER_WIDTH,
+            graph_height as f64 + BORDER_WIDTH,
         );
 
         for tick in &self.y_ticks {
@@ -406,7 +2565,11 @@ impl View {
             ctx.move_to(x, y);
             ctx.line_to(x + TICK_SIZE, y);
 
-            let x = actual_size.width() as f64 - BORDER_WIDTH;
+            let x = self.label_width as f64
+                + TICK_SIZE
+                + TICK_PADDING
+                + graph_width as f64
+                + BORDER_WIDTH;
             ctx.move_to(x, y);
             ctx.line_to(x - TICK_SIZE, y);
         }
@@ -426,40 +2589,30 @@ impl View {
             ctx.line_to(x, y + TICK_SIZE);
         }
 
+        // Draw a wider outline in a contrasting color underneath, so the frame
+        // remains visible where the spectrogram at the edge has a similar color.
+        let luminance = 0.2126 * fg.red() + 0.7152 * fg.green() + 0.0722 * fg.blue();
+        let outline = if luminance > 0.5 { 0.0 } else { 1.0 };
+        ctx.set_line_width(BORDER_WIDTH + 2.0);
+        ctx.set_source_rgba(outline, outline, outline, 0.5 * fg.alpha());
+        ctx.stroke_preserve().unwrap();
+
         ctx.set_line_width(BORDER_WIDTH);
-        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.8);
+        ctx.set_source_rgba(fg.red(), fg.green(), fg.blue(), 0.8 * fg.alpha());
         ctx.stroke().unwrap();
 
-        for tick in &self.y_ticks {
-            let layout = self.window.create_pango_layout(Some(&tick.label[..]));
-
+        for (tick, layout) in self.y_ticks.iter().zip(&self.y_layouts) {
             // Align the label right, next to the tick.
             let (width, _height) = layout.pixel_size();
             let x = self.label_width as f64 - width as f64;
             let y = BORDER_WIDTH + graph_height as f64 * (1.0 - tick.position);
 
             // Vertically align the label text to the tick.
-            // Based on http://gtk.10911.n7.nabble.com/Pango-Accessing-x-height-mean-line-in-Pango-layout-td79374.html.
-            let pango_context = layout.context();
-            let font = layout.font_description();
-            let language = None;
-            let metrics = pango_context.metrics(font.as_ref(), language);
-            let baseline = layout.baseline();
-            let strike_pos = metrics.strikethrough_position();
-            let strike_thick = metrics.strikethrough_thickness();
-            let x_center_font_units = baseline - strike_pos - strike_thick / 2;
-            // Convert font units to view pixels, see also
-            // https://developer.gnome.org/pango/stable/pango-Glyph-Storage.html#PANGO-PIXELS:CAPS
-            let x_center_pixels = (x_center_font_units + 512) >> 10;
-
-            ctx.move_to(x, y - x_center_pixels as f64);
-            pangocairo::functions::show_layout(ctx, &layout);
+            ctx.move_to(x, y - self.label_center);
+            pangocairo::functions::show_layout(ctx, layout);
         }
 
-        // TODO: Fill a vec with these and walk the ticks only once.
-        for tick in &self.x_ticks {
-            let layout = self.window.create_pango_layout(Some(&tick.label[..]));
-
+        for (tick, layout) in self.x_ticks.iter().zip(&self.x_layouts) {
             // Center the label.
             let (width, _height) = layout.pixel_size();
             let x = self.label_width as f64
@@ -471,405 +2624,5992 @@ impl View {
             let y = graph_height as f64 + BORDER_WIDTH + TICK_PADDING + TICK_SIZE;
 
             ctx.move_to(x, y);
-            pangocairo::functions::show_layout(ctx, &layout);
+            pangocairo::functions::show_layout(ctx, layout);
         }
     }
 
-    /// Handle one event. Should only be called on the main thread.
-    fn handle_event(&mut self, event: ViewEvent) {
-        match event {
-            ViewEvent::SetTitle(fname) => {
-                self.window.set_title(&fname);
-                self.header_bar.set_title(Some(&fname));
-            }
-            ViewEvent::SetView(bitmap) => {
-                self.pixbuf = Some(bitmap.into_pixbuf());
-                self.image.queue_draw();
-            }
-            ViewEvent::SetTicks(x_ticks, y_ticks) => {
-                self.x_ticks = x_ticks;
-                self.y_ticks = y_ticks;
-                self.image.queue_draw();
-            }
-        }
+    /// Draw the spectral flux as a strip along the bottom, with onset markers.
+    fn draw_flux(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let bottom = BORDER_WIDTH + graph_height as f64;
+        let strip_height = (graph_height as f64 * 0.15).round();
+
+        // Darken the strip a bit, so the curve stands out against the spectrogram.
+        ctx.rectangle(
+            left,
+            bottom - strip_height,
+            graph_width as f64,
+            strip_height,
+        );
+        ctx.set_source_rgba(0.0, 0.0, 0.0, 0.4);
+        ctx.fill().unwrap();
+
+        for &(x, y) in &self.flux_line {
+            ctx.line_to(left + graph_width as f64 * x, bottom - strip_height * y);
+        }
+        ctx.set_line_width(1.0);
+        ctx.set_source_rgba(0.6, 1.0, 0.4, 0.9);
+        ctx.stroke().unwrap();
+
+        for &x in &self.onsets {
+            let x = left + graph_width as f64 * x;
+            ctx.move_to(x, bottom - strip_height);
+            ctx.line_to(x, bottom);
+        }
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.8);
+        ctx.stroke().unwrap();
+    }
+
+    /// Draw the analysis parameters in the top left corner of the graph.
+    fn draw_legend(&self, ctx: &cairo::Context) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let top = BORDER_WIDTH;
+        let layout = self.window.create_pango_layout(Some(&self.legend[..]));
+        let (width, height) = layout.pixel_size();
+
+        // Use the same backdrop as for the probe labels.
+        let x = left + TICK_PADDING;
+        let y = top + TICK_PADDING;
+        ctx.rectangle(x - 2.0, y, width as f64 + 4.0, height as f64);
+        ctx.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+        ctx.fill().unwrap();
+
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+        ctx.move_to(x, y);
+        pangocairo::functions::show_layout(ctx, &layout);
+    }
+
+    /// Draw dashed horizontal lines at the Nyquist frequency and the bandwidth.
+    fn draw_nyquist_lines(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let right = left + graph_width as f64;
+
+        for line in &self.nyquist_lines {
+            let y = BORDER_WIDTH + graph_height as f64 * (1.0 - line.position);
+            ctx.move_to(left, y);
+            ctx.line_to(right, y);
+            ctx.set_dash(&[4.0, 4.0], 0.0);
+            ctx.set_line_width(1.0);
+            ctx.set_source_rgba(1.0, 0.4, 0.4, 0.9);
+            ctx.stroke().unwrap();
+            ctx.set_dash(&[], 0.0);
+
+            // Put the label right-aligned below the line, which keeps it inside
+            // the graph for the Nyquist line at the top.
+            let layout = self.window.create_pango_layout(Some(&line.label[..]));
+            let (width, _height) = layout.pixel_size();
+            ctx.move_to(right - TICK_PADDING - width as f64, y + 2.0);
+            pangocairo::functions::show_layout(ctx, &layout);
+        }
+    }
+
+    /// Draw faint horizontal lines at the notes, with the name of every C at the left.
+    fn draw_note_lines(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let right = left + graph_width as f64;
+
+        // Towards the bottom of a linear axis the notes get too close to tell
+        // apart. There we draw only the Cs, and lower still, not even those.
+        let mut prev_y = f64::INFINITY;
+        let mut prev_c_y = f64::INFINITY;
+        let mut prev_label_y = f64::INFINITY;
+        for line in &self.note_lines {
+            let y = BORDER_WIDTH + graph_height as f64 * (1.0 - line.position);
+            let is_c = !line.label.is_empty();
+            let spacing = if is_c { prev_c_y - y } else { prev_y - y };
+            prev_y = y;
+            if is_c {
+                prev_c_y = y;
+            }
+            if spacing < MIN_NOTE_SPACING {
+                continue;
+            }
+
+            ctx.move_to(left, y);
+            ctx.line_to(right, y);
+            ctx.set_line_width(1.0);
+            let alpha = if is_c { 0.35 } else { 0.12 };
+            ctx.set_source_rgba(1.0, 1.0, 1.0, alpha);
+            ctx.stroke().unwrap();
+
+            if is_c && prev_label_y - y >= self.label_height as f64 {
+                prev_label_y = y;
+                let layout = self.window.create_pango_layout(Some(&line.label[..]));
+                let (_width, height) = layout.pixel_size();
+                ctx.set_source_rgba(1.0, 1.0, 1.0, 0.7);
+                ctx.move_to(left + TICK_PADDING, y - height as f64);
+                pangocairo::functions::show_layout(ctx, &layout);
+            }
+        }
+    }
+
+    /// Draw the peaks of the previous file as translucent dots, brighter for stronger peaks.
+    fn draw_compare_peaks(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let bottom = BORDER_WIDTH + graph_height as f64;
+        for &(x, y, alpha) in &self.compare_peaks {
+            let x = left + graph_width as f64 * x;
+            let y = bottom - graph_height as f64 * y;
+            ctx.arc(x, y, 1.5, 0.0, 2.0 * std::f64::consts::PI);
+            ctx.set_source_rgba(0.3, 0.9, 1.0, alpha as f64);
+            ctx.fill().unwrap();
+        }
+    }
+
+    /// Draw a horizontal line at the marked frequency, labeled at the left.
+    fn draw_frequency_marker(
+        &self,
+        ctx: &cairo::Context,
+        marker: &Tick,
+        graph_width: i32,
+        graph_height: i32,
+    ) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let y = BORDER_WIDTH + graph_height as f64 * (1.0 - marker.position);
+        ctx.move_to(left, y.round() + 0.5);
+        ctx.line_to(left + graph_width as f64, y.round() + 0.5);
+        ctx.set_line_width(1.0);
+        ctx.set_source_rgba(0.4, 1.0, 0.4, 0.9);
+        ctx.stroke().unwrap();
+
+        // Put the label above the line, unless that is outside of the graph.
+        let layout = self.window.create_pango_layout(Some(&marker.label[..]));
+        let (width, height) = layout.pixel_size();
+        let label_y = if y - height as f64 - 2.0 < BORDER_WIDTH {
+            y + 2.0
+        } else {
+            y - height as f64 - 2.0
+        };
+        ctx.rectangle(left + 2.0, label_y, width as f64 + 4.0, height as f64);
+        ctx.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+        ctx.fill().unwrap();
+        ctx.set_source_rgba(0.4, 1.0, 0.4, 0.9);
+        ctx.move_to(left + 4.0, label_y);
+        pangocairo::functions::show_layout(ctx, &layout);
+    }
+
+    /// Draw dashed vertical lines at the starts of tracks, labeled at the bottom.
+    fn draw_track_markers(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let bottom = BORDER_WIDTH + graph_height as f64;
+
+        for marker in &self.track_markers {
+            let x = left + graph_width as f64 * marker.position;
+            ctx.move_to(x, BORDER_WIDTH);
+            ctx.line_to(x, bottom);
+            ctx.set_dash(&[4.0, 4.0], 0.0);
+            ctx.set_line_width(1.0);
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.7);
+            ctx.stroke().unwrap();
+            ctx.set_dash(&[], 0.0);
+
+            // Put the label to the right of the line, with the same backdrop
+            // as the legend, so it is readable on a bright spectrogram.
+            let layout = self.window.create_pango_layout(Some(&marker.label[..]));
+            let (width, height) = layout.pixel_size();
+            let y = bottom - TICK_PADDING - height as f64;
+            ctx.rectangle(x + 2.0, y, width as f64 + 4.0, height as f64);
+            ctx.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+            ctx.fill().unwrap();
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+            ctx.move_to(x + 4.0, y);
+            pangocairo::functions::show_layout(ctx, &layout);
+        }
+    }
+
+    /// Draw the waveform envelope as faint bands centered vertically on the graph.
+    fn draw_envelope(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let center = BORDER_WIDTH + graph_height as f64 * 0.5;
+        let column_width = graph_width as f64 / self.envelope.len() as f64;
+
+        // Fill the peak envelope first, and then the RMS envelope over it, so
+        // the RMS envelope shows as the brighter band on the inside.
+        for (i, &(peak, _rms)) in self.envelope.iter().enumerate() {
+            let h = peak.min(1.0) as f64 * graph_height as f64 * 0.5;
+            let x = left + i as f64 * column_width;
+            ctx.rectangle(x, center - h, column_width, 2.0 * h);
+        }
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.12);
+        ctx.fill().unwrap();
+
+        for (i, &(_peak, rms)) in self.envelope.iter().enumerate() {
+            let h = rms.min(1.0) as f64 * graph_height as f64 * 0.5;
+            let x = left + i as f64 * column_width;
+            ctx.rectangle(x, center - h, column_width, 2.0 * h);
+        }
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.12);
+        ctx.fill().unwrap();
+    }
+
+    /// Stroke a polyline in graph coordinates with the current source color.
+    fn draw_line(
+        &self,
+        ctx: &cairo::Context,
+        points: &[(f64, f64)],
+        graph_width: i32,
+        graph_height: i32,
+    ) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let top = BORDER_WIDTH;
+
+        for &(x, y) in points {
+            ctx.line_to(
+                left + graph_width as f64 * x,
+                top + graph_height as f64 * (1.0 - y),
+            );
+        }
+
+        ctx.set_line_width(1.5);
+        ctx.stroke().unwrap();
+    }
+
+    /// Draw an outline with its name for every region.
+    fn draw_regions(&self, ctx: &cairo::Context, graph_width: i32, graph_height: i32) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let top = BORDER_WIDTH;
+
+        for region in &self.regions {
+            let (x0, x1, y0, y1) = region.bounds;
+            let x = left + graph_width as f64 * x0;
+            let y = top + graph_height as f64 * (1.0 - y1);
+            ctx.rectangle(
+                x,
+                y,
+                graph_width as f64 * (x1 - x0),
+                graph_height as f64 * (y1 - y0),
+            );
+            ctx.set_line_width(1.0);
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.7);
+            ctx.stroke().unwrap();
+
+            // Put the name inside the top left corner, with the same backdrop
+            // as the probe labels.
+            let layout = self.window.create_pango_layout(Some(&region.name[..]));
+            let (width, height) = layout.pixel_size();
+            ctx.rectangle(x + 1.0, y + 1.0, width as f64 + 4.0, height as f64);
+            ctx.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+            ctx.fill().unwrap();
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+            ctx.move_to(x + 3.0, y + 1.0);
+            pangocairo::functions::show_layout(ctx, &layout);
+        }
+    }
+
+    /// Draw the colorbar to the right of the graph, with its ticks and labels in color `fg`.
+    fn draw_colorbar(
+        &self,
+        ctx: &cairo::Context,
+        graph_width: i32,
+        graph_height: i32,
+        fg: &gdk::RGBA,
+    ) {
+        let colorbar = match self.colorbar.as_ref() {
+            Some(c) => c,
+            None => return,
+        };
+        let left = self.label_width as f64
+            + TICK_SIZE
+            + TICK_PADDING
+            + graph_width as f64
+            + 2.0 * BORDER_WIDTH
+            + COLORBAR_PADDING;
+        let top = BORDER_WIDTH;
+
+        ctx.save().unwrap();
+        ctx.translate(left, top);
+        ctx.scale(
+            COLORBAR_WIDTH / colorbar.width() as f64,
+            graph_height as f64 / colorbar.height() as f64,
+        );
+        ctx.set_source_pixbuf(colorbar, 0.0, 0.0);
+        ctx.paint().unwrap();
+        ctx.restore().unwrap();
+
+        ctx.rectangle(
+            left - BORDER_WIDTH * 0.5,
+            top - BORDER_WIDTH * 0.5,
+            COLORBAR_WIDTH + BORDER_WIDTH,
+            graph_height as f64 + BORDER_WIDTH,
+        );
+        let right = left + COLORBAR_WIDTH;
+        for tick in &self.colorbar_ticks {
+            let y = top + graph_height as f64 * (1.0 - tick.position);
+            ctx.move_to(right, y);
+            ctx.line_to(right + TICK_SIZE, y);
+        }
+        ctx.set_line_width(BORDER_WIDTH);
+        ctx.set_source_rgba(fg.red(), fg.green(), fg.blue(), 0.8 * fg.alpha());
+        ctx.stroke().unwrap();
+
+        for (tick, layout) in self.colorbar_ticks.iter().zip(&self.colorbar_layouts) {
+            let y = top + graph_height as f64 * (1.0 - tick.position);
+            ctx.move_to(right + TICK_SIZE + TICK_PADDING, y - self.label_center);
+            pangocairo::functions::show_layout(ctx, layout);
+        }
+
+        // Under the pointer, mark the level across the colorbar, and put its
+        // value to the left, on a backdrop like that of the crosshairs.
+        let position = match self.colorbar_hover {
+            Some(p) if self.colorbar_shows_levels() => p,
+            _ => return,
+        };
+        let y = top + graph_height as f64 * (1.0 - position);
+        ctx.move_to(left, y);
+        ctx.line_to(right, y);
+        ctx.set_line_width(1.0);
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.8);
+        ctx.stroke().unwrap();
+
+        let label = format!("{:.0} dB", self.level_at_colorbar(position));
+        let layout = self.window.create_pango_layout(Some(&label[..]));
+        let (width, height) = layout.pixel_size();
+        let label_x = left - BORDER_WIDTH - TICK_PADDING - width as f64;
+        let label_y = (y - 0.5 * height as f64)
+            .max(top)
+            .min(top + graph_height as f64 - height as f64);
+        ctx.rectangle(label_x - 2.0, label_y, width as f64 + 4.0, height as f64);
+        ctx.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+        ctx.fill().unwrap();
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+        ctx.move_to(label_x, label_y);
+        pangocairo::functions::show_layout(ctx, &layout);
+    }
+
+    /// Draw a crosshair with its readout for every probe.
+    fn draw_probes(
+        &self,
+        ctx: &cairo::Context,
+        probes: &[Probe],
+        graph_width: i32,
+        graph_height: i32,
+    ) {
+        let left = self.label_width as f64 + TICK_SIZE + TICK_PADDING + BORDER_WIDTH;
+        let top = BORDER_WIDTH;
+
+        for probe in probes {
+            let x = left + graph_width as f64 * probe.x;
+            let y = top + graph_height as f64 * (1.0 - probe.y);
+            ctx.move_to(x, top);
+            ctx.line_to(x, top + graph_height as f64);
+            ctx.move_to(left, y);
+            ctx.line_to(left + graph_width as f64, y);
+        }
+
+        ctx.set_line_width(1.0);
+        ctx.set_source_rgba(1.0, 1.0, 1.0, 0.5);
+        ctx.stroke().unwrap();
+
+        for probe in probes {
+            let layout = self.window.create_pango_layout(Some(&probe.label[..]));
+            let (width, height) = layout.pixel_size();
+            let x = left + graph_width as f64 * probe.x;
+            let y = top + graph_height as f64 * (1.0 - probe.y);
+
+            // Put the label to the top right of the crosshair, but flip it to
+            // the other side when it would extend beyond the graph.
+            let label_x = if x + TICK_PADDING + width as f64 > left + graph_width as f64 {
+                x - TICK_PADDING - width as f64
+            } else {
+                x + TICK_PADDING
+            };
+            let label_y = if y - TICK_PADDING - (height as f64) < top {
+                y + TICK_PADDING
+            } else {
+                y - TICK_PADDING - height as f64
+            };
+
+            // Put a translucent backdrop behind the label, so it remains
+            // readable on top of bright parts of the spectrogram.
+            ctx.rectangle(label_x - 2.0, label_y, width as f64 + 4.0, height as f64);
+            ctx.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+            ctx.fill().unwrap();
+
+            ctx.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+            ctx.move_to(label_x, label_y);
+            pangocairo::functions::show_layout(ctx, &layout);
+        }
+    }
+
+    /// Show a dialog to pick one of the audio files in an archive to open.
+    fn choose_archive_entry(&self, archive_fname: PathBuf, entries: Vec<String>) {
+        let dialog = gtk::Dialog::with_buttons(
+            Some("Open from Archive"),
+            Some(&self.window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Open", gtk::ResponseType::Accept),
+            ],
+        );
+        dialog.set_default_size(400, 300);
+
+        let list = gtk::ListBox::new();
+        for entry in &entries {
+            let label = gtk::Label::new(Some(entry));
+            label.set_xalign(0.0);
+            list.add(&label);
+        }
+        list.select_row(list.row_at_index(0).as_ref());
+
+        let scrolled = gtk::ScrolledWindow::new(gtk::Adjustment::NONE, gtk::Adjustment::NONE);
+        scrolled.add(&list);
+        let expand = true;
+        let fill = true;
+        let padding = 0;
+        dialog
+            .content_area()
+            .pack_start(&scrolled, expand, fill, padding);
+
+        // Double-clicking an entry opens it, like in a file chooser.
+        let dialog_clone = dialog.clone();
+        list.connect_row_activated(move |_self, _row| {
+            dialog_clone.response(gtk::ResponseType::Accept);
+        });
+
+        let sender = self.sender.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(row) = list.selected_row() {
+                    let entry_name = entries[row.index() as usize].clone();
+                    let event = ModelEvent::OpenArchiveEntry(archive_fname.clone(), entry_name);
+                    sender.send(event).unwrap();
+                }
+            }
+            dialog.close();
+        });
+
+        dialog.show_all();
+    }
+
+    /// Ask the user for a file name, and export the view to it as svg.
+    fn choose_svg_file(view_cell: &Rc<RefCell<View>>) {
+        let view = view_cell.borrow();
+        if view.pixbuf.is_none() {
+            return;
+        }
+
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Export as SVG"),
+            Some(&view.window),
+            gtk::FileChooserAction::Save,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Export", gtk::ResponseType::Accept),
+            ],
+        );
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("spectrogram.svg");
+
+        let view_cell = view_cell.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(fname) = dialog.filename() {
+                    let view = view_cell.borrow();
+                    if let Err(err) = view.save_svg(&fname) {
+                        view.show_error(&format!("Failed to write {}: {}", fname.display(), err));
+                    }
+                }
+            }
+            dialog.close();
+        });
+
+        dialog.show_all();
+    }
+
+    /// Ask the user for an audio file or zip archive, and open it.
+    fn choose_file_to_open(&self) {
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Open"),
+            Some(&self.window),
+            gtk::FileChooserAction::Open,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Open", gtk::ResponseType::Accept),
+            ],
+        );
+
+        // Patterns are case-sensitive, so we add upper case too, as some
+        // rippers and cameras produce it.
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some("Audio files"));
+        let mut extensions = archive::AUDIO_EXTENSIONS.to_vec();
+        extensions.extend(external::TOOLS.iter().map(|tool| tool.extension));
+        extensions.push("zip");
+        for extension in extensions {
+            filter.add_pattern(&format!("*.{}", extension));
+            filter.add_pattern(&format!("*.{}", extension.to_uppercase()));
+        }
+        dialog.add_filter(filter);
+
+        let all_files = gtk::FileFilter::new();
+        all_files.set_name(Some("All files"));
+        all_files.add_pattern("*");
+        dialog.add_filter(all_files);
+
+        // This sends the same event as dropping the file on the window.
+        let sender = self.sender.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(fname) = dialog.filename() {
+                    sender.send(ModelEvent::OpenFile(fname)).unwrap();
+                }
+            }
+            dialog.close();
+        });
+
+        dialog.show_all();
+    }
+
+    /// Ask the user for a file name, and write the view to it as a png image.
+    fn choose_png_file(view_cell: &Rc<RefCell<View>>) {
+        let view = view_cell.borrow();
+        if view.pixbuf.is_none() {
+            return;
+        }
+
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Export as PNG"),
+            Some(&view.window),
+            gtk::FileChooserAction::Save,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Export", gtk::ResponseType::Accept),
+            ],
+        );
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("spectrogram.png");
+
+        let view_cell = view_cell.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(fname) = dialog.filename() {
+                    let view = view_cell.borrow();
+                    if let Err(err) = view.save_png(&fname) {
+                        view.show_error(&format!("Failed to write {}: {}", fname.display(), err));
+                    }
+                }
+            }
+            dialog.close();
+        });
+
+        dialog.show_all();
+    }
+
+    /// Ask the user for a file name, and have the model export the spectral peaks to it.
+    fn choose_peaks_file(&self) {
+        if self.pixbuf.is_none() {
+            return;
+        }
+
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Export peaks as CSV"),
+            Some(&self.window),
+            gtk::FileChooserAction::Save,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Export", gtk::ResponseType::Accept),
+            ],
+        );
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("peaks.csv");
+
+        let sender = self.sender.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(fname) = dialog.filename() {
+                    sender.send(ModelEvent::ExportPeaks(fname)).unwrap();
+                }
+            }
+            dialog.close();
+        });
+
+        dialog.show_all();
+    }
+
+    /// Ask the user for a name, and save the current settings as a preset by that name.
+    ///
+    /// The name of the selected preset is filled in, to make updating it easy.
+    fn choose_preset_name(&self) {
+        let dialog = gtk::Dialog::with_buttons(
+            Some("Save Preset"),
+            Some(&self.window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Save", gtk::ResponseType::Accept),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Accept);
+
+        let entry = gtk::Entry::new();
+        entry.set_placeholder_text(Some("Name"));
+        entry.set_activates_default(true);
+        if let Some(name) = self.preset_combo.active_id() {
+            entry.set_text(&name);
+        }
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        dialog
+            .content_area()
+            .pack_start(&entry, expand, fill, padding);
+
+        let sender = self.sender.clone();
+        dialog.connect_response(move |dialog, response| {
+            let name = entry.text().trim().to_string();
+            if response == gtk::ResponseType::Accept && !name.is_empty() {
+                sender.send(ModelEvent::SavePreset(name)).unwrap();
+            }
+            dialog.close();
+        });
+
+        dialog.show_all();
+    }
+
+    /// Delete the preset that is selected in the picker, if any.
+    fn delete_preset(&self) {
+        if let Some(name) = self.preset_combo.active_id() {
+            self.sender
+                .send(ModelEvent::DeletePreset(name.to_string()))
+                .unwrap();
+        }
+    }
+
+    /// Show a message below the title for a moment.
+    fn show_status(&self, message: &str) {
+        self.header_bar.set_subtitle(Some(message));
+
+        // A newer message may have replaced this one by the time it expires.
+        // If not, we go back to the format of the file.
+        let header_bar = self.header_bar.clone();
+        let message = message.to_string();
+        let metadata = self.metadata.clone();
+        glib::timeout_add_local_once(STATUS_DURATION, move || {
+            if header_bar.subtitle().map_or(false, |s| s == message) {
+                header_bar.set_subtitle(metadata.as_deref());
+            }
+        });
+    }
+
+    /// Write the view to an svg file, at the size that it has on screen.
+    ///
+    /// The spectrogram is embedded as a raster image, but the axes, labels,
+    /// and overlays are vector graphics, so they stay sharp when scaled.
+    fn save_svg(&self, fname: &Path) -> Result<(), cairo::Error> {
+        let size = self.image.allocation();
+        let (width, height) = (size.width(), size.height());
+        let surface = cairo::SvgSurface::new(width as f64, height as f64, Some(fname))?;
+        let ctx = cairo::Context::new(&surface)?;
+
+        // Figures usually end up on white paper, so we draw the axes in
+        // black, rather than in the color of the theme.
+        let black = gdk::RGBA::new(0.0, 0.0, 0.0, 1.0);
+        self.draw(&ctx, width, height, &black);
+
+        drop(ctx);
+        surface.finish();
+        surface.status()
+    }
+
+    /// Write the view to a png file, at the size that it has on screen, in device pixels.
+    ///
+    /// Like the svg export, this includes the axes and overlays, drawn in
+    /// black on a white background.
+    fn save_png(&self, fname: &Path) -> Result<(), glib::Error> {
+        let size = self.image.allocation();
+        let (width, height) = (size.width(), size.height());
+        let scale = self.image.scale_factor();
+        let to_glib_error =
+            |err: cairo::Error| glib::Error::new(glib::FileError::Failed, &format!("{:?}", err));
+        let surface =
+            cairo::ImageSurface::create(cairo::Format::Rgb24, width * scale, height * scale)
+                .map_err(to_glib_error)?;
+        let ctx = cairo::Context::new(&surface).map_err(to_glib_error)?;
+        ctx.scale(scale as f64, scale as f64);
+        ctx.set_source_rgb(1.0, 1.0, 1.0);
+        ctx.paint().map_err(to_glib_error)?;
+
+        let black = gdk::RGBA::new(0.0, 0.0, 0.0, 1.0);
+        if self.encode_srgb {
+            self.draw_linear(&ctx, width, height, &black);
+        } else {
+            self.draw(&ctx, width, height, &black);
+        }
+        drop(ctx);
+
+        match gdk::pixbuf_get_from_surface(&surface, 0, 0, width * scale, height * scale) {
+            Some(pixbuf) => pixbuf.savev(fname, "png", &[]),
+            None => Err(glib::Error::new(
+                glib::FileError::Failed,
+                "Failed to read back the image.",
+            )),
+        }
+    }
+
+    /// Enable or disable the export actions, which need a loaded file.
+    fn set_exports_enabled(&self, enabled: bool) {
+        for name in ["export-png", "export-svg", "export-peaks"] {
+            let action = self
+                .window
+                .lookup_action(name)
+                .and_then(|a| a.downcast::<gio::SimpleAction>().ok());
+            if let Some(action) = action {
+                action.set_enabled(enabled);
+            }
+        }
+    }
+
+    /// Handle one event. Should only be called on the main thread.
+    /// Tell the user that something failed, in a dialog.
+    fn show_error(&self, message: &str) {
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Close,
+            message,
+        );
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.show();
+    }
+
+    fn handle_event(&mut self, event: ViewEvent) {
+        match event {
+            ViewEvent::SetTitle(fname) => {
+                self.window.set_title(&fname);
+                self.header_bar.set_title(Some(&fname));
+            }
+            ViewEvent::SetView(bitmap) => {
+                if self.pixbuf.is_none() {
+                    self.set_exports_enabled(true);
+                }
+                self.pixbuf = Some(bitmap.into_pixbuf());
+                self.image.queue_draw();
+            }
+            ViewEvent::SetColorbar(bitmap, ticks) => {
+                self.colorbar = Some(bitmap.into_pixbuf());
+                // The colorbar comes with every repaint, but its labels rarely change.
+                if ticks != self.colorbar_ticks {
+                    self.colorbar_ticks = ticks;
+                    self.layout_labels();
+                }
+                self.image.queue_draw();
+            }
+            ViewEvent::SetTicks(x_ticks, y_ticks) => {
+                self.x_ticks = x_ticks;
+                self.y_ticks = y_ticks;
+                self.layout_labels();
+                self.image.queue_draw();
+            }
+            ViewEvent::SetProbes(placed) => {
+                place_probes(&mut self.probes, placed);
+                self.image.queue_draw();
+            }
+            ViewEvent::CopyFrequency(hz) => {
+                let text = format!("{:.0}", hz);
+                gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&text);
+                self.show_status(&format!("Copied {} Hz", text));
+            }
+            ViewEvent::ShowError(message) => self.show_error(&message),
+            ViewEvent::SetProgress(fraction) => {
+                if fraction >= 1.0 {
+                    self.progress_bar.hide();
+                } else {
+                    if fraction.is_nan() {
+                        self.progress_bar.pulse();
+                    } else {
+                        self.progress_bar.set_fraction(fraction);
+                    }
+                    self.progress_bar.show();
+                }
+            }
+            ViewEvent::SetMetadata {
+                sample_rate,
+                bits_per_sample,
+                channels,
+                duration_seconds,
+            } => {
+                let metadata =
+                    format_metadata(sample_rate, bits_per_sample, channels, duration_seconds);
+                self.header_bar.set_subtitle(Some(&metadata));
+                self.metadata = Some(metadata);
+            }
+            ViewEvent::ClearView => {
+                self.pixbuf = None;
+                self.colorbar = None;
+                self.colorbar_ticks.clear();
+                self.x_ticks.clear();
+                self.y_ticks.clear();
+                self.envelope.clear();
+                self.centroid_line.clear();
+                self.rolloff_line.clear();
+                self.flux_line.clear();
+                self.onsets.clear();
+                self.nyquist_lines.clear();
+                self.note_lines.clear();
+                self.track_markers.clear();
+                self.layout_labels();
+                self.set_exports_enabled(false);
+                self.window.set_title("Audiograter");
+                self.header_bar.set_title(Some("Audiograter"));
+                self.header_bar.set_subtitle(None);
+                self.metadata = None;
+                self.image.queue_draw();
+            }
+            ViewEvent::SetCursorLabel(label) => {
+                // When the pointer left the graph already, there is nothing to label.
+                if let Some(cursor) = self.cursor.as_mut() {
+                    cursor.label = label;
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::SetRegions(regions) => {
+                // Like the probe labels, these may be outdated already.
+                if regions.len() == self.regions.len() {
+                    for (region, (bounds, label)) in self.regions.iter_mut().zip(regions) {
+                        region.bounds = bounds;
+                        region.label = label;
+                    }
+                    self.update_region_list();
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::SetPresetNames(names, selected) => {
+                self.preset_combo.remove_all();
+                for name in &names {
+                    self.preset_combo.append(Some(name), name);
+                }
+                self.preset_combo.set_active_id(selected.as_deref());
+            }
+            ViewEvent::SetYBlend(blend) => {
+                // The model has the value already, sending it back would
+                // record it in the undo history as if the user moved it.
+                self.blend_scale.block_signal(&self.blend_handler);
+                self.blend_scale.set_value(blend);
+                self.blend_scale.unblock_signal(&self.blend_handler);
+            }
+            ViewEvent::SetLevelRange(floor_db, ceiling_db) => {
+                // Like for the blend, the model has the values already. After
+                // setting only the floor, it would get the old ceiling too.
+                let scales = [&self.floor_scale, &self.ceiling_scale];
+                for (scale, handler) in scales.iter().zip(&self.level_handlers) {
+                    scale.block_signal(handler);
+                }
+                self.floor_scale.set_value(floor_db as f64);
+                self.ceiling_scale.set_value(ceiling_db as f64);
+                for (scale, handler) in scales.iter().zip(&self.level_handlers) {
+                    scale.unblock_signal(handler);
+                }
+            }
+            ViewEvent::SetChannelMode(mode) => {
+                let index = self.channel_modes.iter().position(|&m| m == mode);
+                self.channel_combo.set_active(index.map(|i| i as u32));
+            }
+            ViewEvent::SetNumChannels(num_channels) => {
+                // Mixing a single channel gives back that channel, only list it then.
+                self.channel_modes.clear();
+                if num_channels > 1 {
+                    self.channel_modes.push(ChannelMode::Mix);
+                    self.channel_modes.push(ChannelMode::Mid);
+                    self.channel_modes.push(ChannelMode::Side);
+                }
+                self.channel_modes
+                    .extend((0..num_channels).map(ChannelMode::Channel));
+                self.channel_combo.remove_all();
+                for &mode in &self.channel_modes {
+                    self.channel_combo.append_text(&channel_mode_label(mode));
+                }
+            }
+            ViewEvent::SetLegend(legend) => {
+                self.legend = legend;
+                if self.show_legend {
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::SetNyquistLines(lines) => {
+                self.nyquist_lines = lines;
+                if self.show_nyquist {
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::SetNoteLines(lines) => {
+                self.note_lines = lines;
+                if self.show_notes {
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::SetTrackMarkers(markers) => {
+                self.track_markers = markers;
+                self.image.queue_draw();
+            }
+            ViewEvent::SetFrequencyMarker(marker) => {
+                let error = marker.as_ref().and_then(|m| m.as_ref().err());
+                set_entry_error(&self.frequency_entry, error.map(|msg| &msg[..]));
+                self.frequency_marker = marker.and_then(|m| m.ok());
+                self.image.queue_draw();
+            }
+            ViewEvent::SetEnvelope(envelope) => {
+                self.envelope = envelope;
+                if self.show_envelope {
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::ChooseArchiveEntry(archive_fname, entries) => {
+                self.choose_archive_entry(archive_fname, entries);
+            }
+            ViewEvent::SetComparePeaks(peaks) => {
+                self.compare_peaks = peaks;
+                self.image.queue_draw();
+            }
+            ViewEvent::SetFlux(flux, onsets) => {
+                self.flux_line = flux;
+                self.onsets = onsets;
+                if self.show_flux {
+                    self.image.queue_draw();
+                }
+            }
+            ViewEvent::SetScrollPosition(begin, end) => {
+                let page = end - begin;
+                self.scrollbar.adjustment().configure(
+                    begin,
+                    0.0,
+                    1.0,
+                    0.1 * page,
+                    0.9 * page,
+                    page,
+                );
+                self.scrollbar.set_visible(page < 1.0);
+            }
+            ViewEvent::SetSettings(settings) => {
+                self.window_len = settings.window_len;
+                self.window_off = settings.window_off;
+                self.window_fn = settings.window_fn;
+                self.zero_pad_factor = settings.zero_pad_factor;
+                self.show_histogram = settings.show_histogram;
+                self.show_group_delay = settings.show_group_delay;
+                self.show_coherence = settings.show_coherence;
+                self.show_constant_q = settings.show_constant_q;
+                self.show_dc_strip = settings.show_dc_strip;
+                self.y_scale = settings.y_scale;
+                self.colormap = settings.colormap;
+                self.invert_colormap = settings.invert_colormap;
+                self.encode_srgb = settings.encode_srgb;
+                self.adaptive_floor = settings.adaptive_floor;
+                self.show_residual = settings.show_residual;
+                self.auto_hop = settings.auto_hop;
+                self.equal_energy = settings.equal_energy;
+                self.row_step = settings.row_step;
+                self.oversample = settings.oversample;
+                self.image.queue_draw();
+            }
+            ViewEvent::SetFeatureLines(centroid, rolloff) => {
+                self.centroid_line = centroid;
+                self.rolloff_line = rolloff;
+                if self.show_centroid || self.show_rolloff {
+                    self.image.queue_draw();
+                }
+            }
+        }
+    }
+}
+
+impl Model {
+    fn new(
+        sender: glib::SyncSender<ViewEvent>,
+        self_sender: mpsc::SyncSender<ModelEvent>,
+    ) -> Model {
+        Model {
+            reader: None,
+            decode_generation: 0,
+            title: String::new(),
+            path: None,
+            previous_file: None,
+            compare_peaks: false,
+            profile: None,
+            pending_config: None,
+            tracks: Vec::new(),
+            frequency_marker: None,
+            spectrum: Vec::new(),
+            y_blend: DEFAULT_Y_BLEND,
+            floor_db: DEFAULT_FLOOR_DB,
+            ceiling_db: DEFAULT_CEILING_DB,
+            show_dc_strip: false,
+            y_scale: YScale::Blended,
+            window_len: WINDOW_LEN,
+            base_window_off: WINDOW_OFF,
+            window_fn: WindowFn::Hann,
+            window_off: WINDOW_OFF,
+            auto_hop: false,
+            zero_pad_factor: 1,
+            fft_plan: FftPlan::new(WINDOW_LEN),
+            probes: Vec::new(),
+            cursor: None,
+            regions: Vec::new(),
+            centroids: Vec::new(),
+            rolloffs: Vec::new(),
+            fluxes: Vec::new(),
+            floors: Vec::new(),
+            adaptive_floor: false,
+            smoothed_floors: Vec::new(),
+            mean_spectrum: Vec::new(),
+            show_residual: false,
+            equal_energy: false,
+            show_histogram: false,
+            show_group_delay: false,
+            group_delays: Vec::new(),
+            show_coherence: false,
+            coherences: Vec::new(),
+            show_constant_q: false,
+            constant_q: Vec::new(),
+            constant_q_plan: None,
+            colormap: Colormap::Magma,
+            invert_colormap: false,
+            encode_srgb: false,
+            row_step: 1,
+            oversample: 1,
+            row_plan: Vec::new(),
+            column_plan: RefCell::new(ColumnPlan::default()),
+            painted: RefCell::new(None),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_slider_step: None,
+            presets: Vec::new(),
+            bandwidth: None,
+            decoded_channels: Vec::new(),
+            channel_mode: ChannelMode::Channel(0),
+            mixed_samples: Vec::new(),
+            envelope: Vec::new(),
+            envelope_len: 0,
+            target_size: (0, 0),
+            label_size: (0, 0),
+            duration: None,
+            time_view: (0.0, 0.0),
+            freq_view: (0.0, f64::INFINITY),
+            fixed_scale: false,
+            start_time: None,
+            start_time_override: None,
+            sample_rate: 1,
+            bits_per_sample: 0,
+            num_channels: 0,
+            sender: sender,
+            self_sender: self_sender,
+        }
+    }
+
+    pub fn run_event_loop(&mut self, events: mpsc::Receiver<ModelEvent>) {
+        // Block until a new event arrives.
+        for mut current_event in events.iter() {
+            // When we do have an event, don't handle it immediately. First
+            // check if there already is another event behind it.
+            for next_event in events.try_iter() {
+                current_event = match (&current_event, &next_event) {
+                    // If there is another event waiting, and it is a resize
+                    // just like the current event, then the current event is
+                    // already obsolete and we can drop it.
+                    (&ModelEvent::Resize(..), &ModelEvent::Resize(..)) => next_event,
+                    // The same holds for dragging the frequency axis slider.
+                    (&ModelEvent::SetYBlend(..), &ModelEvent::SetYBlend(..)) => next_event,
+                    (&ModelEvent::SetLevelRange(..), &ModelEvent::SetLevelRange(..)) => next_event,
+                    (&ModelEvent::ScrollTo(..), &ModelEvent::ScrollTo(..)) => next_event,
+                    (&ModelEvent::SetCursor(..), &ModelEvent::SetCursor(..)) => next_event,
+                    // Consecutive scroll steps at the same position make one zoom.
+                    (&ModelEvent::ZoomAt(x0, f0), &ModelEvent::ZoomAt(x1, f1)) if x0 == x1 => {
+                        ModelEvent::ZoomAt(x0, f0 * f1)
+                    }
+                    (
+                        &ModelEvent::ZoomFrequencyAt(y0, f0),
+                        &ModelEvent::ZoomFrequencyAt(y1, f1),
+                    ) if y0 == y1 => ModelEvent::ZoomFrequencyAt(y0, f0 * f1),
+                    // In any other case, we need to handle the current event.
+                    // Handle it now, and leave the next event to be handled in
+                    // the next iteration, or after the `try_iter` loop.
+                    _ => {
+                        self.handle_event(current_event);
+                        next_event
+                    }
+                };
+            }
+
+            self.handle_event(current_event);
+        }
+    }
+
+    pub fn handle_event(&mut self, event: ModelEvent) {
+        let is_adjustment = matches!(
+            event,
+            ModelEvent::SetZeroPadFactor(..)
+                | ModelEvent::SetWindowParams(..)
+                | ModelEvent::SetWindowFn(..)
+                | ModelEvent::SetTimeRange(..)
+                | ModelEvent::ZoomAt(..)
+                | ModelEvent::ZoomFrequencyAt(..)
+                | ModelEvent::ResetZoom
+                | ModelEvent::SetGroupDelay(..)
+                | ModelEvent::SetCoherence(..)
+                | ModelEvent::SetConstantQ(..)
+                | ModelEvent::SetDcStrip(..)
+                | ModelEvent::SetYScale(..)
+                | ModelEvent::SetColormap(..)
+                | ModelEvent::SetInvertColormap(..)
+                | ModelEvent::SetEncodeSrgb(..)
+                | ModelEvent::SetAdaptiveFloor(..)
+                | ModelEvent::SetResidual(..)
+                | ModelEvent::SetAutoHop(..)
+                | ModelEvent::SetEqualEnergy(..)
+                | ModelEvent::SetRowStep(..)
+                | ModelEvent::SetOversample(..)
+                | ModelEvent::SetHistogram(..)
+                | ModelEvent::SetYBlend(..)
+                | ModelEvent::SetLevelRange(..)
+                | ModelEvent::ApplyPreset(..)
+        );
+        let is_slider = matches!(
+            event,
+            ModelEvent::SetYBlend(..) | ModelEvent::SetLevelRange(..)
+        );
+        let slider_step = (mem::discriminant(&event), Instant::now());
+        let is_same_drag = match self.last_slider_step {
+            Some((slider, at)) => slider == slider_step.0 && at.elapsed() < SLIDER_DRAG_GAP,
+            None => false,
+        };
+        let before = self.settings();
+
+        self.apply_event(event);
+
+        if is_adjustment && self.settings() != before {
+            if !(is_slider && is_same_drag) {
+                self.undo_stack.push(before);
+            }
+            self.redo_stack.clear();
+            self.last_slider_step = if is_slider { Some(slider_step) } else { None };
+        }
+    }
+
+    /// Handle the event, without recording it in the undo history.
+    fn apply_event(&mut self, event: ModelEvent) {
+        match event {
+            ModelEvent::OpenFile(fname) => {
+                let is_reload = false;
+                self.open_file(fname, is_reload);
+            }
+            ModelEvent::ExportPeaks(fname) => {
+                if let Err(err) = self.save_peaks(&fname) {
+                    self.report_error(format!("Failed to write {}: {}", fname.display(), err));
+                }
+            }
+            ModelEvent::Reload => match self.path.clone() {
+                Some(fname) => {
+                    let is_reload = true;
+                    self.open_file(fname, is_reload);
+                }
+                None => self.report_error(
+                    "The loaded file is not a file on disk, it cannot be reloaded.".to_string(),
+                ),
+            },
+            ModelEvent::OpenArchiveEntry(archive_fname, entry_name) => {
+                let bytes = match archive::read_entry(&archive_fname, &entry_name) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        return self.report_error(format!("Failed to read from archive: {:?}", err))
+                    }
+                };
+                let archive_name = archive_fname
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .unwrap_or("");
+                let title = format!("{} — {}", archive_name, entry_name);
+                self.handle_event(ModelEvent::OpenBytes(bytes, title));
+            }
+            ModelEvent::OpenBytes(bytes, title) => {
+                let is_reload = false;
+                self.open_reader(Box::new(io::Cursor::new(bytes)), title, None, is_reload);
+            }
+            ModelEvent::Resize(width, height, label_width, label_height) => {
+                self.target_size = (width, height);
+                self.label_size = (label_width, label_height);
+                self.update_row_plan();
+                if let Some(config) = self.pending_config.take() {
+                    self.apply_config(&config);
+                }
+
+                // At zero width, the fixed scale would show zero seconds.
+                if self.fixed_scale && width > 0 {
+                    self.apply_fixed_scale();
+                }
+                self.recompute_ticks();
+
+                // With `auto_hop`, the hop follows the width. Transforming
+                // again repaints too, so we only repaint when it does not.
+                let window_off = self.choose_window_off();
+                if window_off != self.window_off {
+                    self.window_off = window_off;
+                    self.recompute_spectrum();
+                } else {
+                    self.repaint();
+                }
+                self.send_feature_lines();
+
+                // The envelope has one entry per column, so we need to start
+                // over when the width changes.
+                if self.envelope.len() != width.max(0) as usize {
+                    self.envelope.clear();
+                    self.update_envelope();
+                }
+            }
+            ModelEvent::Decode(generation) => {
+                if generation == self.decode_generation {
+                    self.decode();
+                }
+            }
+            ModelEvent::AddProbe(id, x, y) => {
+                self.probes.push((id, self.time_at(x), self.hz_at(y)));
+                self.update_probes();
+            }
+            ModelEvent::RemoveProbe(id) => {
+                self.probes.retain(|probe| probe.0 != id);
+            }
+            ModelEvent::CopyFrequencyAt(y) => {
+                if self.duration.is_some() {
+                    let hz = self.hz_at(y);
+                    self.sender.send(ViewEvent::CopyFrequency(hz)).unwrap();
+                }
+            }
+            ModelEvent::SetCursor(cursor) => {
+                self.cursor = cursor;
+                self.send_cursor_label();
+            }
+            ModelEvent::AddRegion(x0, x1, y0, y1) => {
+                let region = (
+                    self.time_at(x0),
+                    self.time_at(x1),
+                    self.hz_at(y0),
+                    self.hz_at(y1),
+                );
+                self.regions.push(region);
+                self.update_regions();
+            }
+            ModelEvent::RemoveRegion(i) => {
+                if i < self.regions.len() {
+                    self.regions.remove(i);
+                    self.update_regions();
+                }
+            }
+            ModelEvent::SetStartTime(start_time) => {
+                self.start_time_override = start_time;
+                self.recompute_ticks();
+            }
+            ModelEvent::SetChannelMode(mode) => {
+                self.channel_mode = self.existing_channel_mode(mode);
+                self.mixed_samples.clear();
+                self.update_mixed_samples();
+                self.envelope.clear();
+                self.update_envelope();
+                self.recompute_spectrum();
+                self.send_legend();
+                self.sender
+                    .send(ViewEvent::SetChannelMode(self.channel_mode))
+                    .unwrap();
+            }
+            ModelEvent::SetZeroPadFactor(factor) => {
+                self.zero_pad_factor = factor;
+                self.update_row_plan();
+                self.recompute_spectrum();
+                self.send_legend();
+            }
+            ModelEvent::SetWindowParams(window_len, window_off) => {
+                // The lowest bin above DC depends on the window length, so
+                // besides the spectrum, the frequency axis changes too.
+                self.window_len = window_len.max(2);
+                self.base_window_off = window_off.max(1);
+                self.window_off = self.choose_window_off();
+                self.update_row_plan();
+                self.recompute_ticks();
+                self.recompute_spectrum();
+                self.send_nyquist_lines();
+                self.send_frequency_marker();
+                self.send_note_lines();
+                self.send_legend();
+            }
+            ModelEvent::SetWindowFn(window_fn) => {
+                self.window_fn = window_fn;
+                self.recompute_spectrum();
+                self.send_legend();
+            }
+            ModelEvent::SetTimeRange(x0, x1) => {
+                // Zooming in picks a scale of its own.
+                self.fixed_scale = false;
+                let begin = self.time_at(x0);
+                let end = self.time_at(x1);
+                self.set_time_view(begin, end);
+            }
+            ModelEvent::ZoomAt(x, factor) => {
+                self.fixed_scale = false;
+                self.zoom_at(x, factor);
+            }
+            ModelEvent::ZoomFrequencyAt(y, factor) => {
+                self.zoom_frequency_at(y, factor);
+            }
+            ModelEvent::ResetZoom if self.fixed_scale => {
+                self.set_freq_view(0.0, f64::INFINITY);
+                self.scroll_to(0.0);
+            }
+            ModelEvent::ResetZoom => {
+                self.set_freq_view(0.0, f64::INFINITY);
+                let duration = self.duration.unwrap_or(0);
+                self.set_time_view(0.0, duration as f64);
+            }
+            ModelEvent::ToggleFixedScale => {
+                self.fixed_scale = !self.fixed_scale;
+                if self.fixed_scale {
+                    self.apply_fixed_scale();
+                } else {
+                    let duration = self.duration.unwrap_or(0);
+                    self.set_time_view(0.0, duration as f64);
+                }
+            }
+            ModelEvent::ScrollTo(fraction) => {
+                let duration = self.duration.unwrap_or(0) as f64;
+                self.scroll_to(fraction * duration);
+            }
+            ModelEvent::SetGroupDelay(show) => {
+                self.show_group_delay = show;
+                if show {
+                    self.compute_group_delays();
+                } else {
+                    self.group_delays.clear();
+                }
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetDcStrip(show) => {
+                // Like a change of the blend, this changes the frequency axis.
+                self.show_dc_strip = show;
+                self.update_row_plan();
+                self.recompute_ticks();
+                self.repaint();
+                self.update_probes();
+                self.update_regions();
+                self.send_feature_lines();
+                self.send_nyquist_lines();
+                self.send_frequency_marker();
+                self.send_note_lines();
+                self.send_legend();
+            }
+            ModelEvent::SetYScale(y_scale) => {
+                self.y_scale = y_scale;
+                self.update_row_plan();
+                self.recompute_ticks();
+                self.repaint();
+                self.update_probes();
+                self.update_regions();
+                self.send_feature_lines();
+                self.send_nyquist_lines();
+                self.send_frequency_marker();
+                self.send_note_lines();
+                self.send_legend();
+            }
+            ModelEvent::SetCoherence(show) => {
+                self.show_coherence = show;
+                if show {
+                    self.compute_coherences();
+                } else {
+                    self.coherences.clear();
+                }
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetConstantQ(show) => {
+                self.show_constant_q = show;
+                if show {
+                    self.compute_constant_q();
+                } else {
+                    self.constant_q.clear();
+                }
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetLevelRange(floor_db, ceiling_db) => {
+                // The values before the colormap change, so this is not just a recolor.
+                self.floor_db = floor_db;
+                self.ceiling_db = ceiling_db.max(floor_db + 1.0);
+                self.repaint();
+                self.send_legend();
+
+                // If we moved the ceiling, the slider should show where it is.
+                // Otherwise the sliders are right already, and echoing the
+                // range would fight with a slider that is still moving.
+                if self.ceiling_db != ceiling_db {
+                    self.sender
+                        .send(ViewEvent::SetLevelRange(self.floor_db, self.ceiling_db))
+                        .unwrap();
+                }
+            }
+            ModelEvent::SetColormap(colormap) => {
+                // The spectrum stays the same, only painting it changes.
+                self.colormap = colormap;
+                self.recolor();
+                self.send_legend();
+            }
+            ModelEvent::SetInvertColormap(invert) => {
+                // This only affects the colors, there is nothing to recompute.
+                self.invert_colormap = invert;
+                self.recolor();
+                self.send_legend();
+            }
+            ModelEvent::SetEncodeSrgb(encode) => {
+                self.encode_srgb = encode;
+                self.recolor();
+                self.send_legend();
+            }
+            ModelEvent::SetAdaptiveFloor(adaptive) => {
+                self.adaptive_floor = adaptive;
+                self.update_smoothed_floors();
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetResidual(show) => {
+                // The mean spectrum is there already, this only affects the colors.
+                self.show_residual = show;
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetEqualEnergy(equal) => {
+                // The row plan has the weights already, this only affects the colors.
+                self.equal_energy = equal;
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetAutoHop(auto) => {
+                self.auto_hop = auto;
+                let window_off = self.choose_window_off();
+                if window_off != self.window_off {
+                    self.window_off = window_off;
+                    self.recompute_spectrum();
+                }
+                self.send_legend();
+            }
+            ModelEvent::SetRowStep(step) => {
+                self.row_step = step.max(1);
+                self.update_row_plan();
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetOversample(factor) => {
+                self.oversample = factor.max(1);
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::SetHistogram(show) => {
+                self.show_histogram = show;
+                self.recompute_ticks();
+                self.repaint();
+                self.send_legend();
+            }
+            ModelEvent::Undo => {
+                if let Some(settings) = self.undo_stack.pop() {
+                    self.redo_stack.push(self.settings());
+                    self.restore_settings(settings);
+                }
+            }
+            ModelEvent::Redo => {
+                if let Some(settings) = self.redo_stack.pop() {
+                    self.undo_stack.push(self.settings());
+                    self.restore_settings(settings);
+                }
+            }
+            ModelEvent::SetYBlend(blend) => {
+                self.y_blend = blend.max(0.0).min(1.0);
+                self.update_row_plan();
+                self.recompute_ticks();
+                self.repaint();
+                self.update_probes();
+                self.update_regions();
+                self.send_feature_lines();
+                self.send_nyquist_lines();
+                self.send_frequency_marker();
+                self.send_note_lines();
+                self.send_legend();
+            }
+            ModelEvent::SwapFiles => {
+                if let Some(previous) = self.previous_file.take() {
+                    self.previous_file = Some(Box::new(self.take_file()));
+                    self.restore_file(*previous);
+                }
+            }
+            ModelEvent::SetComparePeaks(show) => {
+                // The range of the y-axis depends on the previous file while we compare.
+                self.compare_peaks = show;
+                self.fit_freq_view();
+                self.update_frequency_axis();
+            }
+            ModelEvent::SetFrequencyMarker(hz) => {
+                self.frequency_marker = hz;
+                self.send_frequency_marker();
+            }
+            ModelEvent::LoadPresets => {
+                match config::load_presets() {
+                    Ok(presets) => self.presets = presets,
+                    Err(msg) => self.report_error(msg),
+                }
+                self.send_preset_names(None);
+            }
+            ModelEvent::SavePreset(name) => {
+                let preset = self.preset();
+                match self.presets.binary_search_by(|(n, _)| n.cmp(&name)) {
+                    Ok(i) => self.presets[i].1 = preset,
+                    Err(i) => self.presets.insert(i, (name.clone(), preset)),
+                }
+                if let Err(msg) = config::save_presets(&self.presets) {
+                    self.report_error(msg);
+                }
+                self.send_preset_names(Some(name));
+            }
+            ModelEvent::ApplyPreset(name) => {
+                let preset = match self.presets.iter().find(|(n, _)| *n == name) {
+                    Some((_, preset)) => *preset,
+                    None => return,
+                };
+                self.apply_config(&preset);
+            }
+            ModelEvent::DeletePreset(name) => {
+                self.presets.retain(|(n, _)| *n != name);
+                if let Err(msg) = config::save_presets(&self.presets) {
+                    self.report_error(msg);
+                }
+                self.send_preset_names(None);
+            }
+        }
+    }
+
+    /// Return the current settings as a preset, for everything but the time range.
+    fn preset(&self) -> Config {
+        Config {
+            theme: None,
+            window_len: Some(self.window_len),
+            window_off: Some(self.base_window_off),
+            window_fn: Some(self.window_fn),
+            zero_pad_factor: Some(self.zero_pad_factor),
+            row_step: Some(self.row_step),
+            oversample: Some(self.oversample),
+            colormap: Some(self.colormap),
+            invert_colormap: Some(self.invert_colormap),
+            encode_srgb: Some(self.encode_srgb),
+            adaptive_floor: Some(self.adaptive_floor),
+            group_delay: Some(self.show_group_delay),
+            phase_coherence: Some(self.show_coherence),
+            constant_q: Some(self.show_constant_q),
+            dc_strip: Some(self.show_dc_strip),
+            frequency_scale: Some(self.y_scale),
+            histogram: Some(self.show_histogram),
+            residual: Some(self.show_residual),
+            auto_hop: Some(self.auto_hop),
+            equal_energy: Some(self.equal_energy),
+            frequency_axis: Some(self.y_blend),
+            floor_db: Some(self.floor_db),
+            ceiling_db: Some(self.ceiling_db),
+        }
+    }
+
+    /// Send the names of the presets to the view, for the picker.
+    fn send_preset_names(&self, selected: Option<String>) {
+        let names = self.presets.iter().map(|(name, _)| name.clone()).collect();
+        self.sender
+            .send(ViewEvent::SetPresetNames(names, selected))
+            .unwrap();
+    }
+
+    /// Return the current values of the parameters that the user can adjust.
+    fn settings(&self) -> Settings {
+        Settings {
+            time_view: self.time_view,
+            freq_view: self.freq_view,
+            window_len: self.window_len,
+            window_off: self.base_window_off,
+            window_fn: self.window_fn,
+            zero_pad_factor: self.zero_pad_factor,
+            show_histogram: self.show_histogram,
+            show_group_delay: self.show_group_delay,
+            show_coherence: self.show_coherence,
+            show_constant_q: self.show_constant_q,
+            show_dc_strip: self.show_dc_strip,
+            y_scale: self.y_scale,
+            y_blend: self.y_blend,
+            floor_db: self.floor_db,
+            ceiling_db: self.ceiling_db,
+            colormap: self.colormap,
+            invert_colormap: self.invert_colormap,
+            encode_srgb: self.encode_srgb,
+            adaptive_floor: self.adaptive_floor,
+            show_residual: self.show_residual,
+            auto_hop: self.auto_hop,
+            equal_energy: self.equal_energy,
+            row_step: self.row_step,
+            oversample: self.oversample,
+        }
+    }
+
+    /// Return how to map values to colors when painting.
+    fn color_scheme(&self) -> ColorScheme {
+        ColorScheme {
+            colormap: self.colormap,
+            invert: self.invert_colormap,
+            encode_srgb: self.encode_srgb,
+        }
+    }
+
+    /// Change the settings to `settings`, and tell the view about it.
+    ///
+    /// We apply every setting that differs in the same way as when the user
+    /// adjusts it, so we only recompute the parts that depend on it. Only a
+    /// change in zero-padding requires transforming everything again.
+    fn restore_settings(&mut self, settings: Settings) {
+        let current = self.settings();
+        if (settings.window_len, settings.window_off) != (current.window_len, current.window_off) {
+            let event = ModelEvent::SetWindowParams(settings.window_len, settings.window_off);
+            self.apply_event(event);
+        }
+        if settings.window_fn != current.window_fn {
+            self.apply_event(ModelEvent::SetWindowFn(settings.window_fn));
+        }
+        if settings.zero_pad_factor != current.zero_pad_factor {
+            self.apply_event(ModelEvent::SetZeroPadFactor(settings.zero_pad_factor));
+        }
+        if settings.show_histogram != current.show_histogram {
+            self.apply_event(ModelEvent::SetHistogram(settings.show_histogram));
+        }
+        if settings.show_group_delay != current.show_group_delay {
+            self.apply_event(ModelEvent::SetGroupDelay(settings.show_group_delay));
+        }
+        if settings.show_coherence != current.show_coherence {
+            self.apply_event(ModelEvent::SetCoherence(settings.show_coherence));
+        }
+        if settings.show_constant_q != current.show_constant_q {
+            self.apply_event(ModelEvent::SetConstantQ(settings.show_constant_q));
+        }
+        if settings.show_dc_strip != current.show_dc_strip {
+            self.apply_event(ModelEvent::SetDcStrip(settings.show_dc_strip));
+        }
+        if settings.y_scale != current.y_scale {
+            self.apply_event(ModelEvent::SetYScale(settings.y_scale));
+        }
+        if settings.y_blend != current.y_blend {
+            self.apply_event(ModelEvent::SetYBlend(settings.y_blend));
+            self.sender
+                .send(ViewEvent::SetYBlend(self.y_blend))
+                .unwrap();
+        }
+        if (settings.floor_db, settings.ceiling_db) != (current.floor_db, current.ceiling_db) {
+            let event = ModelEvent::SetLevelRange(settings.floor_db, settings.ceiling_db);
+            self.apply_event(event);
+            let event = ViewEvent::SetLevelRange(self.floor_db, self.ceiling_db);
+            self.sender.send(event).unwrap();
+        }
+        if settings.colormap != current.colormap {
+            self.apply_event(ModelEvent::SetColormap(settings.colormap));
+        }
+        if settings.invert_colormap != current.invert_colormap {
+            self.apply_event(ModelEvent::SetInvertColormap(settings.invert_colormap));
+        }
+        if settings.encode_srgb != current.encode_srgb {
+            self.apply_event(ModelEvent::SetEncodeSrgb(settings.encode_srgb));
+        }
+        if settings.adaptive_floor != current.adaptive_floor {
+            self.apply_event(ModelEvent::SetAdaptiveFloor(settings.adaptive_floor));
+        }
+        if settings.show_residual != current.show_residual {
+            self.apply_event(ModelEvent::SetResidual(settings.show_residual));
+        }
+        if settings.auto_hop != current.auto_hop {
+            self.apply_event(ModelEvent::SetAutoHop(settings.auto_hop));
+        }
+        if settings.equal_energy != current.equal_energy {
+            self.apply_event(ModelEvent::SetEqualEnergy(settings.equal_energy));
+        }
+        if settings.row_step != current.row_step {
+            self.apply_event(ModelEvent::SetRowStep(settings.row_step));
+        }
+        if settings.oversample != current.oversample {
+            self.apply_event(ModelEvent::SetOversample(settings.oversample));
+        }
+        if settings.time_view != current.time_view {
+            let (begin, end) = settings.time_view;
+            self.set_time_view(begin, end);
+        }
+        if settings.freq_view != current.freq_view {
+            let (low, high) = settings.freq_view;
+            self.set_freq_view(low, high);
+        }
+        self.sender.send(ViewEvent::SetSettings(settings)).unwrap();
+    }
+
+    /// Apply the settings from the config file or a preset, for keys that it contains.
+    fn apply_config(&mut self, config: &Config) {
+        let mut settings = self.settings();
+        settings.window_len = config.window_len.unwrap_or(settings.window_len);
+        settings.window_off = config.window_off.unwrap_or(settings.window_off);
+        settings.window_fn = config.window_fn.unwrap_or(settings.window_fn);
+        settings.zero_pad_factor = config.zero_pad_factor.unwrap_or(settings.zero_pad_factor);
+        settings.row_step = config.row_step.unwrap_or(settings.row_step);
+        settings.oversample = config.oversample.unwrap_or(settings.oversample);
+        settings.colormap = config.colormap.unwrap_or(settings.colormap);
+        settings.invert_colormap = config.invert_colormap.unwrap_or(settings.invert_colormap);
+        settings.encode_srgb = config.encode_srgb.unwrap_or(settings.encode_srgb);
+        settings.adaptive_floor = config.adaptive_floor.unwrap_or(settings.adaptive_floor);
+        settings.show_group_delay = config.group_delay.unwrap_or(settings.show_group_delay);
+        settings.show_coherence = config.phase_coherence.unwrap_or(settings.show_coherence);
+        settings.show_constant_q = config.constant_q.unwrap_or(settings.show_constant_q);
+        settings.show_dc_strip = config.dc_strip.unwrap_or(settings.show_dc_strip);
+        settings.y_scale = config.frequency_scale.unwrap_or(settings.y_scale);
+        settings.show_histogram = config.histogram.unwrap_or(settings.show_histogram);
+        settings.show_residual = config.residual.unwrap_or(settings.show_residual);
+        settings.auto_hop = config.auto_hop.unwrap_or(settings.auto_hop);
+        settings.equal_energy = config.equal_energy.unwrap_or(settings.equal_energy);
+        settings.y_blend = config.frequency_axis.unwrap_or(settings.y_blend);
+        settings.floor_db = config.floor_db.unwrap_or(settings.floor_db);
+        settings.ceiling_db = config.ceiling_db.unwrap_or(settings.ceiling_db);
+        self.restore_settings(settings);
+    }
+
+    /// Return the index of the window centered closest to time `t` in samples.
+    ///
+    /// The window may not be in the spectrum yet.
+    fn window_at(&self, t: f64) -> usize {
+        ((t - (self.window_len / 2) as f64) / self.window_off as f64)
+            .round()
+            .max(0.0) as usize
+    }
+
+    /// Return the time in samples at horizontal position `x` in graph coordinates.
+    fn time_at(&self, x: f64) -> f64 {
+        let (begin, end) = self.time_view;
+        begin + x * (end - begin)
+    }
+
+    /// Return the horizontal position in graph coordinates of time `t` in samples.
+    fn x_at(&self, t: f64) -> f64 {
+        let (begin, end) = self.time_view;
+        (t - begin) / (end - begin)
+    }
+
+    /// Show the time range from `begin` to `end` (in samples) on the x-axis.
+    ///
+    /// The spectrum does not change, so this only needs to redo the parts that
+    /// depend on the mapping from pixels to time.
+    fn set_time_view(&mut self, begin: f64, end: f64) {
+        if self.duration.is_none() || end - begin < 1.0 {
+            return;
+        }
+        self.time_view = (begin, end);
+        self.recompute_ticks();
+        self.repaint();
+        self.update_probes();
+        self.update_regions();
+        self.send_feature_lines();
+        self.envelope.clear();
+        self.update_envelope();
+        self.send_legend();
+        self.send_scroll_position();
+    }
+
+    /// Scale the length of the time range in view, keeping the time at `x` under `x`.
+    ///
+    /// The range never extends past the file, so zooming out stops at the full file.
+    fn zoom_at(&mut self, x: f64, factor: f64) {
+        let duration = self.duration.unwrap_or(0) as f64;
+        let (begin, end) = self.time_view;
+        let t = self.time_at(x);
+        let len = ((end - begin) * factor).min(duration);
+        let begin = (t - x * len).min(duration - len).max(0.0);
+        self.set_time_view(begin, begin + len);
+    }
+
+    /// Show at most the frequency range from `low` to `high` (in Hz) on the y-axis.
+    ///
+    /// A range that covers the full range of the file resets the zoom, so the
+    /// y-axis spans the full range again after a change of sample rate.
+    fn set_freq_view(&mut self, low: f64, high: f64) {
+        let (full_low, full_high) = self.full_hz_range();
+        let freq_view = if low <= full_low && high >= full_high {
+            (0.0, f64::INFINITY)
+        } else {
+            (low, high)
+        };
+        if freq_view == self.freq_view {
+            return;
+        }
+        self.freq_view = freq_view;
+        self.update_frequency_axis();
+    }
+
+    /// Rebuild the rows and everything positioned by frequency, after the y-axis changed.
+    fn update_frequency_axis(&mut self) {
+        self.update_row_plan();
+        self.recompute_ticks();
+        self.repaint();
+        self.update_probes();
+        self.update_regions();
+        self.send_feature_lines();
+        self.send_nyquist_lines();
+        self.send_frequency_marker();
+        self.send_note_lines();
+        self.send_legend();
+    }
+
+    /// Scale the frequency range in view, keeping the frequency at height `y` in view.
+    ///
+    /// We scale the distance to the frequency at `y` in Hz, so on a linear
+    /// axis it stays at `y`. The range never extends past that of the file,
+    /// and never gets narrower than a few bins.
+    fn zoom_frequency_at(&mut self, y: f64, factor: f64) {
+        let (full_low, full_high) = self.full_hz_range();
+        let (low, high) = self.hz_range();
+        let hz = self.hz_at(y).max(low).min(high);
+        let min_len = 4.0 * self.sample_rate as f64 / self.window_len as f64;
+        let len = ((high - low) * factor).max(min_len);
+        let mut new_low = hz - (hz - low) / (high - low) * len;
+        let mut new_high = new_low + len;
+        if new_low < full_low {
+            new_high += full_low - new_low;
+            new_low = full_low;
+        }
+        if new_high > full_high {
+            new_low = (new_low - (new_high - full_high)).max(full_low);
+            new_high = full_high;
+        }
+        self.set_freq_view(new_low, new_high);
+    }
+
+    /// Tell the UI which part of the file is in view, for the scrollbar.
+    fn send_scroll_position(&self) {
+        let duration = self.duration.unwrap_or(0).max(1) as f64;
+        let (begin, end) = self.time_view;
+        let event = ViewEvent::SetScrollPosition(begin / duration, end / duration);
+        self.sender.send(event).unwrap();
+    }
+
+    /// Move the time range in view to start at `begin` (in samples), keeping its length.
+    fn scroll_to(&mut self, begin: f64) {
+        let duration = self.duration.unwrap_or(0) as f64;
+        let (view_begin, view_end) = self.time_view;
+        let len = view_end - view_begin;
+        let begin = begin.min(duration - len).max(0.0);
+
+        // Setting the scrollbar position reports back a scroll to where we
+        // are already, we need not repaint for that.
+        if (begin - view_begin).abs() >= 1.0 {
+            self.set_time_view(begin, begin + len);
+        }
+    }
+
+    /// Show as much of the file as fits at the fixed scale, from the current position.
+    fn apply_fixed_scale(&mut self) {
+        let duration = self.duration.unwrap_or(0) as f64;
+        let seconds = self.target_size.0 as f64 / FIXED_SCALE_PIXELS_PER_SECOND;
+        let len = (seconds * self.sample_rate as f64).min(duration);
+        let begin = self.time_view.0.min(duration - len).max(0.0);
+        self.set_time_view(begin, begin + len);
+    }
+
+    /// Describe the analysis parameters, and send that to the UI thread.
+    fn send_legend(&self) {
+        let mut lines = vec![format!(
+            "Window: {} samples, {}",
+            self.window_len,
+            window_fn_label(self.window_fn)
+        )];
+        if self.window_off < self.window_len {
+            let overlap = 100 * (self.window_len - self.window_off) / self.window_len;
+            lines.push(format!("Overlap: {}%", overlap));
+        } else {
+            lines.push(format!(
+                "Hop: {} samples, for the file length",
+                self.window_off
+            ));
+        }
+        if self.zero_pad_factor > 1 {
+            lines.push(format!("Zero-padding: {}×", self.zero_pad_factor));
+        }
+        match self.channel_mode {
+            // The first channel is the default, there is nothing to point out.
+            ChannelMode::Channel(0) => {}
+            ChannelMode::Channel(ch) => lines.push(format!("Channel: {}", ch + 1)),
+            ChannelMode::Mix => lines.push("Channel: mix of all".to_string()),
+            ChannelMode::Mid => lines.push("Channel: mid".to_string()),
+            ChannelMode::Side => lines.push("Channel: side".to_string()),
+        }
+        if self.show_dc_strip {
+            let (strip_hz, _strip_height) = self.dc_strip();
+            lines.push(format!(
+                "Frequency: linear from DC to {} in the strip",
+                format_hz(strip_hz)
+            ));
+        }
+        lines.push(match (self.y_scale, self.y_blend) {
+            (YScale::Linear, _) => "Frequency: linear".to_string(),
+            (YScale::Log, _) => "Frequency: log".to_string(),
+            (YScale::Mel, _) => "Frequency: mel".to_string(),
+            (YScale::Blended, b) if b <= 0.0 => "Frequency: log".to_string(),
+            (YScale::Blended, b) if b >= 1.0 => "Frequency: linear".to_string(),
+            (YScale::Blended, b) if b == DEFAULT_Y_BLEND => {
+                "Frequency: blended linear and log".to_string()
+            }
+            (YScale::Blended, b) => format!(
+                "Frequency: blended linear and log, {:.0}% linear",
+                b * 100.0
+            ),
+        });
+
+        let (begin, end) = self.time_view;
+        let inv_sample_rate = (self.sample_rate as f64).recip();
+        let is_zoomed = begin > 0.0 || end < self.duration.unwrap_or(0) as f64;
+        lines.push(match () {
+            () if self.show_histogram => "Time: level distribution".to_string(),
+            () if is_zoomed => format!(
+                "Time: {} to {}",
+                format_time(begin * inv_sample_rate),
+                format_time(end * inv_sample_rate),
+            ),
+            () => "Time: full file".to_string(),
+        });
+        if self.freq_view != (0.0, f64::INFINITY) {
+            let (hz_min, hz_max) = self.hz_range();
+            lines.push(format!(
+                "Frequency range: {} to {}",
+                format_hz(hz_min),
+                format_hz(hz_max)
+            ));
+        }
+
+        if self.row_step > 1 {
+            lines.push(format!("Rows: 1 in {}", self.row_step));
+        }
+        if let (Some(profile), None) = (&self.profile, &self.reader) {
+            lines.push(format!(
+                "Decode: {}",
+                profile.decode_report(self.sample_rate)
+            ));
+            lines.push(format!("Transform: {}", profile.transform_report()));
+        }
+        if let (true, Some(other)) = (self.compare_peaks, &self.previous_file) {
+            lines.push(format!("Dots: peaks of {}", other.title));
+        }
+        if self.oversample > 1 && !self.show_histogram {
+            lines.push(format!("Columns: {}× oversampled", self.oversample));
+        }
+        if self.show_group_delay && !self.show_histogram {
+            lines.push("Color: group delay".to_string());
+        }
+        if self.show_coherence && !self.show_histogram && !self.show_group_delay {
+            lines.push(match self.decoded_channels.len() {
+                0 | 1 => "Color: phase coherence, needs two channels".to_string(),
+                _ => "Color: phase coherence, from antiphase to mono".to_string(),
+            });
+        }
+        if self.show_constant_q {
+            lines.push(format!(
+                "Transform: constant-Q, {} bins per octave from {}",
+                CONSTANT_Q_BINS_PER_OCTAVE,
+                format_hz(CONSTANT_Q_MIN_HZ),
+            ));
+        }
+        if (self.floor_db, self.ceiling_db) != (DEFAULT_FLOOR_DB, DEFAULT_CEILING_DB) {
+            lines.push(format!(
+                "Levels: {} to {} dB",
+                self.floor_db, self.ceiling_db
+            ));
+        }
+        if self.colormap != Colormap::Magma {
+            lines.push(format!("Colormap: {}", self.colormap.name()));
+        }
+        if self.invert_colormap {
+            lines.push("Colormap: inverted".to_string());
+        }
+        if self.encode_srgb {
+            lines.push("Colormap: sRGB-encoded".to_string());
+        }
+        if self.adaptive_floor && !self.show_histogram && !self.show_group_delay {
+            lines.push("Floor: adaptive, not a measurement".to_string());
+        }
+        if self.show_residual && !self.show_histogram && !self.show_group_delay {
+            lines.push("Color: residual over the average spectrum".to_string());
+        }
+        if self.equal_energy && !self.show_histogram && !self.show_group_delay {
+            lines.push("Rows: weighted by bandwidth".to_string());
+        }
+
+        let legend = lines.join("\n");
+        self.sender.send(ViewEvent::SetLegend(legend)).unwrap();
+    }
+
+    /// Open the file in the archive if there is one, or ask which one to open.
+    fn open_archive(&mut self, archive_fname: PathBuf) {
+        let mut entries = match archive::list_audio_entries(&archive_fname) {
+            Ok(entries) => entries,
+            Err(err) => return self.report_error(format!("Failed to open archive: {:?}", err)),
+        };
+
+        match entries.len() {
+            0 => self.report_error("The archive does not contain any audio files.".to_string()),
+            1 => {
+                let entry_name = entries.pop().unwrap();
+                let event = ModelEvent::OpenArchiveEntry(archive_fname, entry_name);
+                self.handle_event(event);
+            }
+            _ => {
+                let event = ViewEvent::ChooseArchiveEntry(archive_fname, entries);
+                self.sender.send(event).unwrap();
+            }
+        }
+    }
+
+    /// Open the file from disk, or list its entries if it is an archive.
+    ///
+    /// When `is_reload` is true, the file replaces the loaded file, see `open_reader`.
+    fn open_file(&mut self, fname: PathBuf, is_reload: bool) {
+        // Build the title in advance, so we can refuse file names
+        // that we would not be able to render in the UI.
+        let title = match fname.file_name().and_then(OsStr::to_str) {
+            // I don't care to support non-utf8 filenames.
+            None => return self.report_error("Invalid file name to open.".to_string()),
+            Some(fname_str) => fname_str.to_string(),
+        };
+
+        if archive::is_archive(&fname) {
+            return self.open_archive(fname);
+        }
+
+        if let Some(tool) = external::tool_for(&fname) {
+            return match external::spawn(tool, &fname) {
+                Ok(output) => self.open_reader(Box::new(output), title, Some(fname), is_reload),
+                Err(msg) => self.report_error(msg),
+            };
+        }
+
+        match fs::File::open(&fname) {
+            Ok(file) => self.open_reader(Box::new(file), title, Some(fname), is_reload),
+            Err(err) => self.report_open_error(&title, &err.to_string()),
+        }
+    }
+
+    /// Start decoding a new file from the reader.
+    ///
+    /// When `is_reload` is true, the reader contains a newer version of the
+    /// loaded file. Then we keep the zoom and the undo history, and we do not
+    /// keep the outdated version around to swap back to.
+    fn open_reader(
+        &mut self,
+        mut reader: Box<dyn io::Read>,
+        title: String,
+        path: Option<PathBuf>,
+        is_reload: bool,
+    ) {
+        // Tell the formats apart by their first bytes, and then put those
+        // bytes back in front, so the decoder can read the full header.
+        let mut magic = [0_u8; 4];
+        if let Err(err) = reader.read_exact(&mut magic) {
+            return self.report_open_error(&title, &format!("{:?}", err));
+        }
+        let reader: Box<dyn io::Read> = Box::new(io::Cursor::new(magic).chain(reader));
+
+        // Try to read the header. If this fails, we don't load the file in the UI.
+        let mut start_time = None;
+        let audio_reader: Box<dyn SampleSource> = match &magic {
+            b"RIFF" => match wav::WavReader::new(reader) {
+                Ok(r) => {
+                    start_time = r.origination_time().and_then(args::parse_time_of_day);
+                    Box::new(r)
+                }
+                Err(err) => return self.report_open_error(&title, &format!("{:?}", err)),
+            },
+            // MP3 starts with an ID3 tag, or with the sync bits of the first frame.
+            [b'I', b'D', b'3', _] | [0xff, 0xe0..=0xff, _, _] => match Mp3Source::new(reader) {
+                Ok(r) => Box::new(r),
+                Err(err) => return self.report_open_error(&title, &format!("{:?}", err)),
+            },
+            _ => match FlacSource::new(reader) {
+                Ok(r) => Box::new(r),
+                Err(err) => return self.report_open_error(&title, &format!("{:?}", err)),
+            },
+        };
+        self.open_source(audio_reader, title, path, start_time, is_reload);
+    }
+
+    /// Load the file that the decoder has read the header of.
+    fn open_source(
+        &mut self,
+        audio_reader: Box<dyn SampleSource>,
+        title: String,
+        path: Option<PathBuf>,
+        start_time: Option<u32>,
+        is_reload: bool,
+    ) {
+        let duration = audio_reader.duration();
+        let sample_rate = audio_reader.sample_rate();
+
+        // Everything after this divides by the sample rate, and reads the
+        // first channel. The decoders reject such headers, but a decoder that
+        // does not would take down the model thread, so check here too.
+        if sample_rate == 0 {
+            return self.report_open_error(&title, "the sample rate is zero");
+        }
+        let num_channels = audio_reader.channels();
+        if num_channels == 0 {
+            return self.report_open_error(&title, "the file has no channels");
+        }
+        let bits_per_sample = audio_reader.bits_per_sample();
+
+        // Keep the file that we had loaded, so the user can swap back to it.
+        if !is_reload && (self.duration.is_some() || self.reader.is_some()) {
+            self.previous_file = Some(Box::new(self.take_file()));
+        }
+
+        // The file may have become shorter, the zoom must stay inside of it.
+        let full_view = (0.0, duration.unwrap_or(0) as f64);
+        self.time_view = match (is_reload, duration) {
+            (true, Some(n)) if self.time_view.0 < n as f64 => {
+                (self.time_view.0, self.time_view.1.min(n as f64))
+            }
+            _ => full_view,
+        };
+
+        self.reader = Some(audio_reader);
+        self.title = title.clone();
+        self.tracks = path.as_deref().map_or(Vec::new(), cue::read_beside);
+        self.path = path;
+        self.duration = duration;
+        self.start_time = start_time;
+        self.set_sample_rate(sample_rate);
+        self.bits_per_sample = bits_per_sample;
+        self.num_channels = num_channels;
+        self.window_off = self.choose_window_off();
+
+        // Clear leftovers from a previous file, if any. The history includes
+        // time ranges, which do not apply to a different file.
+        if !is_reload {
+            self.undo_stack.clear();
+            self.redo_stack.clear();
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            *profile = Profile::default();
+        }
+        self.spectrum.clear();
+        self.group_delays.clear();
+        self.coherences.clear();
+        self.constant_q.clear();
+        self.centroids.clear();
+        self.rolloffs.clear();
+        self.fluxes.clear();
+        self.floors.clear();
+        self.smoothed_floors.clear();
+        self.mean_spectrum.clear();
+        self.bandwidth = None;
+        self.decoded_channels.clear();
+        self.mixed_samples.clear();
+        self.envelope.clear();
+
+        // If we have successfully loaded the file, we can tell the UI
+        // to show that in the title, and we can begin decoding.
+        self.sender.send(ViewEvent::SetTitle(title)).unwrap();
+        self.start_decode();
+
+        // Also, we should tell the UI where the tick labels are going
+        // to be, and what the probes measure in the new file.
+        self.recompute_ticks();
+        self.update_probes();
+        self.update_regions();
+        self.send_nyquist_lines();
+        self.send_frequency_marker();
+        self.send_note_lines();
+        self.send_track_markers();
+        self.send_legend();
+        self.send_metadata();
+        self.send_scroll_position();
+        if self.fixed_scale {
+            self.apply_fixed_scale();
+        }
+    }
+
+    /// Tell the UI the format of the loaded file, for the header bar.
+    fn send_metadata(&self) {
+        let event = ViewEvent::SetMetadata {
+            sample_rate: self.sample_rate,
+            bits_per_sample: self.bits_per_sample,
+            channels: self.num_channels,
+            duration_seconds: self.duration.map(|n| n as f64 / self.sample_rate as f64),
+        };
+        self.sender.send(event).unwrap();
+    }
+
+    /// Tell the user why the file failed to open.
+    fn report_open_error(&self, title: &str, reason: &str) {
+        self.report_error(format!("Failed to open {}: {}", title, reason));
+    }
+
+    /// Tell the user that something failed. The UI shows it in a dialog.
+    fn report_error(&self, message: String) {
+        self.sender.send(ViewEvent::ShowError(message)).unwrap();
+    }
+
+    /// Move the state of the loaded file out of the model, leaving no file loaded.
+    fn take_file(&mut self) -> LoadedFile {
+        self.envelope.clear();
+        LoadedFile {
+            title: mem::take(&mut self.title),
+            path: self.path.take(),
+            tracks: mem::take(&mut self.tracks),
+            reader: self.reader.take(),
+            duration: self.duration.take(),
+            time_view: mem::take(&mut self.time_view),
+            start_time: self.start_time.take(),
+            sample_rate: self.sample_rate,
+            bits_per_sample: self.bits_per_sample,
+            num_channels: self.num_channels,
+            decoded_channels: mem::take(&mut self.decoded_channels),
+            channel_mode: self.channel_mode,
+            spectrum: mem::take(&mut self.spectrum),
+            zero_pad_factor: self.zero_pad_factor,
+            window_len: self.window_len,
+            window_fn: self.window_fn,
+            window_off: self.window_off,
+            group_delays: mem::take(&mut self.group_delays),
+            coherences: mem::take(&mut self.coherences),
+            constant_q: mem::take(&mut self.constant_q),
+            centroids: mem::take(&mut self.centroids),
+            rolloffs: mem::take(&mut self.rolloffs),
+            fluxes: mem::take(&mut self.fluxes),
+            floors: mem::take(&mut self.floors),
+            mean_spectrum: mem::take(&mut self.mean_spectrum),
+            bandwidth: self.bandwidth.take(),
+            undo_stack: mem::take(&mut self.undo_stack),
+            redo_stack: mem::take(&mut self.redo_stack),
+        }
+    }
+
+    /// Make `file` the loaded file, and update the UI for it.
+    ///
+    /// This assumes that no file is loaded, call `take_file` first.
+    fn restore_file(&mut self, file: LoadedFile) {
+        self.title = file.title;
+        self.path = file.path;
+        self.tracks = file.tracks;
+        self.reader = file.reader;
+        self.duration = file.duration;
+        self.time_view = file.time_view;
+        self.start_time = file.start_time;
+        self.set_sample_rate(file.sample_rate);
+        self.bits_per_sample = file.bits_per_sample;
+        self.num_channels = file.num_channels;
+        self.decoded_channels = file.decoded_channels;
+        self.send_channels();
+        self.mixed_samples.clear();
+        self.update_mixed_samples();
+        self.spectrum = file.spectrum;
+        self.group_delays = file.group_delays;
+        self.coherences = file.coherences;
+        self.constant_q = file.constant_q;
+        self.centroids = file.centroids;
+        self.rolloffs = file.rolloffs;
+        self.fluxes = file.fluxes;
+        self.floors = file.floors;
+        self.mean_spectrum = file.mean_spectrum;
+        self.bandwidth = file.bandwidth;
+        self.undo_stack = file.undo_stack;
+        self.redo_stack = file.redo_stack;
+
+        self.sender
+            .send(ViewEvent::SetTitle(self.title.clone()))
+            .unwrap();
+        if self.reader.is_some() {
+            self.start_decode();
+        }
+        self.send_progress();
+
+        // The analysis settings may have changed while the file was not in
+        // view. Only then do we need to transform it again.
+        self.window_off = self.choose_window_off();
+        let is_same_analysis = file.zero_pad_factor == self.zero_pad_factor
+            && file.window_len == self.window_len
+            && file.window_fn == self.window_fn
+            && file.window_off == self.window_off
+            && file.channel_mode == self.channel_mode;
+        if !is_same_analysis {
+            self.group_delays.clear();
+            self.coherences.clear();
+            self.constant_q.clear();
+            self.recompute_spectrum();
+        } else {
+            if self.show_group_delay {
+                self.compute_group_delays();
+            } else {
+                self.group_delays.clear();
+            }
+            if self.show_coherence {
+                self.compute_coherences();
+            } else {
+                self.coherences.clear();
+            }
+            if self.show_constant_q {
+                self.compute_constant_q();
+            } else {
+                self.constant_q.clear();
+            }
+        }
+        self.update_smoothed_floors();
+
+        self.recompute_ticks();
+        self.repaint();
+        self.update_probes();
+        self.update_regions();
+        self.send_feature_lines();
+        self.update_envelope();
+        self.send_nyquist_lines();
+        self.send_frequency_marker();
+        self.send_note_lines();
+        self.send_legend();
+        self.send_metadata();
+        self.send_scroll_position();
+    }
+
+    /// Return the mode, or the first channel if the file does not have the selected one.
+    ///
+    /// Showing the first channel is more useful than showing nothing at all.
+    fn existing_channel_mode(&self, mode: ChannelMode) -> ChannelMode {
+        let num_channels = self.decoded_channels.len();
+        match mode {
+            ChannelMode::Channel(ch) if num_channels > 0 && ch >= num_channels => {
+                ChannelMode::Channel(0)
+            }
+            _ => mode,
+        }
+    }
+
+    /// Tell the view which channels the file has, and which one we analyze.
+    ///
+    /// Call this when the channels change, before computing the spectrum,
+    /// because the selected channel may not exist in the new file.
+    fn send_channels(&mut self) {
+        self.channel_mode = self.existing_channel_mode(self.channel_mode);
+        self.sender
+            .send(ViewEvent::SetNumChannels(self.decoded_channels.len()))
+            .unwrap();
+        self.sender
+            .send(ViewEvent::SetChannelMode(self.channel_mode))
+            .unwrap();
+    }
+
+    /// Begin decoding the reader, and make decode events for earlier readers stale.
+    fn start_decode(&mut self) {
+        self.decode_generation += 1;
+        let event = ModelEvent::Decode(self.decode_generation);
+        self.self_sender.send(event).unwrap();
+    }
+
+    fn decode(&mut self) {
+        // Decode some blocks, but not everything at once. This allows
+        // rendering intermediate updates, and it also keeps the app
+        // more responsive by allowing us to handle other events. Doing
+        // limited work and then re-posting a decode event acts like a
+        // yield point.
+        let decode_begin = Instant::now();
+        let len_before = self.decoded_channels.first().map_or(0, |s| s.len());
+        let num_channels_before = self.decoded_channels.len();
+        let result = match self.reader.as_mut() {
+            None => return,
+            Some(r) => r.read_samples(&mut self.decoded_channels, DECODE_BATCH_LEN),
+        };
+        let have_more = match result {
+            Ok(have_more) => have_more,
+            Err(err) => {
+                self.report_error(format!("Failed to decode {}: {:?}", self.title, err));
+                self.sender.send(ViewEvent::SetProgress(1.0)).unwrap();
+                return self.abandon_file();
+            }
+        };
+        if self.decoded_channels.len() != num_channels_before {
+            self.send_channels();
+            self.send_legend();
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            let len_after = self.decoded_channels.first().map_or(0, |s| s.len());
+            profile.decode_time += decode_begin.elapsed();
+            profile.decoded_samples += (len_after - len_before) as u64;
+        }
+
+        if have_more {
+            // Continue decoding after this event.
+            let event = ModelEvent::Decode(self.decode_generation);
+            self.self_sender.send(event).unwrap();
+        } else {
+            self.reader = None;
+
+            // If the header did not include the duration, now we know it.
+            if self.duration.is_none() {
+                let len = self.decoded_channels.first().map_or(0, |s| s.len());
+                self.duration = Some(len as u64);
+                self.time_view = (0.0, len as f64);
+                self.recompute_ticks();
+                self.send_legend();
+                self.send_metadata();
+                self.send_scroll_position();
+                if self.fixed_scale {
+                    self.apply_fixed_scale();
+                }
+            }
+
+            // Otherwise, pad with silence so we can finish the last window.
+            let min_len = self.spectrum.len() * self.window_off + self.window_len + self.window_off;
+            for samples in self.decoded_channels.iter_mut() {
+                if samples.len() < min_len {
+                    samples.resize(min_len, 0.0);
+                }
+            }
+        }
+
+        self.update_mixed_samples();
+        self.compute_spectrum();
+        self.send_progress();
+        if !have_more {
+            self.update_bandwidth();
+            if let Some(profile) = &self.profile {
+                eprintln!(
+                    "{}: decoded {} samples in {:.3} s, {}; transformed {} windows in {:.3} s, {}.",
+                    self.title,
+                    profile.decoded_samples,
+                    profile.decode_time.as_secs_f64(),
+                    profile.decode_report(self.sample_rate),
+                    profile.transformed_windows,
+                    profile.transform_time.as_secs_f64(),
+                    profile.transform_report(),
+                );
+                self.send_legend();
+            }
+        }
+        self.update_envelope();
+        self.repaint();
+        self.update_probes();
+        self.update_regions();
+        self.send_feature_lines();
+    }
+
+    /// Drop the loaded file, for example because it turned out to be corrupt.
+    ///
+    /// Rather than leave half of the file in view, we go back to the file
+    /// that was loaded before it, or to an empty window if there is none.
+    fn abandon_file(&mut self) {
+        self.take_file();
+        match self.previous_file.take() {
+            Some(previous) => self.restore_file(*previous),
+            None => {
+                self.mixed_samples.clear();
+                self.sender.send(ViewEvent::ClearView).unwrap();
+            }
+        }
+    }
+
+    /// Return the fraction of the file that we transformed, see `ViewEvent::SetProgress`.
+    fn decode_progress(&self) -> f64 {
+        match (&self.reader, self.duration) {
+            (None, _) => 1.0,
+            (Some(_), None) => f64::NAN,
+            (Some(_), Some(n)) => {
+                let num_windows = n as f64 / self.window_off as f64;
+                self.spectrum.len() as f64 / num_windows.max(1.0)
+            }
+        }
+    }
+
+    /// Tell the UI how much of the file we transformed, or that we are done.
+    fn send_progress(&self) {
+        let fraction = self.decode_progress();
+        self.sender.send(ViewEvent::SetProgress(fraction)).unwrap();
+    }
+
+    /// Add the newly decoded samples to the envelope, and send it to the UI thread.
+    ///
+    /// When `envelope` is empty, this starts over from the first sample.
+    fn update_envelope(&mut self) {
+        let duration = match self.duration {
+            None => return,
+            Some(n) => n,
+        };
+        let (width, _height) = self.target_size;
+        if width <= 0 {
+            return;
+        }
+        if self.envelope.is_empty() {
+            self.envelope = vec![EnvelopeColumn::default(); width as usize];
+            self.envelope_len = 0;
+        }
+        let samples = match select_samples(
+            &self.decoded_channels,
+            &self.mixed_samples,
+            self.channel_mode,
+        ) {
+            Some(s) => s,
+            None => return,
+        };
+
+        // Samples past the duration are padding, they are not part of the file.
+        // Of the new samples, we only need the ones in view.
+        let (view_begin, view_end) = self.time_view;
+        let samples_per_px = (view_end - view_begin) / width as f64;
+        let end = samples.len().min(duration as usize).max(self.envelope_len);
+        let begin = self.envelope_len.max(view_begin as usize).min(end);
+        let end_in_view = end.min(view_end.ceil() as usize).max(begin);
+        for (t, &s) in (begin..end_in_view).zip(&samples[begin..end_in_view]) {
+            let x = ((t as f64 - view_begin) / samples_per_px) as usize;
+            let column = &mut self.envelope[x.min(width as usize - 1)];
+            column.peak = column.peak.max(s.abs());
+            column.sum_squares = s.mul_add(s, column.sum_squares);
+            column.count += 1;
+        }
+        self.envelope_len = end;
+
+        let envelope = self
+            .envelope
+            .iter()
+            .map(|c| {
+                let rms = (c.sum_squares / c.count.max(1) as f32).sqrt();
+                (c.peak, rms)
+            })
+            .collect();
+        self.sender.send(ViewEvent::SetEnvelope(envelope)).unwrap();
+    }
+
+    /// Discard the spectrum and recompute it from the decoded samples.
+    ///
+    /// This is needed after a change to the analysis parameters.
+    fn recompute_spectrum(&mut self) {
+        self.spectrum.clear();
+        self.group_delays.clear();
+        self.coherences.clear();
+        self.constant_q.clear();
+        self.centroids.clear();
+        self.rolloffs.clear();
+        self.fluxes.clear();
+        self.floors.clear();
+        self.compute_spectrum();
+
+        // The bins changed, so the mean spectrum needs to be recomputed too,
+        // if we are done decoding. Otherwise we do it when we are.
+        if self.reader.is_none() {
+            self.update_bandwidth();
+        }
+        self.repaint();
+        self.update_probes();
+        self.update_regions();
+        self.send_feature_lines();
+    }
+
+    /// Return the hop between windows to use for the loaded file.
+    ///
+    /// Without `auto_hop`, this is `base_window_off`. With it, we aim for about two
+    /// windows per pixel column when the full file is in view, rounded down
+    /// to a power of two. Transforming more windows than that would be wasted
+    /// work, because they fall in the same column anyway. When zoomed in, the
+    /// time resolution is coarser then, but that is the tradeoff.
+    fn choose_window_off(&self) -> usize {
+        let width = self.target_size.0.max(1) as u64;
+        match self.duration {
+            Some(n) if self.auto_hop => {
+                let ideal = (n / (2 * width)).max(1);
+                let power_of_two = 1_u64 << (63 - ideal.leading_zeros());
+                (power_of_two as usize).max(self.base_window_off)
+            }
+            _ => self.base_window_off,
+        }
+    }
+
+    /// Return the number of frequency bins in the DFT of a window.
+    fn spectrum_len(&self) -> usize {
+        self.unpadded_spectrum_len() * self.zero_pad_factor
+    }
+
+    /// Return the number of frequency bins in the DFT of a window, without zero-padding.
+    fn unpadded_spectrum_len(&self) -> usize {
+        self.window_len / 2
+    }
+
+    /// Return the fractional bin at height `y` in graph coordinates.
+    ///
+    /// The top of the graph is the top of `hz_range`, which is the Nyquist
+    /// frequency of the loaded file, unless we zoom, or compare files.
+    fn bin_at(&self, y: f64) -> f64 {
+        // The mel scale depends on the absolute frequency, so it goes through Hz.
+        if self.show_dc_strip || self.y_scale == YScale::Mel {
+            let fft_len = self.window_len * self.zero_pad_factor;
+            return self.hz_at(y) * fft_len as f64 / self.sample_rate as f64;
+        }
+        // Map onto the bins of the transform without zero-padding and scale
+        // that, so zero-padding does not change the frequency range we show.
+        let max_bin = (self.unpadded_spectrum_len() - 1) as f64;
+        let bins_per_hz = self.window_len as f64 / self.sample_rate as f64;
+        let (hz_min, hz_max) = self.hz_range();
+        let min_bin = (hz_min * bins_per_hz).max(1.0);
+        let max_bin = (hz_max * bins_per_hz).min(max_bin);
+        self.y_scale.map(y, min_bin, max_bin, self.y_blend) * self.zero_pad_factor as f64
+    }
+
+    /// Return the frequency at the top of the DC strip, and the height of the strip.
+    ///
+    /// At very low sample rates, the strip covers at most half of the spectrum.
+    fn dc_strip(&self) -> (f64, f64) {
+        let (_hz_min, hz_max) = self.full_hz_range();
+        (DC_STRIP_HZ.min(0.5 * hz_max), DC_STRIP_HEIGHT)
+    }
+
+    /// Return the frequency in Hz at height `y` in graph coordinates.
+    fn hz_at(&self, y: f64) -> f64 {
+        let (hz_min, hz_max) = self.hz_range();
+        if !self.show_dc_strip {
+            return self.y_scale.map(y, hz_min, hz_max, self.y_blend);
+        }
+        let (strip_hz, strip_height) = self.dc_strip();
+        if y < strip_height {
+            y / strip_height * strip_hz
+        } else {
+            let y_above = (y - strip_height) / (1.0 - strip_height);
+            self.y_scale
+                .map(y_above, strip_hz.max(hz_min), hz_max, self.y_blend)
+        }
+    }
+
+    /// Return the height in graph coordinates of the frequency `hz`, the inverse of `hz_at`.
+    fn y_at_hz(&self, hz: f64) -> f64 {
+        let (hz_min, hz_max) = self.hz_range();
+        if !self.show_dc_strip {
+            return self.y_scale.map_inv(hz, hz_min, hz_max, self.y_blend);
+        }
+        let (strip_hz, strip_height) = self.dc_strip();
+        if hz < strip_hz {
+            hz / strip_hz * strip_height
+        } else {
+            let y_above = self
+                .y_scale
+                .map_inv(hz, strip_hz.max(hz_min), hz_max, self.y_blend);
+            strip_height + y_above * (1.0 - strip_height)
+        }
+    }
+
+    /// Return the height in graph coordinates at which to draw an overlay at frequency `hz`.
+    ///
+    /// Unlike `y_at_hz`, this puts frequencies that are out of view outside of
+    /// the graph, rather than on its edge.
+    fn overlay_y_at_hz(&self, hz: f64) -> f64 {
+        if hz < self.hz_at(0.0) {
+            -1.0
+        } else if hz > self.hz_at(1.0) {
+            2.0
+        } else {
+            self.y_at_hz(hz)
+        }
+    }
+
+    /// Return the bins that the sampled rows of the spectrogram cover, from the top down.
+    ///
+    /// We sample one row for every `row_step` pixel rows.
+    fn row_bins(&self, height: i32) -> Vec<RowBins> {
+        let step = self.row_step as i32;
+        let yf = |y: f64| 1.0 - y / (height - 1).max(1) as f64;
+
+        // The bins per unit of height, if every row covered the same bandwidth.
+        let mean_bins_per_unit = self.bin_at(1.0) - self.bin_at(0.0);
+        (0..height)
+            .step_by(self.row_step)
+            .map(|y| {
+                // The center of the sampled row, and its top and bottom edges,
+                // which are half a pixel beyond the outer pixel rows.
+                let center = y as f64 + 0.5 * (step.min(height - y) - 1) as f64;
+                let top = y as f64 - 0.5;
+                let bottom = (y + step).min(height) as f64 - 0.5;
+
+                // The local derivative of the axis mapping. We take the
+                // difference over the row rather than the derivative at its
+                // center, because at the bottom of a log axis the derivative
+                // changes quickly.
+                let (y_top, y_bottom) = (yf(top).min(1.0), yf(bottom).max(0.0));
+                let bin_top = self.bin_at(y_top);
+                let bin_bottom = self.bin_at(y_bottom);
+                let bins_per_unit = (bin_top - bin_bottom) / (y_top - y_bottom);
+
+                RowBins {
+                    center: self.bin_at(yf(center)),
+                    lo: bin_bottom.ceil() as usize,
+                    hi: bin_top.floor() as usize,
+                    weight: (bins_per_unit / mean_bins_per_unit) as f32,
+                }
+            })
+            .collect()
+    }
+
+    /// Recompute the bins per row, after a change in the geometry.
+    fn update_row_plan(&mut self) {
+        self.row_plan = self.row_bins(self.target_size.1);
+    }
+
+    /// Return the normalized power of a window in a row of the spectrogram.
+    ///
+    /// When the row covers multiple bins, we take the maximum rather than
+    /// sampling a single bin, so narrow peaks remain visible. With
+    /// `equal_energy`, we take the mean instead, which the caller scales by
+    /// the bandwidth of the row, so the result is the energy in the row.
+    fn row_power(&self, spectrum_i: &[f32], row: &RowBins) -> f32 {
+        if row.hi > row.lo {
+            let hi = row.hi.min(spectrum_i.len() - 1);
+            let lo = row.lo.min(hi);
+            let bins = spectrum_i[lo..=hi].iter().cloned();
+            let power = if self.equal_energy {
+                bins.sum::<f32>() / (hi - lo + 1) as f32
+            } else {
+                bins.fold(0.0_f32, f32::max)
+            };
+            power / self.unpadded_spectrum_len() as f32
+        } else {
+            self.power_at(spectrum_i, row.center)
+        }
+    }
+
+    /// Like `row_power`, but for the power in excess of the mean spectrum.
+    ///
+    /// Over multiple bins, this takes the maximum or the mean like `row_power`
+    /// does, so both views show the same energy per row for `equal_energy`.
+    fn row_residual(&self, spectrum_i: &[f32], row: &RowBins) -> f32 {
+        let residual = |j: usize| {
+            let excess = spectrum_i[j] - self.mean_spectrum[j];
+            excess.max(0.0) / self.unpadded_spectrum_len() as f32
+        };
+        let last = spectrum_i.len() - 1;
+        if row.hi > row.lo {
+            let hi = row.hi.min(last);
+            let lo = row.lo.min(hi);
+            let residuals = (lo..=hi).map(residual);
+            if self.equal_energy {
+                residuals.sum::<f32>() / (hi - lo + 1) as f32
+            } else {
+                residuals.fold(0.0_f32, f32::max)
+            }
+        } else {
+            let j = row.center.trunc() as usize;
+            let fract = row.center.fract() as f32;
+            fract * residual((j + 1).min(last)) + (1.0 - fract) * residual(j.min(last))
+        }
+    }
+
+    /// Return the normalized power at fractional bin `jf` of a window.
+    fn power_at(&self, spectrum_i: &[f32], jf: f64) -> f32 {
+        // Interpolate between the two closest frequencies. Zero-padding does
+        // not change the magnitude of the coefficients, so we normalize by the
+        // unpadded length.
+        let last = spectrum_i.len() - 1;
+        let j = jf.trunc() as usize;
+        let s0 = spectrum_i[j.min(last)] / self.unpadded_spectrum_len() as f32;
+        let s1 = spectrum_i[(j + 1).min(last)] / self.unpadded_spectrum_len() as f32;
+        let fract = jf.fract() as f32;
+        fract * s1 + (1.0 - fract) * s0
+    }
+
+    /// Extend `mixed_samples` with the samples that we decoded since the last call.
+    ///
+    /// In a mono file, mid is the only channel, and side is silence.
+    fn update_mixed_samples(&mut self) {
+        let channels = &self.decoded_channels;
+        let (left, right) = match &channels[..] {
+            [] => return,
+            [left] => (left, left),
+            [left, right, ..] => (left, right),
+        };
+        let begin = self.mixed_samples.len().min(left.len());
+        let inv_num_channels = (channels.len() as f32).recip();
+        let mixed = (begin..left.len()).map(|i| match self.channel_mode {
+            ChannelMode::Channel(_) => 0.0,
+            ChannelMode::Mix => channels.iter().map(|s| s[i]).sum::<f32>() * inv_num_channels,
+            ChannelMode::Mid => 0.5 * (left[i] + right[i]),
+            ChannelMode::Side => 0.5 * (left[i] - right[i]),
+        });
+        match self.channel_mode {
+            ChannelMode::Channel(_) => self.mixed_samples.clear(),
+            _ => self.mixed_samples.extend(mixed),
+        }
+    }
+
+    /// Take the DFT of all windows that are fully decoded, but not yet in the spectrum.
+    fn compute_spectrum(&mut self) {
+        let fft_len = self.window_len * self.zero_pad_factor;
+        if self.fft_plan.input_len() != fft_len {
+            self.fft_plan = FftPlan::new(fft_len);
+        }
+
+        let samples = match select_samples(
+            &self.decoded_channels,
+            &self.mixed_samples,
+            self.channel_mode,
+        ) {
+            Some(s) => s,
+            None => return,
+        };
+
+        // Start at the first window that is not yet in the spectrum.
+        let transform_begin = Instant::now();
+        let num_windows_before = self.spectrum.len();
+        let start = (self.spectrum.len() * self.window_off).min(samples.len());
+        // The transform is the expensive part, and every window is independent,
+        // so we transform them in parallel. The features depend on the previous
+        // window, we compute those in order afterwards.
+        let window_fn = self.window_fn;
+        let spectra = Stft::new(
+            &samples[start..],
+            self.window_len,
+            self.window_off,
+            move |len, i| window_fn.coefficient(len, i),
+        )
+        .with_plan(self.fft_plan.clone())
+        .collect_parallel();
+
+        for dft_of_samples in spectra {
+            let hz_per_bin = self.sample_rate as f32 / fft_len as f32;
+            self.centroids
+                .push(analysis::spectral_centroid(&dft_of_samples, hz_per_bin));
+            self.rolloffs.push(analysis::spectral_rolloff(
+                &dft_of_samples,
+                hz_per_bin,
+                ROLLOFF_FRACTION,
+            ));
+            self.fluxes.push(match self.spectrum.last() {
+                Some(previous) => analysis::spectral_flux(previous, &dft_of_samples),
+                None => 0.0,
+            });
+            self.floors.push(analysis::noise_floor(&dft_of_samples));
+
+            self.spectrum.push(dft_of_samples);
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.transform_time += transform_begin.elapsed();
+            profile.transformed_windows += (self.spectrum.len() - num_windows_before) as u64;
+        }
+
+        if self.show_group_delay {
+            self.compute_group_delays();
+        }
+        if self.show_coherence {
+            self.compute_coherences();
+        }
+        if self.show_constant_q {
+            self.compute_constant_q();
+        }
+        self.update_smoothed_floors();
+    }
+
+    /// Smooth the noise floors if we need them, or drop them if we do not.
+    fn update_smoothed_floors(&mut self) {
+        self.smoothed_floors = if self.adaptive_floor {
+            analysis::smooth_log(&self.floors, FLOOR_RADIUS)
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// Compute the group delay for the windows in the spectrum that we do not have it for yet.
+    fn compute_group_delays(&mut self) {
+        let samples = match select_samples(
+            &self.decoded_channels,
+            &self.mixed_samples,
+            self.channel_mode,
+        ) {
+            Some(s) => s,
+            None => return,
+        };
+
+        for i in self.group_delays.len()..self.spectrum.len() {
+            let window = &samples[i * self.window_off..i * self.window_off + self.window_len];
+            let window_fn = |len, i| self.window_fn.coefficient(len, i);
+            let delays = dft::group_delay(window, window_fn, self.zero_pad_factor);
+            self.group_delays.push(delays);
+        }
+    }
+
+    /// Compute the phase coherence for the windows in the spectrum that we do not have it for yet.
+    ///
+    /// We compare the first two channels, which are left and right in a stereo
+    /// file, regardless of the channel that the spectrum is of.
+    fn compute_coherences(&mut self) {
+        let (left, right) = match &self.decoded_channels[..] {
+            [left, right, ..] => (left, right),
+            _ => return,
+        };
+
+        for i in self.coherences.len()..self.spectrum.len() {
+            let range = i * self.window_off..i * self.window_off + self.window_len;
+            let coherence = dft::phase_coherence(
+                &left[range.clone()],
+                &right[range],
+                |len, i| self.window_fn.coefficient(len, i),
+                self.zero_pad_factor,
+                COHERENCE_RADIUS * self.zero_pad_factor,
+            );
+            self.coherences.push(coherence);
+        }
+    }
+
+    /// Compute the constant-Q transform for the windows in the spectrum that we do not have it for yet.
+    fn compute_constant_q(&mut self) {
+        let samples = match select_samples(
+            &self.decoded_channels,
+            &self.mixed_samples,
+            self.channel_mode,
+        ) {
+            Some(s) => s,
+            None => return,
+        };
+        if self.constant_q.len() == self.spectrum.len() {
+            return;
+        }
+
+        let key = (self.sample_rate, self.window_len, self.window_fn);
+        let transform = match &self.constant_q_plan {
+            Some((plan_key, transform)) if *plan_key == key => transform,
+            _ => {
+                let window_fn = self.window_fn;
+                let transform = ConstantQ::new(
+                    self.sample_rate,
+                    self.window_len,
+                    move |len, i| window_fn.coefficient(len, i),
+                    CONSTANT_Q_MIN_HZ,
+                    CONSTANT_Q_BINS_PER_OCTAVE,
+                );
+                &self.constant_q_plan.insert((key, transform)).1
+            }
+        };
+        let windows = transform.transform_parallel(
+            samples,
+            self.window_off,
+            self.constant_q.len(),
+            self.spectrum.len(),
+        );
+        self.constant_q.extend(windows);
+    }
+
+    /// Return the fractional constant-Q bin at the fractional DFT bin `j`.
+    fn constant_q_bin(&self, j: f64) -> f64 {
+        let fft_len = self.window_len * self.zero_pad_factor;
+        let hz = j * self.sample_rate as f64 / fft_len as f64;
+        cqt::bin_at_hz(hz, CONSTANT_Q_MIN_HZ, CONSTANT_Q_BINS_PER_OCTAVE)
+    }
+
+    /// Like `row_power`, but for the constant-Q transform of a window.
+    ///
+    /// The rows are in DFT bins, we map their edges to constant-Q bins. Below
+    /// the lowest constant-Q bin there is no power.
+    fn row_constant_q_power(&self, constant_q_i: &[f32], row: &RowBins) -> f32 {
+        let last = match constant_q_i.len() {
+            0 => return 0.0,
+            n => n - 1,
+        };
+        let k_lo = self.constant_q_bin(row.lo as f64).ceil();
+        let k_hi = self.constant_q_bin(row.hi as f64).floor();
+        let power = if row.hi > row.lo && k_hi >= k_lo.max(0.0) {
+            let hi = (k_hi as usize).min(last);
+            let lo = (k_lo.max(0.0) as usize).min(hi);
+            constant_q_i[lo..=hi]
+                .iter()
+                .cloned()
+                .fold(0.0_f32, f32::max)
+        } else {
+            let kf = self.constant_q_bin(row.center);
+            if kf.is_nan() || kf < 0.0 {
+                return 0.0;
+            }
+            let k = (kf.trunc() as usize).min(last);
+            let fract = kf.fract() as f32;
+            fract * constant_q_i[(k + 1).min(last)] + (1.0 - fract) * constant_q_i[k]
+        };
+        power / self.unpadded_spectrum_len() as f32
+    }
+
+    /// Change the sample rate, and the rows that follow from it.
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.fit_freq_view();
+        self.update_row_plan();
+    }
+
+    /// Reset the frequency zoom if nothing of it lies in the range of the file.
+    ///
+    /// The zoom is in Hz, so it still applies to a file at a different rate,
+    /// unless the file has nothing in that range.
+    fn fit_freq_view(&mut self) {
+        let (low, high) = self.hz_range();
+        if low >= high {
+            self.freq_view = (0.0, f64::INFINITY);
+        }
+    }
+
+    /// Return the frequency range in Hz that the y-axis spans.
+    fn hz_range(&self) -> (f64, f64) {
+        let (hz_min, hz_max) = self.full_hz_range();
+        let (view_min, view_max) = self.freq_view;
+        (hz_min.max(view_min), hz_max.min(view_max))
+    }
+
+    /// Return the frequency range of the file in Hz, regardless of the zoom.
+    ///
+    /// While we compare against the previous file, the range is the part
+    /// that both files share, so that the rows show the same frequencies in
+    /// both files, also after swapping them.
+    fn full_hz_range(&self) -> (f64, f64) {
+        // The minimal period that the DFT picks up, above the constant factor,
+        // is a single window.
+        let hz_min = self.sample_rate as f64 / self.window_len as f64;
+
+        // The maximal frequency is half of `window_len` periods in the window.
+        // As there is one bucket per sample, that is half of the sample rate.
+        let hz_max = self.sample_rate as f64 / 2.0;
+
+        match (self.compare_peaks, &self.previous_file) {
+            (true, Some(other)) => (
+                hz_min.max(other.sample_rate as f64 / other.window_len as f64),
+                hz_max.min(other.sample_rate as f64 / 2.0),
+            ),
+            _ => (hz_min, hz_max),
+        }
+    }
+
+    /// Group per-window values by the pixel column that the window center is in.
+    ///
+    /// Returns the horizontal center of every column in graph coordinates,
+    /// together with the values of the windows in that column. Windows that
+    /// are centered past the end of the file only contain padding, they are
+    /// not included.
+    fn group_by_column<'a>(&self, values: &'a [f32]) -> Vec<(f64, &'a [f32])> {
+        let duration = match self.duration {
+            None => return Vec::new(),
+            Some(n) => n,
+        };
+        let (width, _height) = self.target_size;
+        let column_of = |i: usize| {
+            let center = (i * self.window_off + self.window_len / 2) as u64;
+            let x = self.x_at(center as f64);
+            if center < duration && (0.0..1.0).contains(&x) {
+                Some((x * width as f64) as i32)
+            } else {
+                None
+            }
+        };
+
+        let mut groups = Vec::with_capacity(width.max(0) as usize);
+        let mut begin = 0;
+        while begin < values.len() {
+            let column = match column_of(begin) {
+                Some(c) => c,
+                None => {
+                    begin += 1;
+                    continue;
+                }
+            };
+            let mut end = begin + 1;
+            while end < values.len() && column_of(end) == Some(column) {
+                end += 1;
+            }
+            let x = (column as f64 + 0.5) / width as f64;
+            groups.push((x, &values[begin..end]));
+            begin = end;
+        }
+
+        groups
+    }
+
+    /// Write the strongest spectral peaks of every window as csv, linked into partials.
+    ///
+    /// Every line has the time of the window center in seconds, the number of
+    /// the partial that the peak belongs to, its frequency in Hz, and the
+    /// amplitude of a sine wave with that peak, in dB relative to full scale.
+    /// Windows that are centered past the end of the file are not included.
+    fn write_peaks<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let duration = self.duration.unwrap_or(0);
+        let fft_len = self.window_len * self.zero_pad_factor;
+        let hz_per_bin = self.sample_rate as f32 / fft_len as f32;
+        let frames: Vec<Vec<analysis::Peak>> = self
+            .spectrum
+            .iter()
+            .enumerate()
+            .take_while(|&(i, _)| ((i * self.window_off + self.window_len / 2) as u64) < duration)
+            .map(|(_, spectrum_i)| analysis::find_peaks(spectrum_i, hz_per_bin, PEAKS_PER_WINDOW))
+            .collect();
+
+        // A partial may drift by two bins of the unpadded transform per window.
+        let max_jump_hz = 2.0 * self.sample_rate as f32 / self.window_len as f32;
+        let partials = analysis::track_partials(&frames, max_jump_hz);
+
+        writeln!(out, "time,partial,frequency,amplitude")?;
+        for (i, (peaks, partials)) in frames.iter().zip(&partials).enumerate() {
+            let t = (i * self.window_off + self.window_len / 2) as f64 / self.sample_rate as f64;
+            for (peak, partial) in peaks.iter().zip(partials) {
+                // The Hann window has a mean of 1.0, so a sine wave of
+                // amplitude a has a magnitude of a * window_len / 2.
+                let amplitude = 2.0 * peak.power.sqrt() / self.window_len as f32;
+                let amplitude_db = 20.0 * amplitude.log10();
+                writeln!(
+                    out,
+                    "{:.4},{},{:.2},{:.2}",
+                    t, partial, peak.hz, amplitude_db
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the spectral peaks to a csv file, see `write_peaks`.
+    fn save_peaks(&self, fname: &Path) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(fname)?);
+        self.write_peaks(&mut out)?;
+        io::Write::flush(&mut out)
+    }
+
+    /// Turn per-window frequencies into a polyline with one point per pixel column.
+    fn feature_line(&self, values_hz: &[f32]) -> Vec<(f64, f64)> {
+        self.group_by_column(values_hz)
+            .into_iter()
+            .map(|(x, group)| {
+                let mean = group.iter().map(|&v| v as f64).sum::<f64>() / group.len() as f64;
+                (x, self.y_at_hz(mean))
+            })
+            .collect()
+    }
+
+    /// Average the spectrum over all windows, and estimate the bandwidth from it.
+    ///
+    /// Sends the bandwidth to the UI thread.
+    fn update_bandwidth(&mut self) {
+        let mut mean_spectrum = vec![0.0_f32; self.spectrum_len()];
+        let inv_len = (self.spectrum.len().max(1) as f32).recip();
+        for spectrum_i in &self.spectrum {
+            for (mean, &p) in mean_spectrum.iter_mut().zip(spectrum_i.iter()) {
+                *mean = p.mul_add(inv_len, *mean);
+            }
+        }
+
+        let fft_len = self.window_len * self.zero_pad_factor;
+        let hz_per_bin = self.sample_rate as f32 / fft_len as f32;
+        self.bandwidth = Some(analysis::bandwidth(&mean_spectrum, hz_per_bin));
+        self.mean_spectrum = mean_spectrum;
+        self.send_nyquist_lines();
+    }
+
+    /// Send the positions of the Nyquist frequency and the bandwidth to the UI thread.
+    fn send_nyquist_lines(&self) {
+        let (_hz_min, hz_max) = self.full_hz_range();
+        let (_view_min, view_max) = self.hz_range();
+        let mut lines = Vec::new();
+        if hz_max <= view_max {
+            lines.push(Tick {
+                position: self.y_at_hz(hz_max),
+                label: format!("Nyquist {}", format_hz(hz_max)),
+            });
+        }
+
+        // When the content extends to within 1 kHz of the Nyquist frequency,
+        // the two lines would overlap, and there is nothing to point out. A
+        // lossy encoder at 44.1 kHz may still cut off as high as 20 kHz.
+        // A steep edge suggests the lowpass of a lossy encoder, or resampling,
+        // rather than content that fades out, so we name it that.
+        if let Some(hz) = self.bandwidth {
+            if (hz as f64) < hz_max - 1000.0 && (hz as f64) <= view_max {
+                let fft_len = self.window_len * self.zero_pad_factor;
+                let hz_per_bin = self.sample_rate as f32 / fft_len as f32;
+                let name = if analysis::is_steep_cutoff(&self.mean_spectrum, hz_per_bin, hz) {
+                    "Lowpass"
+                } else {
+                    "Bandwidth"
+                };
+                lines.push(Tick {
+                    position: self.y_at_hz(hz as f64),
+                    label: format!("{} {}", name, format_hz(hz as f64)),
+                });
+            }
+        }
+
+        self.sender.send(ViewEvent::SetNyquistLines(lines)).unwrap();
+    }
+
+    /// Return the position of the frequency marker, or why the frequency is not on the axis.
+    fn frequency_marker(&self) -> Option<Result<Tick, String>> {
+        let hz = self.frequency_marker?;
+        let (hz_bottom, hz_top) = (self.hz_at(0.0), self.hz_at(1.0));
+        if hz < hz_bottom || hz > hz_top {
+            return Some(Err(format!(
+                "The axis spans {} to {}.",
+                format_hz(hz_bottom),
+                format_hz(hz_top),
+            )));
+        }
+        Some(Ok(Tick {
+            position: self.y_at_hz(hz),
+            label: format_hz(hz),
+        }))
+    }
+
+    /// Send the frequency marker to the UI thread.
+    fn send_frequency_marker(&self) {
+        let marker = self.frequency_marker();
+        self.sender
+            .send(ViewEvent::SetFrequencyMarker(marker))
+            .unwrap();
+    }
+
+    /// Send the note grid to the UI thread.
+    fn send_note_lines(&self) {
+        let lines = self.note_lines();
+        self.sender.send(ViewEvent::SetNoteLines(lines)).unwrap();
+    }
+
+    /// Return the positions of the notes in view from low to high, with the names of the Cs.
+    fn note_lines(&self) -> Vec<Tick> {
+        let (hz_bottom, hz_top) = (self.hz_at(0.0), self.hz_at(1.0));
+        (0..=127)
+            .filter(|&note| (hz_bottom..=hz_top).contains(&note_hz(note)))
+            .map(|note| Tick {
+                position: self.y_at_hz(note_hz(note)),
+                label: match note % 12 {
+                    0 => note_name(note),
+                    _ => String::new(),
+                },
+            })
+            .collect()
+    }
+
+    /// Send the spectral flux and detected onsets to the UI thread.
+    fn send_flux(&self) {
+        // Take the maximum per column rather than the mean, so that short
+        // transients remain visible when many windows map to one column.
+        let max_flux = self.fluxes.iter().cloned().fold(0.0_f32, f32::max);
+        let scale = if max_flux > 0.0 {
+            max_flux.recip()
+        } else {
+            0.0
+        };
+        let line = self
+            .group_by_column(&self.fluxes)
+            .into_iter()
+            .map(|(x, group)| {
+                let max = group.iter().cloned().fold(0.0_f32, f32::max);
+                (x, (max * scale) as f64)
+            })
+            .collect();
+
+        let duration = self.duration.unwrap_or(0) as f64;
+        let onsets = analysis::detect_onsets(&self.fluxes)
+            .into_iter()
+            .map(|i| (i * self.window_off + self.window_len / 2) as f64)
+            .filter(|&t| t < duration)
+            .map(|t| self.x_at(t))
+            .filter(|x| (0.0..1.0).contains(x))
+            .collect();
+
+        self.sender.send(ViewEvent::SetFlux(line, onsets)).unwrap();
+    }
+
+    /// Send the spectral centroid and rolloff lines, the flux, and the tracks, to the UI thread.
+    ///
+    /// These are the overlays that are positioned in time, so we send them
+    /// whenever the time range in view changes.
+    fn send_feature_lines(&self) {
+        let event = ViewEvent::SetFeatureLines(
+            self.feature_line(&self.centroids),
+            self.feature_line(&self.rolloffs),
+        );
+        self.sender.send(event).unwrap();
+        self.send_flux();
+        self.send_track_markers();
+        self.send_compare_peaks();
+    }
+
+    /// Return the spectral peaks of the previous file, at the times in view of this one.
+    ///
+    /// The files line up at their start. Per pixel column we take the window
+    /// of the previous file centered closest to it. The opacity of a peak goes
+    /// from transparent at 60 dB below the strongest peak in the window, to
+    /// mostly opaque for the strongest one.
+    fn compare_peaks(&self) -> Vec<(f64, f64, f32)> {
+        let (width, _height) = self.target_size;
+        let other = match &self.previous_file {
+            Some(file) if self.compare_peaks && !file.spectrum.is_empty() => file,
+            _ => return Vec::new(),
+        };
+        let fft_len = other.window_len * other.zero_pad_factor;
+        let hz_per_bin = other.sample_rate as f32 / fft_len as f32;
+        let other_duration = other.duration.unwrap_or(0) as f64;
+        let (hz_bottom, hz_top) = (self.hz_at(0.0), self.hz_at(1.0));
+
+        let mut points = Vec::new();
+        for x in 0..width {
+            let x = (x as f64 + 0.5) / width as f64;
+            let t = self.time_at(x) / self.sample_rate as f64 * other.sample_rate as f64;
+            if t < 0.0 || t >= other_duration {
+                continue;
+            }
+            let i = ((t - (other.window_len / 2) as f64) / other.window_off as f64).round();
+            let spectrum_i = match other.spectrum.get(i.max(0.0) as usize) {
+                Some(s) => s,
+                None => continue,
+            };
+            let peaks = analysis::find_peaks(spectrum_i, hz_per_bin, PEAKS_PER_WINDOW);
+            let max_power = peaks.iter().map(|p| p.power).fold(0.0_f32, f32::max);
+            for peak in &peaks {
+                let hz = peak.hz as f64;
+                let y = self.y_at_hz(hz);
+                let db_below = 10.0 * (max_power / peak.power).log10();
+                if (hz_bottom..=hz_top).contains(&hz) && db_below < 60.0 {
+                    points.push((x, y, 0.8 * (1.0 - db_below / 60.0)));
+                }
+            }
+        }
+        points
+    }
+
+    /// Send the spectral peaks of the previous file to the UI thread.
+    fn send_compare_peaks(&self) {
+        let peaks = self.compare_peaks();
+        self.sender.send(ViewEvent::SetComparePeaks(peaks)).unwrap();
+    }
+
+    /// Return the starts of the tracks in view, in graph coordinates.
+    fn track_markers(&self) -> Vec<Tick> {
+        self.tracks
+            .iter()
+            .map(|track| Tick {
+                position: self.x_at(track.start_sample(self.sample_rate) as f64),
+                label: track.label(),
+            })
+            .filter(|marker| (0.0..1.0).contains(&marker.position))
+            .collect()
+    }
+
+    /// Send the starts of the tracks in view to the UI thread.
+    fn send_track_markers(&self) {
+        let markers = self.track_markers();
+        self.sender
+            .send(ViewEvent::SetTrackMarkers(markers))
+            .unwrap();
+    }
+
+    fn recompute_ticks(&self) {
+        if self.duration.is_none() {
+            return;
+        }
+        let (x_ticks, y_ticks) = self.compute_ticks();
+        self.sender
+            .send(ViewEvent::SetTicks(x_ticks, y_ticks))
+            .unwrap();
+    }
+
+    /// Return the ticks of the x-axis and the y-axis, for the view and the size of the graph.
+    fn compute_ticks(&self) -> (Vec<Tick>, Vec<Tick>) {
+        let (view_begin, view_end) = self.time_view;
+        let view_len = view_end - view_begin;
+
+        let (width, height) = self.target_size;
+        let (label_width, label_height) = self.label_size;
+
+        // Before the first resize, there is no size to divide by yet. In a
+        // very small window, we still place at least two ticks per axis.
+        let num_major_ticks_x = (width / label_width.max(1)).max(2);
+        let num_major_ticks_y = (height / (label_height * 3 - 2).max(1)).max(2);
+
+        let mut x_ticks = Vec::new();
+        let mut y_ticks = Vec::new();
+
+        // In the histogram view, the x-axis shows the power level rather than time.
+        if self.show_histogram {
+            x_ticks = self.level_ticks(num_major_ticks_x);
+        } else {
+            // Make a rough estimate of how many ticks we can fit first. From that,
+            // compute a possible tick duration as a nice round number, and then
+            // fill the time with those ticks.
+            let x_tick_duration_samples = view_len / (num_major_ticks_x - 1) as f64;
+            let x_tick_duration_ms =
+                (x_tick_duration_samples * 1000.0 / self.sample_rate as f64) as u64;
+
+            // Space tick labels times apart that format to "round" numbers as
+            // mm:ss. Below a second, that is round fractions of a second, so
+            // files shorter than a second still get ticks.
+            let quant_x_tick_ms = match x_tick_duration_ms {
+                0..=999 => *[1, 2, 5, 10, 20, 50, 100, 200, 500, 1000]
+                    .iter()
+                    .find(|&&ms| ms >= x_tick_duration_ms)
+                    .unwrap(),
+                _ => 1000 * quantize_tick_seconds(x_tick_duration_ms / 1000),
+            };
+            let decimals = match quant_x_tick_ms {
+                ms if ms % 1000 == 0 => 0,
+                ms if ms % 100 == 0 => 1,
+                ms if ms % 10 == 0 => 2,
+                _ => 3,
+            };
+
+            // Avoid placing labels at the end, because they will be cut off. For
+            // that, we need to know how much time (in axis coordinates) a the label
+            // consumes.
+            let label_duration = label_width as f64 * view_len / (width as f64 * 2.0);
+
+            // When we know the wall-clock time, label that instead of the
+            // time since the start, and place ticks at round wall-clock times.
+            let start_time = self.start_time_override.or(self.start_time);
+            let offset_ms = start_time.unwrap_or(0) as u64 * 1000;
+            let samples_at =
+                |t_ms: u64| (t_ms - offset_ms) as f64 * self.sample_rate as f64 / 1000.0;
+
+            // Start at the first round time in view. Like every other tick,
+            // the first one needs room for its label before the end, in a
+            // view of a few samples it may not have that.
+            let begin_ms = view_begin * 1000.0 / self.sample_rate as f64 + offset_ms as f64;
+            let mut t_ms = (begin_ms / quant_x_tick_ms as f64).ceil() as u64 * quant_x_tick_ms;
+            while samples_at(t_ms) + label_duration <= view_end {
+                let (t_sec, frac_ms) = (t_ms / 1000, t_ms % 1000);
+                let mut label = match start_time {
+                    Some(_) => format_time_of_day(t_sec),
+                    None => format_duration(t_sec),
+                };
+                if decimals > 0 {
+                    let frac = format!("{:03}", frac_ms);
+                    label.push('.');
+                    label.push_str(&frac[..decimals]);
+                }
+                let tick = Tick {
+                    position: self.x_at(samples_at(t_ms)),
+                    label,
+                };
+                x_ticks.push(tick);
+
+                t_ms += quant_x_tick_ms;
+            }
+        }
+
+        // We don't want to place a tick all the way at the top, because the top
+        // half of the label would be cut off. For symmetry, and to get out of
+        // the way of x-axis labels, also move up the bottom tick.
+        let max_t = (height - label_height / 2) as f64 / height.max(1) as f64;
+        let min_t = (label_height / 2) as f64 / height.max(1) as f64;
+
+        for i in 0..num_major_ticks_y {
+            let t = min_t + (max_t - min_t) * (i as f64) / (num_major_ticks_y - 1) as f64;
+            let value_hz = self.hz_at(t);
+            let label = match () {
+                () if value_hz > 10_000.0 => format!("{:.1} kHz", value_hz / 1000.0),
+                () if value_hz > 1000.0 => format!("{:.2} kHz", value_hz / 1000.0),
+                _ => format!("{:.0} Hz", value_hz),
+            };
+            let tick = Tick {
+                position: t,
+                label: label,
+            };
+            y_ticks.push(tick);
+        }
+
+        (x_ticks, y_ticks)
+    }
+
+    /// Return ticks for the power level axis of the histogram view.
+    fn level_ticks(&self, num_major_ticks: i32) -> Vec<Tick> {
+        let range_db = HISTOGRAM_MAX_DB - HISTOGRAM_MIN_DB;
+        let step_db = match range_db / num_major_ticks.max(1) as f64 {
+            s if s <= 10.0 => 10.0,
+            s if s <= 20.0 => 20.0,
+            _ => 50.0,
+        };
+
+        let mut ticks = Vec::new();
+        let mut level_db = (HISTOGRAM_MIN_DB / step_db).ceil() * step_db;
+        while level_db <= HISTOGRAM_MAX_DB {
+            ticks.push(Tick {
+                position: (level_db - HISTOGRAM_MIN_DB) / range_db,
+                label: format!("{:.0} dB", level_db),
+            });
+            level_db += step_db;
+        }
+        ticks
+    }
+
+    /// Format the time, frequency, and amplitude at time `t_samples` and frequency `hz`.
+    fn probe_label(&self, t_samples: f64, hz: f64) -> String {
+        if self.duration.is_none() {
+            return String::new();
+        }
+
+        let t_sec = t_samples / self.sample_rate as f64;
+        let fft_len = self.window_len * self.zero_pad_factor;
+        let jf = hz * fft_len as f64 / self.sample_rate as f64;
+        let hz_label = format_hz(hz);
+
+        // Take the amplitude from the window that is centered closest to the
+        // probe, we have no amplitude while that window is not yet decoded.
+        let i = self.window_at(t_samples);
+        let amplitude_label = match self.spectrum.get(i) {
+            Some(spectrum_i) => {
+                let sample = self.power_at(spectrum_i, jf);
+                format!("  {:.1} dB", 10.0 * sample.log10())
+            }
+            None => String::new(),
+        };
+
+        format!("{}  {}{}", format_time(t_sec), hz_label, amplitude_label)
+    }
+
+    /// Place the probes in the current view, and send them with their readouts to the UI thread.
+    fn update_probes(&self) {
+        self.send_cursor_label();
+        if self.probes.is_empty() {
+            return;
+        }
+
+        let placed = self
+            .probes
+            .iter()
+            .map(|&(id, t, hz)| {
+                let position = (self.x_at(t), self.overlay_y_at_hz(hz));
+                (id, position, self.probe_label(t, hz))
+            })
+            .collect();
+        self.sender.send(ViewEvent::SetProbes(placed)).unwrap();
+    }
+
+    /// Recompute the readout of the crosshair that follows the pointer, if it is on the graph.
+    fn send_cursor_label(&self) {
+        if let Some((x, y)) = self.cursor {
+            let label = self.probe_label(self.time_at(x), self.hz_at(y));
+            self.sender.send(ViewEvent::SetCursorLabel(label)).unwrap();
+        }
+    }
+
+    /// Return the energy in a region, or `None` if no window in it is decoded yet.
+    ///
+    /// The energy is the power summed over the bins in the frequency range,
+    /// integrated over the windows that are centered in the time range. For
+    /// a region that spans every frequency, this is the integral over time of
+    /// the squared signal, in units of full scale squared times seconds.
+    fn region_energy(&self, t0: f64, t1: f64, hz0: f64, hz1: f64) -> Option<f64> {
+        let center = (self.window_len / 2) as f64;
+        let i_min = ((t0 - center) / self.window_off as f64).ceil().max(0.0) as usize;
+        let i_max = ((t1 - center) / self.window_off as f64).floor();
+        if i_max < i_min as f64 || i_min >= self.spectrum.len() {
+            return None;
+        }
+        let i_max = (i_max as usize).min(self.spectrum.len() - 1);
+
+        // A region lower than a bin still measures the nearest bin.
+        let last = self.spectrum_len() - 1;
+        let fft_len = self.window_len * self.zero_pad_factor;
+        let bins_per_hz = fft_len as f64 / self.sample_rate as f64;
+        let j_min = ((hz0 * bins_per_hz).ceil() as usize).min(last);
+        let j_max = ((hz1 * bins_per_hz).floor() as usize).min(last);
+        let (j_min, j_max) = if j_max < j_min {
+            let j = ((0.5 * (hz0 + hz1) * bins_per_hz).round() as usize).min(last);
+            (j, j)
+        } else {
+            (j_min, j_max)
+        };
+
+        let mut sum = 0.0_f64;
+        for spectrum_i in &self.spectrum[i_min..=i_max] {
+            sum += spectrum_i[j_min..=j_max]
+                .iter()
+                .map(|&p| p as f64)
+                .sum::<f64>();
+        }
+
+        // By Parseval, summing the half spectrum over all bins gives the sum
+        // of the squared windowed samples, times half the transform length.
+        let mean_square = self.window_fn.mean_square() as f64;
+        let norm = self.spectrum_len() as f64 * mean_square * self.window_len as f64;
+        let window_duration = self.window_off as f64 / self.sample_rate as f64;
+        Some(sum / norm * window_duration)
+    }
+
+    /// Format the time range, frequency range, and energy of a region.
+    fn region_label(&self, t0: f64, t1: f64, hz0: f64, hz1: f64) -> String {
+        if self.duration.is_none() {
+            return String::new();
+        }
+
+        let sample_rate = self.sample_rate as f64;
+        let energy_label = match self.region_energy(t0, t1, hz0, hz1) {
+            Some(energy) => format!("{:.1} dB", 10.0 * energy.log10()),
+            None => "not decoded yet".to_string(),
+        };
+
+        format!(
+            "{} – {}\n{} – {}\n{}",
+            format_time(t0 / sample_rate),
+            format_time(t1 / sample_rate),
+            format_hz(hz0),
+            format_hz(hz1),
+            energy_label,
+        )
+    }
+
+    /// Place the regions in the current view, and send them with their readouts to the UI thread.
+    fn update_regions(&self) {
+        if self.regions.is_empty() {
+            return;
+        }
+
+        let regions = self
+            .regions
+            .iter()
+            .map(|&(t0, t1, hz0, hz1)| {
+                let bounds = (
+                    self.x_at(t0),
+                    self.x_at(t1),
+                    self.overlay_y_at_hz(hz0),
+                    self.overlay_y_at_hz(hz1),
+                );
+                (bounds, self.region_label(t0, t1, hz0, hz1))
+            })
+            .collect();
+        self.sender.send(ViewEvent::SetRegions(regions)).unwrap();
+    }
+
+    /// Paint a new bitmap and send it over to the UI thread.
+    fn repaint(&self) {
+        let (width, height) = self.target_size;
+
+        // A minimized window, or one in the middle of a change of scale
+        // factor, can have no area. There is nothing to paint then, the next
+        // resize to a proper size paints again.
+        if width <= 0 || height <= 0 {
+            return;
+        }
+
+        if self.show_histogram {
+            return self.repaint_histogram();
+        }
+
+        // Sample one row for every `row_step` pixel rows, and repeat it.
+        let rows = &self.row_plan;
+        let mut values = Vec::with_capacity(rows.len() * width as usize);
+        for row in rows {
+            values.extend(self.row_values(row));
+        }
+
+        let painted = PaintedValues {
+            size: self.target_size,
+            row_step: self.row_step,
+            values,
+        };
+        self.send_painted(&painted);
+        *self.painted.borrow_mut() = Some(painted);
+    }
+
+    /// Paint the last computed values with the current color scheme.
+    ///
+    /// When only the colors change, this skips computing the values, which
+    /// is most of the work of a repaint.
+    fn recolor(&self) {
+        let painted = self.painted.borrow();
+        match painted.as_ref() {
+            Some(p)
+                if !self.show_histogram
+                    && p.size == self.target_size
+                    && p.row_step == self.row_step =>
+            {
+                self.send_painted(p)
+            }
+            _ => {
+                drop(painted);
+                self.repaint();
+            }
+        }
+    }
+
+    /// Map the values through the colormap, and send the bitmap over to the UI thread.
+    fn send_painted(&self, painted: &PaintedValues) {
+        let (width, height) = painted.size;
+        let bitmap = Bitmap::generate(width, height, self.color_scheme(), |x, y| {
+            let row = y as usize / painted.row_step;
+            painted.values[row * width as usize + x as usize]
+        });
+        self.sender.send(ViewEvent::SetView(bitmap)).unwrap();
+        self.send_colorbar();
+    }
+
+    /// Send the colormap as the spectrogram uses it to the UI thread, with ticks that label it.
+    fn send_colorbar(&self) {
+        const COLORBAR_LEN: i32 = 256;
+        let bitmap = Bitmap::generate(1, COLORBAR_LEN, self.color_scheme(), |_x, y| {
+            1.0 - y as f32 / (COLORBAR_LEN - 1) as f32
+        });
+        let event = ViewEvent::SetColorbar(bitmap, self.colorbar_ticks());
+        self.sender.send(event).unwrap();
+    }
+
+    /// Return the ticks of the colorbar, for the values that the colors mean in the current view.
+    fn colorbar_ticks(&self) -> Vec<Tick> {
+        if self.show_group_delay || self.show_histogram {
+            // These are relative, there are no absolute values to label.
+            return Vec::new();
+        }
+        if self.show_coherence {
+            // See `coherence_value` for the mapping.
+            return [(-1.0, "-1"), (0.0, "0"), (1.0, "+1")]
+                .iter()
+                .map(|&(coherence, label)| Tick {
+                    position: 0.15 + 0.85 * 0.5 * (1.0 + coherence),
+                    label: label.to_string(),
+                })
+                .collect();
+        }
+        if self.adaptive_floor {
+            // The floor follows the noise, so a color is no absolute level.
+            return Vec::new();
+        }
+
+        // Aim for a tick every 20 dB or so. The floor and the ceiling always
+        // get one, the round levels in between only if they are not too close.
+        let (floor_db, ceiling_db) = (self.floor_db as f64, self.ceiling_db as f64);
+        let range_db = ceiling_db - floor_db;
+        let step_db = match range_db / 5.0 {
+            s if s <= 5.0 => 5.0,
+            s if s <= 10.0 => 10.0,
+            s if s <= 20.0 => 20.0,
+            _ => 30.0,
+        };
+        let tick = |level_db: f64| Tick {
+            position: (level_db - floor_db) / range_db,
+            label: format!("{:.0} dB", level_db),
+        };
+        let mut ticks = vec![tick(floor_db)];
+        let mut level_db = (floor_db / step_db).ceil() * step_db;
+        while level_db < ceiling_db {
+            if level_db - floor_db > 0.5 * step_db && ceiling_db - level_db > 0.5 * step_db {
+                ticks.push(tick(level_db));
+            }
+            level_db += step_db;
+        }
+        ticks.push(tick(ceiling_db));
+        ticks
+    }
+
+    /// Return the windows of every column, recomputing them if the layout changed.
+    fn column_plan(&self, columns: i32) -> Ref<ColumnPlan> {
+        let key = (
+            columns,
+            self.time_view,
+            self.window_len,
+            self.window_off,
+            self.spectrum.len(),
+        );
+        if self.column_plan.borrow().key != key {
+            let columns = (0..columns)
+                .map(|x| self.column_windows(x, columns))
+                .collect();
+            *self.column_plan.borrow_mut() = ColumnPlan { key, columns };
+        }
+        self.column_plan.borrow()
+    }
+
+    /// Return the brightness of every pixel in a row, for the view that is selected.
+    fn row_values(&self, row: &RowBins) -> Vec<f32> {
+        let (width, _height) = self.target_size;
+
+        // Columns that start past the last window that we transformed show
+        // a hatch while we are still decoding, rather than a stretched window.
+        let decoded_end = match (&self.reader, self.spectrum.len()) {
+            (None, _) => f64::INFINITY,
+            (Some(_), 0) => 0.0,
+            (Some(_), n) => ((n - 1) * self.window_off + self.window_len) as f64,
+        };
+        let is_decoded = |x: i32| self.time_at(x as f64 / width as f64) < decoded_end;
+
+        // Without windows there is nothing to look up, so skip the plan too.
+        if self.spectrum.is_empty() {
+            return (0..width)
+                .map(|x| if is_decoded(x) { 0.0 } else { UNDECODED })
+                .collect();
+        }
+
+        let n = self.oversample as i32;
+        let columns = width * n;
+        let has_spectrum = !self.spectrum.is_empty() && self.duration.is_some();
+        let plan = self.column_plan(columns);
+        (0..width)
+            .map(|x| {
+                if !is_decoded(x) {
+                    return UNDECODED;
+                }
+                let sum: f32 = (x * n..(x + 1) * n)
+                    .map(|sub_x| match () {
+                        () if self.show_group_delay => self.group_delay_value(sub_x, columns, row),
+                        () if self.show_coherence => self.coherence_value(sub_x, columns, row),
+                        () if has_spectrum => self.column_value(&plan.columns[sub_x as usize], row),
+                        () => 0.0,
+                    })
+                    .sum();
+                sum / n as f32
+            })
+            .collect()
+    }
+
+    /// Return the brightness of column `x` of `width` columns in a row of the spectrogram.
+    ///
+    /// Painting goes through the column plan instead, this is for testing one pixel.
+    #[cfg(test)]
+    fn pixel_value(&self, x: i32, width: i32, row: &RowBins) -> f32 {
+        // Paint a black square when we don't have any data yet.
+        if self.spectrum.is_empty() || self.duration.is_none() {
+            return 0.0;
+        }
+        self.column_value(&self.column_windows(x, width), row)
+    }
+
+    /// Return the windows that intersect column `x` of `width` columns, and their weights.
+    ///
+    /// The spectrum must not be empty.
+    fn column_windows(&self, x: i32, width: i32) -> ColumnWindows {
+        // Determine the time (in units of samples) at the left edge of the
+        // current pixel and at the right edge of the current pixel. When
+        // zoomed in far, make the pixel at least one sample wide.
+        let t_min = self.time_at(x as f64 / width as f64) as i64;
+        let t_max = (self.time_at((x + 1) as f64 / width as f64) as i64).max(t_min + 1);
+
+        // Determine the bins that intersect the current pixel.
+        let bin_min = 1 + (t_min - self.window_len as i64) / self.window_off as i64;
+        let bin_max = t_max / self.window_off as i64;
+
+        // Clamp to be in bounds.
+        let i_min = bin_min.max(0).min(self.spectrum.len() as i64 - 1) as usize;
+        let i_max = bin_max.max(0).min(self.spectrum.len() as i64 - 1) as usize;
+
+        // We sample every window that intersects the pixel, and we weigh by
+        // the integral of the Hann window over the intersection. We
+        // normalize contributions of multiple windows.
+        let inv_window_len = (self.window_len as f32).recip();
+        let mut weights: Vec<f32> = (i_min..=i_max)
+            .map(|i| {
+                let window_t_min = i as i64 * self.window_off as i64;
+                let window_t_max = window_t_min + self.window_len as i64;
+                let overlap_min = t_min.max(window_t_min);
+                let overlap_max = t_max.min(window_t_max).max(overlap_min);
+                dft::hann_int(
+                    (overlap_min - window_t_min) as f32 * inv_window_len,
+                    (overlap_max - window_t_min) as f32 * inv_window_len,
+                )
+            })
+            .collect();
+        let total_weight: f32 = weights.iter().sum();
+
+        // When the hop is longer than a window, a pixel can fall in between
+        // two windows. Then we show the window centered nearest to it.
+        if total_weight == 0.0 && self.window_off > self.window_len {
+            let t = 0.5 * (t_min + t_max) as f64;
+            return ColumnWindows {
+                begin: self.window_at(t),
+                weights: vec![1.0],
+            };
+        }
+
+        if total_weight == 0.0 {
+            weights.clear();
+        }
+        for weight in weights.iter_mut() {
+            *weight /= total_weight;
+        }
+        ColumnWindows {
+            begin: i_min,
+            weights,
+        }
+    }
+
+    /// Return the power of window `i` in a row, scaled for the brightness mapping.
+    fn window_value(&self, i: usize, row: &RowBins) -> f32 {
+        let spectrum_i = &self.spectrum[i];
+
+        assert_eq!(spectrum_i.len(), self.spectrum_len());
+        // The constant-Q bins are already equally wide in pitch, so there is
+        // no bandwidth to weigh by, and we have no mean of them to take the
+        // residual of.
+        let mut sample = match self.constant_q.get(i) {
+            Some(constant_q_i) if self.show_constant_q => {
+                self.row_constant_q_power(constant_q_i, row)
+            }
+            _ if self.show_residual && !self.mean_spectrum.is_empty() => {
+                self.row_residual(spectrum_i, row)
+            }
+            _ => self.row_power(spectrum_i, row),
+        };
+        if self.equal_energy && !self.show_constant_q {
+            sample *= row.weight;
+        }
+
+        // Scale the power such that the floor is at the bottom of the
+        // colormap, everything below it is black.
+        if let Some(&floor) = self.smoothed_floors.get(i) {
+            let floor = floor.max(1e-20) / self.unpadded_spectrum_len() as f32;
+            let floor_power =
+                self.unpadded_spectrum_len() as f32 * 10.0_f32.powf(0.1 * self.floor_db);
+            sample *= floor_power / floor;
+        }
+        sample
+    }
+
+    /// Return the brightness of a column in a row of the spectrogram.
+    fn column_value(&self, column: &ColumnWindows, row: &RowBins) -> f32 {
+        let mut value = 0.0;
+        for (i, &weight) in (column.begin..).zip(&column.weights) {
+            value = self.window_value(i, row).mul_add(weight, value);
+        }
+
+        // When no window intersects the pixel, or when all of the windows that
+        // do are silent, there is no level to map, the pixel is the bottom of
+        // the colormap. This way very quiet pixels are not confused with silence.
+        if value == 0.0 {
+            return 0.0;
+        }
+
+        // Should a NaN make it into the spectrum after all, it is black too.
+        self.brightness(value)
+    }
+
+    /// Map a power, normalized by the window length, to a value in [0, 1] for the colormap.
+    fn brightness(&self, power: f32) -> f32 {
+        let level_db = render::level_db(power, self.window_len);
+        render::brightness(level_db, self.floor_db, self.ceiling_db)
+    }
+
+    /// Return the brightness of column `x` of `width` columns in a row of the group delay view.
+    ///
+    /// We take the window centered closest to the pixel, and the strongest bin
+    /// in the row. The delay of bins with little power is meaningless, we
+    /// paint those black. Delays range from half a window early to half a
+    /// window late.
+    fn group_delay_value(&self, x: i32, width: i32, row: &RowBins) -> f32 {
+        if self.group_delays.is_empty() {
+            return 0.0;
+        }
+
+        let t = self.time_at((x as f64 + 0.5) / width as f64);
+        let i = self.window_at(t);
+        let i = i.min(self.group_delays.len() - 1);
+
+        let spectrum_i = &self.spectrum[i];
+        let last = spectrum_i.len() - 1;
+        let j = if row.hi > row.lo {
+            (row.lo.min(last)..=row.hi.min(last))
+                .max_by(|&a, &b| spectrum_i[a].total_cmp(&spectrum_i[b]))
+                .unwrap()
+        } else {
+            (row.center.round() as usize).min(last)
+        };
+
+        // Use the same brightness as in the spectrogram, with a threshold a
+        // quarter of the way up. Silence and NaN are at the bottom.
+        let power = spectrum_i[j] / self.unpadded_spectrum_len() as f32;
+        if self.brightness(power) < 0.25 {
+            return 0.0;
+        }
+
+        let delay = self.group_delays[i][j] / self.window_len as f32;
+        let t = 0.15 + 0.85 * (0.5 + delay);
+        if t.is_nan() {
+            0.0
+        } else {
+            t.min(1.0).max(0.0)
+        }
+    }
+
+    /// Return the brightness of column `x` of `width` columns in a row of the phase coherence view.
+    ///
+    /// Like for the group delay, we take the window centered closest to the
+    /// pixel, and the strongest bin in the row, and we paint bins with little
+    /// power black. Antiphase is at the dark end of the colormap, mono at the
+    /// bright end, and unrelated channels are in between.
+    fn coherence_value(&self, x: i32, width: i32, row: &RowBins) -> f32 {
+        if self.coherences.is_empty() {
+            return 0.0;
+        }
+
+        let t = self.time_at((x as f64 + 0.5) / width as f64);
+        let i = self.window_at(t).min(self.coherences.len() - 1);
+
+        let spectrum_i = &self.spectrum[i];
+        let last = spectrum_i.len() - 1;
+        let j = if row.hi > row.lo {
+            (row.lo.min(last)..=row.hi.min(last))
+                .max_by(|&a, &b| spectrum_i[a].total_cmp(&spectrum_i[b]))
+                .unwrap()
+        } else {
+            (row.center.round() as usize).min(last)
+        };
+
+        // Use the same threshold as the group delay view.
+        let power = spectrum_i[j] / self.unpadded_spectrum_len() as f32;
+        if self.brightness(power) < 0.25 {
+            return 0.0;
+        }
+
+        let t = 0.15 + 0.85 * 0.5 * (1.0 + self.coherences[i][j]);
+        if t.is_nan() {
+            0.0
+        } else {
+            t.min(1.0).max(0.0)
+        }
+    }
+
+    /// Paint the distribution of power levels per frequency, and send it to the UI thread.
+    ///
+    /// Every row is a frequency, with the same mapping as the spectrogram.
+    /// Every column is a power level, and the brightness shows in how many of
+    /// the windows in view the frequency has that level, relative to the most
+    /// common level for that frequency.
+    fn repaint_histogram(&self) {
+        let (width, height) = self.target_size;
+
+        // The rows span the frequencies from top to bottom, which takes at
+        // least two of them. Like `repaint`, skip painting until we have those.
+        if width <= 0 || height <= 1 {
+            return;
+        }
+
+        // Only include windows that are centered in the part of the file in view.
+        let duration = self.duration.unwrap_or(0) as f64;
+        let windows: Vec<&[f32]> = self
+            .spectrum
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| {
+                let center = (i * self.window_off + self.window_len / 2) as f64;
+                center < duration && (0.0..1.0).contains(&self.x_at(center))
+            })
+            .map(|(_, spectrum_i)| &spectrum_i[..])
+            .collect();
+
+        let mut rows = Vec::with_capacity(height.max(0) as usize);
+        let mut counts = vec![0_u32; width as usize];
+        for y in 0..height {
+            let yf = 1.0 - y as f64 / (height - 1) as f64;
+            let jf = self.bin_at(yf);
+            counts.iter_mut().for_each(|c| *c = 0);
+            analysis::level_histogram(
+                windows
+                    .iter()
+                    .map(|spectrum_i| self.power_at(spectrum_i, jf)),
+                HISTOGRAM_MIN_DB as f32,
+                HISTOGRAM_MAX_DB as f32,
+                &mut counts,
+            );
+            let max_count = counts.iter().cloned().max().unwrap_or(0).max(1) as f32;
+            rows.push(
+                counts
+                    .iter()
+                    .map(|&c| c as f32 / max_count)
+                    .collect::<Vec<f32>>(),
+            );
+        }
+
+        let bitmap = Bitmap::generate(width, height, self.color_scheme(), |x, y| {
+            rows[y as usize][x as usize]
+        });
+        self.sender.send(ViewEvent::SetView(bitmap)).unwrap();
+        self.send_colorbar();
+    }
+}
+
+fn run_main(
+    app: &gtk::Application,
+    open_files: &[gio::File],
+    start_time: Option<u32>,
+    channel_mode: Option<ChannelMode>,
+    profile: bool,
+    config: Config,
+) {
+    // Create two bounded one-way message queues. The one that sends
+    // messages back to the view is a tailored glib channel, but it behaves
+    // the same as the mpsc one.
+    let (send_model, recv_model) = mpsc::sync_channel(10);
+    let (send_view, recv_view) =
+        glib::MainContext::sync_channel(glib::source::Priority::DEFAULT, 10);
+
+    // On a background thread, construct the model, and run its event loop.
+    let send_model_clone = send_model.clone();
+    thread::spawn(move || {
+        let mut model = Model::new(send_view, send_model_clone);
+        model.pending_config = Some(config);
+        if profile {
+            model.profile = Some(Profile::default());
+        }
+        model.run_event_loop(recv_model);
+    });
+
+    send_model
+        .send(ModelEvent::SetStartTime(start_time))
+        .unwrap();
+    if let Some(mode) = channel_mode {
+        send_model.send(ModelEvent::SetChannelMode(mode)).unwrap();
+    }
+
+    // Back on the main thread, construct the view.
+    let view_cell = View::new(app, send_model.clone(), &config);
+    send_model.send(ModelEvent::LoadPresets).unwrap();
+
+    // Handle the view's events on this thread, the main thread.
+    recv_view.attach(None, move |event| {
+        view_cell.borrow_mut().handle_event(event);
+        glib::ControlFlow::Continue
+    });
+
+    // If we were supposed to open a file at startup, open it now by sending
+    // the event to the model. There is one window, so it shows the first file.
+    if let Some(fname) = open_files.first().and_then(|file| file.path()) {
+        send_model.send(ModelEvent::OpenFile(fname)).unwrap();
+    }
+}
+
+fn main() {
+    let args = match args::parse(std::env::args()) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    let mut config = match config::load() {
+        Ok(config) => config,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+    args.override_config(&mut config);
+
+    // When we only need to compute, do not touch GTK at all, it would fail
+    // when there is no display.
+    if args.is_headless() {
+        std::process::exit(headless::run(&args, &config));
+    }
+
+    if let Err(err) = gtk::init() {
+        eprintln!("Failed to initialize GTK: {}", err);
+        eprintln!("Pass --output or --summary to run without a display.");
+        std::process::exit(1);
+    }
+    let application = gtk::Application::new(
+        Some("nl.ruuda.audiograter"),
+        // Allow multiple instances of the application, even though we did
+        // provide an application id.
+        gio::ApplicationFlags::NON_UNIQUE |
+        gio::ApplicationFlags::HANDLES_OPEN,
+    );
+
+    if let Some(settings) = gtk::Settings::default() {
+        match args.theme.or(config.theme).unwrap_or(args::Theme::System) {
+            args::Theme::System => {}
+            args::Theme::Light => settings.set_gtk_application_prefer_dark_theme(false),
+            args::Theme::Dark => settings.set_gtk_application_prefer_dark_theme(true),
+        }
+    }
+
+    // When the application starts, run all of this on the main thread.
+    let start_time = args.start_time;
+    let channel_mode = args.channel_mode;
+    let profile = args.profile;
+    application
+        .connect_activate(move |app| run_main(app, &[], start_time, channel_mode, profile, config));
+
+    // Set up handling files provided on the command line.
+    application.connect_open(move |app, files, _| {
+        run_main(app, files, start_time, channel_mode, profile, config)
+    });
+
+    // And run the UI event loop on the main thread. GTK should not see the
+    // flags that we handled already, it would reject them.
+    application.run_with_args(&args.gtk_args);
+}
+
+#[test]
+fn place_probes_matches_probes_by_id() {
+    let probe = |id, x, y| Probe {
+        id,
+        x,
+        y,
+        label: String::new(),
+    };
+
+    // The model placed probes 1 and 2, but meanwhile the user removed 1 and
+    // placed 3. The count still matches, the ids do not.
+    let mut probes = vec![probe(2, 0.5, 0.5), probe(3, 0.3, 0.7)];
+    let placed = vec![
+        (1, (0.1, 0.2), "a".to_string()),
+        (2, (0.6, 0.5), "b".to_string()),
+    ];
+    place_probes(&mut probes, placed);
+    assert_eq!((probes[0].x, probes[0].y), (0.6, 0.5));
+    assert_eq!(probes[0].label, "b");
+    assert_eq!((probes[1].x, probes[1].y), (0.3, 0.7));
+    assert_eq!(probes[1].label, "");
+}
+
+#[test]
+fn snap_to_ticks_snaps_to_nearest_tick_or_edge() {
+    let tick = |position| Tick {
+        position,
+        label: String::new(),
+    };
+    let ticks = [tick(0.25), tick(0.5)];
+    assert_eq!(snap_to_ticks(0.3, &ticks), 0.25);
+    assert_eq!(snap_to_ticks(0.4, &ticks), 0.5);
+    assert_eq!(snap_to_ticks(0.1, &ticks), 0.0);
+    assert_eq!(snap_to_ticks(0.9, &ticks), 1.0);
+}
+
+#[test]
+fn parse_hz_accepts_hz_and_khz() {
+    assert_eq!(parse_hz("15000"), Some(15_000.0));
+    assert_eq!(parse_hz(" 440 Hz "), Some(440.0));
+    assert_eq!(parse_hz("15k"), Some(15_000.0));
+    assert_eq!(parse_hz("2.5 kHz"), Some(2_500.0));
+    assert_eq!(parse_hz("kHz"), None);
+    assert_eq!(parse_hz("inf"), None);
+    assert_eq!(parse_hz("15 MHz"), None);
+}
+
+/// A model that is not connected to a view, for testing.
+#[cfg(test)]
+struct TestModel {
+    model: Model,
+    events: mpsc::Receiver<ModelEvent>,
+    context: glib::MainContext,
+    view_events: Rc<RefCell<Vec<ViewEvent>>>,
+}
+
+#[cfg(test)]
+impl TestModel {
+    fn new() -> TestModel {
+        let (send_model, recv_model) = mpsc::sync_channel(10);
+        let (send_view, recv_view) =
+            glib::MainContext::sync_channel(glib::source::Priority::DEFAULT, 1000);
+        let context = glib::MainContext::new();
+        let view_events = Rc::new(RefCell::new(Vec::new()));
+        let view_events_clone = view_events.clone();
+        recv_view.attach(Some(&context), move |event| {
+            view_events_clone.borrow_mut().push(event);
+            glib::ControlFlow::Continue
+        });
+        TestModel {
+            model: Model::new(send_view, send_model),
+            events: recv_model,
+            context,
+            view_events,
+        }
+    }
+
+    /// Return the events that the model sent to the view since the previous call.
+    fn take_view_events(&mut self) -> Vec<ViewEvent> {
+        let may_block = false;
+        while self.context.iteration(may_block) {}
+        self.view_events.take()
+    }
+
+    /// Handle the event, and the events that the model posts to itself in response.
+    fn handle(&mut self, event: ModelEvent) {
+        self.model.handle_event(event);
+        self.drain();
+    }
+
+    /// Handle the events that the model posted to itself, until there are none left.
+    fn drain(&mut self) {
+        while let Ok(next_event) = self.events.try_recv() {
+            self.model.handle_event(next_event);
+        }
+    }
+}
+
+#[test]
+fn model_decodes_flac_from_bytes() {
+    let left: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let right: Vec<i32> = left.iter().map(|&x| -x).collect();
+    let bytes = test_flac::encode(44_100, 16, &[left.clone(), right]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+
+    let model = &test.model;
+    assert_eq!(model.sample_rate, 44_100);
+    assert_eq!(model.duration, Some(20_000));
+    assert_eq!(model.decoded_channels.len(), 2);
+    assert_eq!(model.decoded_channels[0][99], 29_700.0 / 32_767.0);
+    assert_eq!(model.decoded_channels[1][99], -29_700.0 / 32_767.0);
+
+    // We transform every window that fits in the decoded samples.
+    assert_eq!(model.spectrum.len(), 1 + (20_000 - WINDOW_LEN) / WINDOW_OFF);
+}
+
+#[test]
+fn undo_and_redo_restore_settings() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.handle(ModelEvent::SetRowStep(4));
+    test.handle(ModelEvent::SetTimeRange(0.25, 0.75));
+    test.handle(ModelEvent::SetZeroPadFactor(2));
+    let adjusted = test.model.settings();
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.zero_pad_factor, 1);
+    assert_eq!(test.model.spectrum[0].len(), WINDOW_LEN / 2);
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.time_view, (0.0, 50_000.0));
+    assert_eq!(test.model.row_step, 4);
+
+    test.handle(ModelEvent::Redo);
+    test.handle(ModelEvent::Redo);
+    assert_eq!(test.model.settings(), adjusted);
+
+    // A new adjustment after an undo discards the redo history.
+    test.handle(ModelEvent::Undo);
+    test.handle(ModelEvent::SetHistogram(true));
+    test.handle(ModelEvent::Redo);
+    assert_eq!(test.model.zero_pad_factor, 1);
+}
+
+#[test]
+fn dragging_the_blend_slider_is_one_adjustment() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    for blend in [0.1, 0.2, 0.3] {
+        test.handle(ModelEvent::SetYBlend(blend));
+    }
+    assert_eq!(test.model.undo_stack.len(), 1);
+    test.take_view_events();
+
+    // The slider moves back along with the axis.
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.y_blend, DEFAULT_Y_BLEND);
+    assert!(test
+        .take_view_events()
+        .iter()
+        .any(|event| matches!(event, ViewEvent::SetYBlend(b) if *b == DEFAULT_Y_BLEND)));
+
+    test.handle(ModelEvent::Redo);
+    assert_eq!(test.model.y_blend, 0.3);
+
+    // Another kind of adjustment in between ends the drag.
+    test.handle(ModelEvent::SetRowStep(2));
+    test.handle(ModelEvent::SetYBlend(0.6));
+    assert_eq!(test.model.undo_stack.len(), 3);
+}
+
+#[test]
+fn level_range_is_undoable() {
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::SetLevelRange(-100.0, -10.0));
+    test.handle(ModelEvent::SetLevelRange(-90.0, -10.0));
+    assert_eq!(test.model.undo_stack.len(), 1);
+    test.take_view_events();
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.floor_db, DEFAULT_FLOOR_DB);
+    assert_eq!(test.model.ceiling_db, DEFAULT_CEILING_DB);
+    assert!(test.take_view_events().iter().any(|event| matches!(
+        event,
+        ViewEvent::SetLevelRange(f, c) if (*f, *c) == (DEFAULT_FLOOR_DB, DEFAULT_CEILING_DB)
+    )));
+
+    test.handle(ModelEvent::Redo);
+    assert_eq!((test.model.floor_db, test.model.ceiling_db), (-90.0, -10.0));
+}
+
+#[test]
+fn apply_preset_is_undoable() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.handle(ModelEvent::SetHistogram(true));
+    let before = test.model.settings();
+
+    let detail = Config {
+        zero_pad_factor: Some(4),
+        equal_energy: Some(true),
+        histogram: Some(false),
+        frequency_axis: Some(0.2),
+        floor_db: Some(-100.0),
+        ceiling_db: Some(-30.0),
+        ..Config::default()
+    };
+    test.model.presets = vec![("Detail".to_string(), detail)];
+    test.handle(ModelEvent::ApplyPreset("Missing".into()));
+    assert_eq!(test.model.settings(), before);
+
+    test.handle(ModelEvent::ApplyPreset("Detail".into()));
+    assert_eq!(test.model.zero_pad_factor, 4);
+    assert!(test.model.equal_energy);
+    assert!(!test.model.show_histogram);
+    assert_eq!(test.model.y_blend, 0.2);
+    assert_eq!(
+        (test.model.floor_db, test.model.ceiling_db),
+        (-100.0, -30.0)
+    );
+    assert_eq!(test.model.preset().zero_pad_factor, Some(4));
+
+    // The undo covers the axis and the levels too, in one step.
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.settings(), before);
+}
+
+#[test]
+fn set_window_params_recomputes_the_spectrum() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.handle(ModelEvent::SetWindowParams(2048, 512));
+    assert_eq!(test.model.spectrum.len(), 1 + (50_000 - 2048) / 512);
+    assert_eq!(test.model.spectrum[0].len(), 1024);
+    assert_eq!(test.model.row_plan[0].hi, 1023);
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.window_len, WINDOW_LEN);
+    assert_eq!(
+        test.model.spectrum.len(),
+        1 + (50_000 - WINDOW_LEN) / WINDOW_OFF
+    );
+}
+
+#[test]
+fn set_window_fn_recomputes_the_spectrum() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    let spectrum = test.model.spectrum.clone();
+
+    test.handle(ModelEvent::SetWindowFn(WindowFn::Blackman));
+    assert_eq!(test.model.spectrum.len(), spectrum.len());
+    assert_ne!(test.model.spectrum, spectrum);
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.window_fn, WindowFn::Hann);
+    assert_eq!(test.model.spectrum, spectrum);
+}
+
+#[test]
+fn set_level_range_keeps_the_ceiling_above_the_floor() {
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::SetLevelRange(-60.0, -80.0));
+    assert_eq!(test.model.floor_db, -60.0);
+    assert_eq!(test.model.ceiling_db, -59.0);
+}
+
+#[test]
+fn set_level_range_tells_the_sliders_about_a_moved_ceiling() {
+    let (send_model, _recv_model) = mpsc::sync_channel(10);
+    let (send_view, recv_view) =
+        glib::MainContext::sync_channel(glib::source::Priority::DEFAULT, 100);
+    let context = glib::MainContext::new();
+    let ranges = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let ranges_clone = ranges.clone();
+    recv_view.attach(Some(&context), move |event| {
+        if let ViewEvent::SetLevelRange(floor_db, ceiling_db) = event {
+            ranges_clone.borrow_mut().push((floor_db, ceiling_db));
+        }
+        glib::ControlFlow::Continue
+    });
+    let mut model = Model::new(send_view, send_model);
+    let mut handle = |event| {
+        model.handle_event(event);
+        while context.iteration(false) {}
+    };
+
+    // When the model uses the range as is, the sliders show it already.
+    handle(ModelEvent::SetLevelRange(-60.0, -50.0));
+    assert!(ranges.borrow().is_empty());
+
+    // When the ceiling is at or below the floor, the model moves it up.
+    handle(ModelEvent::SetLevelRange(-60.0, -60.0));
+    handle(ModelEvent::SetLevelRange(-60.0, -80.0));
+    assert_eq!(&ranges.borrow()[..], &[(-60.0, -59.0), (-60.0, -59.0)]);
+}
+
+#[test]
+fn set_colormap_keeps_the_spectrum() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    let spectrum = test.model.spectrum.clone();
+
+    test.handle(ModelEvent::SetColormap(Colormap::Viridis));
+    assert_eq!(test.model.color_scheme().colormap, Colormap::Viridis);
+    assert_eq!(test.model.spectrum, spectrum);
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.colormap, Colormap::Magma);
+}
+
+#[test]
+fn swap_files_restores_the_previous_file() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let flac = test_flac::encode(44_100, 16, &[samples]);
+    let samples: Vec<i16> = (0..30_000).map(|i| ((i % 50) * 300) as i16).collect();
+    let wav = wav::encode_16(48_000, 1, &samples, 60_000);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(flac, "a.flac".into()));
+    test.handle(ModelEvent::SetTimeRange(0.0, 0.5));
+    test.handle(ModelEvent::OpenBytes(wav, "b.wav".into()));
+    let wav_windows = test.model.spectrum.len();
+
+    test.handle(ModelEvent::SwapFiles);
+    assert_eq!(test.model.title, "a.flac");
+    assert_eq!(test.model.sample_rate, 44_100);
+    assert_eq!(test.model.time_view, (0.0, 10_000.0));
+    assert_eq!(
+        test.model.spectrum.len(),
+        1 + (20_000 - WINDOW_LEN) / WINDOW_OFF
+    );
+
+    test.handle(ModelEvent::SwapFiles);
+    assert_eq!(test.model.title, "b.wav");
+    assert_eq!(test.model.spectrum.len(), wav_windows);
+}
+
+#[test]
+fn zoom_at_keeps_the_time_under_the_pointer() {
+    let samples: Vec<i32> = (0..40_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.handle(ModelEvent::ZoomAt(0.25, 0.5));
+    assert_eq!(test.model.time_view, (5_000.0, 25_000.0));
+    assert_eq!(test.model.time_at(0.25), 10_000.0);
+
+    // Zooming out stops at the edges of the file.
+    test.handle(ModelEvent::ZoomAt(0.25, 4.0));
+    assert_eq!(test.model.time_view, (0.0, 40_000.0));
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.time_view, (5_000.0, 25_000.0));
+}
+
+#[test]
+fn zoom_frequency_at_stays_inside_the_file() {
+    let samples: Vec<i32> = (0..40_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.handle(ModelEvent::SetYScale(YScale::Linear));
+    let (full_min, full_max) = test.model.full_hz_range();
+
+    // On a linear axis, the frequency under the pointer stays in place.
+    let hz = test.model.hz_at(0.25);
+    test.handle(ModelEvent::ZoomFrequencyAt(0.25, 0.5));
+    let (hz_min, hz_max) = test.model.hz_range();
+    assert!((hz_max - hz_min - 0.5 * (full_max - full_min)).abs() < 1e-6);
+    assert!((test.model.hz_at(0.25) - hz).abs() < 1e-6);
+    assert!(test.model.bin_at(1.0) < 0.7 * test.model.spectrum_len() as f64);
+
+    // Zooming out past the range of the file resets the zoom.
+    test.handle(ModelEvent::ZoomFrequencyAt(0.25, 4.0));
+    assert_eq!(test.model.freq_view, (0.0, f64::INFINITY));
+    assert_eq!(test.model.hz_range(), (full_min, full_max));
+
+    test.handle(ModelEvent::ZoomFrequencyAt(1.0, 0.1));
+    assert_eq!(test.model.hz_range().1, full_max);
+    test.handle(ModelEvent::ResetZoom);
+    assert_eq!(test.model.freq_view, (0.0, f64::INFINITY));
+    test.handle(ModelEvent::Undo);
+    assert!(test.model.hz_range().0 > full_min);
+}
+
+#[test]
+fn cursor_readout_follows_the_pointer() {
+    let samples: Vec<i32> = (0..44_100).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.handle(ModelEvent::SetYScale(YScale::Linear));
+    test.handle(ModelEvent::SetCursor(Some((0.5, 0.5))));
+    assert_eq!(test.model.cursor, Some((0.5, 0.5)));
+
+    // Halfway is half a second in, and close to half of the Nyquist frequency.
+    let hz = test.model.hz_at(0.5);
+    let label = test.model.probe_label(test.model.time_at(0.5), hz);
+    assert!(label.starts_with(&format_time(0.5)), "{}", label);
+    assert!(label.contains(" 11.0"), "{}", label);
+    assert!(label.contains(&format_hz(hz)), "{}", label);
+
+    test.handle(ModelEvent::SetCursor(None));
+    assert_eq!(test.model.cursor, None);
+}
+
+#[test]
+fn colorbar_ticks_label_the_level_range() {
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::SetLevelRange(-90.0, 0.0));
+    let ticks = test.model.colorbar_ticks();
+    assert_eq!(ticks.len(), 5);
+    assert_eq!(ticks[0].position, 0.0);
+    assert_eq!(ticks[0].label, "-90 dB");
+    assert!((ticks[1].position - 30.0 / 90.0).abs() < 1e-9);
+    assert_eq!(ticks[1].label, "-60 dB");
+    assert_eq!(ticks[4].position, 1.0);
+    assert_eq!(ticks[4].label, "0 dB");
+
+    // In between the ends, the ticks stay on round levels.
+    let labels = |test: &TestModel| -> Vec<String> {
+        let ticks = test.model.colorbar_ticks();
+        ticks.into_iter().map(|t| t.label).collect()
+    };
+    test.handle(ModelEvent::SetLevelRange(-75.0, -25.0));
+    assert_eq!(
+        labels(&test),
+        ["-75 dB", "-60 dB", "-50 dB", "-40 dB", "-25 dB"]
+    );
+
+    // A range narrower than a step still shows where it starts and ends.
+    test.handle(ModelEvent::SetLevelRange(-22.0, -21.0));
+    assert_eq!(labels(&test), ["-22 dB", "-21 dB"]);
+
+    // Relative to an adaptive floor, the colors are not levels.
+    test.handle(ModelEvent::SetAdaptiveFloor(true));
+    assert!(test.model.colorbar_ticks().is_empty());
+    test.handle(ModelEvent::SetAdaptiveFloor(false));
+
+    test.model.show_group_delay = true;
+    assert!(test.model.colorbar_ticks().is_empty());
+}
+
+#[test]
+fn short_files_get_ticks_in_fractions_of_a_second() {
+    // A tenth of a second, shorter than a single window.
+    let samples: Vec<i32> = (0..4_410).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(400, 200, 40, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "short.flac".into()));
+    assert!(!test.model.spectrum.is_empty());
+    assert!(test.model.painted.borrow().is_some());
+
+    // Every tick is in view, so in view of a tenth of a second they are
+    // at most a few hundredths apart.
+    let (ticks, _) = test.model.compute_ticks();
+    assert!(ticks.len() >= 2);
+    assert_eq!(ticks[0].label, "0:00.00");
+    assert_eq!(ticks[1].label, "0:00.02");
+    assert!(ticks.iter().all(|t| (0.0..=1.0).contains(&t.position)));
+
+    // A view of a few samples still works, even when no tick fits in it.
+    test.handle(ModelEvent::SetTimeRange(0.5, 0.5002));
+    let (ticks, _) = test.model.compute_ticks();
+    assert!(ticks.iter().all(|t| (0.0..=1.0).contains(&t.position)));
+
+    // Before the first resize, there are no label sizes to divide by.
+    let mut test = TestModel::new();
+    test.model.duration = Some(100);
+    test.model.time_view = (0.0, 100.0);
+    let (_, y_ticks) = test.model.compute_ticks();
+    assert!(y_ticks.iter().all(|t| t.position.is_finite()));
+}
+
+#[test]
+fn reload_keeps_the_zoom_of_a_changed_file() {
+    let fname = std::env::temp_dir().join(format!("audiograter-{}.wav", std::process::id()));
+    let samples: Vec<i16> = vec![0; 20_000];
+    fs::write(&fname, wav::encode_16(10_000, 1, &samples, 40_000)).unwrap();
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenFile(fname.clone()));
+    test.handle(ModelEvent::SetTimeRange(0.5, 0.75));
+    assert_eq!(test.model.time_view, (10_000.0, 15_000.0));
+
+    // The file became shorter, so the end of the zoomed range is gone.
+    let samples: Vec<i16> = vec![0; 12_000];
+    fs::write(&fname, wav::encode_16(10_000, 1, &samples, 24_000)).unwrap();
+    test.handle(ModelEvent::Reload);
+    fs::remove_file(&fname).unwrap();
+
+    assert_eq!(test.model.duration, Some(12_000));
+    assert_eq!(test.model.time_view, (10_000.0, 12_000.0));
+    assert_eq!(test.model.undo_stack.len(), 1);
+    assert!(test.model.previous_file.is_none());
+}
+
+#[test]
+fn auto_hop_transforms_fewer_windows_for_long_files() {
+    let samples: Vec<i16> = (0..200_000).map(|i| ((i % 100) * 300) as i16).collect();
+    let bytes = wav::encode_16(44_100, 1, &samples, 400_000);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(10, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.wav".into()));
+    let num_windows = test.model.spectrum.len();
+
+    // With 10 columns, two windows per column is a hop of 10k, rounded down to 8192.
+    test.handle(ModelEvent::SetAutoHop(true));
+    assert_eq!(test.model.window_off, 8192);
+    assert!(2 * test.model.spectrum.len() <= num_windows + 2);
+    for x in 0..10 {
+        let value = test
+            .model
+            .pixel_value(x, test.model.target_size.0, &test.model.row_plan[0]);
+        assert!(value > 0.0);
+    }
+
+    // With 5 columns, the hop of 20k rounds down to 16384.
+    test.handle(ModelEvent::Resize(5, 50, 20, 10));
+    assert_eq!(test.model.window_off, 16384);
+    assert!(2 * test.model.spectrum.len() <= num_windows / 2 + 2);
+
+    test.handle(ModelEvent::SetAutoHop(false));
+    assert_eq!(test.model.window_off, WINDOW_OFF);
+    assert_eq!(test.model.spectrum.len(), num_windows);
+}
+
+#[test]
+fn fixed_scale_scrolls_through_the_file() {
+    let samples: Vec<i16> = vec![0; 100_000];
+    let bytes = wav::encode_16(10_000, 1, &samples, 200_000);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(200, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.wav".into()));
+    test.handle(ModelEvent::ToggleFixedScale);
+
+    // At 100 pixels per second, 200 pixels span two seconds.
+    assert_eq!(test.model.time_view, (0.0, 20_000.0));
+    test.handle(ModelEvent::ScrollTo(0.5));
+    assert_eq!(test.model.time_view, (50_000.0, 70_000.0));
+    test.handle(ModelEvent::ScrollTo(0.9));
+    assert_eq!(test.model.time_view, (80_000.0, 100_000.0));
+
+    test.handle(ModelEvent::ToggleFixedScale);
+    assert_eq!(test.model.time_view, (0.0, 100_000.0));
+}
+
+#[test]
+fn row_bins_do_not_skip_bins() {
+    let mut test = TestModel::new();
+    test.model.row_step = 4;
+    let rows = test.model.row_bins(100);
+    assert_eq!(rows.len(), 25);
+    assert_eq!(rows[0].hi, WINDOW_LEN / 2 - 1);
+
+    // Every bin is in a row, or between the centers of two rows.
+    for pair in rows.windows(2) {
+        assert!(pair[1].hi + 1 >= pair[0].lo);
+    }
+}
+
+#[test]
+fn row_weights_conserve_the_bandwidth() {
+    let mut test = TestModel::new();
+    test.model.y_blend = 0.0;
+    let rows = test.model.row_bins(100);
+
+    // On a log axis, rows at the top cover more bins than rows at the bottom.
+    assert!(rows[0].weight > 1.0);
+    assert!(rows[99].weight < 1.0);
+    for pair in rows.windows(2) {
+        assert!(pair[0].weight > pair[1].weight);
+    }
+
+    test.model.y_blend = 1.0;
+    let rows = test.model.row_bins(100);
+    assert!(rows.iter().all(|row| (row.weight - 1.0).abs() < 1e-4));
+}
+
+#[test]
+fn set_y_scale_overrides_the_blend() {
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::SetYScale(YScale::Linear));
+    assert_eq!(test.model.y_blend, DEFAULT_Y_BLEND);
+    let rows = test.model.row_bins(100);
+    assert!(rows.iter().all(|row| (row.weight - 1.0).abs() < 1e-4));
+
+    test.handle(ModelEvent::Undo);
+    assert_eq!(test.model.y_scale, YScale::Blended);
+}
+
+#[test]
+fn mel_scale_maps_rows_through_hz() {
+    let mut test = TestModel::new();
+    test.model.sample_rate = 16_000;
+    test.handle(ModelEvent::SetYScale(YScale::Mel));
+
+    // Halfway up the axis is halfway in mel, and the bin is that of the frequency.
+    let hz = test.model.hz_at(0.5);
+    let (hz_min, hz_max) = test.model.hz_range();
+    let mid_mel = 0.5 * (render::hz_to_mel(hz_min) + render::hz_to_mel(hz_max));
+    assert!((render::hz_to_mel(hz) - mid_mel).abs() < 1e-6);
+    let bin = hz * WINDOW_LEN as f64 / 16_000.0;
+    assert!((test.model.bin_at(0.5) - bin).abs() < 1e-6);
+    assert!((test.model.y_at_hz(hz) - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn dc_strip_includes_bin_zero() {
+    let mut test = TestModel::new();
+    test.model.sample_rate = 44_100;
+    test.model.show_dc_strip = true;
+
+    // The strip spans from bin 0 up to 20 Hz, the axis above it continues from there.
+    assert_eq!(test.model.bin_at(0.0), 0.0);
+    let bin_20_hz = 20.0 * WINDOW_LEN as f64 / 44_100.0;
+    assert!((test.model.bin_at(DC_STRIP_HEIGHT) - bin_20_hz).abs() < 1e-9);
+    assert!((test.model.hz_at(1.0) - 22_050.0).abs() < 1e-6);
+    for &hz in &[0.0, 5.0, 19.0, 20.0, 100.0, 10_000.0] {
+        let y = test.model.y_at_hz(hz);
+        assert!((test.model.hz_at(y) - hz).abs() < 1e-6, "{} Hz", hz);
     }
+
+    let rows = test.model.row_bins(100);
+    assert_eq!(rows[99].lo, 0);
 }
 
-impl Model {
-    fn new(
-        sender: glib::SyncSender<ViewEvent>,
-        self_sender: mpsc::SyncSender<ModelEvent>,
-    ) -> Model {
-        Model {
-            flac_reader: None,
-            spectrum: Vec::new(),
-            samples: Vec::new(),
-            target_size: (0, 0),
-            label_size: (0, 0),
-            duration: None,
-            sample_rate: 1,
-            sender: sender,
-            self_sender: self_sender,
+#[test]
+fn frequency_marker_must_be_on_the_axis() {
+    let mut test = TestModel::new();
+    test.model.sample_rate = 44_100;
+    assert!(test.model.frequency_marker().is_none());
+
+    test.model.frequency_marker = Some(15_000.0);
+    let marker = test.model.frequency_marker().unwrap().unwrap();
+    assert!((test.model.hz_at(marker.position) - 15_000.0).abs() < 1e-6);
+    assert_eq!(marker.label, "15.00 kHz");
+
+    // Past the Nyquist frequency of a lower sample rate, there is no axis.
+    test.model.sample_rate = 22_050;
+    assert!(test.model.frequency_marker().unwrap().is_err());
+
+    // Below the lowest frequency there is only axis in the DC strip.
+    test.model.frequency_marker = Some(2.0);
+    assert!(test.model.frequency_marker().unwrap().is_err());
+    test.model.show_dc_strip = true;
+    assert!(test.model.frequency_marker().unwrap().is_ok());
+}
+
+#[test]
+fn render_path_is_finite_with_nan_input() {
+    assert_eq!(sanitize_sample(f32::NAN), 0.0);
+    assert_eq!(sanitize_sample(f32::NEG_INFINITY), 0.0);
+    assert_eq!(sanitize_sample(0.5), 0.5);
+
+    // Bypass the decoder, which would sanitize the NaN, to check that a NaN
+    // in the spectrum cannot corrupt the image either.
+    let mut samples: Vec<f32> = (0..40_000).map(|i| (i as f32 * 0.1).sin()).collect();
+    samples[20_000] = f32::NAN;
+
+    let mut test = TestModel::new();
+    test.model.target_size = (50, 20);
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+    assert!(test.model.spectrum.iter().any(|s| s[1].is_nan()));
+
+    for row in &test.model.row_bins(20) {
+        for x in 0..50 {
+            let value = test.model.pixel_value(x, test.model.target_size.0, row);
+            assert!(
+                (0.0..=1.0).contains(&value),
+                "Value {} out of range.",
+                value
+            );
         }
     }
+}
 
-    pub fn run_event_loop(&mut self, events: mpsc::Receiver<ModelEvent>) {
-        // Block until a new event arrives.
-        for mut current_event in events.iter() {
-            // When we do have an event, don't handle it immediately. First
-            // check if there already is another event behind it.
-            for next_event in events.try_iter() {
-                current_event = match (&current_event, &next_event) {
-                    // If there is another event waiting, and it is a resize
-                    // just like the current event, then the current event is
-                    // already obsolete and we can drop it.
-                    (&ModelEvent::Resize(..), &ModelEvent::Resize(..)) => next_event,
-                    // In any other case, we need to handle the current event.
-                    // Handle it now, and leave the next event to be handled in
-                    // the next iteration, or after the `try_iter` loop.
-                    _ => {
-                        self.handle_event(current_event);
-                        next_event
-                    }
-                };
-            }
+#[test]
+fn silence_renders_as_bottom_of_colormap() {
+    let samples = vec![0.0_f32; 40_000];
+
+    let mut test = TestModel::new();
+    test.model.target_size = (50, 20);
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+
+    let rows = test.model.row_bins(20);
+    let scheme = test.model.color_scheme();
+    let bitmap = Bitmap::generate(50, 20, scheme, |x, y| {
+        test.model
+            .pixel_value(x, test.model.target_size.0, &rows[y as usize])
+    });
 
-            self.handle_event(current_event);
-        }
+    let (r, g, b) = audiograter::render::colormap_magma(0.0);
+    let bottom = [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8];
+    for pixel in bitmap.data.chunks(3) {
+        assert_eq!(pixel, bottom);
     }
+}
 
-    pub fn handle_event(&mut self, event: ModelEvent) {
-        match event {
-            ModelEvent::OpenFile(fname) => {
-                // Build the view event in advance, so we can refuse file names
-                // that we would not be able to render in the UI.
-                let view_event = match fname.file_name().and_then(OsStr::to_str) {
-                    // I don't care to support non-utf8 filenames.
-                    None => return eprintln!("Invalid file name to open."),
-                    Some(fname_str) => ViewEvent::SetTitle(fname_str.into()),
-                };
+#[test]
+fn residual_highlights_departures_from_the_average() {
+    // A steady tone, with a click in the middle.
+    let mut samples: Vec<f32> = (0..80_000).map(|i| (i as f32 * 0.3).sin()).collect();
+    samples[40_000] = 10.0;
+
+    let mut test = TestModel::new();
+    test.model.target_size = (80, 20);
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+    test.model.update_bandwidth();
+    test.model.show_residual = true;
+
+    // High up there is no tone, only the click stands out there.
+    let rows = test.model.row_bins(20);
+    let top = &rows[0];
+    assert_eq!(
+        test.model.pixel_value(5, test.model.target_size.0, top),
+        0.0
+    );
+    assert!(test.model.pixel_value(40, test.model.target_size.0, top) > 0.0);
+}
 
-                // Then try to open the file itself. If this fails, we don't
-                // load the file in the UI.
-                self.flac_reader = match claxon::FlacReader::open(&fname) {
-                    Ok(r) => {
-                        let streaminfo = r.streaminfo();
-                        self.duration = streaminfo.samples;
-                        self.sample_rate = streaminfo.sample_rate;
-                        Some(r)
-                    }
-                    Err(err) => return eprintln!("Failed to open file: {:?}", err),
-                };
+#[test]
+fn bandwidth_line_shows_a_cutoff_up_to_1_khz_below_nyquist() {
+    let samples: Vec<i32> = (0..10_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+
+    let mut labels_for = |hz: f32| {
+        test.model.bandwidth = Some(hz);
+        test.take_view_events();
+        test.model.send_nyquist_lines();
+        test.take_view_events()
+            .into_iter()
+            .find_map(|event| match event {
+                ViewEvent::SetNyquistLines(lines) => Some(lines),
+                _ => None,
+            })
+            .unwrap()
+            .into_iter()
+            .map(|tick| tick.label)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(labels_for(20_000.0).len(), 2);
+    assert_eq!(labels_for(21_500.0).len(), 1);
+}
 
-                // Clear leftovers from a previous file, if any.
-                self.spectrum.clear();
-                self.samples.clear();
+#[test]
+fn column_plan_waits_for_a_spectrum() {
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    let row = &test.model.row_bins(50)[0];
+    assert_eq!(test.model.row_values(row), vec![0.0; 100]);
+    assert!(test.model.column_plan.borrow().columns.is_empty());
+}
 
-                // If we have successfully loaded the file, we can tell the UI
-                // to show that in the title, and we can begin decoding.
-                self.sender.send(view_event).unwrap();
-                self.self_sender.send(ModelEvent::Decode).unwrap();
+#[test]
+fn column_plan_survives_a_change_of_height() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    let columns = test.model.column_plan.borrow().columns.as_ptr();
+    assert_eq!(test.model.column_plan.borrow().columns.len(), 100);
+
+    test.handle(ModelEvent::Resize(100, 80, 20, 10));
+    assert_eq!(test.model.column_plan.borrow().columns.as_ptr(), columns);
+    assert_eq!(
+        test.model.painted.borrow().as_ref().unwrap().values.len(),
+        100 * 80
+    );
 
-                // Also, we should tell the UI where the tick labels are going
-                // to be.
-                self.recompute_ticks();
-            }
-            ModelEvent::Resize(width, height, label_width, label_height) => {
-                self.target_size = (width, height);
-                self.label_size = (label_width, label_height);
-                self.recompute_ticks();
-                self.repaint();
-            }
-            ModelEvent::Decode => {
-                self.decode();
-            }
-        }
+    // A change of colors reuses the values, a change of width does not.
+    test.handle(ModelEvent::SetInvertColormap(true));
+    assert_eq!(
+        test.model.painted.borrow().as_ref().unwrap().size,
+        (100, 80)
+    );
+    test.handle(ModelEvent::Resize(60, 80, 20, 10));
+    assert_eq!(test.model.column_plan.borrow().columns.len(), 60);
+}
+
+#[test]
+fn compute_spectrum_continues_at_the_next_window() {
+    let samples: Vec<f32> = (0..50_000).map(|i| (i as f32 * 0.01).sin()).collect();
+
+    let mut whole = TestModel::new();
+    whole.model.decoded_channels = vec![samples.clone()];
+    whole.model.compute_spectrum();
+
+    // Decoding in batches that end in the middle of a window must yield the
+    // same windows, at the same offsets, as transforming everything at once.
+    let mut batched = TestModel::new();
+    for end in [10_000, 10_001, 23_456, 50_000] {
+        batched.model.decoded_channels = vec![samples[..end].to_vec()];
+        batched.model.compute_spectrum();
+    }
+    assert_eq!(
+        batched.model.spectrum.len(),
+        1 + (50_000 - WINDOW_LEN) / WINDOW_OFF
+    );
+    assert_eq!(batched.model.spectrum, whole.model.spectrum);
+    assert_eq!(batched.model.fluxes, whole.model.fluxes);
+}
+
+#[test]
+fn oversample_averages_a_wider_row() {
+    // A steady tone, with a click that falls inside one of the narrower columns.
+    let mut samples: Vec<f32> = (0..80_000).map(|i| (i as f32 * 0.3).sin()).collect();
+    samples[41_000] = 10.0;
+
+    let mut test = TestModel::new();
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+    let row = &test.model.row_bins(20)[0];
+
+    test.model.target_size = (80, 20);
+    let wide = test.model.row_values(row);
+
+    test.model.target_size = (20, 20);
+    test.model.oversample = 4;
+    let narrow = test.model.row_values(row);
+
+    assert_eq!(narrow.len(), 20);
+    for (x, value) in narrow.iter().enumerate() {
+        let mean = wide[x * 4..x * 4 + 4].iter().sum::<f32>() / 4.0;
+        assert!(
+            (value - mean).abs() < 1e-6,
+            "{} != {} at {}",
+            value,
+            mean,
+            x
+        );
     }
+}
 
-    fn decode(&mut self) {
-        let flac_reader = match self.flac_reader.as_mut() {
-            Some(r) => r,
-            None => return,
-        };
+#[test]
+fn region_energy_measures_the_tone_inside() {
+    // A 1 kHz tone at amplitude 0.5 has a mean square of 0.125.
+    let omega = 2.0 * std::f32::consts::PI * 1000.0 / 44_100.0;
+    let samples: Vec<f32> = (0..88_200)
+        .map(|i| 0.5 * (i as f32 * omega).sin())
+        .collect();
+
+    let mut test = TestModel::new();
+    test.model.target_size = (100, 50);
+    test.model.sample_rate = 44_100;
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+
+    // Over the full height, the energy is the sum of the squared samples.
+    let (t0, t1) = (22_050.0, 66_150.0);
+    let expected = 0.125 * (t1 - t0) / 44_100.0;
+    let full = test.model.region_energy(t0, t1, 0.0, 22_050.0).unwrap();
+    assert!(
+        (full / expected - 1.0).abs() < 0.1,
+        "{} vs {}",
+        full,
+        expected
+    );
 
-        let bits_per_sample = flac_reader.streaminfo().bits_per_sample;
-        assert!(bits_per_sample < 32);
-        let max = (i32::MAX >> (32 - bits_per_sample)) as f32;
-        let inv_max = max.recip();
+    // Near the top, far from the tone, there is hardly any energy.
+    let top = test
+        .model
+        .region_energy(t0, t1, 20_000.0, 22_050.0)
+        .unwrap();
+    assert!(top < 1e-6 * full);
+
+    // Past the end of the spectrum, nothing is decoded.
+    let end = 4.0 * 88_200.0;
+    assert_eq!(
+        test.model.region_energy(2.0 * 88_200.0, end, 0.0, 22_050.0),
+        None
+    );
+}
 
-        let mut blocks = flac_reader.blocks();
-        let mut have_more = true;
+#[test]
+fn track_markers_follow_the_time_range() {
+    let mut test = TestModel::new();
+    test.model.sample_rate = 44_100;
+    test.model.time_view = (0.0, 44_100.0 * 100.0);
+    test.model.tracks = cue::parse(
+        "FILE \"a.flac\" WAVE\n\
+         TRACK 01 AUDIO\n  INDEX 01 00:00:00\n\
+         TRACK 02 AUDIO\n  TITLE \"Two\"\n  INDEX 01 00:50:00\n",
+    );
 
-        // Decode some blocks, but not everything at once. This allows
-        // rendering intermediate updates, and it also keeps the app
-        // more responsive by allowing us to handle other events. Doing
-        // limited work and then re-posting a decode event acts like a
-        // yield point.
-        let mut buffer = Vec::new();
-        for _ in 0..100 {
-            let block = match blocks.read_next_or_eof(buffer) {
-                Ok(Some(b)) => b,
-                Ok(None) => {
-                    have_more = false;
-                    break;
-                }
-                Err(err) => return eprintln!("Failed to decode: {:?}", err),
-            };
+    let markers = test.model.track_markers();
+    assert_eq!(markers.len(), 2);
+    assert_eq!(markers[1].position, 0.5);
+    assert_eq!(markers[1].label, "02 Two");
 
-            // Add channel 0 to the samples buffer, converting to f32,
-            // regardless of the bit depth of the input.
-            self.samples.reserve(block.duration() as usize);
-            for &si in block.channel(0).iter() {
-                self.samples.push(inv_max * si as f32);
-            }
+    // When zoomed in on the second half, the first track starts out of view.
+    test.model.time_view = (44_100.0 * 40.0, 44_100.0 * 60.0);
+    let markers = test.model.track_markers();
+    assert_eq!(markers.len(), 1);
+    assert_eq!(markers[0].position, 0.5);
+}
 
-            buffer = block.into_buffer();
-        }
+#[test]
+fn row_residual_reduces_bins_like_row_power() {
+    let mut test = TestModel::new();
+    let spectrum_i: Vec<f32> = (0..64).map(|j| (j % 7) as f32).collect();
+    test.model.mean_spectrum = vec![0.0; 64];
+    let row = RowBins {
+        center: 12.0,
+        lo: 10,
+        hi: 14,
+        weight: 5.0,
+    };
+    for equal_energy in [false, true] {
+        test.model.equal_energy = equal_energy;
+        assert_eq!(
+            test.model.row_residual(&spectrum_i, &row),
+            test.model.row_power(&spectrum_i, &row),
+        );
+    }
+}
 
-        if have_more {
-            // Continue decoding after this event.
-            self.self_sender.send(ModelEvent::Decode).unwrap();
-        } else {
-            // Otherwise, pad with silence so we can finish the last window.
-            while self.samples.len() < WINDOW_LEN + WINDOW_OFF {
-                self.samples.push(0.0);
-            }
-        }
+#[test]
+fn coherence_tells_mono_from_antiphase() {
+    let omega = 2.0 * std::f32::consts::PI * 1000.0 / 44_100.0;
+    let left: Vec<f32> = (0..44_100).map(|i| (i as f32 * omega).sin()).collect();
+    let inverted: Vec<f32> = left.iter().map(|x| -x).collect();
+
+    let mut test = TestModel::new();
+    test.model.target_size = (10, 100);
+    test.model.update_row_plan();
+    test.model.sample_rate = 44_100;
+    test.model.duration = Some(left.len() as u64);
+    test.model.time_view = (0.0, left.len() as f64);
+    test.model.decoded_channels = vec![left.clone(), left.clone()];
+    test.model.show_coherence = true;
+    test.model.compute_spectrum();
+    assert_eq!(test.model.coherences.len(), test.model.spectrum.len());
+
+    // The row with the tone is the brightest in the mono case.
+    let rows = test.model.row_bins(100);
+    let tone_row = rows
+        .iter()
+        .max_by(|a, b| {
+            let pa = test.model.row_power(&test.model.spectrum[2], a);
+            let pb = test.model.row_power(&test.model.spectrum[2], b);
+            pa.total_cmp(&pb)
+        })
+        .unwrap();
+    assert!(
+        test.model
+            .coherence_value(5, test.model.target_size.0, tone_row)
+            > 0.99
+    );
 
-        self.compute_spectrum();
-        self.repaint();
+    test.model.decoded_channels[1] = inverted;
+    test.model.recompute_spectrum();
+    let value = test
+        .model
+        .coherence_value(5, test.model.target_size.0, tone_row);
+    assert!(value > 0.0 && value < 0.16, "{}", value);
+}
+
+#[test]
+fn channel_mode_picks_the_analyzed_signal() {
+    let left: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.3).sin()).collect();
+    let mut test = TestModel::new();
+    test.model.target_size = (10, 10);
+    test.model.update_row_plan();
+    test.model.decoded_channels = vec![left.clone(), left];
+
+    test.handle(ModelEvent::SetChannelMode(ChannelMode::Mid));
+    assert_eq!(test.model.mixed_samples.len(), 20_000);
+    assert!(test
+        .model
+        .spectrum
+        .iter()
+        .any(|s| s.iter().any(|&p| p > 0.0)));
+
+    // Both channels are the same, so the side signal is silence.
+    test.handle(ModelEvent::SetChannelMode(ChannelMode::Side));
+    assert!(test
+        .model
+        .spectrum
+        .iter()
+        .all(|s| s.iter().all(|&p| p == 0.0)));
+
+    // There is no need to keep a copy of a channel.
+    test.handle(ModelEvent::SetChannelMode(ChannelMode::Channel(1)));
+    assert!(test.model.mixed_samples.is_empty());
+    assert!(!test.model.spectrum.is_empty());
+
+    // A channel that the file does not have, we replace by the first one.
+    test.handle(ModelEvent::SetChannelMode(ChannelMode::Channel(2)));
+    assert_eq!(test.model.channel_mode, ChannelMode::Channel(0));
+    assert!(!test.model.spectrum.is_empty());
+}
+
+#[test]
+fn write_peaks_finds_the_partials_of_a_chord() {
+    // Two tones at different amplitudes, -6 dB and -20 dB.
+    let tone = |hz: f32, i: usize| (2.0 * std::f32::consts::PI * hz * i as f32 / 44_100.0).sin();
+    let samples: Vec<f32> = (0..44_100)
+        .map(|i| 0.5 * tone(440.0, i) + 0.1 * tone(1000.0, i))
+        .collect();
+
+    let mut test = TestModel::new();
+    test.model.sample_rate = 44_100;
+    test.model.duration = Some(samples.len() as u64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+
+    let mut out = Vec::new();
+    test.model.write_peaks(&mut out).unwrap();
+    let csv = String::from_utf8(out).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("time,partial,frequency,amplitude"));
+
+    // The two strongest peaks of every window are the tones, in two partials.
+    let rows: Vec<Vec<f64>> = lines
+        .map(|line| line.split(',').map(|x| x.parse().unwrap()).collect())
+        .collect();
+    for &(hz, db) in &[(440.0, -6.02), (1000.0, -20.0)] {
+        let nearest: Vec<&Vec<f64>> = rows.iter().filter(|r| (r[2] - hz).abs() < 2.0).collect();
+        assert!(
+            nearest.len() >= 4,
+            "{} Hz found {} times",
+            hz,
+            nearest.len()
+        );
+        assert!(
+            nearest.iter().all(|r| (r[3] - db).abs() < 0.5),
+            "{:?}",
+            nearest
+        );
+        assert!(nearest.iter().all(|r| r[1] == nearest[0][1]));
+    }
+}
+
+#[test]
+fn compare_peaks_shows_the_tone_of_the_previous_file() {
+    let tone = |hz: f32| -> Vec<f32> {
+        (0..44_100)
+            .map(|i| (2.0 * std::f32::consts::PI * hz * i as f32 / 44_100.0).sin())
+            .collect()
+    };
+
+    let mut test = TestModel::new();
+    test.model.target_size = (50, 20);
+    test.model.sample_rate = 44_100;
+    test.model.duration = Some(44_100);
+    test.model.decoded_channels = vec![tone(1000.0)];
+    test.model.compute_spectrum();
+    let previous = test.model.take_file();
+    test.model.previous_file = Some(Box::new(previous));
+
+    test.model.duration = Some(44_100);
+    test.model.time_view = (0.0, 44_100.0);
+    test.model.decoded_channels = vec![tone(440.0)];
+    test.model.compute_spectrum();
+    assert!(test.model.compare_peaks().is_empty());
+
+    test.model.compare_peaks = true;
+    let peaks = test.model.compare_peaks();
+    assert!(peaks.len() >= 40);
+
+    // The strongest peak in every column is the tone of the previous file.
+    let y_1000 = test.model.y_at_hz(1000.0);
+    for &(_x, y, alpha) in peaks.iter().filter(|p| p.2 >= 0.79) {
+        assert!((y - y_1000).abs() < 0.01, "{} != {}", y, y_1000);
+        assert!(alpha <= 0.8);
     }
+}
 
-    fn compute_spectrum(&mut self) {
-        while self.samples.len() >= WINDOW_LEN {
-            let dft_of_samples = dft::dft_fast(&self.samples[..WINDOW_LEN], dft::hann);
-            self.spectrum.push(dft_of_samples);
+#[test]
+fn profile_reports_throughput() {
+    let profile = Profile {
+        decode_time: Duration::from_millis(500),
+        decoded_samples: 4_410_000,
+        transform_time: Duration::from_millis(250),
+        transformed_windows: 2_000,
+    };
+    assert_eq!(
+        profile.decode_report(44_100),
+        "8.8M samples/s, 200× real time"
+    );
+    assert_eq!(profile.transform_report(), "8.0k windows/s");
+
+    // The model measures while it decodes.
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+    let mut test = TestModel::new();
+    test.model.profile = Some(Profile::default());
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    let profile = test.model.profile.as_ref().unwrap();
+    assert_eq!(profile.decoded_samples, 50_000);
+    assert_eq!(
+        profile.transformed_windows,
+        test.model.spectrum.len() as u64
+    );
+}
 
-            // Drop some samples to advance to the next window.
-            self.samples = self.samples.split_off(WINDOW_OFF);
-        }
+#[test]
+fn resize_to_zero_skips_painting() {
+    let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.3).sin()).collect();
+    let mut test = TestModel::new();
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+    test.model.fixed_scale = true;
+
+    for &(width, height) in &[(0, 0), (0, 100), (100, 0), (-5, -5)] {
+        test.handle(ModelEvent::Resize(width, height, 30, 10));
     }
+    test.handle(ModelEvent::SetHistogram(true));
+    test.handle(ModelEvent::SetHistogram(false));
+
+    // Once the size is valid again, we paint again, and the fixed scale
+    // did not collapse the time range while the window had no width.
+    test.handle(ModelEvent::Resize(100, 50, 30, 10));
+    assert_eq!(test.model.row_plan.len(), 50);
+    assert!(test.model.time_view.1 > test.model.time_view.0);
+}
 
-    fn recompute_ticks(&self) {
-        let duration = match self.duration {
-            None => return,
-            Some(n) => n,
-        };
+#[test]
+fn model_decodes_wav_of_unknown_length() {
+    let samples: Vec<i16> = (0..30_000).map(|i| ((i % 100) * 300) as i16).collect();
+    let bytes = wav::encode_16(48_000, 1, &samples, 0xffff_ffff);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.wav".into()));
+
+    // Once decoded, we know the duration, even though the header did not say.
+    let model = &test.model;
+    assert_eq!(model.sample_rate, 48_000);
+    assert_eq!(model.duration, Some(30_000));
+    assert_eq!(model.time_view, (0.0, 30_000.0));
+    assert_eq!(model.decoded_channels.len(), 1);
+    assert_eq!(model.decoded_channels[0][99], 29_700.0 / 32_768.0);
+}
 
-        let (width, height) = self.target_size;
-        let (label_width, label_height) = self.label_size;
+#[test]
+fn ticks_survive_a_window_narrower_than_a_label() {
+    let samples: Vec<i32> = (0..44_100).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+
+    // Narrower than a single label, and lower than a single label.
+    test.handle(ModelEvent::Resize(10, 5, 40, 10));
+    let (x_ticks, y_ticks) = test.model.compute_ticks();
+    assert!(x_ticks.iter().all(|t| t.position.is_finite()));
+    assert!(y_ticks.iter().all(|t| t.position.is_finite()));
+    assert_eq!(y_ticks.len(), 2);
+}
 
-        let num_major_ticks_x = width / label_width;
-        let num_major_ticks_y = height / (label_height * 3 - 2);
+#[test]
+fn malformed_headers_keep_the_loaded_file() {
+    let samples: Vec<i32> = (0..10_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+
+    test.take_view_events();
+
+    let no_sample_rate = wav::encode_16(0, 1, &[0; 100], 200);
+    test.handle(ModelEvent::OpenBytes(no_sample_rate, "bad.wav".into()));
+    let no_channels = wav::encode_16(44_100, 0, &[], 0);
+    test.handle(ModelEvent::OpenBytes(no_channels, "bad.wav".into()));
+    test.handle(ModelEvent::OpenBytes(b"fLaC".to_vec(), "bad.flac".into()));
+
+    let errors = test
+        .take_view_events()
+        .into_iter()
+        .filter(|event| matches!(event, ViewEvent::ShowError(..)))
+        .count();
+    assert_eq!(errors, 3);
+    assert_eq!(test.model.title, "test.flac");
+    assert_eq!(test.model.duration, Some(10_000));
+    assert_eq!(test.model.sample_rate, 44_100);
+}
 
-        let mut x_ticks = Vec::new();
-        let mut y_ticks = Vec::new();
+#[test]
+fn failed_reload_and_export_show_an_error() {
+    let samples: Vec<i32> = (0..10_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.take_view_events();
+
+    // The bytes did not come from a file, so there is nothing to reload.
+    test.handle(ModelEvent::Reload);
+    let missing_dir = PathBuf::from("/nonexistent/peaks.csv");
+    test.handle(ModelEvent::ExportPeaks(missing_dir));
+    let errors: Vec<String> = test
+        .take_view_events()
+        .into_iter()
+        .filter_map(|event| match event {
+            ViewEvent::ShowError(message) => Some(message),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].contains("cannot be reloaded"), "{}", errors[0]);
+    assert!(
+        errors[1].starts_with("Failed to write /nonexistent/peaks.csv"),
+        "{}",
+        errors[1]
+    );
+}
 
-        // Make a rough estimate of how many ticks we can fit first. From that,
-        // compute a possible tick duration as a nice round number, and then
-        // fill the time with those ticks.
-        let x_tick_duration_samples = duration / (num_major_ticks_x.max(2) - 1) as u64;
-        let x_tick_duration_seconds = x_tick_duration_samples / self.sample_rate as u64;
-
-        // Space tick labels times apart that format to "round" numbers as mm:ss.
-        let quant_x_tick_secs = match x_tick_duration_seconds {
-            0 => 1,
-            1..=5 => 5,
-            6..=10 => 10,
-            11..=15 => 15,
-            16..=30 => 30,
-            31..=60 => 60,
-            61..=90 => 90,
-            91..=120 => 120,
-            121..=300 => 300,
-            301..=600 => 600,
-            601..=900 => 900,
-            _ => 1200,
-        };
-
-        // Avoid placing labels at the end, because they will be cut off. For
-        // that, we need to know how much time (in axis coordinates) a the label
-        // consumes.
-        let label_duration = label_width as u64 * duration as u64 / (width as u64 * 2);
-
-        let inv_duration = (duration as f64).recip();
-        let mut t_sec = 0_u64;
-        loop {
-            let min = t_sec / 60;
-            let sec = t_sec % 60;
-            let tick = Tick {
-                position: (t_sec * self.sample_rate as u64) as f64 * inv_duration,
-                label: format!("{:}:{:02}", min, sec),
-            };
-            x_ticks.push(tick);
+/// A decoder that reads a header, but no samples.
+#[cfg(test)]
+struct HeaderOnly {
+    sample_rate: u32,
+    channels: u32,
+}
 
-            t_sec += quant_x_tick_secs;
+#[cfg(test)]
+impl SampleSource for HeaderOnly {
+    fn read_samples(&mut self, _channels: &mut Vec<Vec<f32>>, _max_len: usize) -> io::Result<bool> {
+        Ok(false)
+    }
 
-            if t_sec * self.sample_rate as u64 + label_duration > duration {
-                break;
-            }
-        }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
 
-        // The minimal period that the DFT picks up, above the constant factor,
-        // is a single window.
-        let hz_min = self.sample_rate as f64 / WINDOW_LEN as f64;
+    fn channels(&self) -> u32 {
+        self.channels
+    }
 
-        // The maximal frequency is half of `WINDOW_LEN` periods in the window.
-        // As there is one bucket per sample, that is half of the sample rate.
-        let hz_max = self.sample_rate as f64 / 2.0;
+    fn bits_per_sample(&self) -> u32 {
+        16
+    }
 
-        // We don't want to place a tick all the way at the top, because the top
-        // half of the label would be cut off. For symmetry, and to get out of
-        // the way of x-axis labels, also move up the bottom tick.
-        let max_t = (height - label_height / 2) as f64 / height as f64;
-        let min_t = (label_height / 2) as f64 / height as f64;
+    fn duration(&self) -> Option<u64> {
+        Some(0)
+    }
+}
 
-        for i in 0..num_major_ticks_y {
-            let t = min_t + (max_t - min_t) * (i as f64) / (num_major_ticks_y - 1) as f64;
-            let value_hz = map_y_axis(t, hz_min, hz_max);
-            let label = match () {
-                () if value_hz > 10_000.0 => format!("{:.1} kHz", value_hz / 1000.0),
-                () if value_hz > 1000.0 => format!("{:.2} kHz", value_hz / 1000.0),
-                _ => format!("{:.0} Hz", value_hz),
-            };
-            let tick = Tick {
-                position: t,
-                label: label,
-            };
-            y_ticks.push(tick);
-        }
+#[test]
+fn headers_that_the_decoders_let_through_are_checked_too() {
+    // Claxon and our wav reader reject these headers already, so we can
+    // only get them past the decoder with a decoder of our own.
+    let samples: Vec<i32> = (0..10_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.take_view_events();
+
+    for (sample_rate, channels, reason) in [
+        (0, 1, "the sample rate is zero"),
+        (44_100, 0, "the file has no channels"),
+    ] {
+        let source = Box::new(HeaderOnly {
+            sample_rate,
+            channels,
+        });
+        test.model
+            .open_source(source, "bad".into(), None, None, false);
+        let events = test.take_view_events();
+        assert!(matches!(
+            &events[..],
+            [ViewEvent::ShowError(message)] if message == &format!("Failed to open bad: {}", reason),
+        ));
+    }
 
-        self.sender
-            .send(ViewEvent::SetTicks(x_ticks, y_ticks))
-            .unwrap();
+    assert_eq!(test.model.title, "test.flac");
+    assert_eq!(test.model.duration, Some(10_000));
+    assert_eq!(test.model.sample_rate, 44_100);
+}
+
+#[test]
+fn corrupt_files_are_abandoned_for_the_previous_file() {
+    let samples: Vec<i32> = (0..50_000).map(|i| (i % 100) * 300).collect();
+    let good = test_flac::encode(44_100, 16, &[samples]);
+
+    // The header is fine, but the frames after it are not.
+    let mut corrupt = good.clone();
+    for x in &mut corrupt[200..] {
+        *x = 0xff;
     }
+    assert!(FlacSource::new(io::Cursor::new(&corrupt)).is_ok());
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(
+        corrupt.clone(),
+        "corrupt.flac".into(),
+    ));
+    assert_eq!(test.model.duration, None);
+    assert!(test.model.decoded_channels.is_empty());
+    assert!(test.model.spectrum.is_empty());
+
+    test.handle(ModelEvent::OpenBytes(good, "good.flac".into()));
+    test.handle(ModelEvent::OpenBytes(corrupt, "corrupt.flac".into()));
+    assert_eq!(test.model.title, "good.flac");
+    assert_eq!(test.model.duration, Some(50_000));
+    assert_eq!(test.model.decoded_channels[0].len(), 50_000);
+}
 
-    /// Paint a new bitmap and send it over to the UI thread.
-    fn repaint(&self) {
-        let (width, height) = self.target_size;
-        assert!(width > 0);
-
-        let bitmap = Bitmap::generate(width, height, |x, y| {
-            // Paint a black square when we don't have any data yet.
-            let duration = match (self.spectrum.len(), self.duration) {
-                (0, _) => return 0.0,
-                (_, None) => return 0.0,
-                (_, Some(n)) => n,
-            };
+#[test]
+fn opening_a_file_makes_pending_decodes_stale() {
+    let a: Vec<i32> = (0..30_000).map(|i| (i % 100) * 300).collect();
+    let b: Vec<i32> = (0..20_000).map(|i| (i % 50) * 600).collect();
+    let a = test_flac::encode(44_100, 16, &[a]);
+    let b = test_flac::encode(44_100, 16, &[b]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+
+    // Open b while the decode event for a is still queued.
+    test.model
+        .handle_event(ModelEvent::OpenBytes(a, "a.flac".into()));
+    let stale = test.model.decode_generation;
+    test.model
+        .handle_event(ModelEvent::OpenBytes(b, "b.flac".into()));
+    assert_ne!(test.model.decode_generation, stale);
+
+    test.model.handle_event(ModelEvent::Decode(stale));
+    assert!(test.model.decoded_channels.is_empty());
+
+    test.drain();
+    assert_eq!(test.model.title, "b.flac");
+    assert!(test.model.reader.is_none());
+    assert!(test.model.decoded_channels[0].len() >= 20_000);
+}
 
-            // Determine the time (in units of samples) at the left edge of the
-            // current pixel and at the right edge of the current pixel.
-            let t_min = x as i64 * duration as i64 / width as i64;
-            let t_max = (x + 1) as i64 * duration as i64 / width as i64;
-
-            // Determine the bins that intersect the current pixel.
-            let bin_min = 1 + (t_min - WINDOW_LEN as i64) / WINDOW_OFF as i64;
-            let bin_max = t_max / WINDOW_OFF as i64;
-
-            // Clamp to be in bounds.
-            let i_min = bin_min.max(0).min(self.spectrum.len() as i64 - 1) as usize;
-            let i_max = bin_max.max(0).min(self.spectrum.len() as i64 - 1) as usize;
-
-            // We sample every window that intersects the pixel, and we weigh by
-            // the integral of the Hann window over the intersection. We
-            // normalize contributions of multiple windows.
-            let inv_window_len = (WINDOW_LEN as f32).recip();
-
-            // Start with a small nonzero weight to avoid division by zero.
-            let mut value = 0.0;
-            let mut total_weight = 0.0001;
-
-            // Sample every bin that intersects the pixel, and weigh by the
-            // length of the intersection.
-            for i in i_min..=i_max {
-                let window_t_min = i as i64 * WINDOW_OFF as i64;
-                let window_t_max = i as i64 * WINDOW_OFF as i64 + WINDOW_LEN as i64;
-                let overlap_min = t_min.max(window_t_min);
-                let overlap_max = t_max.min(window_t_max).max(overlap_min);
-                let weight = dft::hann_int(
-                    (overlap_min - window_t_min) as f32 * inv_window_len,
-                    (overlap_max - window_t_min) as f32 * inv_window_len,
-                );
+#[test]
+fn decode_progress_grows_until_the_file_is_decoded() {
+    let len = 2 * DECODE_BATCH_LEN as i32 + 1_000;
+    let samples: Vec<i32> = (0..len).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.model
+        .handle_event(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    assert_eq!(test.model.decode_progress(), 0.0);
+
+    let mut progress = Vec::new();
+    while let Ok(event) = test.events.try_recv() {
+        test.model.handle_event(event);
+        progress.push(test.model.decode_progress());
+    }
+    assert!(progress.len() > 2);
+    assert!(progress.windows(2).all(|p| p[0] <= p[1]));
+    assert!(progress[..progress.len() - 1].iter().all(|&p| p < 1.0));
+    assert_eq!(progress.last(), Some(&1.0));
+}
 
-                let spectrum_i = &self.spectrum[i];
+#[test]
+fn format_time_carries_rounded_seconds_into_minutes() {
+    assert_eq!(format_time(0.0), "0:00.0");
+    assert_eq!(format_time(12.34), "0:12.3");
+    assert_eq!(format_time(59.96), "1:00.0");
+    assert_eq!(format_time(119.99), "2:00.0");
+    assert_eq!(format_time(125.04), "2:05.0");
+}
 
-                assert_eq!(spectrum_i.len(), SPECTRUM_LEN);
-                let yf = 1.0 - y as f64 / (height - 1) as f64;
-                let jf = map_y_axis(yf, 1.0, (SPECTRUM_LEN - 1) as f64) as f32;
+#[test]
+fn format_metadata_describes_the_file() {
+    assert_eq!(
+        format_metadata(44_100, 16, 2, Some(205.4)),
+        "44100 Hz, 16-bit, stereo, 3:25"
+    );
+    assert_eq!(format_metadata(8_000, 24, 1, None), "8000 Hz, 24-bit, mono");
+    assert_eq!(
+        format_metadata(48_000, 32, 6, Some(59.6)),
+        "48000 Hz, 32-bit, 6 channels, 1:00"
+    );
+}
 
-                // Interpolate between the two closest frequencies.
-                // TODO: What if there are multiple buckets in a pixel?
-                let j = jf.trunc() as usize;
-                let s0 = spectrum_i[j.min(SPECTRUM_LEN - 1)] / SPECTRUM_LEN as f32;
-                let s1 = spectrum_i[(j + 1).min(SPECTRUM_LEN - 1)] / SPECTRUM_LEN as f32;
-                let sample = jf.fract() * s1 + (1.0 - jf.fract()) * s0;
+#[test]
+fn note_hz_and_note_name_follow_midi_numbers() {
+    assert_eq!(note_hz(69), 440.0);
+    assert_eq!(note_hz(81), 880.0);
+    assert!((note_hz(60) - 261.626).abs() < 1e-3);
+    assert_eq!(note_name(60), "C4");
+    assert_eq!(note_name(69), "A4");
+    assert_eq!(note_name(61), "C♯4");
+    assert_eq!(note_name(0), "C-1");
+}
 
-                value = sample.mul_add(weight, value);
-                total_weight += weight;
-            }
+#[test]
+fn note_lines_label_the_cs_in_view() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+
+    let lines = test.model.note_lines();
+    assert!(lines.windows(2).all(|w| w[0].position < w[1].position));
+    assert!(lines.iter().all(|t| (0.0..=1.0).contains(&t.position)));
+    let c4 = lines.iter().find(|t| t.label == "C4").unwrap();
+    assert!((test.model.hz_at(c4.position) - note_hz(60)).abs() < 0.01);
+    assert_eq!(lines.iter().filter(|t| !t.label.is_empty()).count(), 11);
+}
 
-            value = value / total_weight;
+#[test]
+fn constant_q_paints_a_tone_in_its_row() {
+    let omega = 2.0 * std::f64::consts::PI * 440.0 / 44_100.0;
+    let samples: Vec<f32> = (0..60_000)
+        .map(|i| (omega * i as f64).sin() as f32)
+        .collect();
+
+    let mut test = TestModel::new();
+    test.model.sample_rate = 44_100;
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+    test.handle(ModelEvent::Resize(20, 200, 20, 10));
+    test.handle(ModelEvent::SetConstantQ(true));
+    assert_eq!(test.model.constant_q.len(), test.model.spectrum.len());
+
+    let rows = test.model.row_bins(200);
+    let values: Vec<f32> = rows
+        .iter()
+        .map(|row| test.model.pixel_value(10, 20, row))
+        .collect();
+    let brightest = (0..rows.len())
+        .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .unwrap();
+    let fft_len = (test.model.window_len * test.model.zero_pad_factor) as f64;
+    let hz = rows[brightest].center * 44_100.0 / fft_len;
+    assert!((hz / 440.0).log2().abs() < 1.0 / 12.0, "{} Hz", hz);
+
+    // Below the lowest bin, there is nothing to paint.
+    assert_eq!(values[199], 0.0);
+
+    // The kernels stay for the next batch, until the window changes.
+    let plan_key = |test: &TestModel| test.model.constant_q_plan.as_ref().map(|(key, _)| *key);
+    assert_eq!(plan_key(&test), Some((44_100, WINDOW_LEN, WindowFn::Hann)));
+    test.handle(ModelEvent::SetWindowFn(WindowFn::Blackman));
+    assert_eq!(
+        plan_key(&test),
+        Some((44_100, WINDOW_LEN, WindowFn::Blackman))
+    );
 
-            (0.5 + value.ln() * 0.05).min(1.0).max(0.0)
-        });
-        self.sender.send(ViewEvent::SetView(bitmap)).unwrap();
-    }
+    test.handle(ModelEvent::SetConstantQ(false));
+    assert!(test.model.constant_q.is_empty());
 }
 
-fn run_main(app: &gtk::Application, open_files: &[gio::File]) {
-    // Create two bounded one-way message queues. The one that sends
-    // messages back to the view is a tailored glib channel, but it behaves
-    // the same as the mpsc one.
-    let (send_model, recv_model) = mpsc::sync_channel(10);
-    let (send_view, recv_view) =
-        glib::MainContext::sync_channel(glib::source::Priority::DEFAULT, 10);
+#[test]
+fn config_waits_for_the_first_resize() {
+    let config = Config {
+        invert_colormap: Some(true),
+        floor_db: Some(-90.0),
+        ..Config::default()
+    };
+    let mut test = TestModel::new();
+    test.model.pending_config = Some(config);
+    assert!(!test.model.invert_colormap);
+
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    assert!(test.model.invert_colormap);
+    assert_eq!(test.model.floor_db, -90.0);
+    assert_eq!(test.model.pending_config, None);
+}
 
-    // On a background thread, construct the model, and run its event loop.
-    let send_model_clone = send_model.clone();
-    thread::spawn(move || {
-        let mut model = Model::new(send_view, send_model_clone);
-        model.run_event_loop(recv_model);
-    });
+#[test]
+fn odd_window_lengths_transform_the_phase() {
+    let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.3).sin()).collect();
+    let mut test = TestModel::new();
+    test.model.target_size = (10, 100);
+    test.model.update_row_plan();
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples.clone(), samples];
+    test.model.window_len = 1001;
+    test.model.show_group_delay = true;
+    test.model.show_coherence = true;
+    test.model.compute_spectrum();
+    assert_eq!(test.model.spectrum[0].len(), 500);
+    assert_eq!(test.model.coherences.len(), test.model.spectrum.len());
+}
 
-    // Back on the main thread, construct the view.
-    let view_cell = View::new(app, send_model.clone());
+#[test]
+fn row_plan_follows_the_sample_rate_of_the_file() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let flac = test_flac::encode(44_100, 16, &[samples]);
+    let samples: Vec<i16> = (0..20_000).map(|i| ((i % 50) * 300) as i16).collect();
+    let wav = wav::encode_16(8_000, 1, &samples, 40_000);
+
+    let is_fresh = |model: &Model| {
+        let fresh = model.row_bins(model.target_size.1);
+        model.row_plan.len() == fresh.len()
+            && model
+                .row_plan
+                .iter()
+                .zip(&fresh)
+                .all(|(a, b)| a.center == b.center && a.lo == b.lo && a.hi == b.hi)
+    };
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::SetYScale(YScale::Mel));
+    test.handle(ModelEvent::OpenBytes(flac, "a.flac".into()));
+    assert!(is_fresh(&test.model));
+
+    test.handle(ModelEvent::OpenBytes(wav, "b.wav".into()));
+    assert_eq!(test.model.sample_rate, 8_000);
+    assert!(is_fresh(&test.model));
+
+    test.handle(ModelEvent::SwapFiles);
+    assert_eq!(test.model.sample_rate, 44_100);
+    assert!(is_fresh(&test.model));
+}
 
-    // Handle the view's events on this thread, the main thread.
-    recv_view.attach(None, move |event| {
-        view_cell.borrow_mut().handle_event(event);
-        glib::ControlFlow::Continue
-    });
+#[test]
+fn frequency_zoom_carries_over_to_a_file_at_another_rate() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let flac = test_flac::encode(44_100, 16, &[samples]);
+    let samples: Vec<i16> = (0..20_000).map(|i| ((i % 50) * 300) as i16).collect();
+    let wav = wav::encode_16(8_000, 1, &samples, 40_000);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(flac, "a.flac".into()));
+
+    // A zoom above the Nyquist frequency of the next file does not apply to it.
+    test.model.set_freq_view(10_000.0, 15_000.0);
+    test.handle(ModelEvent::OpenBytes(wav, "b.wav".into()));
+    assert_eq!(test.model.freq_view, (0.0, f64::INFINITY));
+
+    // A zoom that fits in both files does apply to both.
+    test.model.set_freq_view(1_000.0, 3_000.0);
+    test.handle(ModelEvent::SwapFiles);
+    assert_eq!(test.model.sample_rate, 44_100);
+    assert_eq!(test.model.hz_range(), (1_000.0, 3_000.0));
+    assert!(test.model.row_plan[0].hi < test.model.spectrum_len() / 4);
+}
 
-    // If we were supposed to open any files at startup, open them now by
-    // sending the event to the model.
-    for file in open_files.iter() {
-        if let Some(fname) = file.path() {
-            send_model.send(ModelEvent::OpenFile(fname)).unwrap();
-        }
-        break;
+#[test]
+fn comparing_files_shares_the_frequency_range() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let flac = test_flac::encode(44_100, 16, &[samples]);
+    let samples: Vec<i16> = (0..20_000).map(|i| ((i % 50) * 300) as i16).collect();
+    let wav = wav::encode_16(48_000, 1, &samples, 40_000);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(flac, "a.flac".into()));
+    test.handle(ModelEvent::OpenBytes(wav, "b.wav".into()));
+    assert_eq!(test.model.hz_at(1.0), 24_000.0);
+
+    test.handle(ModelEvent::SetComparePeaks(true));
+    assert_eq!(test.model.hz_at(1.0), 22_050.0);
+    let rows_hz: Vec<f64> = (0..10).map(|i| test.model.hz_at(i as f64 / 9.0)).collect();
+    let top_bin = test.model.bin_at(1.0) / test.model.zero_pad_factor as f64;
+    assert!((top_bin - 22_050.0 / 48_000.0 * WINDOW_LEN as f64).abs() < 1e-6);
+
+    // After swapping, every height still shows the same frequency.
+    test.handle(ModelEvent::SwapFiles);
+    assert_eq!(test.model.sample_rate, 44_100);
+    for (i, &hz) in rows_hz.iter().enumerate() {
+        assert!((test.model.hz_at(i as f64 / 9.0) - hz).abs() < 1e-6);
     }
+
+    test.handle(ModelEvent::SetComparePeaks(false));
+    assert_eq!(test.model.hz_at(1.0), 22_050.0);
+    test.handle(ModelEvent::SwapFiles);
+    assert_eq!(test.model.hz_at(1.0), 24_000.0);
 }
 
-fn main() {
-    gtk::init().unwrap();
-    let application = gtk::Application::new(
-        Some("nl.ruuda.audiograter"),
-        // Allow multiple instances of the application, even though we did
-        // provide an application id.
-        gio::ApplicationFlags::NON_UNIQUE |
-        gio::ApplicationFlags::HANDLES_OPEN,
-    );
+#[test]
+fn time_labels_always_fit_before_the_end() {
+    let mut test = TestModel::new();
+    test.model.sample_rate = 44_100;
+    test.model.duration = Some(44_100);
+    test.model.target_size = (100, 50);
+    test.model.label_size = (20, 10);
+
+    // The label of a tick takes a tenth of the width, it starts at the tick.
+    // In a view shorter than a millisecond, the first tick is the only one.
+    for begin in 0..100 {
+        test.model.time_view = (begin as f64, begin as f64 + 40.0);
+        let (ticks, _) = test.model.compute_ticks();
+        for tick in &ticks {
+            assert!(tick.position >= 0.0, "{} at {}", tick.label, begin);
+            assert!(tick.position <= 0.9, "{} at {}", tick.label, begin);
+        }
+    }
+}
 
-    if let Some(settings) = gtk::Settings::default() {
-        settings.set_gtk_application_prefer_dark_theme(true);
+#[test]
+fn histogram_of_a_single_row_skips_painting() {
+    let samples: Vec<f32> = (0..20_000).map(|i| (i as f32 * 0.3).sin()).collect();
+    let mut test = TestModel::new();
+    test.model.duration = Some(samples.len() as u64);
+    test.model.time_view = (0.0, samples.len() as f64);
+    test.model.decoded_channels = vec![samples];
+    test.model.compute_spectrum();
+    test.handle(ModelEvent::SetHistogram(true));
+    let painted_sizes = |events: Vec<ViewEvent>| -> Vec<(i32, i32)> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                ViewEvent::SetView(bitmap) => Some((bitmap.width, bitmap.height)),
+                _ => None,
+            })
+            .collect()
+    };
+
+    test.take_view_events();
+    for &(width, height) in &[(100, 1), (100, 0), (0, 50)] {
+        test.handle(ModelEvent::Resize(width, height, 30, 10));
     }
+    assert!(painted_sizes(test.take_view_events()).is_empty());
 
-    // When the application starts, run all of this on the main thread.
-    application.connect_activate(move |app| run_main(app, &[]));
+    test.handle(ModelEvent::Resize(100, 50, 30, 10));
+    assert_eq!(painted_sizes(test.take_view_events()), [(100, 50)]);
+}
 
-    // Set up handling files provided on the command line.
-    application.connect_open(move |app, files, _| run_main(app, files));
+#[test]
+fn regions_stay_at_their_time_and_frequency() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.take_view_events();
+
+    let regions = |events: Vec<ViewEvent>| {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                ViewEvent::SetRegions(regions) => Some(regions),
+                _ => None,
+            })
+            .last()
+            .unwrap()
+    };
+
+    test.handle(ModelEvent::AddRegion(0.5, 0.75, 0.25, 0.5));
+    let before = regions(test.take_view_events());
+    let (x0, x1, y0, y1) = before[0].0;
+    assert!((x0 - 0.5).abs() < 1e-9 && (x1 - 0.75).abs() < 1e-9);
+    assert!((y0 - 0.25).abs() < 1e-9 && (y1 - 0.5).abs() < 1e-9);
+
+    // After zooming into the right half, the region moves along with the
+    // audio, and it still covers the same times and frequencies.
+    test.handle(ModelEvent::SetTimeRange(0.5, 1.0));
+    test.handle(ModelEvent::ZoomFrequencyAt(0.5, 0.5));
+    let after = regions(test.take_view_events());
+    let (x0, x1, y0, y1) = after[0].0;
+    assert!(x0.abs() < 1e-9 && (x1 - 0.5).abs() < 1e-9);
+    assert!(y1 - y0 > 0.25);
+    assert_eq!(after[0].1, before[0].1);
+
+    // Zoomed far enough, the region extends beyond the top of the graph.
+    test.handle(ModelEvent::ZoomFrequencyAt(0.0, 0.1));
+    let (_, _, y0, y1) = regions(test.take_view_events())[0].0;
+    assert!(y0 < 1.0 && y1 > 1.0);
+
+    test.handle(ModelEvent::RemoveRegion(0));
+    test.handle(ModelEvent::SetTimeRange(0.0, 0.5));
+    assert!(test
+        .take_view_events()
+        .iter()
+        .all(|event| !matches!(event, ViewEvent::SetRegions(..))));
+}
 
-    // And run the UI event loop on the main thread.
-    application.run();
+#[test]
+fn probes_stay_at_their_time_and_frequency() {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 100) * 300).collect();
+    let bytes = test_flac::encode(44_100, 16, &[samples]);
+
+    let mut test = TestModel::new();
+    test.handle(ModelEvent::Resize(100, 50, 20, 10));
+    test.handle(ModelEvent::OpenBytes(bytes, "test.flac".into()));
+    test.take_view_events();
+
+    let placed = |events: Vec<ViewEvent>| {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                ViewEvent::SetProbes(placed) => Some(placed),
+                _ => None,
+            })
+            .last()
+            .unwrap()
+    };
+    let near = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9;
+
+    test.handle(ModelEvent::AddProbe(1, 0.25, 0.5));
+    test.handle(ModelEvent::AddProbe(2, 0.75, 0.1));
+    let before = placed(test.take_view_events());
+    assert_eq!(before.iter().map(|p| p.0).collect::<Vec<_>>(), [1, 2]);
+    assert!(near(before[0].1, (0.25, 0.5)) && near(before[1].1, (0.75, 0.1)));
+
+    // Zooming into the right half moves the probes along with the audio,
+    // so they still measure at the same time.
+    test.handle(ModelEvent::SetTimeRange(0.5, 1.0));
+    let after = placed(test.take_view_events());
+    assert!(near(after[0].1, (-0.5, 0.5)) && near(after[1].1, (0.5, 0.1)));
+    assert_eq!(after[1].2, before[1].2);
+
+    // Likewise, they move along with the frequency axis, whether we change
+    // its scale, or zoom it.
+    let hz = test.model.hz_at(0.1);
+    test.handle(ModelEvent::SetYScale(YScale::Linear));
+    let after = placed(test.take_view_events());
+    assert!(after[1].1 .1 < 0.05);
+    assert!((test.model.hz_at(after[1].1 .1) / hz - 1.0).abs() < 1e-6);
+    assert_eq!(after[1].2, before[1].2);
+
+    test.handle(ModelEvent::ZoomFrequencyAt(0.5, 0.5));
+    let after = placed(test.take_view_events());
+    assert!(after[1].1 .1 < 0.0);
+    assert_eq!(after[1].2, before[1].2);
+
+    test.handle(ModelEvent::RemoveProbe(1));
+    test.handle(ModelEvent::SetTimeRange(0.0, 0.5));
+    let after = placed(test.take_view_events());
+    assert_eq!(after.iter().map(|p| p.0).collect::<Vec<_>>(), [2]);
+    assert!((after[0].1 .0 - 1.0).abs() < 1e-9);
 }
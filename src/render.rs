@@ -0,0 +1,680 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module turns values into colors, and maps the frequency axis. The
+// application uses it to paint the spectrogram, and other programs can use
+// `render_spectrogram` to paint one without running the application. With the
+// `gui` feature, bitmaps convert to a `gdk_pixbuf::Pixbuf`, for embedding the
+// spectrogram in a GTK program.
+
+use crate::dft;
+use crate::stft::Stft;
+
+/// The number of samples in a single DFT window, as in the application.
+const WINDOW_LEN: usize = 8192;
+
+/// The number of samples between two DFT windows, as in the application by default.
+const WINDOW_OFF: usize = 4096;
+
+/// The blend between a log and linear frequency axis that we start with, see `map_y_axis`.
+pub const DEFAULT_Y_BLEND: f64 = 0.5;
+
+/// The level at the bottom of the colormap that we start with, in dB relative to full scale, see `level_db`.
+pub const DEFAULT_FLOOR_DB: f32 = -90.0;
+
+/// The level at the top of the colormap that we start with, in dB relative to full scale.
+pub const DEFAULT_CEILING_DB: f32 = 0.0;
+
+/// Evaluate a polynomial per color channel, given its coefficients from high to low degree.
+#[inline]
+fn eval_polynomial(c: &[[f32; 3]; 7], t: f32) -> (f32, f32, f32) {
+    let mut result = c[0];
+
+    // LLVM, if you can hear this comment, please unroll and vectorize.
+    for j in 1..7 {
+        for i in 0..3 {
+            result[i] = result[i].mul_add(t, c[j][i]);
+        }
+    }
+
+    (result[0], result[1], result[2])
+}
+
+/// Given t in [0, 1], return an RGB value in [0, 1]^3.
+pub fn colormap_magma(t: f32) -> (f32, f32, f32) {
+    // Based on https://www.shadertoy.com/view/WlfXRN (licensed CC0), which in
+    // turn is a fit of https://github.com/BIDS/colormap/blob/master/colormaps.py,
+    // which is also licensed CC0.
+
+    let c: [[f32; 3]; 7] = [
+        [18.65570506591883, -11.48977351997711, -5.601961508734096],
+        [-50.76852536473588, 29.04658282127291, 4.23415299384598],
+        [52.17613981234068, -27.94360607168351, 12.94416944238394],
+        [-27.66873308576866, 14.26473078096533, -13.64921318813922],
+        [8.353717279216625, -3.577719514958484, 0.3144679030132573],
+        [0.2516605407371642, 0.6775232436837668, 2.494026599312351],
+        [
+            -0.002136485053939582,
+            -0.000749655052795221,
+            -0.005386127855323933,
+        ],
+    ];
+
+    eval_polynomial(&c, t)
+}
+
+/// Given t in [0, 1], return an RGB value in [0, 1]^3.
+///
+/// Unlike magma, viridis does not go to black, so the quiet parts stay
+/// distinguishable on a dark screen.
+pub fn colormap_viridis(t: f32) -> (f32, f32, f32) {
+    // From the same source as `colormap_magma`.
+    let c: [[f32; 3]; 7] = [
+        [-5.435456, 4.6458526, 26.312435],
+        [4.776385, -13.745146, -65.353035],
+        [6.22827, 14.179934, 56.69055],
+        [-4.6342306, -5.799101, -19.332441],
+        [-0.33086184, 0.21484756, 0.095095165],
+        [0.10509304, 1.4046135, 1.3845901],
+        [0.27772734, 0.0054073445, 0.3340998],
+    ];
+
+    eval_polynomial(&c, t)
+}
+
+/// The gamma that `Colormap::Grayscale` encodes with, about that of a typical display.
+pub const GRAYSCALE_GAMMA: f32 = 2.2;
+
+/// Given t in [0, 1], return a gray RGB value in [0, 1]^3.
+///
+/// We treat t as a linear intensity and encode it with the given gamma, so
+/// the perceived brightness ramps up more evenly than t itself does. A gamma
+/// of 1.0 maps t to the gray value directly. Out of range values clamp the
+/// same way as in `Bitmap::generate`.
+pub fn colormap_grayscale(t: f32, gamma: f32) -> (f32, f32, f32) {
+    let v = t.min(1.0).max(0.0).powf(gamma.recip());
+    (v, v, v)
+}
+
+/// The colormaps that we can paint with.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Colormap {
+    Magma,
+    Viridis,
+    /// Gray values, for printing, see `colormap_grayscale`.
+    Grayscale,
+}
+
+impl Colormap {
+    /// Given t in [0, 1], return an RGB value in [0, 1]^3.
+    pub fn map(self, t: f32) -> (f32, f32, f32) {
+        match self {
+            Colormap::Magma => colormap_magma(t),
+            Colormap::Viridis => colormap_viridis(t),
+            Colormap::Grayscale => colormap_grayscale(t, GRAYSCALE_GAMMA),
+        }
+    }
+
+    /// Return the name of the colormap, as in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Magma => "magma",
+            Colormap::Viridis => "viridis",
+            Colormap::Grayscale => "grayscale",
+        }
+    }
+}
+
+/// Map the unit interval to the range `(min_y, max_y)`.
+///
+/// The scale is logarithmic near `min_y`, and linear near `max_y`. This way, we
+/// can meaningfully distinguish tones in the low frequencies, and it makes
+/// sense for music, where a doubling of the frequency corresponds to an one
+/// octrave increase in pitch.
+///
+/// Yet, at the high end of the spectrum, I want to be able to see if anything
+/// was cut off around 18 kHz to see if lossy compression was involved, and in
+/// general, to look at patterns in the frequency spectrum that would be
+/// squashed severely on a log scale.
+///
+/// The `blend` in [0, 1] biases the scale: at 0 it is fully logarithmic, at 1
+/// fully linear, and at `DEFAULT_Y_BLEND` the weight of the linear part grows
+/// linearly from bottom to top.
+#[inline]
+pub fn map_y_axis(y: f64, min_y: f64, max_y: f64, blend: f64) -> f64 {
+    let log_min_y = min_y.log2();
+    let log_max_y = max_y.log2();
+    let y_log = (log_min_y + y * (log_max_y - log_min_y)).exp2();
+    let y_lin = min_y + y * (max_y - min_y);
+    let w = y.powf((1.0 - blend) / blend);
+    y_lin * w + y_log * (1.0 - w)
+}
+
+/// Return the pitch in mel of a frequency in Hz.
+pub fn hz_to_mel(hz: f64) -> f64 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+/// Return the frequency in Hz of a pitch in mel, the inverse of `hz_to_mel`.
+pub fn mel_to_hz(mel: f64) -> f64 {
+    700.0 * (10.0_f64.powf(mel / 2595.0) - 1.0)
+}
+
+/// Map the unit interval to the range `(min_hz, max_hz)`, evenly spaced in mel.
+///
+/// The mel scale follows the perceived pitch: it is nearly linear below
+/// 1 kHz, and logarithmic above. That suits speech, where the formants that
+/// matter are in the low kilohertz.
+pub fn map_y_axis_mel(y: f64, min_hz: f64, max_hz: f64) -> f64 {
+    let min_mel = hz_to_mel(min_hz);
+    let max_mel = hz_to_mel(max_hz);
+    mel_to_hz(min_mel + y * (max_mel - min_mel))
+}
+
+/// Inverse of `map_y_axis_mel`, map a frequency in `(min_hz, max_hz)` to the unit interval.
+///
+/// Like `map_y_axis_inv`, this clamps frequencies outside the range.
+pub fn map_y_axis_mel_inv(hz: f64, min_hz: f64, max_hz: f64) -> f64 {
+    let min_mel = hz_to_mel(min_hz);
+    let max_mel = hz_to_mel(max_hz);
+    ((hz_to_mel(hz) - min_mel) / (max_mel - min_mel))
+        .max(0.0)
+        .min(1.0)
+}
+
+/// How to space frequencies along the y-axis.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum YScale {
+    /// Blend a log scale at the low end with a linear scale at the high end, see `map_y_axis`.
+    Blended,
+    Linear,
+    Log,
+    /// Evenly spaced in mel, see `map_y_axis_mel`. Unlike the others, this
+    /// is not scale-invariant, so the range must be in Hz.
+    Mel,
+}
+
+impl YScale {
+    /// Map the unit interval to the range `(min_y, max_y)` on this scale.
+    ///
+    /// The `blend` is that of the blended scale, the other scales ignore it.
+    pub fn map(self, y: f64, min_y: f64, max_y: f64, blend: f64) -> f64 {
+        match self {
+            YScale::Blended => map_y_axis(y, min_y, max_y, blend),
+            YScale::Linear => map_y_axis(y, min_y, max_y, 1.0),
+            YScale::Log => map_y_axis(y, min_y, max_y, 0.0),
+            YScale::Mel => map_y_axis_mel(y, min_y, max_y),
+        }
+    }
+
+    /// Inverse of `map`, map a value in `(min_y, max_y)` to the unit interval.
+    pub fn map_inv(self, v: f64, min_y: f64, max_y: f64, blend: f64) -> f64 {
+        match self {
+            YScale::Blended => map_y_axis_inv(v, min_y, max_y, blend),
+            YScale::Linear => map_y_axis_inv(v, min_y, max_y, 1.0),
+            YScale::Log => map_y_axis_inv(v, min_y, max_y, 0.0),
+            YScale::Mel => map_y_axis_mel_inv(v, min_y, max_y),
+        }
+    }
+
+    /// Return the name of the scale, as in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            YScale::Blended => "blended",
+            YScale::Linear => "linear",
+            YScale::Log => "log",
+            YScale::Mel => "mel",
+        }
+    }
+}
+
+/// Inverse of `map_y_axis`, map a value in `(min_y, max_y)` to the unit interval.
+///
+/// The blended mapping has no closed-form inverse, but it is monotonic, so we
+/// can find the inverse by bisection. Values outside the range are clamped.
+pub fn map_y_axis_inv(v: f64, min_y: f64, max_y: f64, blend: f64) -> f64 {
+    let mut lo = 0.0;
+    let mut hi = 1.0;
+    for _ in 0..52 {
+        let mid = 0.5 * (lo + hi);
+        if map_y_axis(mid, min_y, max_y, blend) < v {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+/// Thread-safe bitmap that we can fill on one thread and display on another.
+pub struct Bitmap {
+    pub data: Vec<u8>,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Apply the sRGB transfer function to a linear intensity in [0, 1].
+pub fn srgb_encode(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(2.4_f32.recip()) - 0.055
+    }
+}
+
+/// Undo the sRGB transfer function, the inverse of `srgb_encode`.
+pub fn srgb_decode(encoded: f32) -> f32 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Blend `src` over `dst` in linear light, where both hold sRGB-encoded pixels.
+///
+/// The pixels are premultiplied ARGB in native endian 32-bit words, the
+/// layout of `cairo::Format::ARgb32`. Cairo blends the encoded values, like
+/// most software does, which makes a translucent color over a dark one too
+/// dark. Here we decode to linear intensities before we blend.
+pub fn blend_over_linear(dst: &mut [u8], src: &[u8]) {
+    assert_eq!(dst.len(), src.len(), "Layers must have the same size.");
+    for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+        let s_argb = u32::from_ne_bytes([s[0], s[1], s[2], s[3]]);
+        let d_argb = u32::from_ne_bytes([d[0], d[1], d[2], d[3]]);
+        let sa = (s_argb >> 24) as f32 / 255.0;
+        let da = (d_argb >> 24) as f32 / 255.0;
+        if sa == 0.0 {
+            continue;
+        }
+        if sa == 1.0 {
+            d.copy_from_slice(s);
+            continue;
+        }
+
+        // Unpremultiply, decode, and premultiply again, per channel.
+        let linear = |argb: u32, shift: u32, alpha: f32| -> f32 {
+            if alpha == 0.0 {
+                return 0.0;
+            }
+            let c = ((argb >> shift) & 0xff) as f32 / 255.0;
+            srgb_decode((c / alpha).min(1.0)) * alpha
+        };
+        let alpha = sa + da * (1.0 - sa);
+        let mut result = ((alpha * 255.0).round() as u32) << 24;
+        for shift in [0, 8, 16] {
+            let c = linear(s_argb, shift, sa) + linear(d_argb, shift, da) * (1.0 - sa);
+            let c = srgb_encode((c / alpha).min(1.0)) * alpha;
+            result |= ((c * 255.0).round() as u32) << shift;
+        }
+        d.copy_from_slice(&result.to_ne_bytes());
+    }
+}
+
+/// How to turn the values of a bitmap into colors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorScheme {
+    /// The colormap to map values through.
+    pub colormap: Colormap,
+
+    /// Traverse the colormap in the opposite direction, so high values are dark.
+    pub invert: bool,
+
+    /// Treat the colormap output as linear intensities, and sRGB-encode them.
+    ///
+    /// The magma fit already produces sRGB values, so this brightens the
+    /// dark end. It is there to compare against a linear-light pipeline.
+    pub encode_srgb: bool,
+}
+
+/// A value for `Bitmap::generate` that marks a pixel that we have not decoded yet.
+pub const UNDECODED: f32 = -1.0;
+
+impl Bitmap {
+    /// Fill the bitmap by mapping the value at every pixel through the colormap.
+    ///
+    /// Pixels with value `UNDECODED` get a gray hatch instead, so they do not
+    /// look like silence. Values outside of [0, 1] are clamped, and NaN and
+    /// infinities map to 0, so the colormap only sees values in its domain.
+    pub fn generate<F: Fn(i32, i32) -> f32>(
+        width: i32,
+        height: i32,
+        scheme: ColorScheme,
+        f: F,
+    ) -> Bitmap {
+        let len = width * height * 3;
+        let mut data = Vec::with_capacity(len as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let t = f(x, y);
+                if t == UNDECODED {
+                    let gray = if (x + y) / 6 % 2 == 0 { 40 } else { 28 };
+                    data.extend_from_slice(&[gray, gray, gray]);
+                    continue;
+                }
+                // Note that `min` and `max` would turn NaN into 1.0, not 0.0.
+                let t = if t.is_finite() {
+                    t.min(1.0).max(0.0)
+                } else {
+                    0.0
+                };
+                let t = if scheme.invert { 1.0 - t } else { t };
+                let (r, g, b) = scheme.colormap.map(t);
+                let (r, g, b) = if scheme.encode_srgb {
+                    let encode = |c: f32| srgb_encode(c.max(0.0));
+                    (encode(r), encode(g), encode(b))
+                } else {
+                    (r, g, b)
+                };
+                data.push((r.min(1.0).max(0.0) * 255.0) as u8);
+                data.push((g.min(1.0).max(0.0) * 255.0) as u8);
+                data.push((b.min(1.0).max(0.0) * 255.0) as u8);
+            }
+        }
+
+        Bitmap {
+            data,
+            width,
+            height,
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn into_pixbuf(self) -> gdk_pixbuf::Pixbuf {
+        let has_alpha = false;
+        let bits_per_sample = 8;
+        let row_stride = 3 * self.width;
+        gdk_pixbuf::Pixbuf::from_mut_slice(
+            self.data,
+            gdk_pixbuf::Colorspace::Rgb,
+            has_alpha,
+            bits_per_sample,
+            self.width,
+            self.height,
+            row_stride,
+        )
+    }
+}
+
+/// Return the level of a power, normalized by the window length, in dB relative to full scale.
+///
+/// A sine of amplitude 1, the loudest that a sample format can represent, has
+/// a power of `window_len / 2` in its peak bin, with a window normalized like
+/// `dft::hann`. That is 0 dB. Silence is at negative infinity.
+pub fn level_db(power: f32, window_len: usize) -> f32 {
+    10.0 * (2.0 * power / window_len as f32).log10()
+}
+
+/// Map a level in dB to a value in [0, 1] for the colormap.
+///
+/// Levels at or below `floor_db` map to the bottom of the colormap, and so
+/// does NaN. Levels at or above `ceiling_db` map to the top.
+pub fn brightness(level_db: f32, floor_db: f32, ceiling_db: f32) -> f32 {
+    // Note that `min` and `max` would turn NaN into 1.0, not 0.0.
+    let t = (level_db - floor_db) / (ceiling_db - floor_db);
+    if t.is_nan() {
+        0.0
+    } else {
+        t.min(1.0).max(0.0)
+    }
+}
+
+/// Return the level in dB that maps to `brightness`, the inverse of `brightness`.
+///
+/// Only levels from `floor_db` to `ceiling_db` map to distinct brightnesses,
+/// so brightnesses outside of [0, 1] map to one of those.
+pub fn level_at_brightness(brightness: f32, floor_db: f32, ceiling_db: f32) -> f32 {
+    floor_db + brightness.min(1.0).max(0.0) * (ceiling_db - floor_db)
+}
+
+/// Render the spectrogram of a mono signal to a bitmap of the given size.
+///
+/// This uses the same window and colors as the application, with the default
+/// frequency axis. It is simpler than the application in one way: every pixel
+/// shows the window that is centered nearest to it, rather than a weighted
+/// average of the windows that overlap it.
+pub fn render_spectrogram(samples: &[f32], width: i32, height: i32, scheme: ColorScheme) -> Bitmap {
+    let spectrum: Vec<Box<[f32]>> = Stft::new(samples, WINDOW_LEN, WINDOW_OFF, dft::hann).collect();
+    let spectrum_len = WINDOW_LEN / 2;
+
+    // Rows are independent of the column, so find their bins once.
+    let bins: Vec<f64> = (0..height)
+        .map(|y| {
+            let v = 1.0 - (y as f64 + 0.5) / height as f64;
+            map_y_axis(v, 1.0, (spectrum_len - 1) as f64, DEFAULT_Y_BLEND)
+        })
+        .collect();
+
+    Bitmap::generate(width, height, scheme, |x, y| {
+        if spectrum.is_empty() {
+            return 0.0;
+        }
+        let t = (x as f64 + 0.5) / width as f64 * samples.len() as f64;
+        let i = ((t - (WINDOW_LEN / 2) as f64) / WINDOW_OFF as f64)
+            .round()
+            .max(0.0) as usize;
+        let spectrum_i = &spectrum[i.min(spectrum.len() - 1)];
+
+        // Interpolate between the two closest bins.
+        let jf = bins[y as usize];
+        let j = jf.trunc() as usize;
+        let s0 = spectrum_i[j.min(spectrum_len - 1)];
+        let s1 = spectrum_i[(j + 1).min(spectrum_len - 1)];
+        let fract = jf.fract() as f32;
+        let power = (fract * s1 + (1.0 - fract) * s0) / spectrum_len as f32;
+        brightness(
+            level_db(power, WINDOW_LEN),
+            DEFAULT_FLOOR_DB,
+            DEFAULT_CEILING_DB,
+        )
+    })
+}
+
+/// Render the spectrogram of a mono signal to a pixbuf, see `render_spectrogram`.
+#[cfg(feature = "gui")]
+pub fn render_pixbuf(
+    samples: &[f32],
+    width: i32,
+    height: i32,
+    scheme: ColorScheme,
+) -> gdk_pixbuf::Pixbuf {
+    render_spectrogram(samples, width, height, scheme).into_pixbuf()
+}
+
+#[test]
+fn brightness_maps_the_level_range() {
+    let (floor, ceiling) = (-90.0, 0.0);
+    assert_eq!(level_db(512.0, 1024), 0.0);
+    assert_eq!(brightness(level_db(512.0, 1024), floor, ceiling), 1.0);
+    assert_eq!(brightness(-45.0, floor, ceiling), 0.5);
+    assert_eq!(brightness(6.0, floor, ceiling), 1.0);
+    assert_eq!(brightness(-120.0, floor, ceiling), 0.0);
+    assert_eq!(brightness(level_db(0.0, 1024), floor, ceiling), 0.0);
+    assert_eq!(brightness(f32::NAN, floor, ceiling), 0.0);
+}
+
+#[test]
+fn level_at_brightness_inverts_brightness() {
+    let (floor, ceiling) = (-90.0, -10.0);
+    for &level in &[-90.0, -63.5, -40.0, -10.0] {
+        let b = brightness(level, floor, ceiling);
+        assert!((level_at_brightness(b, floor, ceiling) - level).abs() < 1e-4);
+    }
+    assert_eq!(level_at_brightness(-0.5, floor, ceiling), floor);
+    assert_eq!(level_at_brightness(1.5, floor, ceiling), ceiling);
+}
+
+#[test]
+fn srgb_decode_inverts_srgb_encode() {
+    assert_eq!(srgb_decode(0.0), 0.0);
+    assert!((srgb_decode(1.0) - 1.0).abs() < 1e-6);
+    for &x in &[0.001, 0.003_130_8, 0.18, 0.5, 0.9] {
+        assert!((srgb_decode(srgb_encode(x)) - x).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn blend_over_linear_blends_intensities() {
+    let pixel = |a: u8, r: u8, g: u8, b: u8| {
+        (u32::from(a) << 24 | u32::from(r) << 16 | u32::from(g) << 8 | u32::from(b)).to_ne_bytes()
+    };
+    let mut dst: Vec<u8> = [pixel(255, 0, 0, 0), pixel(255, 0, 0, 0), pixel(0, 0, 0, 0)].concat();
+    let src: Vec<u8> = [
+        pixel(0, 0, 0, 0),
+        pixel(128, 128, 128, 128),
+        pixel(255, 10, 20, 30),
+    ]
+    .concat();
+    blend_over_linear(&mut dst, &src);
+
+    // Transparent leaves the pixel as it was, opaque replaces it.
+    assert_eq!(&dst[0..4], &pixel(255, 0, 0, 0));
+    assert_eq!(&dst[8..12], &pixel(255, 10, 20, 30));
+
+    // Half of white over black is half the intensity, which encodes to 188, not 128.
+    assert_eq!(&dst[4..8], &pixel(255, 188, 188, 188));
+}
+
+#[test]
+fn srgb_encode_matches_known_points() {
+    assert_eq!(srgb_encode(0.0), 0.0);
+    assert!((srgb_encode(1.0) - 1.0).abs() < 1e-6);
+    // Both pieces meet at the threshold.
+    assert!((srgb_encode(0.003_130_8) - 0.040_45).abs() < 1e-5);
+    assert!((srgb_encode(0.18) - 0.461_356).abs() < 1e-5);
+    assert!((srgb_encode(0.5) - 0.735_357).abs() < 1e-5);
+}
+
+#[test]
+fn bitmap_hatches_undecoded_pixels() {
+    let scheme = ColorScheme {
+        colormap: Colormap::Magma,
+        invert: true,
+        encode_srgb: false,
+    };
+    let bitmap = Bitmap::generate(2, 1, scheme, |x, _y| [1.0, UNDECODED][x as usize]);
+    // Inverted, the brightest value is the darkest color.
+    assert_eq!(&bitmap.data[..3], &[0, 0, 0]);
+    assert_eq!(&bitmap.data[3..], &[40, 40, 40]);
+}
+
+#[test]
+fn bitmap_paints_non_finite_values_as_the_bottom() {
+    let scheme = ColorScheme {
+        colormap: Colormap::Magma,
+        invert: false,
+        encode_srgb: false,
+    };
+    let values = [0.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -0.5];
+    let bitmap = Bitmap::generate(5, 1, scheme, |x, _y| values[x as usize]);
+    let bottom = &bitmap.data[..3];
+    assert!(bitmap.data.chunks(3).all(|pixel| pixel == bottom));
+
+    // A silent signal is uniformly the bottom of the colormap too.
+    let bitmap = render_spectrogram(&[0.0; 20_000], 8, 10, scheme);
+    assert!(bitmap.data.chunks(3).all(|pixel| pixel == bottom));
+}
+
+#[test]
+fn map_y_axis_inv_inverts_map_y_axis() {
+    for i in 0..=100 {
+        let y = i as f64 / 100.0;
+        for &blend in &[0.0, 0.2, DEFAULT_Y_BLEND, 0.9, 1.0] {
+            let v = map_y_axis(y, 5.0, 22_050.0, blend);
+            assert!((map_y_axis_inv(v, 5.0, 22_050.0, blend) - y).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn y_scale_ignores_the_blend_unless_blended() {
+    let blend = DEFAULT_Y_BLEND;
+    assert_eq!(YScale::Linear.map(0.5, 10.0, 20.0, blend), 15.0);
+    assert!((YScale::Log.map(0.5, 10.0, 40.0, blend) - 20.0).abs() < 1e-9);
+    assert_eq!(
+        YScale::Blended.map(0.3, 10.0, 40.0, blend),
+        map_y_axis(0.3, 10.0, 40.0, blend)
+    );
+    for scale in [YScale::Blended, YScale::Linear, YScale::Log, YScale::Mel] {
+        let v = scale.map(0.7, 10.0, 40.0, blend);
+        assert!((scale.map_inv(v, 10.0, 40.0, blend) - 0.7).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn map_y_axis_mel_is_even_in_mel() {
+    assert!((hz_to_mel(1000.0) - 1000.0).abs() < 0.1);
+    assert!((mel_to_hz(hz_to_mel(4321.0)) - 4321.0).abs() < 1e-6);
+
+    // Halfway up is halfway in mel, which is well below halfway in Hz.
+    let hz = map_y_axis_mel(0.5, 0.0, 8000.0);
+    assert!((hz_to_mel(hz) - 0.5 * hz_to_mel(8000.0)).abs() < 1e-6);
+    assert!(hz < 4000.0);
+    assert!((map_y_axis_mel_inv(hz, 0.0, 8000.0) - 0.5).abs() < 1e-9);
+    assert_eq!(map_y_axis_mel_inv(9000.0, 0.0, 8000.0), 1.0);
+}
+
+#[test]
+fn map_y_axis_blend_spans_log_to_linear() {
+    // Halfway up, a log scale is at the geometric mean, a linear one at the mean.
+    assert!((map_y_axis(0.5, 10.0, 1000.0, 0.0) - 100.0).abs() < 1e-9);
+    assert!((map_y_axis(0.5, 10.0, 1000.0, 1.0) - 505.0).abs() < 1e-9);
+    assert!((map_y_axis(0.5, 10.0, 1000.0, DEFAULT_Y_BLEND) - 302.5).abs() < 1e-9);
+    assert_eq!(map_y_axis(1.0, 10.0, 1000.0, 0.0), 1000.0);
+}
+
+#[test]
+fn render_spectrogram_shows_a_tone_in_one_band() {
+    let scheme = ColorScheme {
+        colormap: Colormap::Magma,
+        invert: false,
+        encode_srgb: false,
+    };
+    let omega = 2.0 * std::f32::consts::PI * 1000.0 / 44_100.0;
+    let samples: Vec<f32> = (0..44_100).map(|i| (i as f32 * omega).sin()).collect();
+    let bitmap = render_spectrogram(&samples, 8, 100, scheme);
+    assert_eq!(bitmap.data.len(), 8 * 100 * 3);
+
+    // Every column shows the tone in the same row, and that row is the brightest.
+    let brightest_row = |x: usize| {
+        (0..100)
+            .max_by_key(|&y| {
+                bitmap.data[(y * 8 + x) * 3] as u32 + bitmap.data[(y * 8 + x) * 3 + 1] as u32
+            })
+            .unwrap()
+    };
+    let row = brightest_row(0);
+    assert!((1..8).all(|x| brightest_row(x) == row));
+    assert!(row > 0 && row < 99);
+
+    // Too short for a single window, nothing to show.
+    let bitmap = render_spectrogram(&samples[..100], 4, 4, scheme);
+    let (r, g, b) = colormap_magma(0.0);
+    let bottom = [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8];
+    assert!(bitmap.data.chunks(3).all(|pixel| pixel == bottom));
+}
+
+#[test]
+fn colormap_viridis_goes_from_purple_to_yellow() {
+    // Reference values of the table that the polynomial fits.
+    let close = |(r, g, b): (f32, f32, f32), (r0, g0, b0): (f32, f32, f32)| {
+        (r - r0).abs() < 0.02 && (g - g0).abs() < 0.02 && (b - b0).abs() < 0.02
+    };
+    assert!(close(colormap_viridis(0.0), (0.267, 0.005, 0.329)));
+    assert!(close(colormap_viridis(0.5), (0.128, 0.567, 0.551)));
+    assert!(close(colormap_viridis(1.0), (0.993, 0.906, 0.144)));
+}
+
+#[test]
+fn colormap_grayscale_clamps_and_applies_gamma() {
+    assert_eq!(colormap_grayscale(0.25, 1.0), (0.25, 0.25, 0.25));
+    assert_eq!(colormap_grayscale(-0.5, GRAYSCALE_GAMMA), (0.0, 0.0, 0.0));
+    assert_eq!(colormap_grayscale(1.5, GRAYSCALE_GAMMA), (1.0, 1.0, 1.0));
+    let (v, _, _) = colormap_grayscale(0.218, GRAYSCALE_GAMMA);
+    assert!((v - 0.5).abs() < 0.01, "{}", v);
+}
@@ -0,0 +1,311 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module puts the decoders of the formats that we support behind one
+// trait, so the model can decode a file in batches without knowing its format.
+// Formats that we decode through an external program arrive here as wav.
+
+use std::io;
+
+use crate::sanitize_sample;
+use crate::wav::WavReader;
+
+/// A decoder that produces samples, normalized to [-1, 1].
+pub trait SampleSource {
+    /// Decode about `max_len` samples per channel, and append them to `channels`.
+    ///
+    /// Returns whether there may be more samples to decode.
+    fn read_samples(&mut self, channels: &mut Vec<Vec<f32>>, max_len: usize) -> io::Result<bool>;
+
+    /// Return the number of samples per second, per channel.
+    fn sample_rate(&self) -> u32;
+
+    /// Return the number of channels, which is the length of the `channels`
+    /// that `read_samples` appends to.
+    fn channels(&self) -> u32;
+
+    /// Return the bit depth of the encoded samples, as stored in the file.
+    fn bits_per_sample(&self) -> u32;
+
+    /// Return the number of samples per channel, if the header includes it.
+    fn duration(&self) -> Option<u64>;
+}
+
+/// Decodes FLAC through claxon.
+pub struct FlacSource<R: io::Read> {
+    reader: claxon::FlacReader<R>,
+}
+
+impl<R: io::Read> FlacSource<R> {
+    /// Read the header, up to the first block.
+    pub fn new(reader: R) -> claxon::Result<FlacSource<R>> {
+        let reader = claxon::FlacReader::new(reader)?;
+        Ok(FlacSource { reader })
+    }
+}
+
+/// Return the factor that normalizes integer samples of the bit depth to [-1, 1].
+///
+/// The largest value of the bit depth maps to 1. We compute it in 64 bits,
+/// so a bit depth of 32 does not overflow.
+fn sample_scale(bits_per_sample: u32) -> f32 {
+    assert!((1..=32).contains(&bits_per_sample));
+    let max = (1_u64 << (bits_per_sample - 1)) - 1;
+    (1.0 / max.max(1) as f64) as f32
+}
+
+/// Convert a claxon error into an io error, keeping the io error if there was one.
+fn into_io_error(err: claxon::Error) -> io::Error {
+    match err {
+        claxon::Error::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)),
+    }
+}
+
+impl<R: io::Read> SampleSource for FlacSource<R> {
+    /// Decode whole blocks, until we have at least `max_len` samples per channel.
+    fn read_samples(&mut self, channels: &mut Vec<Vec<f32>>, max_len: usize) -> io::Result<bool> {
+        let inv_max = sample_scale(self.reader.streaminfo().bits_per_sample);
+
+        let mut blocks = self.reader.blocks();
+        let mut buffer = Vec::new();
+        let mut len = 0;
+        while len < max_len {
+            let block = match blocks.read_next_or_eof(buffer).map_err(into_io_error)? {
+                Some(b) => b,
+                None => return Ok(false),
+            };
+
+            // Add every channel to its samples buffer, converting to f32,
+            // regardless of the bit depth of the input.
+            channels.resize_with(block.channels() as usize, Vec::new);
+            for (ch, samples) in channels.iter_mut().enumerate() {
+                samples.reserve(block.duration() as usize);
+                for &si in block.channel(ch as u32).iter() {
+                    samples.push(sanitize_sample(inv_max * si as f32));
+                }
+            }
+
+            len += block.duration() as usize;
+            buffer = block.into_buffer();
+        }
+
+        Ok(true)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.reader.streaminfo().sample_rate
+    }
+
+    fn channels(&self) -> u32 {
+        self.reader.streaminfo().channels
+    }
+
+    fn bits_per_sample(&self) -> u32 {
+        self.reader.streaminfo().bits_per_sample
+    }
+
+    fn duration(&self) -> Option<u64> {
+        self.reader.streaminfo().samples
+    }
+}
+
+/// Decodes MP3 through minimp3.
+///
+/// MP3 has no header that describes the stream, so we decode the first frame
+/// up front, to learn the sample rate and the number of channels.
+pub struct Mp3Source<R: io::Read> {
+    decoder: minimp3::Decoder<R>,
+
+    /// The first frame, until `read_samples` takes it.
+    first_frame: Option<minimp3::Frame>,
+
+    sample_rate: u32,
+    channels: u32,
+}
+
+impl<R: io::Read> Mp3Source<R> {
+    /// Decode the first frame, skipping any tags before it.
+    pub fn new(reader: R) -> io::Result<Mp3Source<R>> {
+        let mut decoder = minimp3::Decoder::new(reader);
+        let frame = match decoder.next_frame() {
+            Ok(frame) => frame,
+            Err(minimp3::Error::Eof) => {
+                let msg = "The file contains no MP3 frames.";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            Err(err) => return Err(mp3_into_io_error(err)),
+        };
+        Ok(Mp3Source {
+            decoder,
+            sample_rate: frame.sample_rate as u32,
+            channels: frame.channels as u32,
+            first_frame: Some(frame),
+        })
+    }
+}
+
+/// Convert a minimp3 error into an io error, keeping the io error if there was one.
+fn mp3_into_io_error(err: minimp3::Error) -> io::Error {
+    match err {
+        minimp3::Error::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)),
+    }
+}
+
+impl<R: io::Read> SampleSource for Mp3Source<R> {
+    /// Decode whole frames, until we have at least `max_len` samples per channel.
+    fn read_samples(&mut self, channels: &mut Vec<Vec<f32>>, max_len: usize) -> io::Result<bool> {
+        let inv_max = sample_scale(self.bits_per_sample());
+
+        channels.resize_with(self.channels as usize, Vec::new);
+        let mut len = 0;
+        while len < max_len {
+            let frame = match self.first_frame.take() {
+                Some(frame) => frame,
+                None => match self.decoder.next_frame() {
+                    Ok(frame) => frame,
+                    Err(minimp3::Error::Eof) => return Ok(false),
+                    Err(err) => return Err(mp3_into_io_error(err)),
+                },
+            };
+
+            // The number of channels can change between frames, but we keep
+            // the one of the first frame, so every channel stays as long.
+            // A mono frame goes into every channel.
+            let frame_channels = frame.channels.max(1);
+            for (ch, samples) in channels.iter_mut().enumerate() {
+                let frame_samples = frame.data[ch.min(frame_channels - 1)..]
+                    .iter()
+                    .step_by(frame_channels);
+                samples.extend(frame_samples.map(|&si| sanitize_sample(inv_max * si as f32)));
+            }
+
+            len += frame.data.len() / frame_channels;
+        }
+
+        Ok(true)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    /// MP3 stores no bit depth, minimp3 decodes to 16 bits.
+    fn bits_per_sample(&self) -> u32 {
+        16
+    }
+
+    /// MP3 does not store the length, we only learn it at the end of the stream.
+    fn duration(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl<R: io::Read> SampleSource for WavReader<R> {
+    fn read_samples(&mut self, channels: &mut Vec<Vec<f32>>, max_len: usize) -> io::Result<bool> {
+        let begin = channels.first().map_or(0, |samples| samples.len());
+        let have_more = self.read_into(channels, max_len)?;
+
+        // Float wav files can contain NaN and infinity.
+        for samples in channels.iter_mut() {
+            for x in &mut samples[begin..] {
+                *x = sanitize_sample(*x);
+            }
+        }
+
+        Ok(have_more)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        WavReader::sample_rate(self)
+    }
+
+    fn channels(&self) -> u32 {
+        WavReader::channels(self) as u32
+    }
+
+    fn bits_per_sample(&self) -> u32 {
+        WavReader::bits_per_sample(self) as u32
+    }
+
+    fn duration(&self) -> Option<u64> {
+        WavReader::duration(self)
+    }
+}
+
+#[test]
+fn sample_scale_maps_the_largest_value_to_one() {
+    for bits_per_sample in [8, 16, 24, 32] {
+        let max = (i32::MAX >> (32 - bits_per_sample)) as f32;
+        let min = -max - 1.0;
+        let scale = sample_scale(bits_per_sample);
+        assert!((max * scale - 1.0).abs() < 1e-6, "{} bits", bits_per_sample);
+        assert!(min * scale >= -1.0 - 1e-2, "{} bits", bits_per_sample);
+    }
+    assert_eq!(sample_scale(16), 1.0 / 32_767.0);
+}
+
+#[test]
+fn flac_and_wav_sources_decode_the_same_samples() {
+    let samples: Vec<i32> = (0..10_000).map(|i| (i % 100) * 300 - 15_000).collect();
+    let flac = crate::test_flac::encode(8_000, 16, &[samples.clone()]);
+    let samples_16: Vec<i16> = samples.iter().map(|&x| x as i16).collect();
+    let wav = crate::wav::encode_16(8_000, 1, &samples_16, 20_000);
+
+    let mut sources: Vec<Box<dyn SampleSource>> = vec![
+        Box::new(FlacSource::new(io::Cursor::new(flac)).unwrap()),
+        Box::new(WavReader::new(io::Cursor::new(wav)).unwrap()),
+    ];
+    let mut decoded = Vec::new();
+    for source in sources.iter_mut() {
+        assert_eq!(source.sample_rate(), 8_000);
+        assert_eq!(source.duration(), Some(10_000));
+        let mut channels = Vec::new();
+        while source.read_samples(&mut channels, 4096).unwrap() {}
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].len(), 10_000);
+        decoded.push(channels);
+    }
+
+    // FLAC divides by the largest value, wav by a power of two, so they differ slightly.
+    for (a, b) in decoded[0][0].iter().zip(&decoded[1][0]) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+}
+
+#[test]
+fn mp3_source_decodes_every_frame() {
+    // A frame header for MPEG-1 layer III, at 128 kbps and 44.1 kHz, in
+    // mono. With all-zero side info and main data, the frame is silent.
+    let mut mp3 = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    for _ in 0..20 {
+        let frame_len = 144 * 128_000 / 44_100;
+        mp3.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        mp3.resize(mp3.len() + frame_len - 4, 0);
+    }
+
+    let mut source = Mp3Source::new(io::Cursor::new(mp3)).unwrap();
+    assert_eq!(source.sample_rate(), 44_100);
+    assert_eq!(source.channels(), 1);
+    assert_eq!(source.duration(), None);
+
+    let mut channels = Vec::new();
+    assert!(source.read_samples(&mut channels, 2000).unwrap());
+    assert_eq!(channels[0].len(), 2 * 1152);
+    while source.read_samples(&mut channels, 4096).unwrap() {}
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].len(), 20 * 1152);
+    assert!(channels[0].iter().all(|&x| x == 0.0));
+
+    let not_mp3 = io::Cursor::new(vec![0_u8; 1000]);
+    assert!(Mp3Source::new(not_mp3).is_err());
+}
@@ -0,0 +1,190 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::thread;
+
+#[cfg(test)]
+use crate::dft;
+use crate::dft::FftPlan;
+
+/// Iterator over the spectra of overlapping windows of a signal.
+///
+/// This is a short-time Fourier transform: it yields the DFT of the window
+/// that starts at sample 0, then of the window that starts at sample `hop`,
+/// and so on, for as long as the window fits in the signal. Every spectrum is
+/// in the format returned by `dft::dft_fast`.
+pub struct Stft<'a, W> {
+    samples: &'a [f32],
+    window_len: usize,
+    hop: usize,
+    window_fn: W,
+    zero_pad_factor: usize,
+
+    /// The plan for windows of `window_len * zero_pad_factor`, made on first use.
+    plan: Option<FftPlan>,
+
+    /// The index of the window that `next` yields.
+    next_index: usize,
+}
+
+impl<'a, W: Fn(usize, usize) -> f32> Stft<'a, W> {
+    pub fn new(samples: &'a [f32], window_len: usize, hop: usize, window_fn: W) -> Stft<'a, W> {
+        assert!(hop > 0, "Hop must be positive.");
+        Stft {
+            samples,
+            window_len,
+            hop,
+            window_fn,
+            zero_pad_factor: 1,
+            plan: None,
+            next_index: 0,
+        }
+    }
+
+    /// Return the number of windows that fit in the signal.
+    fn num_windows(&self) -> usize {
+        match self.samples.len().checked_sub(self.window_len) {
+            Some(excess) => 1 + excess / self.hop,
+            None => 0,
+        }
+    }
+
+    /// Return the plan that we transform with, or make one if we have none yet.
+    fn plan(&self) -> FftPlan {
+        match &self.plan {
+            Some(plan) => plan.clone(),
+            None => FftPlan::new(self.window_len * self.zero_pad_factor),
+        }
+    }
+
+    /// Return the DFT of the window that starts at sample `i * hop`.
+    fn window_spectrum(&self, plan: &mut FftPlan, i: usize) -> Box<[f32]> {
+        let start = i * self.hop;
+        let window = &self.samples[start..start + self.window_len];
+        plan.dft_windowed(window, &self.window_fn)
+    }
+
+    /// Zero-pad every window to `factor` times its length before the DFT.
+    pub fn with_zero_pad_factor(mut self, factor: usize) -> Stft<'a, W> {
+        self.zero_pad_factor = factor;
+        self.plan = None;
+        self
+    }
+
+    /// Transform with an existing plan, to not compute its twiddle factors again.
+    ///
+    /// The plan determines the zero-padding factor, its length must be a
+    /// multiple of the window length.
+    pub fn with_plan(mut self, plan: FftPlan) -> Stft<'a, W> {
+        assert_eq!(
+            plan.input_len() % self.window_len,
+            0,
+            "Plan is for another length."
+        );
+        self.zero_pad_factor = plan.input_len() / self.window_len;
+        self.plan = Some(plan);
+        self
+    }
+}
+
+impl<'a, W: Fn(usize, usize) -> f32> Iterator for Stft<'a, W> {
+    type Item = Box<[f32]>;
+
+    fn next(&mut self) -> Option<Box<[f32]>> {
+        if self.next_index >= self.num_windows() {
+            return None;
+        }
+        let mut plan = match self.plan.take() {
+            Some(plan) => plan,
+            None => self.plan(),
+        };
+        let spectrum = self.window_spectrum(&mut plan, self.next_index);
+        self.plan = Some(plan);
+        self.next_index += 1;
+        Some(spectrum)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.num_windows() - self.next_index.min(self.num_windows());
+        (n, Some(n))
+    }
+}
+
+impl<'a, W: Fn(usize, usize) -> f32> ExactSizeIterator for Stft<'a, W> {}
+
+impl<'a, W: Fn(usize, usize) -> f32 + Sync> Stft<'a, W> {
+    /// Collect the remaining spectra, computing them on as many threads as
+    /// there are cores.
+    ///
+    /// The windows are independent, so every thread takes a contiguous range
+    /// of them, with its own copy of the plan. The result is in the same order
+    /// as the iterator yields them.
+    pub fn collect_parallel(self) -> Vec<Box<[f32]>> {
+        let begin = self.next_index.min(self.num_windows());
+        let end = self.num_windows();
+        let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let num_threads = num_threads.min(end - begin);
+        if num_threads <= 1 {
+            return self.collect();
+        }
+
+        let chunk_len = (end - begin + num_threads - 1) / num_threads;
+        let stft = &self;
+        let plan = self.plan();
+        let plan = &plan;
+        thread::scope(|scope| {
+            let chunks: Vec<_> = (begin..end)
+                .step_by(chunk_len)
+                .map(|chunk_begin| {
+                    let chunk_end = (chunk_begin + chunk_len).min(end);
+                    scope.spawn(move || {
+                        let mut plan = plan.clone();
+                        (chunk_begin..chunk_end)
+                            .map(|i| stft.window_spectrum(&mut plan, i))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            chunks
+                .into_iter()
+                .flat_map(|chunk| chunk.join().unwrap())
+                .collect()
+        })
+    }
+}
+
+#[test]
+fn stft_yields_overlapping_windows() {
+    let rect_window = |_, _| 1.0;
+
+    // With a rectangular window, the first coefficient is the sum of the
+    // samples in the window, so its square is easy to predict.
+    let samples = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+    let stft = Stft::new(&samples, 4, 2, rect_window);
+    assert_eq!(stft.len(), 3);
+
+    let dcs: Vec<f32> = stft.map(|spectrum| spectrum[0]).collect();
+    assert_eq!(dcs, [100.0, 324.0, 676.0]);
+}
+
+#[test]
+fn collect_parallel_matches_the_iterator() {
+    let samples: Vec<f32> = (0..5_000).map(|i| (i as f32 * 0.1).sin()).collect();
+    let stft = || Stft::new(&samples, 256, 100, dft::hann).with_zero_pad_factor(2);
+    let sequential: Vec<Box<[f32]>> = stft().collect();
+    assert_eq!(sequential.len(), 48);
+    assert_eq!(stft().collect_parallel(), sequential);
+
+    // Windows that the iterator already yielded are not included.
+    let mut partial = stft();
+    partial.next();
+    assert_eq!(partial.collect_parallel(), &sequential[1..]);
+
+    // A plan for twice the window length zero-pads by a factor 2.
+    let planned = Stft::new(&samples, 256, 100, dft::hann).with_plan(FftPlan::new(512));
+    assert_eq!(planned.collect_parallel(), sequential);
+}
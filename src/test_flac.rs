@@ -0,0 +1,139 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module contains a minimal FLAC encoder, to generate test inputs in
+// memory rather than keeping binary files around. It stores every subframe
+// verbatim, so it does not compress, but the output is a valid FLAC stream.
+
+/// The number of inter-channel samples per frame.
+const BLOCK_SIZE: usize = 4096;
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0_u8;
+    for &b in bytes {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0_u16;
+    for &b in bytes {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Append the frame number in the UTF-8-like coding that FLAC uses.
+fn push_utf8(out: &mut Vec<u8>, n: u32) {
+    if n < 0x80 {
+        return out.push(n as u8);
+    }
+    let mut continuation = Vec::new();
+    let mut rest = n;
+    let mut first_max = 0x3f_u32;
+    while rest > first_max {
+        continuation.push(0x80 | (rest & 0x3f) as u8);
+        rest >>= 6;
+        first_max >>= 1;
+    }
+    let num_bytes = continuation.len() + 1;
+    let prefix = !(0xff_u32 >> num_bytes) as u8;
+    out.push(prefix | rest as u8);
+    out.extend(continuation.iter().rev());
+}
+
+/// Encode the channels as a FLAC stream. All channels must have equal length.
+///
+/// The bit depth must be a multiple of 8, so every sample is a whole number
+/// of bytes. Samples must fit in the given number of bits.
+pub fn encode(sample_rate: u32, bits_per_sample: u32, channels: &[Vec<i32>]) -> Vec<u8> {
+    assert_eq!(bits_per_sample % 8, 0, "Bit depth must be a multiple of 8.");
+    let num_samples = channels.first().map_or(0, |ch| ch.len());
+    let bytes_per_sample = bits_per_sample as usize / 8;
+
+    let mut out = b"fLaC".to_vec();
+
+    // The STREAMINFO block, which is the last metadata block.
+    out.extend_from_slice(&[0x80, 0, 0, 34]);
+    out.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    out.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    out.extend_from_slice(&[0; 6]);
+    let packed: u64 = (sample_rate as u64) << 44
+        | (channels.len() as u64 - 1) << 41
+        | (bits_per_sample as u64 - 1) << 36
+        | num_samples as u64;
+    out.extend_from_slice(&packed.to_be_bytes());
+    out.extend_from_slice(&[0; 16]);
+
+    for (frame_number, start) in (0..num_samples).step_by(BLOCK_SIZE).enumerate() {
+        let len = (num_samples - start).min(BLOCK_SIZE);
+        let frame_start = out.len();
+
+        // Fixed block size, block size in a 16-bit field at the end of the
+        // header, sample rate from STREAMINFO, independent channels.
+        let channel_bits = (channels.len() as u8 - 1) << 4;
+        let depth_bits = match bits_per_sample {
+            8 => 0b001,
+            16 => 0b100,
+            24 => 0b110,
+            _ => 0b111,
+        } << 1;
+        out.extend_from_slice(&[0xff, 0xf8, 0x70, channel_bits | depth_bits]);
+        push_utf8(&mut out, frame_number as u32);
+        out.extend_from_slice(&(len as u16 - 1).to_be_bytes());
+        let header_crc = crc8(&out[frame_start..]);
+        out.push(header_crc);
+
+        for channel in channels {
+            // A verbatim subframe without wasted bits.
+            out.push(0x02);
+            for &sample in &channel[start..start + len] {
+                let bytes = sample.to_be_bytes();
+                out.extend_from_slice(&bytes[4 - bytes_per_sample..]);
+            }
+        }
+
+        let frame_crc = crc16(&out[frame_start..]);
+        out.extend_from_slice(&frame_crc.to_be_bytes());
+    }
+
+    out
+}
+
+#[test]
+fn encode_roundtrips_through_claxon() {
+    let left: Vec<i32> = (0..10_000).map(|i| (i % 2000) - 1000).collect();
+    let right: Vec<i32> = left.iter().map(|&x| -x).collect();
+    let bytes = encode(44_100, 16, &[left.clone(), right.clone()]);
+
+    let mut reader = claxon::FlacReader::new(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.streaminfo().samples, Some(10_000));
+    assert_eq!(reader.streaminfo().channels, 2);
+
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let expected: Vec<i32> = left
+        .iter()
+        .zip(&right)
+        .flat_map(|(&l, &r)| [l, r])
+        .collect();
+    assert_eq!(samples, expected);
+}
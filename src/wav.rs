@@ -0,0 +1,282 @@
+// Audiograter -- Spectrogram viewer
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+// This module decodes wav files. It reads the stream front to back without
+// seeking, so it can also decode the output of a program through a pipe. Such
+// output may not know its length in advance, then we read until the end.
+
+use std::io;
+use std::io::Read;
+
+/// The encoding of the samples in the data chunk.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum SampleFormat {
+    Int,
+    Float,
+}
+
+pub struct WavReader<R: Read> {
+    reader: R,
+    format: SampleFormat,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+
+    /// The number of bytes left in the data chunk, if known.
+    remaining: Option<u64>,
+
+    /// The time of day at which the recording started, from the bext chunk.
+    origination_time: Option<String>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Read up to `buffer.len()` bytes, fewer only at the end of the stream.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buffer.len() {
+        match reader.read(&mut buffer[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(n)
+}
+
+impl<R: Read> WavReader<R> {
+    /// Read the header, up to the start of the samples.
+    pub fn new(mut reader: R) -> io::Result<WavReader<R>> {
+        let mut header = [0_u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err(invalid("Not a wav file."));
+        }
+
+        // Read chunks until the data chunk, which contains the samples. We
+        // need the fmt chunk that precedes it, other chunks we skip.
+        let mut fmt = None;
+        let mut origination_time = None;
+        let data_size = loop {
+            let mut chunk_header = [0_u8; 8];
+            reader.read_exact(&mut chunk_header)?;
+            let size = read_u32(&chunk_header[4..]);
+
+            // Chunks are padded to an even size.
+            let padded_size = size as usize + (size as usize & 1);
+
+            match &chunk_header[0..4] {
+                b"data" => break size,
+                b"fmt " if size >= 16 => {
+                    let mut body = vec![0_u8; padded_size];
+                    reader.read_exact(&mut body)?;
+                    fmt = Some(body);
+                }
+                b"fmt " => return Err(invalid("Invalid fmt chunk.")),
+                b"bext" => {
+                    let mut body = vec![0_u8; padded_size];
+                    reader.read_exact(&mut body)?;
+                    // The broadcast wave extension puts the time after a
+                    // description, originator, reference, and date.
+                    origination_time = body
+                        .get(330..338)
+                        .and_then(|time| std::str::from_utf8(time).ok())
+                        .map(|time| time.to_string());
+                }
+                _ => {
+                    let mut chunk = (&mut reader).take(padded_size as u64);
+                    io::copy(&mut chunk, &mut io::sink())?;
+                }
+            }
+        };
+
+        let fmt = match fmt {
+            Some(fmt) => fmt,
+            None => return Err(invalid("Missing fmt chunk before the data chunk.")),
+        };
+
+        // For the extensible format, the actual format is in the subformat.
+        let format_tag = match read_u16(&fmt[0..]) {
+            0xfffe if fmt.len() >= 26 => read_u16(&fmt[24..]),
+            tag => tag,
+        };
+        let format = match format_tag {
+            1 => SampleFormat::Int,
+            3 => SampleFormat::Float,
+            _ => return Err(invalid("Unsupported wav sample format.")),
+        };
+
+        let channels = read_u16(&fmt[2..]);
+        let sample_rate = read_u32(&fmt[4..]);
+        let bits_per_sample = read_u16(&fmt[14..]);
+        let supported = match format {
+            SampleFormat::Int => matches!(bits_per_sample, 8 | 16 | 24 | 32),
+            SampleFormat::Float => matches!(bits_per_sample, 32 | 64),
+        };
+        if !supported || channels == 0 || sample_rate == 0 {
+            return Err(invalid(
+                "Unsupported wav bit depth, channel count, or sample rate.",
+            ));
+        }
+
+        // Programs that write to a pipe cannot go back to fill in the size
+        // when they are done, they put a placeholder instead.
+        let remaining = match data_size {
+            0 | 0xffff_ffff => None,
+            n => Some(n as u64),
+        };
+
+        Ok(WavReader {
+            reader,
+            format,
+            channels,
+            sample_rate,
+            bits_per_sample,
+            remaining,
+            origination_time,
+        })
+    }
+
+    /// Return the time of day at which the recording started as hh:mm:ss, if known.
+    pub fn origination_time(&self) -> Option<&str> {
+        self.origination_time.as_deref()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        self.bits_per_sample
+    }
+
+    /// The number of bytes that one sample of every channel takes up.
+    fn block_align(&self) -> usize {
+        self.channels as usize * self.bits_per_sample as usize / 8
+    }
+
+    /// Return the number of inter-channel samples, if the header includes it.
+    pub fn duration(&self) -> Option<u64> {
+        self.remaining.map(|n| n / self.block_align() as u64)
+    }
+
+    /// Decode one sample, normalized to [-1, 1].
+    fn decode_sample(&self, bytes: &[u8]) -> f32 {
+        match (self.format, self.bits_per_sample) {
+            (SampleFormat::Int, 8) => (bytes[0] as f32 - 128.0) / 128.0,
+            (SampleFormat::Int, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32_768.0,
+            (SampleFormat::Int, 24) => {
+                let x = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                x as f32 / 8_388_608.0
+            }
+            (SampleFormat::Int, _) => read_u32(bytes) as i32 as f32 / 2_147_483_648.0,
+            (SampleFormat::Float, 32) => {
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+            (SampleFormat::Float, _) => {
+                let mut b = [0_u8; 8];
+                b.copy_from_slice(&bytes[..8]);
+                f64::from_le_bytes(b) as f32
+            }
+        }
+    }
+
+    /// Decode up to `max_len` samples per channel, and append them to `channels`.
+    ///
+    /// Returns whether there may be more samples to decode.
+    pub fn read_into(&mut self, channels: &mut Vec<Vec<f32>>, max_len: usize) -> io::Result<bool> {
+        let block_align = self.block_align();
+        let mut len = max_len * block_align;
+        if let Some(remaining) = self.remaining {
+            len = len.min(remaining as usize);
+        }
+
+        let mut buffer = vec![0_u8; len];
+        let n = read_full(&mut self.reader, &mut buffer)?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= n as u64;
+        }
+
+        // Drop a partial sample at the end, if the stream was cut off.
+        let num_blocks = n / block_align;
+        let sample_len = self.bits_per_sample as usize / 8;
+        channels.resize_with(self.channels as usize, Vec::new);
+        for (ch, samples) in channels.iter_mut().enumerate() {
+            samples.reserve(num_blocks);
+            for block in buffer[..num_blocks * block_align].chunks_exact(block_align) {
+                let offset = ch * sample_len;
+                samples.push(self.decode_sample(&block[offset..offset + sample_len]));
+            }
+        }
+
+        let is_done = n < len || self.remaining == Some(0);
+        Ok(!is_done)
+    }
+}
+
+/// Encode 16-bit samples as a wav file, with the given size in the header.
+#[cfg(test)]
+pub fn encode_16(sample_rate: u32, channels: u16, samples: &[i16], data_size: u32) -> Vec<u8> {
+    let mut out = b"RIFF\0\0\0\0WAVE".to_vec();
+    out.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+    out.extend_from_slice(b"fmt \x10\0\0\0");
+    out.extend_from_slice(&1_u16.to_le_bytes());
+    out.extend_from_slice(&channels.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+    out.extend_from_slice(&(channels * 2).to_le_bytes());
+    out.extend_from_slice(&16_u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_size.to_le_bytes());
+    for s in samples {
+        out.extend_from_slice(&s.to_le_bytes());
+    }
+    out
+}
+
+#[test]
+fn wav_reader_decodes_interleaved_samples() {
+    let samples = [0, -32_768, 16_384, 8_192, -16_384, 0];
+    let bytes = encode_16(8_000, 2, &samples, 12);
+
+    let mut reader = WavReader::new(io::Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.sample_rate(), 8_000);
+    assert_eq!(reader.duration(), Some(3));
+
+    let mut channels = Vec::new();
+    assert!(reader.read_into(&mut channels, 2).unwrap());
+    assert!(!reader.read_into(&mut channels, 2).unwrap());
+    assert_eq!(channels, [vec![0.0, 0.5, -0.5], vec![-1.0, 0.25, 0.0]]);
+}
+
+#[test]
+fn wav_reader_reads_until_end_without_size() {
+    let samples = [1_000; 10];
+    let bytes = encode_16(8_000, 1, &samples, 0xffff_ffff);
+
+    let mut reader = WavReader::new(io::Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.duration(), None);
+
+    let mut channels = Vec::new();
+    assert!(!reader.read_into(&mut channels, 100).unwrap());
+    assert_eq!(channels[0].len(), 10);
+}